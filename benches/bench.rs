@@ -5,7 +5,8 @@ extern crate test;
 extern crate flate2;
 use test::Bencher;
 use flate2::Compression;
-use deflate::{CompressionOptions, deflate_bytes_zlib_conf, deflate_bytes_zlib, lz77_compress};
+use deflate::{CompressionOptions, deflate_bytes_conf, deflate_bytes_zlib_conf, deflate_bytes_zlib,
+              lz77_compress};
 
 fn load_from_file(name: &str) -> Vec<u8> {
     use std::fs::File;
@@ -23,6 +24,44 @@ fn get_test_data() -> Vec<u8> {
     load_from_file(&path)
 }
 
+/// The test data repeated until it's at least 4 MiB long.
+fn get_large_test_data() -> Vec<u8> {
+    let test_data = get_test_data();
+    let mut data = Vec::new();
+    while data.len() < 4 << 20 {
+        data.extend_from_slice(&test_data);
+    }
+    data
+}
+
+/// Benchmark compressing a large input, buffering `buffer_windows` windows of it at a time.
+fn bench_large(b: &mut Bencher, options: CompressionOptions, buffer_windows: u8) {
+    let test_data = get_large_test_data();
+    let options = CompressionOptions { buffer_windows, ..options };
+    b.bytes = test_data.len() as u64;
+    b.iter(|| deflate_bytes_conf(&test_data, options));
+}
+
+#[bench]
+fn test_large_fast(b: &mut Bencher) {
+    bench_large(b, CompressionOptions::fast(), 1);
+}
+
+#[bench]
+fn test_large_fast_four_windows(b: &mut Bencher) {
+    bench_large(b, CompressionOptions::fast(), 4);
+}
+
+#[bench]
+fn test_large_def(b: &mut Bencher) {
+    bench_large(b, CompressionOptions::default(), 1);
+}
+
+#[bench]
+fn test_large_def_four_windows(b: &mut Bencher) {
+    bench_large(b, CompressionOptions::default(), 4);
+}
+
 #[bench]
 fn test_file_zlib_lz77_only(b: &mut Bencher) {
     let test_data = get_test_data();
@@ -33,21 +72,21 @@ fn test_file_zlib_lz77_only(b: &mut Bencher) {
 fn test_file_zlib_def(b: &mut Bencher) {
     let test_data = get_test_data();
 
-    b.iter(|| deflate_bytes_zlib(&test_data));
+    b.iter(|| deflate_bytes_zlib(&test_data).unwrap());
 }
 
 #[bench]
 fn test_file_zlib_high(b: &mut Bencher) {
     let test_data = get_test_data();
 
-    b.iter(|| deflate_bytes_zlib_conf(&test_data, CompressionOptions::high()));
+    b.iter(|| deflate_bytes_zlib_conf(&test_data, CompressionOptions::high()).unwrap());
 }
 
 #[bench]
 fn test_file_zlib_fast(b: &mut Bencher) {
     let test_data = get_test_data();
 
-    b.iter(|| deflate_bytes_zlib_conf(&test_data, CompressionOptions::fast()));
+    b.iter(|| deflate_bytes_zlib_conf(&test_data, CompressionOptions::fast()).unwrap());
 }
 
 
@@ -0,0 +1,107 @@
+//! Print the block structure of a DEFLATE or zlib stream.
+//!
+//! Usage: `cargo run --example inspect -- [--zlib] [--lengths] <file>`
+
+extern crate deflate;
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::process;
+
+use deflate::inspect::{inspect_deflate, inspect_zlib, BlockInfo};
+
+fn percent(bits: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        bits as f64 * 100.0 / total as f64
+    }
+}
+
+fn print_block(n: usize, block: &BlockInfo, show_lengths: bool) {
+    println!("block {}: {:?}{} at bit {} (byte {})",
+             n,
+             block.block_type,
+             if block.final_block { " (final)" } else { "" },
+             block.bit_offset,
+             block.bit_offset / 8);
+    if let Some(ref header) = block.header {
+        println!("  HLIT {} HDIST {} HCLEN {}",
+                 header.hlit,
+                 header.hdist,
+                 header.hclen);
+        if show_lengths {
+            println!("  code length lengths: {:?}", &header.code_length_lengths[..]);
+            println!("  literal/length lengths: {:?}", header.literal_length_lengths);
+            println!("  distance lengths: {:?}", header.distance_lengths);
+        }
+    }
+    println!("  {} literals, {} matches, {} bytes decoded",
+             block.literals,
+             block.matches,
+             block.decoded_bytes);
+    let total = block.total_bits;
+    println!("  {} bits: header {} ({:.1}%), literals {} ({:.1}%), lengths {} ({:.1}%), \
+              distances {} ({:.1}%), end of block {}",
+             total,
+             block.header_bits,
+             percent(block.header_bits, total),
+             block.literal_bits,
+             percent(block.literal_bits, total),
+             block.length_bits,
+             percent(block.length_bits, total),
+             block.distance_bits,
+             percent(block.distance_bits, total),
+             block.end_of_block_bits);
+}
+
+fn main() {
+    let mut zlib = false;
+    let mut show_lengths = false;
+    let mut path = None;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--zlib" => zlib = true,
+            "--lengths" => show_lengths = true,
+            _ => path = Some(arg),
+        }
+    }
+
+    let path = match path {
+        Some(p) => p,
+        None => {
+            eprintln!("Usage: inspect [--zlib] [--lengths] <file>");
+            process::exit(2);
+        }
+    };
+
+    let mut data = Vec::new();
+    if let Err(e) = File::open(&path).and_then(|mut f| f.read_to_end(&mut data)) {
+        eprintln!("Failed to read {}: {}", path, e);
+        process::exit(1);
+    }
+
+    let result = if zlib {
+        inspect_zlib(&data)
+    } else {
+        inspect_deflate(&data)
+    };
+
+    match result {
+        Ok(blocks) => {
+            for (n, block) in blocks.iter().enumerate() {
+                print_block(n, block, show_lengths);
+            }
+            let decoded: usize = blocks.iter().map(|b| b.decoded_bytes).sum();
+            println!("{} blocks, {} bytes in, {} bytes decoded",
+                     blocks.len(),
+                     data.len(),
+                     decoded);
+        }
+        Err(e) => {
+            eprintln!("Failed to parse {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,238 @@
+#ifndef DEFLATE_RS_H
+#define DEFLATE_RS_H
+
+/* This file is generated by cbindgen from src/capi.rs, do not edit it manually. */
+
+#include <stdint.h>
+
+/*
+ Success.
+ */
+#define DRS_OK 0
+
+/*
+ All input has been compressed and all output has been delivered.
+ */
+#define DRS_STREAM_END 1
+
+/*
+ Invalid parameters or stream state.
+ */
+#define DRS_STREAM_ERROR -2
+
+/*
+ No progress was possible, e.g because the output buffer is full.
+ */
+#define DRS_BUF_ERROR -5
+
+/*
+ Compress as much as possible, leaving the rest for later calls.
+ */
+#define DRS_NO_FLUSH 0
+
+/*
+ Treated the same as `DRS_SYNC_FLUSH`.
+ */
+#define DRS_PARTIAL_FLUSH 1
+
+/*
+ Output all the data compressed so far, followed by an empty stored block.
+ */
+#define DRS_SYNC_FLUSH 2
+
+/*
+ Complete the stream.
+ */
+#define DRS_FINISH 4
+
+/*
+ Only output stored (uncompressed) blocks.
+ */
+#define DRS_NO_COMPRESSION 0
+
+#define DRS_BEST_SPEED 1
+
+#define DRS_BEST_COMPRESSION 9
+
+/*
+ Use the default compression level (6).
+ */
+#define DRS_DEFAULT_COMPRESSION -1
+
+#define DRS_DEFAULT_STRATEGY 0
+
+/*
+ Only use matches longer than 5 bytes, for data produced by a filter or predictor.
+ */
+#define DRS_FILTERED 1
+
+/*
+ Don't look for matches, only use huffman coding.
+ */
+#define DRS_HUFFMAN_ONLY 2
+
+/*
+ Only use the fixed huffman codes.
+ */
+#define DRS_FIXED 4
+
+/*
+ The only supported compression method.
+ */
+#define DRS_DEFLATED 8
+
+/*
+ A compression stream.
+
+ The application sets `next_in`/`avail_in` and `next_out`/`avail_out` before each call to
+ `drs_deflate`, which updates them to reflect the data consumed and produced.
+ */
+typedef struct drs_stream {
+  /*
+   Next input byte.
+   */
+  const uint8_t *next_in;
+  /*
+   Number of bytes available at `next_in`.
+   */
+  unsigned int avail_in;
+  /*
+   Total number of input bytes read so far.
+   */
+  unsigned long total_in;
+  /*
+   Where the next output byte will be written.
+   */
+  uint8_t *next_out;
+  /*
+   Remaining free space at `next_out`.
+   */
+  unsigned int avail_out;
+  /*
+   Total number of bytes output so far.
+   */
+  unsigned long total_out;
+  /*
+   Description of the last error, or null if there was none.
+   */
+  const char *msg;
+  /*
+   Internal state, not to be touched by the application.
+   */
+  void *state;
+} drs_stream;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Initialize a compression stream with the default window size and strategy, producing zlib
+ output.
+
+ # Safety
+
+ `strm` has to be null or point to a valid `drs_stream`.
+ */
+int drs_deflateInit(struct drs_stream *strm, int level);
+
+/*
+ Initialize a compression stream.
+
+ * `level`: `DRS_DEFAULT_COMPRESSION`, or `0` (stored only) to `9` (best compression).
+ * `method`: Has to be `DRS_DEFLATED`.
+ * `window_bits`: `8..15` for zlib output with a window of `2^window_bits` bytes,
+   `-8..-15` for raw deflate output, or `24..31` (`16` plus the window bits) for gzip output.
+ * `mem_level`: Accepted for compatibility with zlib (`1..9`), but has no effect.
+ * `strategy`: `DRS_DEFAULT_STRATEGY`, `DRS_FILTERED`, `DRS_HUFFMAN_ONLY` or `DRS_FIXED`.
+
+ Returns `DRS_OK` on success, or `DRS_STREAM_ERROR` if any of the parameters are invalid.
+ The stream has to be freed with `drs_deflateEnd`.
+
+ # Safety
+
+ `strm` has to be null or point to a valid `drs_stream`. Any state it contains from an
+ earlier initialization is not freed.
+ */
+int drs_deflateInit2(struct drs_stream *strm,
+                     int level,
+                     int method,
+                     int window_bits,
+                     int mem_level,
+                     int strategy);
+
+/*
+ Compress the available input, and copy as much of the compressed data as there is room for
+ to the output buffer.
+
+ All of the input is always consumed. `flush` is one of `DRS_NO_FLUSH`, `DRS_PARTIAL_FLUSH`,
+ `DRS_SYNC_FLUSH` or `DRS_FINISH`. After `DRS_FINISH` has been used, `drs_deflate` should be
+ called with `DRS_FINISH` and more output space until it returns `DRS_STREAM_END`.
+
+ Returns `DRS_OK` if progress was made, `DRS_STREAM_END` once all output of a finished
+ stream has been delivered, `DRS_BUF_ERROR` if no progress was possible, or
+ `DRS_STREAM_ERROR` if the stream or parameters are invalid.
+
+ # Safety
+
+ `strm` has to be null or point to a `drs_stream` initialized by `drs_deflateInit2`, and
+ `next_in`/`next_out` have to be valid for reading `avail_in` and writing `avail_out` bytes.
+ */
+int drs_deflate(struct drs_stream *strm, int flush);
+
+/*
+ Free the internal state of the stream.
+
+ # Safety
+
+ `strm` has to be null or point to a `drs_stream` initialized by `drs_deflateInit2`.
+ */
+int drs_deflateEnd(struct drs_stream *strm);
+
+/*
+ Reset the stream so it can be used to compress a new stream with the same parameters.
+
+ Any preset dictionary is discarded.
+
+ # Safety
+
+ `strm` has to be null or point to a `drs_stream` initialized by `drs_deflateInit2`.
+ */
+int drs_deflateReset(struct drs_stream *strm);
+
+/*
+ Use the provided data as a preset dictionary.
+
+ This has to be called after initialization, before the first call to `drs_deflate`. Preset
+ dictionaries are not supported for gzip output.
+
+ # Safety
+
+ `strm` has to be null or point to a `drs_stream` initialized by `drs_deflateInit2`, and
+ `dictionary` has to be valid for reading `dict_length` bytes.
+ */
+int drs_deflateSetDictionary(struct drs_stream *strm,
+                             const uint8_t *dictionary,
+                             unsigned int dict_length);
+
+/*
+ Compute the maximum size of the output from compressing `source_length` bytes in one call
+ using `DRS_FINISH`, so an output buffer of this size is always large enough.
+
+ Literals take at most 9 bits with the fixed huffman codes, matches take less per byte, and
+ dynamic codes are never longer in total than the fixed ones, so the compressed data is at
+ most 9/8 of the input. On top of that come the block headers and end of block codes, one
+ for every 32 KiB of input at most, and the zlib or gzip header and trailer.
+
+ # Safety
+
+ `strm` has to be null or point to a `drs_stream` initialized by `drs_deflateInit2`. If it
+ is null, the size of the zlib header and trailer is assumed.
+ */
+unsigned long drs_deflateBound(struct drs_stream *strm, unsigned long source_length);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* DEFLATE_RS_H */
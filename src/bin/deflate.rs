@@ -0,0 +1,279 @@
+//! Command line tool compressing files or stdin to raw DEFLATE, zlib or gzip.
+//!
+//! The output is produced by the library encoders, so it is byte-identical to what the library
+//! API produces with the same options.
+//!
+//! Usage: `deflate [OPTIONS] [FILE]`, see `deflate --help`.
+
+extern crate deflate;
+
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process;
+
+use deflate::{CompressionOptions, CompressionStats, Format, MatchingType, SpecialOptions};
+use deflate::write::Encoder;
+
+const USAGE: &str = "\
+Usage: deflate [OPTIONS] [FILE]
+
+Compress FILE, or standard input if FILE is missing or `-`.
+
+Options:
+  -o, --output FILE        Write the output to FILE instead of standard output.
+  -f, --format FORMAT      Output format: raw, zlib or gzip. [default: gzip]
+  -l, --level LEVEL        Compression level, 0 (stored only) to 9 (best). [default: 6]
+  -s, --strategy STRATEGY  Matching strategy: default, greedy, lazy, lazy2, filtered,
+                           huffman-only or fixed.
+  -w, --window-bits BITS   Base-2 logarithm of the window size, 8 to 15. [default: 15]
+  -d, --dictionary FILE    Use the contents of FILE as a preset dictionary (raw and zlib only).
+      --rsyncable          Reset the encoder at points determined by the input, so changes
+                           to it only change the output locally.
+      --stats              Print compression statistics to standard error.
+  -h, --help               Print this help text.
+";
+
+/// The size of the chunks the input is read and compressed in.
+const CHUNK_SIZE: usize = 1 << 20;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Strategy {
+    Default,
+    Greedy,
+    Lazy,
+    Lazy2,
+    Filtered,
+    HuffmanOnly,
+    Fixed,
+}
+
+struct Args {
+    input: Option<String>,
+    output: Option<String>,
+    format: Format,
+    level: u8,
+    strategy: Strategy,
+    window_bits: u8,
+    dictionary: Option<String>,
+    rsyncable: bool,
+    stats: bool,
+}
+
+impl Args {
+    fn compression_options(&self) -> CompressionOptions {
+        let mut options = match self.strategy {
+            Strategy::HuffmanOnly => CompressionOptions::huffman_only(),
+            _ => CompressionOptions::from_level(self.level),
+        };
+        match self.strategy {
+            Strategy::Greedy => options.matching_type = MatchingType::Greedy,
+            Strategy::Lazy => options.matching_type = MatchingType::Lazy,
+            Strategy::Lazy2 => options.matching_type = MatchingType::Lazy2,
+            Strategy::Filtered => options.special = SpecialOptions::Filtered,
+            Strategy::Fixed => options.special = SpecialOptions::ForceFixed,
+            Strategy::Default | Strategy::HuffmanOnly => (),
+        }
+        options.window_bits = self.window_bits;
+        options.rsyncable = self.rsyncable;
+        options
+    }
+}
+
+/// An error in the command line arguments.
+struct UsageError(String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+fn parse_number(option: &str, value: &str, min: u8, max: u8) -> Result<u8, UsageError> {
+    match value.parse::<u8>() {
+        Ok(n) if n >= min && n <= max => Ok(n),
+        _ => {
+            Err(UsageError(format!("Invalid value `{}` for {}, expected a number from {} to {}",
+                                   value,
+                                   option,
+                                   min,
+                                   max)))
+        }
+    }
+}
+
+/// Parse the command line arguments.
+///
+/// Returns `Ok(None)` if the help text was requested.
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Args>, UsageError> {
+    let mut parsed = Args {
+        input: None,
+        output: None,
+        format: Format::Gzip,
+        level: 6,
+        strategy: Strategy::Default,
+        window_bits: 15,
+        dictionary: None,
+        rsyncable: false,
+        stats: false,
+    };
+
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Ok(None);
+        }
+        if arg == "--stats" {
+            parsed.stats = true;
+            continue;
+        }
+        if arg == "--rsyncable" {
+            parsed.rsyncable = true;
+            continue;
+        }
+        if arg == "-" || !arg.starts_with('-') {
+            if parsed.input.is_some() {
+                return Err(UsageError("Only one input file can be specified".to_owned()));
+            }
+            parsed.input = Some(arg);
+            continue;
+        }
+
+        let value = match args.next() {
+            Some(value) => value,
+            None => return Err(UsageError(format!("Missing value for {}", arg))),
+        };
+        match arg.as_str() {
+            "-o" | "--output" => parsed.output = Some(value),
+            "-f" | "--format" => {
+                parsed.format = match value.as_str() {
+                    "raw" | "deflate" => Format::Raw,
+                    "zlib" => Format::Zlib,
+                    "gzip" => Format::Gzip,
+                    _ => return Err(UsageError(format!("Unknown format `{}`", value))),
+                }
+            }
+            "-l" | "--level" => parsed.level = parse_number(&arg, &value, 0, 9)?,
+            "-s" | "--strategy" => {
+                parsed.strategy = match value.as_str() {
+                    "default" => Strategy::Default,
+                    "greedy" => Strategy::Greedy,
+                    "lazy" => Strategy::Lazy,
+                    "lazy2" => Strategy::Lazy2,
+                    "filtered" => Strategy::Filtered,
+                    "huffman-only" => Strategy::HuffmanOnly,
+                    "fixed" => Strategy::Fixed,
+                    _ => return Err(UsageError(format!("Unknown strategy `{}`", value))),
+                }
+            }
+            "-w" | "--window-bits" => parsed.window_bits = parse_number(&arg, &value, 8, 15)?,
+            "-d" | "--dictionary" => parsed.dictionary = Some(value),
+            _ => return Err(UsageError(format!("Unknown option {}", arg))),
+        }
+    }
+
+    if parsed.dictionary.is_some() && parsed.format == Format::Gzip {
+        return Err(UsageError("The gzip format does not support preset dictionaries".to_owned()));
+    }
+
+    Ok(Some(parsed))
+}
+
+fn read_file(path: &str) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Read all of `input`, compress it and write it to `output`.
+fn compress<R: Read, W: Write>(mut input: R,
+                               output: W,
+                               args: &Args)
+                               -> io::Result<CompressionStats> {
+    let mut encoder = Encoder::new(output, args.format, args.compression_options());
+    if let Some(ref path) = args.dictionary {
+        encoder.set_dictionary(&read_file(path)?)?;
+    }
+
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        // Fill the buffer as far as possible, so the chunks passed to the encoder don't depend
+        // on how the input is delivered.
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match input.read(&mut buffer[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+        encoder.write_all(&buffer[..filled])?;
+        if filled < buffer.len() {
+            break;
+        }
+    }
+
+    let (mut output, stats) = encoder.finish_with_stats()?;
+    output.flush()?;
+    Ok(stats)
+}
+
+fn print_stats(stats: &CompressionStats) {
+    let ratio = if stats.bytes_in == 0 {
+        0.0
+    } else {
+        stats.bytes_out as f64 * 100.0 / stats.bytes_in as f64
+    };
+    eprintln!("bytes in:         {}", stats.bytes_in);
+    eprintln!("bytes out:        {} ({:.2}%)", stats.bytes_out, ratio);
+    eprintln!("blocks:           {} ({} stored, {} fixed, {} dynamic)",
+              stats.blocks(),
+              stats.stored_blocks,
+              stats.fixed_blocks,
+              stats.dynamic_blocks);
+    eprintln!("literals:         {}", stats.literals);
+    eprintln!("matches:          {}", stats.matches);
+    eprintln!("average length:   {:.2}", stats.average_match_length());
+    eprintln!("average distance: {:.2}", stats.average_match_distance());
+    eprintln!("hash probes:      {}", stats.hash_probes);
+}
+
+fn run(args: &Args) -> io::Result<CompressionStats> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let input: Box<dyn Read> = match args.input {
+        Some(ref path) if path != "-" => Box::new(File::open(path)?),
+        _ => Box::new(stdin.lock()),
+    };
+    match args.output {
+        Some(ref path) => compress(input, File::create(path)?, args),
+        None => compress(input, stdout.lock(), args),
+    }
+}
+
+fn main() {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            print!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("deflate: {}\nTry `deflate --help` for more information.", e);
+            process::exit(2);
+        }
+    };
+
+    match run(&args) {
+        Ok(stats) => {
+            if args.stats {
+                print_stats(&stats);
+            }
+        }
+        Err(e) => {
+            eprintln!("deflate: {}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! A binary tree match finder, like the ones used by 7-zip and libdeflate.
+//!
+//! The strings starting at the previous positions in the window are kept in binary search trees,
+//! one for each hash of their first three bytes, ordered by their contents. Searching the tree
+//! for the string at the current position only visits the strings sharing the longest prefixes
+//! with it, so long matches are found with far fewer comparisons than when walking a hash chain.
+//! The current string becomes the new root of the tree as it is searched for, so every position
+//! has to go through the tree, including the ones inside matches.
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cmp;
+
+use matching::get_match_length;
+
+const TREE_HASH_BITS: u32 = 16;
+const TREE_HASH_SIZE: usize = 1 << TREE_HASH_BITS;
+/// Marks a missing node.
+const NIL: u32 = u32::MAX;
+/// The shortest match reported.
+const MIN_MATCH: usize = 3;
+
+/// A match found at a position.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Match {
+    pub length: usize,
+    pub distance: usize,
+}
+
+/// Hash the three bytes at `position`.
+#[inline]
+fn hash(data: &[u8], position: usize) -> usize {
+    let bytes = u32::from(data[position]) | u32::from(data[position + 1]) << 8 |
+                u32::from(data[position + 2]) << 16;
+    (bytes.wrapping_mul(0x1E35_A7BD) >> (32 - TREE_HASH_BITS)) as usize
+}
+
+pub struct BinaryTree {
+    /// The root of the tree for each hash value, the latest position with that hash.
+    head: Box<[u32]>,
+    /// The left (smaller) and right (greater) children of the node for each position, stored at
+    /// twice the position modulo the window size.
+    children: Box<[u32]>,
+    /// Mask used to get the index in `children` of a position, one less than the window size.
+    window_mask: usize,
+}
+
+impl BinaryTree {
+    /// Create a match finder for a buffer laid out with the window size `window_size`, which
+    /// has to be a power of two.
+    pub fn new(window_size: usize) -> BinaryTree {
+        debug_assert!(window_size.is_power_of_two());
+        BinaryTree {
+            head: vec![NIL; TREE_HASH_SIZE].into_boxed_slice(),
+            children: vec![NIL; 2 * window_size].into_boxed_slice(),
+            window_mask: window_size - 1,
+        }
+    }
+
+    /// Remove all the positions from the trees.
+    pub fn reset(&mut self) {
+        for node in self.head.iter_mut().chain(self.children.iter_mut()) {
+            *node = NIL;
+        }
+    }
+
+    /// Move all the positions down by `bytes`, like `ChainedHashTable::slide`, forgetting the
+    /// ones that end up before the start of the buffer.
+    pub fn slide(&mut self, bytes: usize) {
+        let bytes = bytes as u32;
+        for node in self.head.iter_mut().chain(self.children.iter_mut()) {
+            *node = if *node != NIL && *node >= bytes {
+                *node - bytes
+            } else {
+                NIL
+            };
+        }
+        self.children.rotate_left(2 * (bytes as usize & self.window_mask));
+    }
+
+    /// Add the string at `position` to its tree, calling `on_match` with the length and distance
+    /// of each match found that is longer than the ones before it.
+    ///
+    /// At most `max_depth` nodes are checked, and the matches are at most `window_size` bytes
+    /// back and `max_length` bytes long. There have to be at least three bytes at `position`.
+    #[inline]
+    #[allow(clippy::too_many_arguments)]
+    fn advance<F: FnMut(usize, usize)>(&mut self,
+                                       data: &[u8],
+                                       position: usize,
+                                       max_depth: u16,
+                                       window_size: usize,
+                                       max_length: usize,
+                                       hash_probes: &mut u64,
+                                       mut on_match: F) {
+        let max_length = cmp::min(max_length, data.len() - position);
+        // The node a whole window back shares its children with the current position, so it
+        // can't be used.
+        let max_distance = cmp::min(window_size, self.window_mask);
+        let hash = hash(data, position);
+        let mut node = self.head[hash];
+        self.head[hash] = position as u32;
+
+        // Where to link the next nodes found that are smaller and greater than the current
+        // string, and how long prefixes they were known to share with it.
+        let mut pending_lt = 2 * (position & self.window_mask);
+        let mut pending_gt = pending_lt + 1;
+        let mut lt_length = 0;
+        let mut gt_length = 0;
+
+        let mut best_length = MIN_MATCH - 1;
+        let mut depth = max_depth;
+        let mut probes = 0;
+        loop {
+            let n = node as usize;
+            if node == NIL || n >= position || position - n > max_distance || depth == 0 {
+                self.children[pending_lt] = NIL;
+                self.children[pending_gt] = NIL;
+                break;
+            }
+            probes += 1;
+            depth -= 1;
+
+            // All the strings between the closest smaller and greater ones found so far share
+            // the shorter of their prefixes with the current string.
+            let known = cmp::min(lt_length, gt_length);
+            let length = known + get_match_length(data, position + known, n + known,
+                                                  max_length - known);
+            if length > best_length {
+                best_length = length;
+                on_match(length, position - n);
+            }
+
+            let node_children = 2 * (n & self.window_mask);
+            if length >= max_length {
+                // The strings are equal as far as they can be compared, so the current string
+                // takes the place of the node in the tree.
+                self.children[pending_lt] = self.children[node_children];
+                self.children[pending_gt] = self.children[node_children + 1];
+                break;
+            }
+            if data[n + length] < data[position + length] {
+                // The node is smaller, so the strings between it and the current one are in its
+                // right subtree.
+                self.children[pending_lt] = node;
+                pending_lt = node_children + 1;
+                lt_length = length;
+                node = self.children[node_children + 1];
+            } else {
+                self.children[pending_gt] = node;
+                pending_gt = node_children;
+                gt_length = length;
+                node = self.children[node_children];
+            }
+        }
+        *hash_probes += probes;
+    }
+
+    /// Add the string at `position` to its tree without looking for matches.
+    pub fn skip(&mut self,
+                data: &[u8],
+                position: usize,
+                max_depth: u16,
+                window_size: usize,
+                max_length: usize,
+                hash_probes: &mut u64) {
+        self.advance(data, position, max_depth, window_size, max_length, hash_probes, |_, _| ());
+    }
+
+    /// Add the string at `position` to its tree, and return the length and distance of the
+    /// longest match found, like `matching::longest_match`.
+    ///
+    /// The strings are only compared up to `nice_length` bytes, so the search stops once a match
+    /// that long is found, which is then extended to up to `max_length` bytes. `skip` has to be
+    /// called with `nice_length` as the maximum length, as the trees are ordered by that many
+    /// bytes. If there is no match longer than `prev_length`, the length returned is 2.
+    #[allow(clippy::too_many_arguments)]
+    pub fn longest_match(&mut self,
+                         data: &[u8],
+                         position: usize,
+                         prev_length: usize,
+                         max_depth: u16,
+                         window_size: usize,
+                         max_length: usize,
+                         nice_length: usize,
+                         hash_probes: &mut u64)
+                         -> (usize, usize) {
+        let nice_length = cmp::min(nice_length, max_length);
+        let mut best = (0, 0);
+        self.advance(data,
+                     position,
+                     max_depth,
+                     window_size,
+                     nice_length,
+                     hash_probes,
+                     |length, distance| best = (length, distance));
+        let (mut length, distance) = best;
+        if length == nice_length && length < max_length {
+            length += get_match_length(data,
+                                       position + length,
+                                       position - distance + length,
+                                       max_length - length);
+        }
+        if length > prev_length {
+            (length, distance)
+        } else {
+            (2, 0)
+        }
+    }
+
+    /// Add the string at `position` to its tree, and store all the matches found in `matches`,
+    /// each one longer than the ones before it.
+    ///
+    /// The closest match of each length is found, which is what an optimal parser needs.
+    #[allow(dead_code, clippy::too_many_arguments)]
+    pub fn find_matches(&mut self,
+                        data: &[u8],
+                        position: usize,
+                        max_depth: u16,
+                        window_size: usize,
+                        max_length: usize,
+                        hash_probes: &mut u64,
+                        matches: &mut Vec<Match>) {
+        matches.clear();
+        self.advance(data,
+                     position,
+                     max_depth,
+                     window_size,
+                     max_length,
+                     hash_probes,
+                     |length, distance| {
+                         matches.push(Match {
+                             length,
+                             distance,
+                         })
+                     });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_utils::get_test_data;
+
+    const WINDOW_SIZE: usize = 32768;
+
+    /// Find the matches at each position of `data`, checking that they are valid.
+    fn check_matches(data: &[u8], window_size: usize) -> usize {
+        let mut tree = BinaryTree::new(WINDOW_SIZE);
+        let mut matches = Vec::new();
+        let mut total = 0;
+        for position in 0..data.len() - 2 {
+            tree.find_matches(data, position, 4096, window_size, 258, &mut 0, &mut matches);
+            let mut prev_length = 2;
+            for m in &matches {
+                assert!(m.length > prev_length);
+                assert!(m.distance <= window_size && m.distance <= position);
+                let start = position - m.distance;
+                assert_eq!(data[start..start + m.length], data[position..position + m.length]);
+                prev_length = m.length;
+            }
+            total += matches.len();
+        }
+        total
+    }
+
+    #[test]
+    fn matches_are_valid() {
+        let data = get_test_data();
+        assert!(check_matches(&data[..100000], WINDOW_SIZE) > 0);
+        assert!(check_matches(&data[..20000], 1000) > 0);
+    }
+
+    #[test]
+    fn all_lengths_reported() {
+        let data = b"abcdefZabcdeYabcdXabcdefg";
+        let mut tree = BinaryTree::new(WINDOW_SIZE);
+        let mut matches = Vec::new();
+        let position = data.len() - 7;
+        for p in 0..position {
+            tree.skip(data, p, 64, WINDOW_SIZE, 258, &mut 0);
+        }
+        tree.find_matches(data, position, 64, WINDOW_SIZE, 258, &mut 0, &mut matches);
+        // Each of the earlier strings gives a longer match than the closer ones.
+        assert_eq!(matches,
+                   [Match { length: 4, distance: 5 },
+                    Match { length: 5, distance: 11 },
+                    Match { length: 6, distance: 18 }]);
+    }
+
+    #[test]
+    fn nice_length_extended() {
+        let mut data = vec![0; 50];
+        data.extend((0..20).map(|n| n as u8));
+        data.extend((0..20).map(|n| n as u8));
+        let position = 70;
+        let mut tree = BinaryTree::new(WINDOW_SIZE);
+        for p in 0..position {
+            tree.skip(&data, p, 64, WINDOW_SIZE, 8, &mut 0);
+        }
+        // The match is found using the first 8 bytes, and then extended to the end of the data.
+        assert_eq!(tree.longest_match(&data, position, 2, 64, WINDOW_SIZE, 258, 8, &mut 0),
+                   (20, 20));
+        // Matches no longer than the previous one aren't reported.
+        assert_eq!(tree.longest_match(&data, position + 1, 19, 64, WINDOW_SIZE, 258, 8, &mut 0),
+                   (2, 0));
+    }
+
+    #[test]
+    fn slide() {
+        let data = get_test_data();
+        let data = &data[..3 * WINDOW_SIZE];
+        let mut tree = BinaryTree::new(WINDOW_SIZE);
+        for position in 0..2 * WINDOW_SIZE {
+            tree.skip(data, position, 64, WINDOW_SIZE, 258, &mut 0);
+        }
+        tree.slide(WINDOW_SIZE);
+        let data = &data[WINDOW_SIZE..];
+        let mut found = 0;
+        for position in WINDOW_SIZE..data.len() - 2 {
+            let (length, distance) =
+                tree.longest_match(data, position, 2, 64, WINDOW_SIZE, 258, 258, &mut 0);
+            if length > 2 {
+                assert!(data[position - distance..position - distance + length] ==
+                        data[position..position + length]);
+                found += 1;
+            }
+        }
+        assert!(found > 0);
+    }
+}
@@ -1,4 +1,4 @@
-use std::cmp::min;
+use core::cmp::min;
 
 /// Reverse the first length bits of n.
 /// (Passing more than 16 as length will act the same as passing 16)
@@ -6,8 +6,14 @@
 //! This module provides bit readers and writers
 #![allow(dead_code)]
 
+use alloc::vec::Vec;
+use core::cmp;
+#[cfg(feature = "std")]
 use std::io::{self, Write};
 
+use error::Error;
+use sink::{self, Sink};
+
 /// Containes either the consumed bytes and reconstructed bits or
 /// only the consumed bytes if the supplied buffer was not bit enough
 pub enum Bits {
@@ -23,12 +29,76 @@ pub trait BitReader {
     fn read_bits(&mut self, buf: &[u8], n: u8) -> Bits;
 }
 
+/// Storage for the output of a bit writer that hasn't been written to the contained writer yet.
+pub trait PendingBuffer {
+    /// The bytes in the buffer.
+    fn bytes(&self) -> &[u8];
+    /// Add as much of `buf` as there is room for, returning the number of bytes added.
+    fn append(&mut self, buf: &[u8]) -> usize;
+    /// Remove all the bytes from the buffer.
+    fn clear(&mut self);
+}
+
+impl PendingBuffer for Vec<u8> {
+    fn bytes(&self) -> &[u8] {
+        self
+    }
+
+    #[inline]
+    fn append(&mut self, buf: &[u8]) -> usize {
+        self.extend_from_slice(buf);
+        buf.len()
+    }
+
+    fn clear(&mut self) {
+        Vec::clear(self)
+    }
+}
+
+/// A pending output buffer using a fixed slice, which is written out whenever it fills up.
+pub struct FixedBuffer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> FixedBuffer<'a> {
+    /// Use `buf` to store pending output.
+    pub fn new(buf: &'a mut [u8]) -> FixedBuffer<'a> {
+        // Nothing could be written to an empty buffer.
+        debug_assert!(!buf.is_empty());
+        FixedBuffer { buf, len: 0 }
+    }
+}
+
+impl<'a> PendingBuffer for FixedBuffer<'a> {
+    fn bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    #[inline]
+    fn append(&mut self, buf: &[u8]) -> usize {
+        let n = cmp::min(buf.len(), self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&buf[..n]);
+        self.len += n;
+        n
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
 /// A bit writer.
-pub trait BitWriter: Write {
+pub trait BitWriter {
     /// Writes the next `n` bits.
-    fn write_bits(&mut self, v: u16, n: u8) -> io::Result<()>;
+    fn write_bits(&mut self, v: u16, n: u8) -> Result<(), Error>;
 }
 
+/// The number of bits in the bit buffer of a bit writer after which the whole bytes in it are
+/// moved to the pending output. As at most 32 bits are written at a time, the buffer never
+/// overflows.
+const BIT_BUFFER_LIMIT: u8 = 32;
+
 macro_rules! define_bit_readers {
     {$(
         $name:ident, #[$doc:meta];
@@ -129,46 +199,145 @@ $( // START Structure definitions
 
 #[$doc]
 #[allow(dead_code)]
-pub struct $name<W: Write> {
+pub struct $name<W: Sink, P: PendingBuffer = Vec<u8>> {
     // NOTE(oyvindln) Made this public for now so it can be replaced after initialization.
     pub w: W,
+    // The number of bits in `acc`, kept below `BIT_BUFFER_LIMIT` between writes.
     bits: u8,
-    acc: u32,
+    acc: u64,
+    // Output that hasn't been written to `w` yet, starting at `pending_start`.
+    // Everything is written here first, so output isn't lost if `w` fails partway through, and
+    // can be written out once `w` accepts more data. If `pending` has a fixed size, it's written
+    // out whenever it fills up.
+    pending: P,
+    pending_start: usize,
+    // Number of bytes written to `w`.
+    bytes_written: u64,
 }
 
-impl<W: Write> $name<W> {
+impl<W: Sink> $name<W> {
     /// Creates a new bit reader
     #[allow(dead_code)]
     pub fn new(writer: W) -> $name<W> {
+        $name::with_buffer(writer, Vec::new())
+    }
+}
+
+impl<W: Sink, P: PendingBuffer> $name<W, P> {
+    /// Creates a new bit writer storing pending output in `pending`.
+    pub fn with_buffer(writer: W, pending: P) -> $name<W, P> {
         $name {
             w: writer,
             bits: 0,
             acc: 0,
+            pending,
+            pending_start: 0,
+            bytes_written: 0,
         }
     }
-}
 
-impl<W: Write> Write for $name<W> {
+    /// Returns the number of bytes written to the contained writer so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
 
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        if self.acc == 0 {
-            self.w.write(buf)
+    /// Reset the count of bytes written.
+    pub fn reset_bytes_written(&mut self) {
+        self.bytes_written = 0;
+    }
+
+    /// Returns the number of bytes of output that haven't been written to the contained writer
+    /// yet.
+    pub fn pending_len(&self) -> usize {
+        self.pending.bytes().len() - self.pending_start
+    }
+
+    /// Write the pending output to the contained writer.
+    ///
+    /// Interrupted writes are retried. If any other error occurs, the output that hasn't been
+    /// written yet is kept, so this can be called again to resume once the writer is ready.
+    pub fn write_pending(&mut self) -> Result<(), Error> {
+        while self.pending_start < self.pending.bytes().len() {
+            match self.w.write_output(&self.pending.bytes()[self.pending_start..]) {
+                Ok(0) => return Err(Error::Io(sink::write_zero_error())),
+                Ok(n) => {
+                    self.pending_start += n;
+                    self.bytes_written += n as u64;
+                }
+                Err(ref e) if sink::is_interrupted(e) => (),
+                Err(e) => return Err(Error::Io(e)),
+            }
+        }
+        self.pending.clear();
+        self.pending_start = 0;
+        Ok(())
+    }
+
+    /// Move the whole bytes in the bit buffer to the pending output, writing the pending output
+    /// to the contained writer if there is no room for them.
+    fn output_bytes(&mut self) -> Result<(), Error> {
+        loop {
+            let bytes = self.output_byte_order();
+            let n = self.pending.append(&bytes[..usize::from(self.bits / 8)]);
+            // The buffer holds fewer than 64 bits, so this shifts by less than 64.
+            self.acc = self.consume_bytes(n);
+            self.bits -= n as u8 * 8;
+            if self.bits < 8 {
+                return Ok(());
+            }
+            self.write_pending()?;
+        }
+    }
+
+    /// Output the remaining bits, padded with zeroes to a byte boundary.
+    ///
+    /// The output is only added to the pending output, see `write_pending`.
+    pub fn pad_to_byte(&mut self) -> Result<(), Error> {
+        // Pad to a byte boundary, without outputting an extra byte if we are already at one.
+        self.bits += (8 - self.bits % 8) % 8;
+        self.output_bytes()
+    }
+
+    /// Write whole bytes.
+    ///
+    /// The output is only added to the pending output, see `write_pending`.
+    pub fn write_bytes(&mut self, mut buf: &[u8]) -> Result<(), Error> {
+        self.output_bytes()?;
+        if self.bits == 0 {
+            loop {
+                let n = self.pending.append(buf);
+                buf = &buf[n..];
+                if buf.is_empty() {
+                    break;
+                }
+                self.write_pending()?;
+            }
         } else {
             for &byte in buf.iter() {
-                try!(self.write_bits(byte as u16, 8))
+                self.write_bits(byte as u16, 8)?;
             }
-            Ok(buf.len())
         }
+        Ok(())
+    }
+
+    /// Output the remaining bits, write all the pending output and flush the contained writer.
+    pub fn flush_all(&mut self) -> Result<(), Error> {
+        self.pad_to_byte()?;
+        self.write_pending()?;
+        self.w.flush_output().map_err(Error::Io)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Write, P: PendingBuffer> Write for $name<W, P> {
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_bytes(buf)?;
+        Ok(buf.len())
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        let missing = 8 - self.bits;
-        // NOTE:(oyvindln) Had to add a test for self.bits > 0 here,
-        // otherwise flush would output an extra byte when flush was called at a byte boundary
-        if missing > 0 && self.bits > 0 {
-            try!(self.write_bits(0, missing));
-        }
-        self.w.flush()
+        self.flush_all().map_err(io::Error::from)
     }
 }
 
@@ -182,30 +351,70 @@ define_bit_writers!{
     MsbWriter, #[doc = "Writes bits to a byte stream, MSB first."];
 }
 
-impl<W: Write> BitWriter for LsbWriter<W> {
-    fn write_bits(&mut self, v: u16, n: u8) -> io::Result<()> {
+impl<W: Sink, P: PendingBuffer> LsbWriter<W, P> {
+    /// Writes the next `n` bits, where `n` is at most 32.
+    ///
+    /// This allows a huffman code to be written together with its extra bits.
+    #[inline]
+    pub fn write_bits_u32(&mut self, v: u32, n: u8) -> Result<(), Error> {
+        debug_assert!(n <= 32);
         // NOTE:(oyvindln) This outputs garbage data if n is 0, but v is not 0
-        self.acc |= (v as u32) << self.bits;
+        self.acc |= u64::from(v) << self.bits;
         self.bits += n;
-        while self.bits >= 8 {
-            self.w.write_all(&[self.acc as u8])?;
-            self.acc >>= 8;
-            self.bits -= 8
-
+        if self.bits >= BIT_BUFFER_LIMIT {
+            // Output four bytes at a time, which is quicker than outputting all the whole bytes
+            // in the buffer, as the number of bytes is fixed.
+            let n = self.pending.append(&(self.acc as u32).to_le_bytes());
+            self.acc >>= n * 8;
+            self.bits -= n as u8 * 8;
+            if n < 4 {
+                // The pending output is full.
+                self.output_bytes()?;
+            }
         }
         Ok(())
     }
+
+    /// The bytes in the bit buffer, in the order they are output.
+    #[inline]
+    fn output_byte_order(&self) -> [u8; 8] {
+        self.acc.to_le_bytes()
+    }
+
+    /// The bit buffer after `n` bytes of it have been output.
+    #[inline]
+    fn consume_bytes(&self, n: usize) -> u64 {
+        self.acc >> (n * 8)
+    }
 }
 
-impl<W: Write> BitWriter for MsbWriter<W> {
-    fn write_bits(&mut self, v: u16, n: u8) -> io::Result<()> {
-        self.acc |= (v as u32) << (32 - n - self.bits);
-        self.bits += n;
-        while self.bits >= 8 {
-            self.w.write_all(&[(self.acc >> 24) as u8])?;
-            self.acc <<= 8;
-            self.bits -= 8
+impl<W: Sink, P: PendingBuffer> BitWriter for LsbWriter<W, P> {
+    #[inline]
+    fn write_bits(&mut self, v: u16, n: u8) -> Result<(), Error> {
+        self.write_bits_u32(u32::from(v), n)
+    }
+}
+
+impl<W: Sink, P: PendingBuffer> MsbWriter<W, P> {
+    /// The bytes in the bit buffer, in the order they are output.
+    #[inline]
+    fn output_byte_order(&self) -> [u8; 8] {
+        self.acc.to_be_bytes()
+    }
 
+    /// The bit buffer after `n` bytes of it have been output.
+    #[inline]
+    fn consume_bytes(&self, n: usize) -> u64 {
+        self.acc << (n * 8)
+    }
+}
+
+impl<W: Sink, P: PendingBuffer> BitWriter for MsbWriter<W, P> {
+    fn write_bits(&mut self, v: u16, n: u8) -> Result<(), Error> {
+        self.acc |= u64::from(v) << (64 - n - self.bits);
+        self.bits += n;
+        if self.bits >= BIT_BUFFER_LIMIT {
+            self.output_bytes()?;
         }
         Ok(())
     }
@@ -213,7 +422,8 @@ impl<W: Write> BitWriter for MsbWriter<W> {
 
 #[cfg(test)]
 mod test {
-    use super::{BitReader, BitWriter, Bits};
+    use super::{BitReader, BitWriter, Bits, FixedBuffer, LsbWriter};
+    use core::cmp;
 
     #[test]
     fn reader_writer() {
@@ -231,7 +441,42 @@ mod test {
             for &datum in expanded_data.iter() {
                 let _ = writer.write_bits(datum, 10);
             }
+            writer.flush_all().unwrap();
         }
         assert_eq!(&data[..], &compressed_data[..])
     }
+
+    #[test]
+    fn wide_writes() {
+        // Pseudo-random values of every length up to 32 bits.
+        let values: Vec<(u32, u8)> = (0..5000u32)
+            .map(|n| (n.wrapping_mul(0x9E37_79B9), (n % 33) as u8))
+            .map(|(v, n)| (v.checked_shr(32 - u32::from(n)).unwrap_or(0), n))
+            .collect();
+
+        let mut expected = Vec::new();
+        {
+            let mut writer = LsbWriter::new(&mut expected);
+            for &(v, n) in &values {
+                let low = cmp::min(n, 16);
+                writer.write_bits(v as u16 & ((1u32 << low) - 1) as u16, low).unwrap();
+                writer.write_bits((v >> low) as u16, n - low).unwrap();
+            }
+            writer.write_bytes(b"bytes").unwrap();
+            writer.flush_all().unwrap();
+        }
+
+        // The output is the same with a pending buffer that keeps filling up.
+        let mut output = Vec::new();
+        let mut pending = [0; 7];
+        {
+            let mut writer = LsbWriter::with_buffer(&mut output, FixedBuffer::new(&mut pending));
+            for &(v, n) in &values {
+                writer.write_bits_u32(v, n).unwrap();
+            }
+            writer.write_bytes(b"bytes").unwrap();
+            writer.flush_all().unwrap();
+        }
+        assert!(output == expected);
+    }
 }
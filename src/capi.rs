@@ -0,0 +1,648 @@
+//! A C-compatible interface modelled after the `deflate` functions in zlib.
+//!
+//! This module is only available with the `capi` feature. The functions and constants mirror
+//! their zlib counterparts, prefixed with `drs_`/`DRS_` so they can be linked alongside zlib.
+//! The C declarations are in `include/deflate.h`, which is generated from this module with
+//! `cbindgen --config cbindgen.toml --output include/deflate.h src/capi.rs`.
+//!
+//! Unlike zlib, `drs_deflate` always consumes all the available input, keeping any compressed
+//! data that does not fit in the output buffer until the next call.
+#![allow(non_camel_case_types)]
+
+use std::io::Write;
+use std::os::raw::{c_char, c_int, c_uint, c_ulong, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use compression_options::{CompressionOptions, SpecialOptions};
+use framing::Format;
+use writer::Encoder;
+
+/// Success.
+pub const DRS_OK: c_int = 0;
+/// All input has been compressed and all output has been delivered.
+pub const DRS_STREAM_END: c_int = 1;
+/// Invalid parameters or stream state.
+pub const DRS_STREAM_ERROR: c_int = -2;
+/// No progress was possible, e.g because the output buffer is full.
+pub const DRS_BUF_ERROR: c_int = -5;
+
+/// Compress as much as possible, leaving the rest for later calls.
+pub const DRS_NO_FLUSH: c_int = 0;
+/// Treated the same as `DRS_SYNC_FLUSH`.
+pub const DRS_PARTIAL_FLUSH: c_int = 1;
+/// Output all the data compressed so far, followed by an empty stored block.
+pub const DRS_SYNC_FLUSH: c_int = 2;
+/// Complete the stream.
+pub const DRS_FINISH: c_int = 4;
+
+/// Only output stored (uncompressed) blocks.
+pub const DRS_NO_COMPRESSION: c_int = 0;
+pub const DRS_BEST_SPEED: c_int = 1;
+pub const DRS_BEST_COMPRESSION: c_int = 9;
+/// Use the default compression level (6).
+pub const DRS_DEFAULT_COMPRESSION: c_int = -1;
+
+pub const DRS_DEFAULT_STRATEGY: c_int = 0;
+/// Only use matches longer than 5 bytes, for data produced by a filter or predictor.
+pub const DRS_FILTERED: c_int = 1;
+/// Don't look for matches, only use huffman coding.
+pub const DRS_HUFFMAN_ONLY: c_int = 2;
+/// Only use the fixed huffman codes.
+pub const DRS_FIXED: c_int = 4;
+
+/// The only supported compression method.
+pub const DRS_DEFLATED: c_int = 8;
+
+const DEFAULT_LEVEL: c_int = 6;
+const DEFAULT_WINDOW_BITS: c_int = 15;
+const DEFAULT_MEM_LEVEL: c_int = 8;
+
+/// A compression stream.
+///
+/// The application sets `next_in`/`avail_in` and `next_out`/`avail_out` before each call to
+/// `drs_deflate`, which updates them to reflect the data consumed and produced.
+#[repr(C)]
+pub struct drs_stream {
+    /// Next input byte.
+    pub next_in: *const u8,
+    /// Number of bytes available at `next_in`.
+    pub avail_in: c_uint,
+    /// Total number of input bytes read so far.
+    pub total_in: c_ulong,
+    /// Where the next output byte will be written.
+    pub next_out: *mut u8,
+    /// Remaining free space at `next_out`.
+    pub avail_out: c_uint,
+    /// Total number of bytes output so far.
+    pub total_out: c_ulong,
+    /// Description of the last error, or null if there was none.
+    pub msg: *const c_char,
+    /// Internal state, not to be touched by the application.
+    pub state: *mut c_void,
+}
+
+/// The internal state behind `drs_stream::state`.
+struct Stream {
+    format: Format,
+    options: CompressionOptions,
+    /// The encoder, or `None` once the stream has been finished.
+    encoder: Option<Encoder<Vec<u8>>>,
+    /// The complete output once the stream has been finished.
+    finished_output: Vec<u8>,
+    /// How much of the pending output has already been copied to the application.
+    output_pos: usize,
+}
+
+impl Stream {
+    fn new(format: Format, options: CompressionOptions) -> Stream {
+        Stream {
+            format,
+            options,
+            encoder: Some(Encoder::new(Vec::new(), format, options)),
+            finished_output: Vec::new(),
+            output_pos: 0,
+        }
+    }
+
+    fn pending_output(&mut self) -> &mut Vec<u8> {
+        match self.encoder {
+            Some(ref mut e) => e.get_mut(),
+            None => &mut self.finished_output,
+        }
+    }
+
+    fn has_pending_output(&mut self) -> bool {
+        let output_pos = self.output_pos;
+        self.pending_output().len() > output_pos
+    }
+
+    /// Copy as much pending output as there is room for to the output buffer.
+    ///
+    /// Returns the number of bytes copied.
+    unsafe fn copy_output(&mut self, strm: &mut drs_stream) -> usize {
+        let output_pos = self.output_pos;
+        let copied = {
+            let pending = &self.pending_output()[output_pos..];
+            let n = pending.len().min(strm.avail_out as usize);
+            if n > 0 {
+                ptr::copy_nonoverlapping(pending.as_ptr(), strm.next_out, n);
+            }
+            n
+        };
+        strm.next_out = strm.next_out.wrapping_add(copied);
+        strm.avail_out -= copied as c_uint;
+        strm.total_out = strm.total_out.wrapping_add(copied as c_ulong);
+
+        self.output_pos += copied;
+        if self.output_pos == self.pending_output().len() {
+            // Everything has been delivered, so reuse the buffer.
+            self.pending_output().clear();
+            self.output_pos = 0;
+        }
+        copied
+    }
+
+    unsafe fn deflate(&mut self, strm: &mut drs_stream, flush: c_int) -> c_int {
+        let input_length = strm.avail_in as usize;
+        let finish = match self.encoder {
+            None => {
+                if input_length > 0 || flush != DRS_FINISH {
+                    return error(strm, DRS_STREAM_ERROR, b"stream has already been finished\0");
+                }
+                false
+            }
+            Some(ref mut encoder) => {
+                if input_length > 0 {
+                    let input = slice::from_raw_parts(strm.next_in, input_length);
+                    if encoder.write_all(input).is_err() {
+                        return error(strm, DRS_STREAM_ERROR, b"compression failed\0");
+                    }
+                    strm.next_in = strm.next_in.wrapping_add(input_length);
+                    strm.avail_in = 0;
+                    strm.total_in = strm.total_in.wrapping_add(input_length as c_ulong);
+                }
+
+                let result = match flush {
+                    DRS_NO_FLUSH => Ok(()),
+                    DRS_PARTIAL_FLUSH | DRS_SYNC_FLUSH => encoder.flush(),
+                    _ => Ok(()),
+                };
+                if result.is_err() {
+                    return error(strm, DRS_STREAM_ERROR, b"compression failed\0");
+                }
+                flush != DRS_NO_FLUSH && flush != DRS_PARTIAL_FLUSH && flush != DRS_SYNC_FLUSH
+            }
+        };
+        if finish {
+            if let Some(encoder) = self.encoder.take() {
+                match encoder.finish() {
+                    Ok(output) => self.finished_output = output,
+                    Err(_) => return error(strm, DRS_STREAM_ERROR, b"compression failed\0"),
+                }
+            }
+        }
+
+        let copied = self.copy_output(strm);
+        if self.encoder.is_none() && !self.has_pending_output() {
+            DRS_STREAM_END
+        } else if input_length == 0 && copied == 0 && flush != DRS_FINISH {
+            DRS_BUF_ERROR
+        } else {
+            DRS_OK
+        }
+    }
+}
+
+fn error(strm: &mut drs_stream, code: c_int, msg: &'static [u8]) -> c_int {
+    strm.msg = msg.as_ptr() as *const c_char;
+    code
+}
+
+/// Run `f`, turning a panic into `DRS_STREAM_ERROR` rather than unwinding into C code.
+fn guard<F: FnOnce() -> c_int>(f: F) -> c_int {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(DRS_STREAM_ERROR)
+}
+
+/// Get the stream and its internal state, if both are valid.
+unsafe fn stream_state<'a>(strm: *mut drs_stream) -> Option<(&'a mut drs_stream, &'a mut Stream)> {
+    let strm = strm.as_mut()?;
+    let state = (strm.state as *mut Stream).as_mut()?;
+    Some((strm, state))
+}
+
+fn compression_options(level: c_int, window_bits: u8, strategy: c_int) -> CompressionOptions {
+    let level = if level == DRS_DEFAULT_COMPRESSION {
+        DEFAULT_LEVEL
+    } else {
+        level
+    };
+    let mut options = if strategy == DRS_HUFFMAN_ONLY && level != DRS_NO_COMPRESSION {
+        CompressionOptions::huffman_only()
+    } else {
+        CompressionOptions::from_level(level as u8)
+    };
+    if level != DRS_NO_COMPRESSION {
+        if strategy == DRS_FIXED {
+            options.special = SpecialOptions::ForceFixed;
+        } else if strategy == DRS_FILTERED {
+            options.special = SpecialOptions::Filtered;
+        }
+    }
+    options.window_bits = window_bits;
+    options
+}
+
+/// Initialize a compression stream with the default window size and strategy, producing zlib
+/// output.
+///
+/// # Safety
+///
+/// `strm` has to be null or point to a valid `drs_stream`.
+#[no_mangle]
+pub unsafe extern "C" fn drs_deflateInit(strm: *mut drs_stream, level: c_int) -> c_int {
+    drs_deflateInit2(strm,
+                     level,
+                     DRS_DEFLATED,
+                     DEFAULT_WINDOW_BITS,
+                     DEFAULT_MEM_LEVEL,
+                     DRS_DEFAULT_STRATEGY)
+}
+
+/// Initialize a compression stream.
+///
+/// * `level`: `DRS_DEFAULT_COMPRESSION`, or `0` (stored only) to `9` (best compression).
+/// * `method`: Has to be `DRS_DEFLATED`.
+/// * `window_bits`: `8..15` for zlib output with a window of `2^window_bits` bytes,
+///   `-8..-15` for raw deflate output, or `24..31` (`16` plus the window bits) for gzip output.
+/// * `mem_level`: Accepted for compatibility with zlib (`1..9`), but has no effect.
+/// * `strategy`: `DRS_DEFAULT_STRATEGY`, `DRS_FILTERED`, `DRS_HUFFMAN_ONLY` or `DRS_FIXED`.
+///
+/// Returns `DRS_OK` on success, or `DRS_STREAM_ERROR` if any of the parameters are invalid.
+/// The stream has to be freed with `drs_deflateEnd`.
+///
+/// # Safety
+///
+/// `strm` has to be null or point to a valid `drs_stream`. Any state it contains from an
+/// earlier initialization is not freed.
+#[no_mangle]
+pub unsafe extern "C" fn drs_deflateInit2(strm: *mut drs_stream,
+                                          level: c_int,
+                                          method: c_int,
+                                          window_bits: c_int,
+                                          mem_level: c_int,
+                                          strategy: c_int)
+                                          -> c_int {
+    guard(|| {
+        let strm = match strm.as_mut() {
+            Some(strm) => strm,
+            None => return DRS_STREAM_ERROR,
+        };
+        strm.state = ptr::null_mut();
+        strm.msg = ptr::null();
+
+        let (format, window_bits) = match Format::from_window_bits(window_bits) {
+            Some(f) => f,
+            None => return error(strm, DRS_STREAM_ERROR, b"invalid window bits\0"),
+        };
+        let valid_strategy = strategy == DRS_DEFAULT_STRATEGY || strategy == DRS_FILTERED ||
+                             strategy == DRS_HUFFMAN_ONLY ||
+                             strategy == DRS_FIXED;
+        if !(DRS_DEFAULT_COMPRESSION..=DRS_BEST_COMPRESSION).contains(&level) ||
+           method != DRS_DEFLATED || !(1..=9).contains(&mem_level) || !valid_strategy {
+            return error(strm, DRS_STREAM_ERROR, b"invalid parameters\0");
+        }
+
+        let options = compression_options(level, window_bits, strategy);
+        strm.total_in = 0;
+        strm.total_out = 0;
+        strm.state = Box::into_raw(Box::new(Stream::new(format, options))) as *mut c_void;
+        DRS_OK
+    })
+}
+
+/// Compress the available input, and copy as much of the compressed data as there is room for
+/// to the output buffer.
+///
+/// All of the input is always consumed. `flush` is one of `DRS_NO_FLUSH`, `DRS_PARTIAL_FLUSH`,
+/// `DRS_SYNC_FLUSH` or `DRS_FINISH`. After `DRS_FINISH` has been used, `drs_deflate` should be
+/// called with `DRS_FINISH` and more output space until it returns `DRS_STREAM_END`.
+///
+/// Returns `DRS_OK` if progress was made, `DRS_STREAM_END` once all output of a finished
+/// stream has been delivered, `DRS_BUF_ERROR` if no progress was possible, or
+/// `DRS_STREAM_ERROR` if the stream or parameters are invalid.
+///
+/// # Safety
+///
+/// `strm` has to be null or point to a `drs_stream` initialized by `drs_deflateInit2`, and
+/// `next_in`/`next_out` have to be valid for reading `avail_in` and writing `avail_out` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn drs_deflate(strm: *mut drs_stream, flush: c_int) -> c_int {
+    guard(|| {
+        let (strm, state) = match stream_state(strm) {
+            Some(s) => s,
+            None => return DRS_STREAM_ERROR,
+        };
+        if (strm.next_in.is_null() && strm.avail_in > 0) ||
+           (strm.next_out.is_null() && strm.avail_out > 0) {
+            return error(strm, DRS_STREAM_ERROR, b"invalid buffer\0");
+        }
+        match flush {
+            DRS_NO_FLUSH | DRS_PARTIAL_FLUSH | DRS_SYNC_FLUSH | DRS_FINISH => {
+                state.deflate(strm, flush)
+            }
+            _ => error(strm, DRS_STREAM_ERROR, b"unsupported flush mode\0"),
+        }
+    })
+}
+
+/// Free the internal state of the stream.
+///
+/// # Safety
+///
+/// `strm` has to be null or point to a `drs_stream` initialized by `drs_deflateInit2`.
+#[no_mangle]
+pub unsafe extern "C" fn drs_deflateEnd(strm: *mut drs_stream) -> c_int {
+    guard(|| {
+        let strm = match strm.as_mut() {
+            Some(strm) if !strm.state.is_null() => strm,
+            _ => return DRS_STREAM_ERROR,
+        };
+        drop(Box::from_raw(strm.state as *mut Stream));
+        strm.state = ptr::null_mut();
+        DRS_OK
+    })
+}
+
+/// Reset the stream so it can be used to compress a new stream with the same parameters.
+///
+/// Any preset dictionary is discarded.
+///
+/// # Safety
+///
+/// `strm` has to be null or point to a `drs_stream` initialized by `drs_deflateInit2`.
+#[no_mangle]
+pub unsafe extern "C" fn drs_deflateReset(strm: *mut drs_stream) -> c_int {
+    guard(|| {
+        let (strm, state) = match stream_state(strm) {
+            Some(s) => s,
+            None => return DRS_STREAM_ERROR,
+        };
+        *state = Stream::new(state.format, state.options);
+        strm.total_in = 0;
+        strm.total_out = 0;
+        strm.msg = ptr::null();
+        DRS_OK
+    })
+}
+
+/// Use the provided data as a preset dictionary.
+///
+/// This has to be called after initialization, before the first call to `drs_deflate`. Preset
+/// dictionaries are not supported for gzip output.
+///
+/// # Safety
+///
+/// `strm` has to be null or point to a `drs_stream` initialized by `drs_deflateInit2`, and
+/// `dictionary` has to be valid for reading `dict_length` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn drs_deflateSetDictionary(strm: *mut drs_stream,
+                                                  dictionary: *const u8,
+                                                  dict_length: c_uint)
+                                                  -> c_int {
+    guard(|| {
+        let (strm, state) = match stream_state(strm) {
+            Some(s) => s,
+            None => return DRS_STREAM_ERROR,
+        };
+        if dictionary.is_null() || strm.total_in > 0 {
+            return error(strm, DRS_STREAM_ERROR, b"invalid dictionary\0");
+        }
+        let dictionary = slice::from_raw_parts(dictionary, dict_length as usize);
+        let result = match state.encoder {
+            Some(ref mut encoder) => encoder.set_dictionary(dictionary),
+            None => return error(strm, DRS_STREAM_ERROR, b"stream has already been finished\0"),
+        };
+        match result {
+            Ok(()) => DRS_OK,
+            Err(_) => error(strm, DRS_STREAM_ERROR, b"dictionary can not be used\0"),
+        }
+    })
+}
+
+/// Compute the maximum size of the output from compressing `source_length` bytes in one call
+/// using `DRS_FINISH`, so an output buffer of this size is always large enough.
+///
+/// Literals take at most 9 bits with the fixed huffman codes, matches take less per byte, and
+/// dynamic codes are never longer in total than the fixed ones, so the compressed data is at
+/// most 9/8 of the input. On top of that come the block headers and end of block codes, one
+/// for every 32 KiB of input at most, and the zlib or gzip header and trailer.
+///
+/// # Safety
+///
+/// `strm` has to be null or point to a `drs_stream` initialized by `drs_deflateInit2`. If it
+/// is null, the size of the zlib header and trailer is assumed.
+#[no_mangle]
+pub unsafe extern "C" fn drs_deflateBound(strm: *mut drs_stream,
+                                          source_length: c_ulong)
+                                          -> c_ulong {
+    // Header and trailer, including the dictionary id for zlib.
+    let wrapper = match stream_state(strm) {
+        Some((_, state)) => {
+            match state.format {
+                Format::Raw => 0,
+                Format::Zlib => 10,
+                Format::Gzip => 18,
+            }
+        }
+        None => 10,
+    };
+    // A block header with dynamic codes takes at most 3 + 14 + 19 * 3 + 316 * 7 bits, and the
+    // end of block code at most 15 bits, which rounds up to 288 bytes.
+    const BLOCK_OVERHEAD: c_ulong = 288;
+    // The first block ends after the first window, and later ones hold at least 64k symbols,
+    // so apart from the last block there is at most one for every 32 KiB of input.
+    let blocks = source_length / (32 * 1024) + 2;
+    // `c_ulong` is only 32 bits on some platforms, so saturate rather than overflow. Stored
+    // blocks only add 5 bytes for every 65535 bytes of input, which this also covers.
+    source_length.saturating_add(source_length / 8)
+        .saturating_add(blocks * BLOCK_OVERHEAD)
+        .saturating_add(1 + wrapper)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_utils::{get_test_data, decompress_zlib, decompress_gzip, decompress_to_end};
+    use writer::ZlibEncoder;
+    use std::mem;
+
+    fn new_stream() -> drs_stream {
+        unsafe { mem::zeroed() }
+    }
+
+    /// Compress `data` in pieces using small output buffers.
+    unsafe fn compress(strm: &mut drs_stream, data: &[u8], out_chunk: usize) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut buffer = vec![0; out_chunk];
+        for chunk in data.chunks(10000) {
+            strm.next_in = chunk.as_ptr();
+            strm.avail_in = chunk.len() as c_uint;
+            loop {
+                strm.next_out = buffer.as_mut_ptr();
+                strm.avail_out = out_chunk as c_uint;
+                let res = drs_deflate(strm, DRS_NO_FLUSH);
+                assert!(res == DRS_OK || res == DRS_BUF_ERROR);
+                output.extend_from_slice(&buffer[..out_chunk - strm.avail_out as usize]);
+                if strm.avail_out > 0 {
+                    break;
+                }
+            }
+        }
+        loop {
+            strm.next_out = buffer.as_mut_ptr();
+            strm.avail_out = out_chunk as c_uint;
+            let res = drs_deflate(strm, DRS_FINISH);
+            output.extend_from_slice(&buffer[..out_chunk - strm.avail_out as usize]);
+            if res == DRS_STREAM_END {
+                break;
+            }
+            assert_eq!(res, DRS_OK);
+        }
+        output
+    }
+
+    #[test]
+    fn capi_formats() {
+        let data = get_test_data();
+        unsafe {
+            let mut strm = new_stream();
+            assert_eq!(drs_deflateInit(&mut strm, DRS_DEFAULT_COMPRESSION), DRS_OK);
+            let compressed = compress(&mut strm, &data, 1000);
+            assert_eq!(strm.total_in, data.len() as c_ulong);
+            assert_eq!(strm.total_out, compressed.len() as c_ulong);
+            assert!(decompress_zlib(&compressed) == data);
+            let bound = drs_deflateBound(&mut strm, data.len() as c_ulong);
+            assert!(compressed.len() as c_ulong <= bound);
+
+            // Resetting should give the same output again.
+            assert_eq!(drs_deflateReset(&mut strm), DRS_OK);
+            assert!(compress(&mut strm, &data, 777) == compressed);
+            assert_eq!(drs_deflateEnd(&mut strm), DRS_OK);
+
+            assert_eq!(drs_deflateInit2(&mut strm, 9, DRS_DEFLATED, -15, 8, DRS_FIXED), DRS_OK);
+            assert!(decompress_to_end(&compress(&mut strm, &data, 4096)) == data);
+            assert_eq!(drs_deflateEnd(&mut strm), DRS_OK);
+
+            assert_eq!(drs_deflateInit2(&mut strm, 1, DRS_DEFLATED, 31, 8, DRS_HUFFMAN_ONLY),
+                       DRS_OK);
+            assert!(decompress_gzip(&compress(&mut strm, &data, 65536)) == data);
+            assert_eq!(drs_deflateEnd(&mut strm), DRS_OK);
+        }
+    }
+
+    #[test]
+    /// Test that a single call with `DRS_FINISH` and an output buffer of the size given by
+    /// `drs_deflateBound` always completes the stream.
+    fn capi_bound_single_call() {
+        // Pseudo-random bytes that are all 9 bits long with the fixed codes, and pseudo-random
+        // bytes of any value.
+        let mut seed = 0x9E3779B9u32;
+        let mut random = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        let high: Vec<u8> = (0..150000).map(|_| 144 + (random() % 112) as u8).collect();
+        let any: Vec<u8> = (0..150000).map(|_| random() as u8).collect();
+        let text = get_test_data();
+        let inputs: Vec<&[u8]> = vec![&[], &high[..1], &high[..24], &high[..25], &any[..1000],
+                                      &high, &any, &text];
+
+        for &level in &[0, 1, 6, 9] {
+            for &strategy in &[DRS_DEFAULT_STRATEGY, DRS_FILTERED, DRS_HUFFMAN_ONLY, DRS_FIXED] {
+                for &window_bits in &[-15, 9, 31] {
+                    for input in &inputs {
+                        unsafe {
+                            let mut strm = new_stream();
+                            assert_eq!(drs_deflateInit2(&mut strm,
+                                                        level,
+                                                        DRS_DEFLATED,
+                                                        window_bits,
+                                                        8,
+                                                        strategy),
+                                       DRS_OK);
+                            let bound = drs_deflateBound(&mut strm, input.len() as c_ulong);
+                            let mut output = vec![0; bound as usize];
+                            strm.next_in = input.as_ptr();
+                            strm.avail_in = input.len() as c_uint;
+                            strm.next_out = output.as_mut_ptr();
+                            strm.avail_out = bound as c_uint;
+                            assert_eq!(drs_deflate(&mut strm, DRS_FINISH),
+                                       DRS_STREAM_END,
+                                       "level {}, strategy {}, window bits {}, length {}",
+                                       level,
+                                       strategy,
+                                       window_bits,
+                                       input.len());
+                            assert_eq!(drs_deflateEnd(&mut strm), DRS_OK);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn capi_sync_flush() {
+        unsafe {
+            let mut strm = new_stream();
+            assert_eq!(drs_deflateInit2(&mut strm, 6, DRS_DEFLATED, -15, 8, 0), DRS_OK);
+            let data = b"Some data to flush";
+            let mut buffer = [0u8; 256];
+            strm.next_in = data.as_ptr();
+            strm.avail_in = data.len() as c_uint;
+            strm.next_out = buffer.as_mut_ptr();
+            strm.avail_out = buffer.len() as c_uint;
+            assert_eq!(drs_deflate(&mut strm, DRS_SYNC_FLUSH), DRS_OK);
+            let n = buffer.len() - strm.avail_out as usize;
+            // A sync flush ends with an empty stored block.
+            assert_eq!(&buffer[n - 4..n], &[0, 0, 0xFF, 0xFF]);
+            // The data should be decodable up to here, so terminate the stream with an empty
+            // final block to check.
+            let mut flushed = buffer[..n].to_vec();
+            flushed.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+            assert_eq!(decompress_to_end(&flushed), &data[..]);
+            // Nothing to do.
+            assert_eq!(drs_deflate(&mut strm, DRS_NO_FLUSH), DRS_BUF_ERROR);
+            assert_eq!(drs_deflateEnd(&mut strm), DRS_OK);
+        }
+    }
+
+    #[test]
+    fn capi_invalid() {
+        unsafe {
+            let mut strm = new_stream();
+            assert_eq!(drs_deflateInit2(&mut strm, 10, DRS_DEFLATED, 15, 8, 0), DRS_STREAM_ERROR);
+            assert!(!strm.msg.is_null());
+            assert_eq!(drs_deflateInit2(&mut strm, 6, DRS_DEFLATED, 16, 8, 0), DRS_STREAM_ERROR);
+            assert_eq!(drs_deflateInit2(&mut strm, 6, 7, 15, 8, 0), DRS_STREAM_ERROR);
+            assert_eq!(drs_deflateInit2(&mut strm, 6, DRS_DEFLATED, 15, 8, 3), DRS_STREAM_ERROR);
+            assert_eq!(drs_deflate(&mut strm, DRS_FINISH), DRS_STREAM_ERROR);
+            assert_eq!(drs_deflate(ptr::null_mut(), DRS_FINISH), DRS_STREAM_ERROR);
+            assert_eq!(drs_deflateEnd(&mut strm), DRS_STREAM_ERROR);
+
+            assert_eq!(drs_deflateInit2(&mut strm, 6, DRS_DEFLATED, 31, 8, 0), DRS_OK);
+            let dictionary = b"dictionary";
+            assert_eq!(drs_deflateSetDictionary(&mut strm, dictionary.as_ptr(), 10),
+                       DRS_STREAM_ERROR);
+            assert_eq!(drs_deflate(&mut strm, 3), DRS_STREAM_ERROR);
+            assert_eq!(drs_deflateEnd(&mut strm), DRS_OK);
+        }
+    }
+
+    #[test]
+    fn capi_dictionary() {
+        let data = get_test_data();
+        let (dictionary, data) = data.split_at(20000);
+        unsafe {
+            let mut strm = new_stream();
+            assert_eq!(drs_deflateInit(&mut strm, 6), DRS_OK);
+            assert_eq!(drs_deflateSetDictionary(&mut strm,
+                                                dictionary.as_ptr(),
+                                                dictionary.len() as c_uint),
+                       DRS_OK);
+            let compressed = compress(&mut strm, data, 1 << 16);
+            assert_eq!(drs_deflateEnd(&mut strm), DRS_OK);
+
+            let mut encoder = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+            encoder.set_dictionary(dictionary).unwrap();
+            for chunk in data.chunks(10000) {
+                encoder.write_all(chunk).unwrap();
+            }
+            assert!(compressed == encoder.finish().unwrap());
+        }
+    }
+}
@@ -1,9 +1,12 @@
+use alloc::boxed::Box;
+
 pub const WINDOW_SIZE: usize = 32768;
-const WINDOW_MASK: usize = WINDOW_SIZE - 1;
+// The number of different hash values, and thus hash chains.
+const HASH_SIZE: usize = 32768;
 #[cfg(test)]
 pub const HASH_BYTES: usize = 3;
 const HASH_SHIFT: u16 = 5;
-const HASH_MASK: u16 = WINDOW_MASK as u16;
+const HASH_MASK: u16 = (HASH_SIZE - 1) as u16;
 
 /// Returns a new hash value based on the previous value and the next byte
 #[inline]
@@ -16,9 +19,9 @@ fn update_hash_conf(current_hash: u16, to_insert: u8, shift: u16, mask: u16) ->
     ((current_hash << shift) ^ (to_insert as u16)) & mask
 }
 
-fn init_array(arr: &mut [u16; WINDOW_SIZE]) {
+fn init_array(arr: &mut [u32]) {
     for (n, mut b) in arr.iter_mut().enumerate() {
-        *b = n as u16;
+        *b = n as u32;
     }
 }
 
@@ -26,25 +29,38 @@ pub struct ChainedHashTable {
     // Current running hash value of the last 3 bytes
     current_hash: u16,
     // Starts of hash chains (in prev)
-    head: Box<[u16; WINDOW_SIZE]>,
+    head: Box<[u32; HASH_SIZE]>,
     // link to previous occurence of this hash value
-    prev: Box<[u16; WINDOW_SIZE]>,
+    prev: Box<[u32]>,
+    // Mask used to get the index in `prev` of a position, one less than the window size.
+    window_mask: usize,
 }
 
 impl ChainedHashTable {
-    fn new() -> ChainedHashTable {
+    /// Create a new hash table for a buffer laid out with the windows size `window_size`,
+    /// which has to be a power of two.
+    fn new(window_size: usize) -> ChainedHashTable {
+        debug_assert!(window_size.is_power_of_two());
         let mut c = ChainedHashTable {
             current_hash: 0,
-            head: Box::new([0; WINDOW_SIZE]),
-            prev: Box::new([0; WINDOW_SIZE]),
+            head: Box::new([0; HASH_SIZE]),
+            prev: vec![0; window_size].into_boxed_slice(),
+            window_mask: window_size - 1,
         };
-        init_array(&mut c.head);
+        init_array(&mut c.head[..]);
         init_array(&mut c.prev);
         c
     }
 
+    #[cfg(test)]
     pub fn from_starting_values(v1: u8, v2: u8) -> ChainedHashTable {
-        let mut t = ChainedHashTable::new();
+        ChainedHashTable::with_window_size(WINDOW_SIZE, v1, v2)
+    }
+
+    /// Create a hash table for the window size `window_size`, adding the two first values to
+    /// the hash.
+    pub fn with_window_size(window_size: usize, v1: u8, v2: u8) -> ChainedHashTable {
+        let mut t = ChainedHashTable::new(window_size);
         t.current_hash = update_hash(t.current_hash, v1);
         t.current_hash = update_hash(t.current_hash, v2);
         t
@@ -52,7 +68,7 @@ impl ChainedHashTable {
 
     /// Resets the hash value and hash chains
     pub fn reset(&mut self) {
-        *self = ChainedHashTable::from_starting_values(55, 77);
+        *self = ChainedHashTable::with_window_size(self.prev.len(), 55, 77);
     }
 
     pub fn add_initial_hash_values(&mut self, v1: u8, v2: u8) {
@@ -66,11 +82,10 @@ impl ChainedHashTable {
         // bounds checks in this function.
         let new_hash = update_hash(self.current_hash, value);
 
-        self.prev[position & WINDOW_MASK] = self.head[new_hash as usize];
+        self.prev[position & self.window_mask] = self.head[new_hash as usize];
 
-        // Ignoring any bits over 16 here is deliberate, as we only concern ourselves about
-        // where in the buffer (which is 64k bytes) we are referring to.
-        self.head[new_hash as usize] = position as u16;
+        // Positions are within the input buffer, which is much smaller than 4 GiB.
+        self.head[new_hash as usize] = position as u32;
 
         // Update the stored hash value with the new hash.
         self.current_hash = new_hash;
@@ -79,7 +94,7 @@ impl ChainedHashTable {
     // Get the head of the hash chain for the current hash value
     #[cfg(test)]
     #[inline]
-    pub fn current_head(&self) -> u16 {
+    pub fn current_head(&self) -> u32 {
         self.head[self.current_hash as usize]
     }
 
@@ -96,32 +111,36 @@ impl ChainedHashTable {
     }
 
     #[inline]
-    pub fn get_prev(&self, bytes: usize) -> u16 {
-        self.prev[bytes & WINDOW_MASK]
+    pub fn get_prev(&self, bytes: usize) -> u32 {
+        self.prev[bytes & self.window_mask]
     }
 
-    fn slide_value(b: u16, pos: u16, bytes: u16) -> u16 {
+    fn slide_value(b: u32, pos: u32, bytes: u32) -> u32 {
         if b > bytes { b - bytes } else { pos }
     }
 
-    fn slide_table(table: &mut [u16], bytes: u16) {
+    fn slide_table(table: &mut [u32], bytes: u32) {
         for (n, b) in table.iter_mut().enumerate() {
-            *b = ChainedHashTable::slide_value(*b, n as u16, bytes);
+            *b = ChainedHashTable::slide_value(*b, n as u32, bytes);
         }
     }
 
     pub fn slide(&mut self, bytes: usize) {
-        ChainedHashTable::slide_table(&mut self.head[..], bytes as u16);
-        ChainedHashTable::slide_table(&mut self.prev[..], bytes as u16);
+        ChainedHashTable::slide_table(&mut self.head[..], bytes as u32);
+        ChainedHashTable::slide_table(&mut self.prev[..], bytes as u32);
+        // The chain links are stored at their position modulo the window size, so unless the
+        // positions were moved by a multiple of the window size (e.g when moving the buffer down
+        // after a sync flush), the links have to be moved along with them.
+        self.prev.rotate_left(bytes & self.window_mask);
     }
 
     // #[cfg(test)]
-    pub fn _get_head_arr(&self) -> &[u16] {
+    pub fn _get_head_arr(&self) -> &[u32] {
         &self.head[..]
     }
 
     // #[cfg(test)]
-    pub fn _get_prev_arr(&self) -> &[u16] {
+    pub fn _get_prev_arr(&self) -> &[u32] {
         &self.prev[..]
     }
 }
@@ -183,7 +202,7 @@ mod test {
         let hash_table = filled_hash_table(&test_data);
         let prev_pos = hash_table.get_prev(hash_table.current_head() as usize);
         // Since all sequences in the input are unique, there shouldn't be any previous values
-        assert_eq!(prev_pos, hash_table.current_hash());
+        assert_eq!(prev_pos, u32::from(hash_table.current_hash()));
     }
 
     #[test]
@@ -193,7 +212,7 @@ mod test {
         use std::str;
 
         let window_size = super::WINDOW_SIZE;
-        let window_size16 = super::WINDOW_SIZE as u16;
+        let window_size32 = super::WINDOW_SIZE as u32;
 
         let mut input = Vec::new();
 
@@ -212,11 +231,11 @@ mod test {
             let max_head = hash_table.head.iter().max().unwrap();
             // After sliding there should be no hashes referring to values
             // higher than the window size
-            assert!(*max_head < window_size16);
+            assert!(*max_head < window_size32);
             assert!(*max_head > 0);
             let pos = hash_table.get_prev(hash_table.current_head() as usize);
             // There should be a previous occurence since we inserted the data 3 times
-            assert!(pos < window_size16);
+            assert!(pos < window_size32);
             assert!(pos > 0);
         }
 
@@ -227,18 +246,39 @@ mod test {
         // There should hashes referring to values in the upper part of the input window
         // at this point
         let max_prev = hash_table.prev.iter().max().unwrap();
-        assert!(*max_prev > window_size16);
+        assert!(*max_prev > window_size32);
 
         let mut pos = hash_table.current_head();
         // There should be a previous occurence since we inserted the data 3 times
-        assert!(pos > window_size16);
+        assert!(pos > window_size32);
         let end_byte = input[(window_size / 2) - 1];
         let mut iterations = 0;
-        while pos > window_size16 && iterations < 5000 {
+        while pos > window_size32 && iterations < 5000 {
             assert_eq!(input[pos as usize & window_size - 1], end_byte);
 
             pos = hash_table.get_prev(pos as usize);
             iterations += 1;
         }
     }
+    #[test]
+    fn table_slide_partial() {
+        use test_utils::get_test_data;
+
+        let window_size = super::WINDOW_SIZE;
+        let input = get_test_data();
+        let input = &input[..window_size + 5000];
+
+        let mut hash_table = filled_hash_table(input);
+        let before: Vec<_> = (window_size..input.len()).map(|n| hash_table.get_prev(n)).collect();
+
+        // Sliding by an amount that is not a multiple of the window size should keep the chains
+        // pointing to the same data.
+        let bytes = 3000;
+        hash_table.slide(bytes);
+        for (n, &prev) in (window_size..input.len()).zip(before.iter()) {
+            if prev as usize > bytes {
+                assert_eq!(hash_table.get_prev(n - bytes) as usize, prev as usize - bytes);
+            }
+        }
+    }
 }
@@ -6,31 +6,6 @@ pub trait RollingChecksum {
     fn current_hash(&self) -> u32;
 }
 
-pub struct NoChecksum {
-}
-
-impl NoChecksum {
-    pub fn new() -> NoChecksum {
-        NoChecksum {}
-    }
-}
-
-impl RollingChecksum for NoChecksum {
-    fn update(&mut self, _: u8) {}
-    fn update_from_slice(&mut self, _: &[u8]) {}
-    fn current_hash(&self) -> u32 {
-        1
-    }
-}
-
-impl<'a> RollingChecksum for &'a mut NoChecksum {
-    fn update(&mut self, _: u8) {}
-    fn update_from_slice(&mut self, _: &[u8]) {}
-    fn current_hash(&self) -> u32 {
-        1
-    }
-}
-
 pub struct Adler32Checksum {
     adler32: RollingAdler32,
 }
@@ -69,3 +44,84 @@ impl<'a> RollingChecksum for &'a mut Adler32Checksum {
         self.adler32.hash()
     }
 }
+
+/// Generate the lookup table for the CRC-32 (IEEE 802.3) polynomial used by gzip.
+const fn make_crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xEDB8_8320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+static CRC32_TABLE: [u32; 256] = make_crc32_table();
+
+pub struct Crc32Checksum {
+    crc: u32,
+}
+
+impl Crc32Checksum {
+    pub fn new() -> Crc32Checksum {
+        Crc32Checksum { crc: 0 }
+    }
+}
+
+impl RollingChecksum for Crc32Checksum {
+    fn update(&mut self, byte: u8) {
+        let c = !self.crc;
+        self.crc = !(CRC32_TABLE[((c ^ u32::from(byte)) & 0xFF) as usize] ^ (c >> 8));
+    }
+
+    fn update_from_slice(&mut self, data: &[u8]) {
+        let mut c = !self.crc;
+        for &b in data {
+            c = CRC32_TABLE[((c ^ u32::from(b)) & 0xFF) as usize] ^ (c >> 8);
+        }
+        self.crc = !c;
+    }
+
+    fn current_hash(&self) -> u32 {
+        self.crc
+    }
+}
+
+impl RollingChecksum for &mut Crc32Checksum {
+    fn update(&mut self, byte: u8) {
+        (**self).update(byte);
+    }
+
+    fn update_from_slice(&mut self, data: &[u8]) {
+        (**self).update_from_slice(data);
+    }
+
+    fn current_hash(&self) -> u32 {
+        (**self).current_hash()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn crc32() {
+        let mut checksum = Crc32Checksum::new();
+        assert_eq!(checksum.current_hash(), 0);
+        checksum.update_from_slice(b"123456789");
+        assert_eq!(checksum.current_hash(), 0xCBF4_3926);
+
+        let mut checksum = Crc32Checksum::new();
+        for &b in b"The quick brown fox jumps over the lazy dog" {
+            checksum.update(b);
+        }
+        assert_eq!(checksum.current_hash(), 0x414F_A339);
+    }
+}
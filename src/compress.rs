@@ -1,16 +1,21 @@
-use std::io::Write;
-use std::io;
+use core::cmp;
+use bitstream::PendingBuffer;
 
 use deflate_state::DeflateState;
 use encoder_state::{EncoderState, BType};
 use lzvalue::LZValue;
 use lz77::{lz77_compress_block, LZ77Status};
-use length_encode::huffman_lengths_from_frequency;
+use length_encode::huffman_lengths_from_frequency_into;
 use huffman_lengths::{write_huffman_lengths, remove_trailing_zeroes, MIN_NUM_LITERALS_AND_LENGTHS,
                       MIN_NUM_DISTANCES};
-use huffman_table::{MAX_CODE_LENGTH, FIXED_CODE_LENGTHS, FIXED_CODE_LENGTHS_DISTANCE};
+use huffman_table::{MAX_CODE_LENGTH, FIXED_CODE_LENGTHS, FIXED_CODE_LENGTHS_DISTANCE,
+                    NUM_LITERALS_AND_LENGTHS, NUM_DISTANCE_CODES};
 use output_writer::OutputWriter;
-use stored_block::{compress_block_stored, write_stored_header};
+use stored_block::{compress_block_stored, write_stored_header, MAX_STORED_BLOCK_LENGTH};
+use stats::CompressionStats;
+use compression_options::SpecialOptions;
+use error::Error;
+use sink::Sink;
 
 #[derive(Eq, PartialEq, Debug, Copy, Clone)]
 pub enum Flush {
@@ -18,19 +23,26 @@ pub enum Flush {
     Sync,
     _Partial,
     _Block,
-    _Full,
+    /// Like `Sync`, but also forget the previous input, so data after the flush point doesn't
+    /// refer back to data before it.
+    Full,
     Finish,
 }
 
 /// Write all the lz77 encoded data in the buffer using the specified `EncoderState`, and finish
 /// with the end of block code.
 ///
+/// The literals and matches written are counted in `stats`.
+///
 /// Returns `Err` if writing should fail at any point.
-pub fn flush_to_bitstream<W: Write>(buffer: &[LZValue],
-                                    state: &mut EncoderState<W>)
-                                    -> io::Result<()> {
+pub fn flush_to_bitstream<W: Sink, P: PendingBuffer>(buffer: &[LZValue],
+                                                     state: &mut EncoderState<W, P>,
+                                                     stats: &mut CompressionStats)
+                                                     -> Result<(), Error> {
     for &b in buffer {
-        state.write_lzvalue2(b.value())?
+        let value = b.value();
+        stats.add_lzvalue(value);
+        state.write_lzvalue2(value)?
     }
     state.write_end_of_block()
 }
@@ -66,31 +78,32 @@ pub fn compress_data_fixed(input: &[u8]) -> Vec<u8> {
 
         // We currently don't split blocks here(this function is just used for tests anyhow)
         state.write_start_of_block(true, true).expect("Write error!");
-        flush_to_bitstream(&compressed, &mut state).expect("Write error!");
+        flush_to_bitstream(&compressed, &mut state, &mut CompressionStats::default())
+            .expect("Write error!");
 
-        state.flush().expect("Write error!");
+        state.writer.flush_all().expect("Write error!");
 
     }
     writer
 }
 
-fn write_stored_block<W: Write>(input: &[u8],
+fn write_stored_block<W: Sink>(input: &[u8],
                                 deflate_state: &mut DeflateState<W>,
                                 final_block: bool)
-                                -> io::Result<usize> {
+                                -> Result<usize, Error> {
     // Write the block header
     write_stored_header(&mut deflate_state.encoder_state.writer, final_block)?;
+    deflate_state.stats.stored_blocks += 1;
     // Output some extra zeroes if needed to align with the byte boundary.
-    deflate_state.encoder_state.flush()?;
+    deflate_state.encoder_state.pad_to_byte()?;
 
     if input.len() > 0 {
         // Add the current input data to the input buffer.
         let rem = deflate_state.input_buffer.add_data(input);
 
         if rem.is_some() {
-            return Err(io::Error::new(io::ErrorKind::InvalidInput,
-                                      "write_stored_block called with more data\
-                                       than currently supported!"));
+            return Err(Error::Internal("write_stored_block called with more data than \
+                                        currently supported!"));
         }
 
         // Write the length of the data and the actual (uncompressed) data.
@@ -102,11 +115,151 @@ fn write_stored_block<W: Write>(input: &[u8],
     }
 }
 
+/// Generate huffman code lengths for the current block from the frequencies of the symbols in it,
+/// write them to the output and update the huffman table to use them.
+fn write_dynamic_lengths<W: Sink>(deflate_state: &mut DeflateState<W>) -> Result<(), Error> {
+    // Generate the lengths of the huffman codes we will be using, using the
+    // frequency of the different symbols/lengths/distances.
+    let mut l_length_buffer = [0u8; NUM_LITERALS_AND_LENGTHS];
+    let mut d_length_buffer = [0u8; NUM_DISTANCE_CODES + 2];
+    let algorithm = deflate_state.compression_options.huffman_algorithm;
+    let (l_lengths, d_lengths) = {
+        let (l_freqs, d_freqs) = deflate_state.lz77_writer.get_frequencies();
+        // The huffman spec allows us to exclude zeroes at the end of the table
+        // of huffman lengths. Since a frequency of 0 will give an huffman
+        // length of 0. We strip off the trailing zeroes before even generating
+        // the lengths to save some work.
+        // There is however a minimum number of values we have to keep according
+        // to the deflate spec.
+        let l_freqs = remove_trailing_zeroes(l_freqs, MIN_NUM_LITERALS_AND_LENGTHS);
+        let d_freqs = remove_trailing_zeroes(d_freqs, MIN_NUM_DISTANCES);
+        huffman_lengths_from_frequency_into(l_freqs,
+                                            MAX_CODE_LENGTH,
+                                            algorithm,
+                                            &mut l_length_buffer);
+        huffman_lengths_from_frequency_into(d_freqs,
+                                            MAX_CODE_LENGTH,
+                                            algorithm,
+                                            &mut d_length_buffer);
+        (&mut l_length_buffer[..l_freqs.len()], &d_length_buffer[..d_freqs.len()])
+    };
+    // If the block only contains the end of block marker, it is the only symbol with a code, and
+    // the code is incomplete. Some decoders reject this, so we give an unused literal a code
+    // as well to complete it, as zlib does.
+    if l_lengths.iter().filter(|&&l| l > 0).count() == 1 {
+        l_lengths[0] = 1;
+    }
+    // Output the lengths of the huffman codes used in this block.
+    write_huffman_lengths(l_lengths,
+                          d_lengths,
+                          algorithm,
+                          &mut deflate_state.encoder_state.writer)?;
+
+    // Output update the huffman table that will be used to encode the
+    // lz77-compressed data.
+    deflate_state.encoder_state
+        .update_huffman_table(l_lengths, d_lengths)?;
+    Ok(())
+}
+
+/// Compress the input using only stored blocks.
+///
+/// Data is kept in the input buffer until there is enough to fill a stored block, or we are
+/// flushing.
+fn compress_data_stored_n<W: Sink>(input: &[u8],
+                                    deflate_state: &mut DeflateState<W>,
+                                    flush: Flush)
+                                    -> Result<usize, Error> {
+    let mut slice = input;
+    loop {
+        let buffered = deflate_state.input_buffer.current_end();
+        let length = cmp::min(MAX_STORED_BLOCK_LENGTH - buffered, slice.len());
+        let rem = deflate_state.input_buffer.add_data(&slice[..length]);
+        debug_assert!(rem.is_none());
+        slice = &slice[length..];
+        deflate_state.bytes_written += length as u64;
+
+        let full = deflate_state.input_buffer.current_end() == MAX_STORED_BLOCK_LENGTH;
+        if full || (slice.is_empty() && flush != Flush::None) {
+            let final_block = flush == Flush::Finish && slice.is_empty();
+            // An empty block is only needed to end the stream.
+            if final_block || deflate_state.input_buffer.current_end() > 0 {
+                write_stored_header(&mut deflate_state.encoder_state.writer, final_block)?;
+                compress_block_stored(deflate_state.input_buffer.get_buffer(),
+                                      &mut deflate_state.encoder_state.writer)?;
+                deflate_state.stats.stored_blocks += 1;
+                deflate_state.input_buffer.clear();
+            }
+        }
+
+        if slice.is_empty() {
+            break;
+        }
+    }
+
+    if flush == Flush::Sync {
+        write_stored_block(&[], deflate_state, false)?;
+    }
+
+    deflate_state.encoder_state.pad_to_byte().map(|()| input.len())
+}
+
 /// Inner compression function used by both the writers and the simple compression functions.
-pub fn compress_data_dynamic_n<W: Write>(input: &[u8],
+///
+/// If the output should be rsyncable, the input is split at the sync points found in it, and a
+/// full flush is done at each of them.
+pub fn compress_data_dynamic_n<W: Sink>(input: &[u8],
                                          deflate_state: &mut DeflateState<W>,
                                          flush: Flush)
-                                         -> io::Result<usize> {
+                                         -> Result<usize, Error> {
+    let mut slice = input;
+    let mut bytes_written = 0;
+    loop {
+        let sync_point = match deflate_state.rsync_checksum {
+            Some(ref mut checksum) => checksum.find_sync_point(slice),
+            None => None,
+        };
+        match sync_point {
+            Some(n) => {
+                bytes_written += compress_data_flush(&slice[..n], deflate_state, Flush::Full)?;
+                slice = &slice[n..];
+            }
+            None => return Ok(bytes_written + compress_data_flush(slice, deflate_state, flush)?),
+        }
+    }
+}
+
+/// Compress the input, doing a full flush afterwards if requested.
+fn compress_data_flush<W: Sink>(input: &[u8],
+                                 deflate_state: &mut DeflateState<W>,
+                                 flush: Flush)
+                                 -> Result<usize, Error> {
+    if let Some(ref mut zlib_state) = deflate_state.zlib_state {
+        zlib_state.deflate(input,
+                           flush,
+                           &mut deflate_state.encoder_state,
+                           &mut deflate_state.stats)?;
+        deflate_state.bytes_written += input.len() as u64;
+        return Ok(input.len());
+    }
+    if flush == Flush::Full {
+        // All the input is consumed when syncing, so nothing is lost by clearing the buffers.
+        let bytes_written = compress_data_n(input, deflate_state, Flush::Sync)?;
+        deflate_state.clear_history();
+        Ok(bytes_written)
+    } else {
+        compress_data_n(input, deflate_state, flush)
+    }
+}
+
+fn compress_data_n<W: Sink>(input: &[u8],
+                             deflate_state: &mut DeflateState<W>,
+                             flush: Flush)
+                             -> Result<usize, Error> {
+    if deflate_state.compression_options.special == SpecialOptions::ForceStored {
+        return compress_data_stored_n(input, deflate_state, flush);
+    }
+    let force_fixed = deflate_state.compression_options.special == SpecialOptions::ForceFixed;
 
     // If we are flushing and have not yet written anything to the output stream (which is the case
     // if is_first_window is true), we check if it will be shorter to used fixed huffman codes
@@ -118,8 +271,7 @@ pub fn compress_data_dynamic_n<W: Write>(input: &[u8],
                                flush == Flush::Sync {
         BType::DynamicHuffman
     } else {
-        println!("compress called with {:?}", flush);
-        unimplemented!();
+        return Err(Error::Internal("Unsupported flush mode!"));
     };
 
     let mut bytes_written = 0;
@@ -139,7 +291,7 @@ pub fn compress_data_dynamic_n<W: Write>(input: &[u8],
                         // Bytes written in this call
                         bytes_written += written;
                         // Total bytes written since the compression process started
-                        deflate_state.bytes_written += bytes_written as u64;
+                        deflate_state.bytes_written += written as u64;
 
                         if status == LZ77Status::NeedInput {
                             // If we've consumed all the data input so far, and we're not
@@ -151,41 +303,23 @@ pub fn compress_data_dynamic_n<W: Write>(input: &[u8],
                         // Increment start of input data
                         slice = &slice[written..];
                         deflate_state.encoder_state
-                            .write_start_of_block(false, deflate_state.lz77_state.is_last_block())?;
-
-                        // Generate the lengths of the huffman codes we will be using, using the
-                        // frequency of the different symbols/lengths/distances.
-                        let (l_lengths, d_lengths) = {
-                            let (l_freqs, d_freqs) = deflate_state.lz77_writer.get_frequencies();
-                            // The huffman spec allows us to exclude zeroes at the end of the table
-                            // of huffman lengths. Since a frequency of 0 will give an huffman
-                            // length of 0. We strip off the trailing zeroes before even generating
-                            // the lengths to save some work.
-                            // There is however a minimum number of values we have to keep according
-                            // to the deflate spec.
-                            (
-                                huffman_lengths_from_frequency(
-                                    remove_trailing_zeroes(l_freqs, MIN_NUM_LITERALS_AND_LENGTHS),
-                                    MAX_CODE_LENGTH
-                            ),
-                                huffman_lengths_from_frequency(
-                                    remove_trailing_zeroes(d_freqs, MIN_NUM_DISTANCES),
-                                    MAX_CODE_LENGTH)
-                            )
-                        };
-                        // Output the lengths of the huffman codes used in this block.
-                        write_huffman_lengths(&l_lengths,
-                                              &d_lengths,
-                                              &mut deflate_state.encoder_state.writer)?;
-
-                        // Output update the huffman table that will be used to encode the
-                        // lz77-compressed data.
-                        deflate_state.encoder_state
-                            .update_huffman_table(&l_lengths, &d_lengths)?;
+                            .write_start_of_block(force_fixed,
+                                                  deflate_state.lz77_state.is_last_block())?;
+
+                        if force_fixed {
+                            deflate_state.stats.fixed_blocks += 1;
+                            deflate_state.encoder_state
+                                .update_huffman_table(&FIXED_CODE_LENGTHS,
+                                                      &FIXED_CODE_LENGTHS_DISTANCE)?;
+                        } else {
+                            deflate_state.stats.dynamic_blocks += 1;
+                            write_dynamic_lengths(deflate_state)?;
+                        }
 
                         // write the huffman compressed data and end of block code.
                         flush_to_bitstream(deflate_state.lz77_writer.get_buffer(),
-                                           &mut deflate_state.encoder_state)?;
+                                           &mut deflate_state.encoder_state,
+                                           &mut deflate_state.stats)?;
 
                         // End of block is written in flush_to_bitstream.
 
@@ -213,19 +347,21 @@ pub fn compress_data_dynamic_n<W: Write>(input: &[u8],
                     deflate_state.encoder_state
                         .update_huffman_table(&FIXED_CODE_LENGTHS, &FIXED_CODE_LENGTHS_DISTANCE)?;
                     deflate_state.encoder_state.write_start_of_block(true, flush == Flush::Finish)?;
+                    deflate_state.stats.fixed_blocks += 1;
                     flush_to_bitstream(deflate_state.lz77_writer.get_buffer(),
-                                       &mut deflate_state.encoder_state)?;
+                                       &mut deflate_state.encoder_state,
+                                       &mut deflate_state.stats)?;
                     // Clear the current lz77 data in the writer for the next call.
                     deflate_state.lz77_writer.clear();
                 }
                 BType::NoCompression => {
-                    unreachable!();
+                    return Err(Error::Internal("Invalid block type!"));
                 }
             }
 
         }
         BType::NoCompression => {
-            assert!(flush != Flush::None);
+            debug_assert!(flush != Flush::None);
 
             write_stored_block(input, deflate_state, flush == Flush::Finish)?;
 
@@ -242,7 +378,7 @@ pub fn compress_data_dynamic_n<W: Write>(input: &[u8],
 
     // Make sure we've output everything, and return the number of bytes written if everything
     // went well.
-    deflate_state.encoder_state.flush().map(|()| bytes_written)
+    deflate_state.encoder_state.pad_to_byte().map(|()| bytes_written)
 }
 
 #[cfg(test)]
@@ -303,4 +439,44 @@ mod test {
         // Not using assert_eq here deliberately to avoid massive amounts of output spam.
         assert!(input == result);
     }
+
+    #[test]
+    /// Test that the input consumed is only counted once when a call compresses several blocks.
+    fn bytes_written_counted_once() {
+        use compression_options::CompressionOptions;
+        let input = get_test_data();
+
+        let mut state = DeflateState::new(CompressionOptions::default(), Vec::new());
+        let written = compress_data_dynamic_n(&input, &mut state, Flush::None).unwrap();
+        assert_eq!(state.bytes_written, written as u64);
+        compress_data_dynamic_n(&input[written..], &mut state, Flush::Finish).unwrap();
+        assert_eq!(state.bytes_written, input.len() as u64);
+    }
+
+    #[test]
+    /// Test that the literal/length codes are complete even for a block with no data.
+    fn empty_dynamic_block() {
+        use compression_options::CompressionOptions;
+        use inspect::inspect_deflate;
+
+        let input = get_test_data();
+        let mut state = DeflateState::new(CompressionOptions::default(), Vec::new());
+        compress_data_dynamic_n(&input[..100000], &mut state, Flush::Sync).unwrap();
+        compress_data_dynamic_n(&[], &mut state, Flush::Finish).unwrap();
+        state.encoder_state.writer.write_pending().unwrap();
+        let compressed = state.encoder_state.writer.w;
+        assert!(decompress_to_end(&compressed) == &input[..100000]);
+
+        let blocks = inspect_deflate(&compressed).unwrap();
+        let last = blocks.last().unwrap();
+        assert_eq!(last.decoded_bytes, 0);
+        for header in blocks.iter().filter_map(|b| b.header.as_ref()) {
+            let code_space: u32 = header.literal_length_lengths
+                .iter()
+                .filter(|&&l| l > 0)
+                .map(|&l| 1 << (15 - l))
+                .sum();
+            assert_eq!(code_space, 1 << 15);
+        }
+    }
 }
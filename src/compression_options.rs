@@ -1,5 +1,8 @@
-use lz77::MatchingType;
-use std::convert::From;
+use lz77::{MatchingType, MatchFinder};
+use length_encode::HuffmanAlgorithm;
+use huffman_table::{MAX_MATCH, DEFLATE64_MAX_MATCH};
+use core::convert::From;
+use core::cmp;
 
 pub const HIGH_MAX_HASH_CHECKS: u16 = 768;
 pub const HIGH_LAZY_IF_LESS_THAN: u16 = 128;
@@ -7,6 +10,22 @@ pub const HIGH_LAZY_IF_LESS_THAN: u16 = 128;
 pub const MAX_HASH_CHECKS: u16 = 32 * 1024;
 pub const DEFAULT_MAX_HASH_CHECKS: u16 = 128;
 pub const DEFAULT_LAZY_IF_LESS_THAN: u16 = 32;
+pub const HIGH_GOOD_LENGTH: u16 = 32;
+pub const HIGH_NICE_LENGTH: u16 = MAX_MATCH;
+pub const DEFAULT_GOOD_LENGTH: u16 = 8;
+pub const DEFAULT_NICE_LENGTH: u16 = 128;
+/// The base-2 logarithm of the largest window size allowed by the deflate spec (32k).
+pub const MAX_WINDOW_BITS: u8 = 15;
+/// The window size used by Deflate64.
+pub const DEFLATE64_WINDOW_SIZE: usize = 65536;
+/// The base-2 logarithm of the smallest window size that can be specified in a zlib header.
+pub const MIN_WINDOW_BITS: u8 = 8;
+/// The number of windows of input buffered by default.
+pub const DEFAULT_BUFFER_WINDOWS: u8 = 1;
+/// The largest number of windows of input that can be buffered.
+pub const MAX_BUFFER_WINDOWS: u8 = 64;
+/// The highest numeric compression level.
+pub const MAX_LEVEL: u8 = 9;
 
 /// An enum describing the level of compression to be used by the encoder
 ///
@@ -29,22 +48,66 @@ pub enum Compression {
     Best,
 }
 
-/// Enum allowing some special options (not implemented yet)!
-#[derive(Copy, Clone, Debug)]
+/// Enum allowing some special options.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum SpecialOptions {
     /// Compress normally.
     Normal,
-    /// Force fixed huffman tables. (Unimplemented!).
-    _ForceFixed,
-    /// Force stored (uncompressed) blocks only. (Unimplemented!).
-    _ForceStored,
+    /// Force fixed huffman tables.
+    ForceFixed,
+    /// Force stored (uncompressed) blocks only.
+    ForceStored,
+    /// Only use matches of at least `FILTERED_MIN_MATCH_LENGTH` bytes, like the `Z_FILTERED`
+    /// strategy in zlib.
+    ///
+    /// This works better for data produced by a filter or predictor, such as PNG image data,
+    /// where short matches are mostly chance and cost more than the literals they replace.
+    Filtered,
+}
+
+/// The strategies of zlib, which decide how it searches for matches.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ZlibStrategy {
+    /// Normal matching (`Z_DEFAULT_STRATEGY`).
+    Default,
+    /// Discard short matches (`Z_FILTERED`).
+    Filtered,
+    /// Only output literals (`Z_HUFFMAN_ONLY`).
+    HuffmanOnly,
+    /// Only look for runs of the same byte (`Z_RLE`).
+    Rle,
+    /// Never use dynamic huffman codes (`Z_FIXED`).
+    Fixed,
 }
 
+/// The zlib settings to mirror in the zlib compatible mode.
+///
+/// [See `zlib_compat`](./struct.CompressionOptions.html#structfield.zlib_compat)
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ZlibCompat {
+    /// The zlib compression level, `0` to `9`. Higher values are treated as `9`.
+    pub level: u8,
+    /// The zlib compression strategy.
+    pub strategy: ZlibStrategy,
+}
+
+/// The shortest match used with `SpecialOptions::Filtered`.
+pub const FILTERED_MIN_MATCH_LENGTH: usize = 6;
+
 pub const DEFAULT_OPTIONS: CompressionOptions = CompressionOptions {
     max_hash_checks: DEFAULT_MAX_HASH_CHECKS,
     lazy_if_less_than: DEFAULT_LAZY_IF_LESS_THAN,
+    good_length: DEFAULT_GOOD_LENGTH,
+    nice_length: DEFAULT_NICE_LENGTH,
     matching_type: MatchingType::Lazy,
+    match_finder: MatchFinder::HashChains,
     special: SpecialOptions::Normal,
+    window_bits: MAX_WINDOW_BITS,
+    buffer_windows: DEFAULT_BUFFER_WINDOWS,
+    huffman_algorithm: HuffmanAlgorithm::InPlace,
+    deflate64: false,
+    rsyncable: false,
+    zlib_compat: None,
 };
 
 // const RLE_ONLY: CompressionOptions {
@@ -58,16 +121,55 @@ pub const DEFAULT_OPTIONS: CompressionOptions = CompressionOptions {
 pub const _HUFFMAN_ONLY: CompressionOptions = CompressionOptions {
     max_hash_checks: 0,
     lazy_if_less_than: 0,
+    good_length: 0,
+    nice_length: 0,
     matching_type: MatchingType::Greedy,
+    match_finder: MatchFinder::HashChains,
     special: SpecialOptions::Normal,
+    window_bits: MAX_WINDOW_BITS,
+    buffer_windows: DEFAULT_BUFFER_WINDOWS,
+    huffman_algorithm: HuffmanAlgorithm::InPlace,
+    deflate64: false,
+    rsyncable: false,
+    zlib_compat: None,
 };
 
+/// Hash checks, lazy matching limit, good and nice lengths, matching type and huffman algorithm
+/// used for the numeric compression levels `1` to `9`. The good and nice lengths are the ones
+/// zlib uses for the same levels.
+const LEVELS: [(u16, u16, u16, u16, MatchingType, HuffmanAlgorithm); 9] =
+    [(1, 0, 4, 8, MatchingType::Greedy, HuffmanAlgorithm::InPlace),
+     (8, 0, 4, 16, MatchingType::Greedy, HuffmanAlgorithm::InPlace),
+     (32, 0, 4, 32, MatchingType::Greedy, HuffmanAlgorithm::InPlace),
+     (16, 8, 4, 16, MatchingType::Lazy, HuffmanAlgorithm::InPlace),
+     (64, 16, 8, 32, MatchingType::Lazy, HuffmanAlgorithm::InPlace),
+     (DEFAULT_MAX_HASH_CHECKS,
+      DEFAULT_LAZY_IF_LESS_THAN,
+      DEFAULT_GOOD_LENGTH,
+      DEFAULT_NICE_LENGTH,
+      MatchingType::Lazy,
+      HuffmanAlgorithm::InPlace),
+     (256, 64, 8, 128, MatchingType::Lazy, HuffmanAlgorithm::InPlace),
+     (512,
+      HIGH_LAZY_IF_LESS_THAN,
+      HIGH_GOOD_LENGTH,
+      HIGH_NICE_LENGTH,
+      MatchingType::Lazy,
+      HuffmanAlgorithm::PackageMerge),
+     (HIGH_MAX_HASH_CHECKS,
+      HIGH_LAZY_IF_LESS_THAN,
+      HIGH_GOOD_LENGTH,
+      HIGH_NICE_LENGTH,
+      MatchingType::Lazy,
+      HuffmanAlgorithm::PackageMerge)];
+
 /// A struct describing the options for a compressor or compression function.
 ///
 /// These values are not stable and still subject to change!
 #[derive(Copy, Clone, Debug)]
 pub struct CompressionOptions {
-    /// The maximum number of checks to make in the hash table for matches.
+    /// The maximum number of checks to make in the hash table for matches, like `max_chain` in
+    /// zlib.
     ///
     /// Higher numbers mean slower, but better compression. Very high (say `>1024`) values
     /// will impact compression speed a lot.
@@ -87,8 +189,22 @@ pub struct CompressionOptions {
     ///
     /// * Default value: `32`
     pub lazy_if_less_than: u16,
-
-    // pub _decent_match: u16,
+    /// Only make a quarter of `max_hash_checks` checks when looking for a better match at the
+    /// next byte if the current match is at least this long, like `good_length` in zlib.
+    ///
+    /// Lower values make compression faster, but slightly worse. This has no effect with
+    /// greedy matching.
+    ///
+    /// * Default value: `8`
+    pub good_length: u16,
+    /// Stop searching for a match once one at least this long is found, like `nice_length` in
+    /// zlib.
+    ///
+    /// Lower values make compression faster, but worse. The match found can still be longer
+    /// than this.
+    ///
+    /// * Default value: `128`
+    pub nice_length: u16,
     /// Whether to use lazy or greedy matching.
     ///
     /// Lazy matching will provide better compression, at the expense of compression speed.
@@ -97,8 +213,75 @@ pub struct CompressionOptions {
     ///
     /// * Default value: `MatchingType::Lazy`
     pub matching_type: MatchingType,
-    /// Force fixed/stored blocks (Not implemented yet).
+    /// The data structure used to find matches.
+    ///
+    /// `MatchFinder::BinaryTree` can be much faster than hash chains with high values of
+    /// `max_hash_checks` on data with a small alphabet, but is slower on text.
+    ///
+    /// [See `MatchFinder`](./enum.MatchFinder.html)
+    ///
+    /// * Default value: `MatchFinder::HashChains`
+    pub match_finder: MatchFinder,
+    /// Force fixed/stored blocks.
     pub special: SpecialOptions,
+    /// The base-2 logarithm of the window size, i.e the maximum distance back matches can refer
+    /// to.
+    ///
+    /// Smaller windows reduce the memory needed by the decompressor at the cost of compression.
+    /// Values are clamped to the range `8...15`.
+    ///
+    /// * Default value: `15`
+    pub window_bits: u8,
+    /// The number of windows of input to buffer before the buffered data and the positions in
+    /// the match finder have to be moved down to make room for more.
+    ///
+    /// Higher values move the positions less often, at the cost of a larger input buffer, though
+    /// this hasn't been measured to make compression noticeably faster. The output is the same
+    /// whatever the value. Values are clamped to the range `1...64`.
+    ///
+    /// * Default value: `1`
+    pub buffer_windows: u8,
+    /// The algorithm used to generate the lengths of the huffman codes for each block.
+    ///
+    /// `HuffmanAlgorithm::PackageMerge` gives optimal codes within the length limits of
+    /// deflate, which can make blocks with very skewed symbol frequencies slightly smaller.
+    /// This is ignored when `zlib_compat` is set.
+    ///
+    /// [See `HuffmanAlgorithm`](./enum.HuffmanAlgorithm.html)
+    ///
+    /// * Default value: `HuffmanAlgorithm::InPlace`
+    pub huffman_algorithm: HuffmanAlgorithm,
+    /// Output Deflate64 (also known as enhanced deflate) instead of standard deflate.
+    ///
+    /// Deflate64 uses a 64k window and allows matches of up to `65535` bytes, which often gives
+    /// slightly better compression. The output can only be read by decoders supporting
+    /// Deflate64, and is only meaningful as raw deflate data, such as in a zip entry using
+    /// compression method `9`. `window_bits` is ignored when this is set.
+    ///
+    /// * Default value: `false`
+    pub deflate64: bool,
+    /// Make the output friendlier to `rsync` and deduplication, like `gzip --rsyncable`.
+    ///
+    /// The encoder does a full flush at points determined by the contents of the input, so that
+    /// nothing after such a point refers back to data before it. Changes to the input then only
+    /// change the output up to the next such point. The points are on average around 8k apart,
+    /// so this makes compression noticeably worse for data that compresses well using
+    /// matches far back.
+    ///
+    /// * Default value: `false`
+    pub rsyncable: bool,
+    /// Produce output that is byte for byte identical to that of zlib 1.2.12 and 1.2.13 with the
+    /// given level and strategy.
+    ///
+    /// This uses a separate implementation of zlib's algorithms rather than the usual encoder,
+    /// so the output is the same as what zlib gives for the same sequence of writes and flushes,
+    /// as long as zlib is used with the default `memLevel` of `8` and is never short of output
+    /// space. `window_bits` is used like the `windowBits` parameter of zlib, where `8` means `9`.
+    /// The other matching options, `special` and `deflate64` are ignored. The zlib and gzip
+    /// headers are also written the way zlib writes them, using the values zlib uses on Unix.
+    ///
+    /// * Default value: `None`
+    pub zlib_compat: Option<ZlibCompat>,
 }
 
 impl CompressionOptions {
@@ -107,8 +290,17 @@ impl CompressionOptions {
         CompressionOptions {
             max_hash_checks: HIGH_MAX_HASH_CHECKS,
             lazy_if_less_than: HIGH_LAZY_IF_LESS_THAN,
+            good_length: HIGH_GOOD_LENGTH,
+            nice_length: HIGH_NICE_LENGTH,
             matching_type: MatchingType::Lazy,
+            match_finder: MatchFinder::HashChains,
             special: SpecialOptions::Normal,
+            window_bits: MAX_WINDOW_BITS,
+            buffer_windows: DEFAULT_BUFFER_WINDOWS,
+            huffman_algorithm: HuffmanAlgorithm::PackageMerge,
+            deflate64: false,
+            rsyncable: false,
+            zlib_compat: None,
         }
     }
 
@@ -122,8 +314,106 @@ impl CompressionOptions {
         CompressionOptions {
             max_hash_checks: 1,
             lazy_if_less_than: 0,
+            good_length: 4,
+            nice_length: 8,
             matching_type: MatchingType::Greedy,
+            match_finder: MatchFinder::HashChains,
             special: SpecialOptions::Normal,
+            window_bits: MAX_WINDOW_BITS,
+            buffer_windows: DEFAULT_BUFFER_WINDOWS,
+            huffman_algorithm: HuffmanAlgorithm::InPlace,
+            deflate64: false,
+            rsyncable: false,
+            zlib_compat: None,
+        }
+    }
+
+    /// Returns compression settings for a zlib-style numeric compression level.
+    ///
+    /// Level `0` only outputs stored (uncompressed) blocks, `1` is the same as `fast()`, `6` the
+    /// same as `default()` and `9` the same as `high()`. Levels above `9` are treated as `9`.
+    pub fn from_level(level: u8) -> CompressionOptions {
+        if level == 0 {
+            return CompressionOptions {
+                special: SpecialOptions::ForceStored,
+                ..CompressionOptions::fast()
+            };
+        }
+        let (max_hash_checks,
+             lazy_if_less_than,
+             good_length,
+             nice_length,
+             matching_type,
+             huffman_algorithm) = LEVELS[usize::from(cmp::min(level, MAX_LEVEL)) - 1];
+        CompressionOptions {
+            max_hash_checks,
+            lazy_if_less_than,
+            good_length,
+            nice_length,
+            matching_type,
+            huffman_algorithm,
+            ..DEFAULT_OPTIONS
+        }
+    }
+
+    /// Returns settings giving the same output as zlib using the given level and strategy.
+    ///
+    /// [See `zlib_compat`](#structfield.zlib_compat)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use deflate::{deflate_bytes_zlib_conf, CompressionOptions, ZlibStrategy};
+    ///
+    /// let options = CompressionOptions::zlib_compatible(9, ZlibStrategy::Default);
+    /// // zlib 1.2.x compresses this to these exact bytes at level 9.
+    /// assert_eq!(deflate_bytes_zlib_conf(b"aaaaaaaaaa", options).unwrap(),
+    ///            [0x78, 0xda, 0x4b, 0x4c, 0x84, 0x01, 0x00, 0x14, 0xe1, 0x03, 0xcb]);
+    /// ```
+    pub fn zlib_compatible(level: u8, strategy: ZlibStrategy) -> CompressionOptions {
+        CompressionOptions {
+            zlib_compat: Some(ZlibCompat { level, strategy }),
+            ..CompressionOptions::from_level(level)
+        }
+    }
+
+    /// Returns compression settings that don't search for matches at all, only using huffman
+    /// coding to compress the data.
+    pub fn huffman_only() -> CompressionOptions {
+        _HUFFMAN_ONLY
+    }
+
+    /// The window size in bytes, with `window_bits` clamped to the valid range.
+    pub(crate) fn window_size(&self) -> usize {
+        if self.deflate64 {
+            DEFLATE64_WINDOW_SIZE
+        } else {
+            1 << self.clamped_window_bits()
+        }
+    }
+
+    /// `buffer_windows` clamped to the valid range.
+    pub(crate) fn clamped_buffer_windows(&self) -> usize {
+        usize::from(self.buffer_windows.clamp(1, MAX_BUFFER_WINDOWS))
+    }
+
+    /// The longest match allowed by the output format.
+    pub(crate) fn max_match_length(&self) -> usize {
+        if self.deflate64 {
+            usize::from(DEFLATE64_MAX_MATCH)
+        } else {
+            usize::from(MAX_MATCH)
+        }
+    }
+
+    /// `window_bits` clamped to the range allowed by the zlib format.
+    pub(crate) fn clamped_window_bits(&self) -> u8 {
+        let window_bits = self.window_bits.clamp(MIN_WINDOW_BITS, MAX_WINDOW_BITS);
+        if self.zlib_compat.is_some() {
+            // zlib doesn't support a 256 byte window, and uses 512 bytes instead.
+            cmp::max(window_bits, MIN_WINDOW_BITS + 1)
+        } else {
+            window_bits
         }
     }
 }
@@ -144,3 +434,39 @@ impl From<Compression> for CompressionOptions {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn levels() {
+        let matches = |a: CompressionOptions, b: CompressionOptions| {
+            a.max_hash_checks == b.max_hash_checks &&
+            a.lazy_if_less_than == b.lazy_if_less_than && a.good_length == b.good_length &&
+            a.nice_length == b.nice_length && a.special == b.special &&
+            a.huffman_algorithm == b.huffman_algorithm
+        };
+        assert!(matches(CompressionOptions::from_level(1), CompressionOptions::fast()));
+        assert!(matches(CompressionOptions::from_level(6), CompressionOptions::default()));
+        assert!(matches(CompressionOptions::from_level(9), CompressionOptions::high()));
+        assert!(matches(CompressionOptions::from_level(200), CompressionOptions::high()));
+        assert_eq!(CompressionOptions::from_level(0).special, SpecialOptions::ForceStored);
+    }
+
+    #[test]
+    fn window_bits_clamped() {
+        let mut options = CompressionOptions::default();
+        assert_eq!(options.window_size(), 32768);
+        options.window_bits = 20;
+        assert_eq!(options.window_size(), 32768);
+        options.window_bits = 0;
+        assert_eq!(options.window_size(), 256);
+        options.deflate64 = true;
+        assert_eq!(options.window_size(), 65536);
+
+        let mut options = CompressionOptions::zlib_compatible(6, ZlibStrategy::Default);
+        options.window_bits = 8;
+        assert_eq!(options.window_size(), 512);
+    }
+}
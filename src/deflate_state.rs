@@ -1,50 +1,87 @@
-use std::io::Write;
+use alloc::boxed::Box;
 use lz77::LZ77State;
 use output_writer::DynamicWriter;
 use encoder_state::EncoderState;
 use input_buffer::InputBuffer;
-use compression_options::CompressionOptions;
+use compression_options::{CompressionOptions, SpecialOptions, FILTERED_MIN_MATCH_LENGTH};
 use huffman_table::HuffmanTable;
-use std::io;
+use stats::CompressionStats;
+use rsyncable::RsyncChecksum;
+use zlib_compat::ZlibState;
+use error::Error;
+use sink::Sink;
 pub use huffman_table::MAX_MATCH;
 
-pub struct DeflateState<W: Write> {
+pub struct DeflateState<W: Sink> {
     pub lz77_state: LZ77State,
     pub input_buffer: InputBuffer,
     pub compression_options: CompressionOptions,
     pub encoder_state: EncoderState<W>,
     pub lz77_writer: DynamicWriter,
     pub bytes_written: u64,
+    /// Block and symbol counts.
+    ///
+    /// The input, output and hash probe counts are kept elsewhere and filled in by `stats()`.
+    pub stats: CompressionStats,
+    /// Rolling checksum used to find sync points if the output is rsyncable.
+    pub rsync_checksum: Option<RsyncChecksum>,
+    /// The encoder used instead of the normal one in the zlib compatible mode.
+    pub zlib_state: Option<Box<ZlibState>>,
 }
 
-impl<W: Write> DeflateState<W> {
+impl<W: Sink> DeflateState<W> {
     pub fn _new_with_data(input: &[u8],
                           compression_options: CompressionOptions,
                           writer: W)
                           -> DeflateState<W> {
-        DeflateState {
-            input_buffer: InputBuffer::empty(),
-            lz77_state: LZ77State::_new_warmup(input,
-                                               compression_options.max_hash_checks,
-                                               compression_options.lazy_if_less_than,
-                                               compression_options.matching_type),
-            encoder_state: EncoderState::new(HuffmanTable::empty(), writer),
-            lz77_writer: DynamicWriter::new(),
-            compression_options: compression_options,
-            bytes_written: 0,
-        }
+        let lz77_state = LZ77State::_new_warmup(input,
+                                                    compression_options.max_hash_checks,
+                                                    compression_options.lazy_if_less_than,
+                                                    compression_options.matching_type,
+                                                    compression_options.window_size());
+        DeflateState::from_parts(lz77_state, compression_options, writer)
     }
 
     pub fn new(compression_options: CompressionOptions, writer: W) -> DeflateState<W> {
+        let lz77_state = LZ77State::new(compression_options.max_hash_checks,
+                                            compression_options.lazy_if_less_than,
+                                            compression_options.matching_type,
+                                            compression_options.window_size());
+        DeflateState::from_parts(lz77_state, compression_options, writer)
+    }
+
+    /// Set up the rest of the state around `lz77_state` according to the compression options.
+    fn from_parts(mut lz77_state: LZ77State,
+                  compression_options: CompressionOptions,
+                  writer: W)
+                  -> DeflateState<W> {
+        if compression_options.special == SpecialOptions::Filtered {
+            lz77_state.set_min_match_length(FILTERED_MIN_MATCH_LENGTH);
+        }
+        lz77_state.set_max_match_length(compression_options.max_match_length());
+        lz77_state.set_good_length(usize::from(compression_options.good_length));
+        lz77_state.set_nice_length(usize::from(compression_options.nice_length));
+        lz77_state.set_match_finder(compression_options.match_finder);
+        let mut encoder_state = EncoderState::new(HuffmanTable::empty(), writer);
+        encoder_state.set_deflate64(compression_options.deflate64 &&
+                                    compression_options.zlib_compat.is_none());
         DeflateState {
-            input_buffer: InputBuffer::empty(),
-            lz77_state: LZ77State::new(compression_options.max_hash_checks,
-                                       compression_options.lazy_if_less_than,
-                                       compression_options.matching_type),
-            encoder_state: EncoderState::new(HuffmanTable::empty(), writer),
+            input_buffer: InputBuffer::with_windows(compression_options.window_size(),
+                                                    compression_options.clamped_buffer_windows()),
+            lz77_state,
+            encoder_state,
             lz77_writer: DynamicWriter::new(),
             compression_options: compression_options,
             bytes_written: 0,
+            stats: CompressionStats::default(),
+            rsync_checksum: if compression_options.rsyncable {
+                Some(RsyncChecksum::new())
+            } else {
+                None
+            },
+            zlib_state: compression_options.zlib_compat.map(|compat| {
+                Box::new(ZlibState::new(compat, compression_options.clamped_window_bits()))
+            }),
         }
     }
 
@@ -53,12 +90,62 @@ impl<W: Write> DeflateState<W> {
     /// If flushing the current writer succeeds, it is replaced with the provided one,
     /// buffers and status (except compression options) is reset and the old writer
     /// is returned.
-    pub fn reset(&mut self, writer: W) -> io::Result<W> {
+    pub fn reset(&mut self, writer: W) -> Result<W, Error> {
         let ret = self.encoder_state.reset(writer)?;
-        self.input_buffer = InputBuffer::empty();
+        self.input_buffer.clear();
         self.lz77_writer.clear();
         self.lz77_state.reset();
         self.bytes_written = 0;
+        self.stats = CompressionStats::default();
+        if let Some(ref mut checksum) = self.rsync_checksum {
+            checksum.reset();
+        }
+        if let Some(ref mut zlib_state) = self.zlib_state {
+            zlib_state.reset();
+        }
         Ok(ret)
     }
+
+    /// Forget all previous input, so that data written after this doesn't refer back to it.
+    ///
+    /// Has to be called right after a sync flush, as data still in the buffers would be lost
+    /// otherwise.
+    pub fn clear_history(&mut self) {
+        self.input_buffer.clear();
+        self.lz77_state.clear_history();
+    }
+
+    /// Use the provided data as a preset dictionary that matches can refer back to.
+    ///
+    /// Only the last window size bytes of the dictionary are used. This has to be called
+    /// before any data is compressed.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<(), Error> {
+        if self.bytes_written > 0 || self.input_buffer.current_end() > 0 ||
+           !self.lz77_state.is_first_window() || self.lz77_state.has_dictionary() {
+            return Err(Error::InvalidInput("The dictionary has to be set before any data is \
+                                            written!"));
+        }
+        if let Some(ref mut zlib_state) = self.zlib_state {
+            zlib_state.set_dictionary(dictionary);
+            return Ok(());
+        }
+        let window_size = self.compression_options.window_size();
+        let start = dictionary.len().saturating_sub(window_size);
+        let dictionary = &dictionary[start..];
+        // The buffer is empty and can hold more than one window of data, so this will not fail.
+        let rem = self.input_buffer.add_data(dictionary);
+        debug_assert!(rem.is_none());
+        self.lz77_state.set_dictionary_length(dictionary.len());
+        Ok(())
+    }
+
+    /// Get the statistics for the data compressed so far.
+    pub fn stats(&self) -> CompressionStats {
+        CompressionStats {
+            bytes_in: self.bytes_written,
+            bytes_out: self.encoder_state.writer.bytes_written(),
+            hash_probes: self.lz77_state.hash_probes(),
+            ..self.stats
+        }
+    }
 }
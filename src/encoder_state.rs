@@ -1,9 +1,10 @@
-use std::io;
-use std::io::{Write, ErrorKind};
-use std::mem;
+use core::mem;
+use alloc::vec::Vec;
 use huffman_table::{HuffmanTable, HuffmanError};
-use bitstream::{LsbWriter, BitWriter};
+use bitstream::{LsbWriter, BitWriter, PendingBuffer};
 use lzvalue::LZType;
+use error::Error;
+use sink::Sink;
 
 // The first bits of each block, which describe the type of the block
 // `-TTF` - TT = type, 00 = stored, 01 = fixed, 10 = dynamic, 11 = reserved, F - 1 if final block
@@ -21,18 +22,17 @@ pub enum BType {
 }
 
 /// A struct wrapping a writer that writes data compressed using the provided huffman table
-pub struct EncoderState<W: Write> {
+pub struct EncoderState<W: Sink, P: PendingBuffer = Vec<u8>> {
     huffman_table: HuffmanTable,
-    pub writer: LsbWriter<W>,
+    pub writer: LsbWriter<W, P>,
+    // Whether lengths and distances are encoded using the Deflate64 codes.
+    deflate64: bool,
 }
 
-impl<W: Write> EncoderState<W> {
+impl<W: Sink> EncoderState<W> {
     /// Creates a new encoder state using the provided huffman table and writer
     pub fn new(huffman_table: HuffmanTable, writer: W) -> EncoderState<W> {
-        EncoderState {
-            huffman_table: huffman_table,
-            writer: LsbWriter::new(writer),
-        }
+        EncoderState::with_buffer(huffman_table, writer, Vec::new())
     }
 
     #[cfg(test)]
@@ -40,39 +40,50 @@ impl<W: Write> EncoderState<W> {
     pub fn fixed(writer: W) -> EncoderState<W> {
         EncoderState::new(HuffmanTable::fixed_table(), writer)
     }
+}
+
+impl<W: Sink, P: PendingBuffer> EncoderState<W, P> {
+    /// Creates a new encoder state using the provided huffman table and writer, storing pending
+    /// output in `pending`.
+    pub fn with_buffer(huffman_table: HuffmanTable, writer: W, pending: P) -> EncoderState<W, P> {
+        EncoderState {
+            huffman_table,
+            writer: LsbWriter::with_buffer(writer, pending),
+            deflate64: false,
+        }
+    }
+
+    /// Encode lengths and distances the way Deflate64 does if `deflate64` is true.
+    pub fn set_deflate64(&mut self, deflate64: bool) {
+        self.deflate64 = deflate64;
+    }
 
     /// Encodes a literal value to the writer
-    fn write_literal(&mut self, value: u8) -> io::Result<()> {
+    fn write_literal(&mut self, value: u8) -> Result<(), Error> {
         let code = self.huffman_table.get_literal(value);
         self.writer.write_bits(code.code, code.length)
     }
 
     // Write a LDPair value to the contained writer, returning Err if the write operation fails
-    pub fn write_lzvalue2(&mut self, value: LZType) -> io::Result<()> {
+    pub fn write_lzvalue2(&mut self, value: LZType) -> Result<(), Error> {
         match value {
             LZType::Literal(l) => self.write_literal(l),
             LZType::StoredLengthDistance(l, d) => {
-                let (code, extra_bits_code) = self.huffman_table
-                    .get_length_huffman(l);
-                self.writer
-                    .write_bits(code.code, code.length)?;
-                self.writer.write_bits(extra_bits_code.code, extra_bits_code.length)?;
-
-                let (code, extra_bits_code) = self.huffman_table
-                    .get_distance_huffman(d)
-                    .ok_or_else(|| {
-                        io::Error::new(ErrorKind::Other, "BUG!: Invalid huffman distance value!")
-                    })?;
+                // The codes are at most 15 bits long, and there are at most 16 extra bits, so
+                // each code can be written along with its extra bits.
+                let length = self.huffman_table.get_length_huffman(l, self.deflate64);
+                self.writer.write_bits_u32(length.bits, length.length)?;
 
-                self.writer
-                    .write_bits(code.code, code.length)?;
-                self.writer.write_bits(extra_bits_code.code, extra_bits_code.length)
+                let distance = self.huffman_table
+                    .get_distance_huffman(d, self.deflate64)
+                    .ok_or(Error::Internal("Invalid huffman distance value!"))?;
+                self.writer.write_bits_u32(distance.bits, distance.length)
             }
         }
     }
 
     /// Write the start of a block, returning Err if the write operation fails.
-    pub fn write_start_of_block(&mut self, fixed: bool, final_block: bool) -> io::Result<()> {
+    pub fn write_start_of_block(&mut self, fixed: bool, final_block: bool) -> Result<(), Error> {
         if final_block {
             // The final block has one bit flipped to indicate it's
             // the final one
@@ -89,14 +100,17 @@ impl<W: Write> EncoderState<W> {
     }
 
     // Write the end of block code
-    pub fn write_end_of_block(&mut self) -> io::Result<()> {
+    pub fn write_end_of_block(&mut self) -> Result<(), Error> {
         let code = self.huffman_table.get_end_of_block();
         self.writer.write_bits(code.code, code.length)
     }
 
-    /// Flush the contained writer and it's bitstream wrapper.
-    pub fn flush(&mut self) -> io::Result<()> {
-        self.writer.flush()
+    /// Output the remaining bits, padded to a byte boundary.
+    ///
+    /// This doesn't write anything to the contained writer, the output is kept in the bitstream
+    /// wrapper until `write_pending` is called on it.
+    pub fn pad_to_byte(&mut self) -> Result<(), Error> {
+        self.writer.pad_to_byte()
     }
 
     /// Update the huffman table by generating new huffman codes
@@ -110,15 +124,17 @@ impl<W: Write> EncoderState<W> {
 
     /// Reset the encoder state with a new writer, returning the old one if flushing
     /// succeeds.
-    pub fn reset(&mut self, writer: W) -> io::Result<W> {
-        // Make sure the writer is flushed
+    pub fn reset(&mut self, writer: W) -> Result<W, Error> {
+        // Make sure all the output has been written
         // Ideally this should be done before this function is called, but we
         // do it here just in case.
-        self.flush()?;
+        self.writer.pad_to_byte()?;
+        self.writer.write_pending()?;
         // Reset the huffman table
         // This probably isn't needed, but again, we do it just in case to avoid leaking any data
         // If this turns out to be a performance issue, it can probably be ignored later.
         self.huffman_table = HuffmanTable::empty();
+        self.writer.reset_bytes_written();
         Ok(mem::replace(&mut self.writer.w, writer))
     }
 }
@@ -0,0 +1,104 @@
+//! The error type returned by the encoder.
+
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error;
+#[cfg(feature = "std")]
+use std::io;
+
+use sink::SinkError;
+
+/// An error that occurred while compressing data.
+///
+/// The encoders implementing `Write` return an `io::Error`. Errors other than `Error::Io` are
+/// returned wrapped in one, with the kind `InvalidInput` or `Other`, and can be recovered by
+/// converting the `io::Error` back using `Error::from`.
+#[derive(Debug)]
+pub enum Error {
+    /// Writing the output failed. With the `std` feature, `SinkError` is `io::Error`.
+    Io(SinkError),
+    /// A function was called with arguments or at a time that isn't allowed, for instance
+    /// setting a dictionary after data has been written.
+    InvalidInput(&'static str),
+    /// One of the checks done while encoding failed. This indicates a bug in this library, the
+    /// output written before the error is not valid.
+    Internal(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref err) => write!(f, "Write error: {}", err),
+            Error::InvalidInput(message) => f.write_str(message),
+            Error::Internal(message) => write!(f, "Internal error: {}", message),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for Error {
+    /// Unwrap an error wrapped by `From<Error> for io::Error`, or wrap any other `io::Error`
+    /// in `Error::Io`.
+    fn from(err: io::Error) -> Error {
+        match err.get_ref() {
+            Some(inner) if inner.is::<Error>() => (),
+            _ => return Error::Io(err),
+        }
+        match err.into_inner().map(|inner| inner.downcast::<Error>()) {
+            Some(Ok(inner)) => *inner,
+            _ => Error::Internal("Failed to unwrap an error"),
+        }
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl From<SinkError> for Error {
+    fn from(err: SinkError) -> Error {
+        Error::Io(err)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        let kind = match err {
+            Error::Io(err) => return err,
+            Error::InvalidInput(_) => io::ErrorKind::InvalidInput,
+            Error::Internal(_) => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn io_error_roundtrip() {
+        let err = io::Error::from(Error::InvalidInput("Invalid"));
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(err.to_string(), "Invalid");
+        match Error::from(err) {
+            Error::InvalidInput(message) => assert_eq!(message, "Invalid"),
+            other => panic!("Unexpected error {:?}", other),
+        }
+
+        let err = Error::from(io::Error::new(io::ErrorKind::WriteZero, "Full"));
+        match err {
+            Error::Io(ref inner) => assert_eq!(inner.kind(), io::ErrorKind::WriteZero),
+            ref other => panic!("Unexpected error {:?}", other),
+        }
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::WriteZero);
+    }
+}
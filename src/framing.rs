@@ -0,0 +1,295 @@
+//! This module contains the container formats that can be wrapped around the raw DEFLATE data.
+//!
+//! Each container format implements the `Framing` trait, which writes the header and trailer
+//! and keeps track of the checksum of the uncompressed data. The encoders and the one-shot
+//! compression functions are generic over this trait, so supporting a new container format
+//! only requires a new implementation of it.
+
+use std::io::{self, Write};
+
+use byteorder::{WriteBytesExt, BigEndian};
+
+use checksum::{Adler32Checksum, Crc32Checksum, RollingChecksum};
+use compression_options::CompressionOptions;
+use gzip::{write_gzip_header, write_gzip_trailer, get_zlib_compat_gzip_header};
+use zlib::{write_zlib_header_conf, CompressionLevel};
+use error::Error;
+
+/// The container format the compressed data is wrapped in.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Format {
+    /// Raw DEFLATE data, without any header or trailer.
+    Raw,
+    /// DEFLATE data with a [zlib](https://tools.ietf.org/html/rfc1950) header and an adler32
+    /// trailer.
+    Zlib,
+    /// DEFLATE data with a minimal [gzip](https://tools.ietf.org/html/rfc1952) header and a
+    /// CRC-32 and length trailer.
+    Gzip,
+}
+
+impl Format {
+    /// Select the format and window size from a `windowBits` value, following the convention
+    /// used by `deflateInit2` in zlib.
+    ///
+    /// `8` to `15` selects the zlib format, `-8` to `-15` raw DEFLATE, and `24` to `31` (`16`
+    /// plus the window bits) gzip. Returns the format along with the base-2 logarithm of the
+    /// window size, or `None` if `window_bits` is not in any of these ranges.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use deflate::Format;
+    ///
+    /// assert_eq!(Format::from_window_bits(15), Some((Format::Zlib, 15)));
+    /// assert_eq!(Format::from_window_bits(-9), Some((Format::Raw, 9)));
+    /// assert_eq!(Format::from_window_bits(31), Some((Format::Gzip, 15)));
+    /// assert_eq!(Format::from_window_bits(16), None);
+    /// ```
+    pub fn from_window_bits(window_bits: i32) -> Option<(Format, u8)> {
+        match window_bits {
+            8..=15 => Some((Format::Zlib, window_bits as u8)),
+            -15..=-8 => Some((Format::Raw, (-window_bits) as u8)),
+            24..=31 => Some((Format::Gzip, (window_bits - 16) as u8)),
+            _ => None,
+        }
+    }
+}
+
+/// The header, trailer and checksum of a container format.
+pub trait Framing {
+    /// Write the header, which comes before any compressed data.
+    fn write_header<W: Write>(&mut self,
+                              writer: &mut W,
+                              options: &CompressionOptions)
+                              -> io::Result<()>;
+    /// Update the checksum with the next part of the uncompressed data.
+    fn update(&mut self, data: &[u8]);
+    /// Write the trailer, which comes after the compressed data.
+    ///
+    /// `input_length` is the total length of the uncompressed data.
+    fn write_trailer<W: Write>(&mut self, writer: &mut W, input_length: u64) -> io::Result<()>;
+    /// Return an error if the format does not support preset dictionaries.
+    fn check_dictionary(&self) -> io::Result<()> {
+        Ok(())
+    }
+    /// Note the use of a preset dictionary.
+    ///
+    /// Only called after `check_dictionary` succeeded.
+    fn set_dictionary(&mut self, dictionary: &[u8]);
+    /// Reset the checksum and dictionary, so a new stream can be started.
+    fn reset(&mut self);
+}
+
+/// No header or trailer.
+pub struct RawFraming;
+
+impl Framing for RawFraming {
+    fn write_header<W: Write>(&mut self, _: &mut W, _: &CompressionOptions) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn update(&mut self, _: &[u8]) {}
+
+    fn write_trailer<W: Write>(&mut self, _: &mut W, _: u64) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_dictionary(&mut self, _: &[u8]) {}
+
+    fn reset(&mut self) {}
+}
+
+/// A zlib header, followed by the adler32 checksum of the uncompressed data.
+pub struct ZlibFraming {
+    checksum: Adler32Checksum,
+    // The adler32 checksum of the preset dictionary, if any.
+    dictionary_id: Option<u32>,
+}
+
+impl ZlibFraming {
+    pub fn new() -> ZlibFraming {
+        ZlibFraming {
+            checksum: Adler32Checksum::new(),
+            dictionary_id: None,
+        }
+    }
+}
+
+impl Framing for ZlibFraming {
+    fn write_header<W: Write>(&mut self,
+                              writer: &mut W,
+                              options: &CompressionOptions)
+                              -> io::Result<()> {
+        let level = options.zlib_compat
+            .as_ref()
+            .map_or(CompressionLevel::Default, CompressionLevel::from_zlib_compat);
+        write_zlib_header_conf(writer,
+                               level,
+                               options.clamped_window_bits(),
+                               self.dictionary_id)
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.checksum.update_from_slice(data);
+    }
+
+    fn write_trailer<W: Write>(&mut self, writer: &mut W, _: u64) -> io::Result<()> {
+        writer.write_u32::<BigEndian>(self.checksum.current_hash())
+    }
+
+    fn set_dictionary(&mut self, dictionary: &[u8]) {
+        let mut checksum = Adler32Checksum::new();
+        checksum.update_from_slice(dictionary);
+        self.dictionary_id = Some(checksum.current_hash());
+    }
+
+    fn reset(&mut self) {
+        *self = ZlibFraming::new();
+    }
+}
+
+/// A gzip header, followed by the CRC-32 and the length of the uncompressed data.
+pub struct GzipFraming {
+    checksum: Crc32Checksum,
+}
+
+impl GzipFraming {
+    pub fn new() -> GzipFraming {
+        GzipFraming { checksum: Crc32Checksum::new() }
+    }
+}
+
+impl Framing for GzipFraming {
+    fn write_header<W: Write>(&mut self,
+                              writer: &mut W,
+                              options: &CompressionOptions)
+                              -> io::Result<()> {
+        match options.zlib_compat {
+            Some(ref compat) => writer.write_all(&get_zlib_compat_gzip_header(compat)),
+            None => write_gzip_header(writer),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.checksum.update_from_slice(data);
+    }
+
+    fn write_trailer<W: Write>(&mut self, writer: &mut W, input_length: u64) -> io::Result<()> {
+        write_gzip_trailer(writer, self.checksum.current_hash(), input_length)
+    }
+
+    fn check_dictionary(&self) -> io::Result<()> {
+        Err(Error::InvalidInput("The gzip format does not support preset dictionaries!").into())
+    }
+
+    fn set_dictionary(&mut self, _: &[u8]) {
+        unreachable!("gzip does not support preset dictionaries")
+    }
+
+    fn reset(&mut self) {
+        *self = GzipFraming::new();
+    }
+}
+
+/// The framing of a format selected at runtime.
+pub enum FormatFraming {
+    Raw(RawFraming),
+    Zlib(ZlibFraming),
+    Gzip(GzipFraming),
+}
+
+impl FormatFraming {
+    pub fn new(format: Format) -> FormatFraming {
+        match format {
+            Format::Raw => FormatFraming::Raw(RawFraming),
+            Format::Zlib => FormatFraming::Zlib(ZlibFraming::new()),
+            Format::Gzip => FormatFraming::Gzip(GzipFraming::new()),
+        }
+    }
+
+    pub fn format(&self) -> Format {
+        match *self {
+            FormatFraming::Raw(_) => Format::Raw,
+            FormatFraming::Zlib(_) => Format::Zlib,
+            FormatFraming::Gzip(_) => Format::Gzip,
+        }
+    }
+}
+
+impl Framing for FormatFraming {
+    fn write_header<W: Write>(&mut self,
+                              writer: &mut W,
+                              options: &CompressionOptions)
+                              -> io::Result<()> {
+        match *self {
+            FormatFraming::Raw(ref mut f) => f.write_header(writer, options),
+            FormatFraming::Zlib(ref mut f) => f.write_header(writer, options),
+            FormatFraming::Gzip(ref mut f) => f.write_header(writer, options),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match *self {
+            FormatFraming::Raw(ref mut f) => f.update(data),
+            FormatFraming::Zlib(ref mut f) => f.update(data),
+            FormatFraming::Gzip(ref mut f) => f.update(data),
+        }
+    }
+
+    fn write_trailer<W: Write>(&mut self, writer: &mut W, input_length: u64) -> io::Result<()> {
+        match *self {
+            FormatFraming::Raw(ref mut f) => f.write_trailer(writer, input_length),
+            FormatFraming::Zlib(ref mut f) => f.write_trailer(writer, input_length),
+            FormatFraming::Gzip(ref mut f) => f.write_trailer(writer, input_length),
+        }
+    }
+
+    fn check_dictionary(&self) -> io::Result<()> {
+        match *self {
+            FormatFraming::Raw(ref f) => f.check_dictionary(),
+            FormatFraming::Zlib(ref f) => f.check_dictionary(),
+            FormatFraming::Gzip(ref f) => f.check_dictionary(),
+        }
+    }
+
+    fn set_dictionary(&mut self, dictionary: &[u8]) {
+        match *self {
+            FormatFraming::Raw(ref mut f) => f.set_dictionary(dictionary),
+            FormatFraming::Zlib(ref mut f) => f.set_dictionary(dictionary),
+            FormatFraming::Gzip(ref mut f) => f.set_dictionary(dictionary),
+        }
+    }
+
+    fn reset(&mut self) {
+        match *self {
+            FormatFraming::Raw(ref mut f) => f.reset(),
+            FormatFraming::Zlib(ref mut f) => f.reset(),
+            FormatFraming::Gzip(ref mut f) => f.reset(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn window_bits_convention() {
+        for bits in 8..16 {
+            assert_eq!(Format::from_window_bits(bits), Some((Format::Zlib, bits as u8)));
+            assert_eq!(Format::from_window_bits(-bits), Some((Format::Raw, bits as u8)));
+            assert_eq!(Format::from_window_bits(bits + 16), Some((Format::Gzip, bits as u8)));
+        }
+        for &bits in &[0, 7, 16, 23, 32, -7, -16, i32::min_value(), i32::max_value()] {
+            assert_eq!(Format::from_window_bits(bits), None);
+        }
+    }
+
+    #[test]
+    fn gzip_dictionary() {
+        assert!(GzipFraming::new().check_dictionary().is_err());
+        assert!(FormatFraming::new(Format::Gzip).check_dictionary().is_err());
+        assert!(FormatFraming::new(Format::Zlib).check_dictionary().is_ok());
+    }
+}
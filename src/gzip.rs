@@ -0,0 +1,70 @@
+//! This module contains functionality for generating a [gzip](https://tools.ietf.org/html/rfc1952)
+//! header and trailer.
+//!
+//! Only the minimal header is supported, i.e no file name, comment or modification time is stored.
+
+use std::io::{Write, Result};
+
+use byteorder::{WriteBytesExt, LittleEndian};
+
+use compression_options::{ZlibCompat, ZlibStrategy};
+
+// Magic bytes identifying the gzip format.
+const ID1: u8 = 0x1f;
+const ID2: u8 = 0x8b;
+// CM = 8 means to use the DEFLATE compression method.
+const CM_DEFLATE: u8 = 8;
+// No optional fields are present.
+const FLG: u8 = 0;
+// A modification time of 0 means that no time stamp is available.
+const MTIME: [u8; 4] = [0; 4];
+// No extra flags.
+const XFL: u8 = 0;
+// Extra flags set by zlib when using the slowest and the fastest methods.
+const XFL_SLOWEST: u8 = 2;
+const XFL_FASTEST: u8 = 4;
+// 255 means the file system the data originated from is unknown.
+const OS_UNKNOWN: u8 = 255;
+// The value zlib uses when built for Unix.
+const OS_UNIX: u8 = 3;
+
+/// Get a gzip header with no optional fields.
+pub fn get_gzip_header() -> [u8; 10] {
+    [ID1, ID2, CM_DEFLATE, FLG, MTIME[0], MTIME[1], MTIME[2], MTIME[3], XFL, OS_UNKNOWN]
+}
+
+/// Get the gzip header zlib built for Unix writes when compressing with the given level and
+/// strategy.
+pub fn get_zlib_compat_gzip_header(compat: &ZlibCompat) -> [u8; 10] {
+    let xfl = match compat.strategy {
+        _ if compat.level >= 9 => XFL_SLOWEST,
+        ZlibStrategy::HuffmanOnly | ZlibStrategy::Rle | ZlibStrategy::Fixed => XFL_FASTEST,
+        _ if compat.level < 2 => XFL_FASTEST,
+        _ => XFL,
+    };
+    [ID1, ID2, CM_DEFLATE, FLG, MTIME[0], MTIME[1], MTIME[2], MTIME[3], xfl, OS_UNIX]
+}
+
+/// Write a gzip header with no optional fields to the writer.
+pub fn write_gzip_header<W: Write>(writer: &mut W) -> Result<()> {
+    writer.write_all(&get_gzip_header())
+}
+
+/// Write the gzip trailer, consisting of the CRC-32 of the uncompressed data, and the length of the
+/// uncompressed data modulo 2^32.
+pub fn write_gzip_trailer<W: Write>(writer: &mut W, crc: u32, input_length: u64) -> Result<()> {
+    writer.write_u32::<LittleEndian>(crc)?;
+    writer.write_u32::<LittleEndian>(input_length as u32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn trailer() {
+        let mut out = Vec::new();
+        write_gzip_trailer(&mut out, 0xCBF4_3926, (1 << 32) + 9).unwrap();
+        assert_eq!(out, [0x26, 0x39, 0xF4, 0xCB, 9, 0, 0, 0]);
+    }
+}
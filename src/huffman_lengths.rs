@@ -1,11 +1,12 @@
-use length_encode::EncodedLength;
-use length_encode::{encode_lengths, huffman_lengths_from_frequency, COPY_PREVIOUS,
+use length_encode::{EncodedLength, EncodedLengths, HuffmanAlgorithm};
+use length_encode::{encode_lengths_into, huffman_lengths_from_frequency_into, COPY_PREVIOUS,
                     REPEAT_ZERO_3_BITS, REPEAT_ZERO_7_BITS};
-use huffman_table::{create_codes, NUM_LITERALS_AND_LENGTHS, NUM_DISTANCE_CODES};
+use huffman_table::{create_codes_in_place, HuffmanCode, NUM_LITERALS_AND_LENGTHS,
+                    NUM_DISTANCE_CODES};
 
-use bitstream::{BitWriter, LsbWriter};
-use std::io::{Write, Result};
-use std::cmp;
+use bitstream::BitWriter;
+use core::cmp;
+use error::Error;
 
 // The minimum number of literal/length values
 pub const MIN_NUM_LITERALS_AND_LENGTHS: usize = 257;
@@ -15,13 +16,13 @@ pub const MIN_NUM_DISTANCES: usize = 1;
 // The output ordering of the lengths for the huffman codes used to encode the lengths
 // used to build the full huffman tree for length/literal codes.
 // http://www.gzip.org/zlib/rfc-deflate.html#dyn
-const HUFFMAN_LENGTH_ORDER: [u8; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14,
-                                        1, 15];
+pub const HUFFMAN_LENGTH_ORDER: [u8; 19] =
+    [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
 
 // Number of bits used for the values specifying the number of codes
-const HLIT_BITS: u8 = 5;
-const HDIST_BITS: u8 = 5;
-const HCLEN_BITS: u8 = 4;
+pub const HLIT_BITS: u8 = 5;
+pub const HDIST_BITS: u8 = 5;
+pub const HCLEN_BITS: u8 = 4;
 
 // The longest a huffman code describing another huffman length can be
 const MAX_HUFFMAN_CODE_LENGTH: usize = 7;
@@ -32,16 +33,23 @@ pub fn remove_trailing_zeroes<T: From<u8> + PartialEq>(input: &[T], min_length:
     &input[0..cmp::max(input.len() - num_zeroes, min_length)]
 }
 
-/// Write the specified huffman lengths to the bit writer
-pub fn write_huffman_lengths<W: Write>(literal_len_lengths: &[u8],
-                                       distance_lengths: &[u8],
-                                       writer: &mut LsbWriter<W>)
-                                       -> Result<()> {
-
-    assert!(literal_len_lengths.len() <= NUM_LITERALS_AND_LENGTHS);
-    assert!(literal_len_lengths.len() >= MIN_NUM_LITERALS_AND_LENGTHS);
-    assert!(distance_lengths.len() <= NUM_DISTANCE_CODES);
-    assert!(distance_lengths.len() >= MIN_NUM_DISTANCES);
+/// Write the specified huffman lengths to the bit writer, using `algorithm` to generate the
+/// code used for them.
+///
+/// This doesn't allocate, so it can also be used by the heap-free encoder.
+pub fn write_huffman_lengths<B: BitWriter>(literal_len_lengths: &[u8],
+                                           distance_lengths: &[u8],
+                                           algorithm: HuffmanAlgorithm,
+                                           writer: &mut B)
+                                           -> Result<(), Error> {
+
+    // Deflate64 uses two more distance codes.
+    if literal_len_lengths.len() > NUM_LITERALS_AND_LENGTHS ||
+       literal_len_lengths.len() < MIN_NUM_LITERALS_AND_LENGTHS ||
+       distance_lengths.len() > NUM_DISTANCE_CODES + 2 ||
+       distance_lengths.len() < MIN_NUM_DISTANCES {
+        return Err(Error::Internal("Invalid number of huffman lengths!"));
+    }
 
     // Number of length codes - 257
     let hlit = (literal_len_lengths.len() - MIN_NUM_LITERALS_AND_LENGTHS) as u16;
@@ -51,11 +59,17 @@ pub fn write_huffman_lengths<W: Write>(literal_len_lengths: &[u8],
     writer.write_bits(hdist, HDIST_BITS)?;
 
     // Encode length values
-    let (encoded, freqs) =
-        encode_lengths(literal_len_lengths.iter().chain(distance_lengths.iter()).cloned()).unwrap();
+    let mut encoded = EncodedLengths::new();
+    let freqs = encode_lengths_into(literal_len_lengths.iter().chain(distance_lengths).cloned(),
+                                    &mut encoded)
+        .ok_or(Error::Internal("No huffman lengths to encode!"))?;
 
     // Create huffman lengths for the length/distance code lengths
-    let huffman_table_lengths = huffman_lengths_from_frequency(&freqs, MAX_HUFFMAN_CODE_LENGTH);
+    let mut huffman_table_lengths = [0u8; 19];
+    huffman_lengths_from_frequency_into(&freqs,
+                                        MAX_HUFFMAN_CODE_LENGTH,
+                                        algorithm,
+                                        &mut huffman_table_lengths);
 
     let used_hclens = HUFFMAN_LENGTH_ORDER.len() -
                       HUFFMAN_LENGTH_ORDER.iter()
@@ -76,10 +90,27 @@ pub fn write_huffman_lengths<W: Write>(literal_len_lengths: &[u8],
     }
 
     // Generate codes for the main huffman table using the lengths we just wrote
-    let codes = create_codes(&huffman_table_lengths).expect("Failed to create huffman codes!");
+    let mut codes = [HuffmanCode::default(); 19];
+    create_codes_in_place(&mut codes, &huffman_table_lengths)?;
 
     // Write the actual huffman lengths
-    for v in encoded {
+    write_encoded_lengths(encoded.as_slice(), &codes, writer)
+}
+
+/// The value of the extra bits for a repeat count that has to be between `min` and `max`.
+fn repeat_count_bits(n: u8, min: u8, max: u8) -> Result<u16, Error> {
+    if n < min || n > max {
+        return Err(Error::Internal("Invalid huffman length repeat count!"));
+    }
+    Ok(u16::from(n - min))
+}
+
+/// Write run-length encoded huffman lengths using the provided codes for the length symbols.
+pub fn write_encoded_lengths<B: BitWriter>(encoded: &[EncodedLength],
+                                           codes: &[HuffmanCode],
+                                           writer: &mut B)
+                                           -> Result<(), Error> {
+    for &v in encoded {
         match v {
             EncodedLength::Length(n) => {
                 let code = codes[usize::from(n)];
@@ -88,22 +119,17 @@ pub fn write_huffman_lengths<W: Write>(literal_len_lengths: &[u8],
             EncodedLength::CopyPrevious(n) => {
                 let code = codes[COPY_PREVIOUS];
                 writer.write_bits(code.code, code.length)?;
-                assert!(n >= 3);
-                assert!(n <= 6);
-                writer.write_bits((n - 3).into(), 2)?;
+                writer.write_bits(repeat_count_bits(n, 3, 6)?, 2)?;
             }
             EncodedLength::RepeatZero3Bits(n) => {
                 let code = codes[REPEAT_ZERO_3_BITS];
                 writer.write_bits(code.code, code.length)?;
-                assert!(n >= 3);
-                writer.write_bits((n - 3).into(), 3)?;
+                writer.write_bits(repeat_count_bits(n, 3, 10)?, 3)?;
             }
             EncodedLength::RepeatZero7Bits(n) => {
                 let code = codes[REPEAT_ZERO_7_BITS];
                 writer.write_bits(code.code, code.length)?;
-                assert!(n >= 11);
-                assert!(n <= 138);
-                writer.write_bits((n - 11).into(), 7)?;
+                writer.write_bits(repeat_count_bits(n, 11, 138)?, 7)?;
             }
         }
     }
@@ -1,7 +1,11 @@
-use std::fmt;
+use core::fmt;
+#[cfg(test)]
+use alloc::vec::Vec;
 use bit_reverse::reverse_bits;
+#[cfg(feature = "std")]
 use std::io;
 use lzvalue::StoredLength;
+use error::Error;
 
 #[derive(Debug)]
 pub enum HuffmanError {
@@ -10,21 +14,24 @@ pub enum HuffmanError {
     _TooManyOfLength,
 }
 
-impl From<HuffmanError> for io::Error {
-    fn from(err: HuffmanError) -> io::Error {
-        // As these errors indicate bugs, rather than invalid input,
-        // we don't bother returning custom error types for them to the user.
-        match err {
-            HuffmanError::EmptyLengthTable => {
-                io::Error::new(io::ErrorKind::Other, "BUG! The length table was empty!")
-            }
+impl From<HuffmanError> for Error {
+    fn from(err: HuffmanError) -> Error {
+        // As these errors indicate bugs, rather than invalid input, they are internal errors.
+        Error::Internal(match err {
+            HuffmanError::EmptyLengthTable => "The length table was empty!",
             HuffmanError::CodeTooLong => {
-                io::Error::new(io::ErrorKind::Other,
-                               "BUG! One or more huffman codes had a length that exceeded the \
-                                maximum allowed length value.")
+                "One or more huffman codes had a length that exceeded the maximum allowed length \
+                 value."
             }
-            _ => unimplemented!(),
-        }
+            HuffmanError::_TooManyOfLength => "There were too many huffman codes of one length.",
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<HuffmanError> for io::Error {
+    fn from(err: HuffmanError) -> io::Error {
+        Error::from(err).into()
     }
 }
 
@@ -50,6 +57,16 @@ pub const MAX_MATCH: u16 = 258;
 pub const MIN_DISTANCE: u16 = 1;
 pub const MAX_DISTANCE: u16 = 32768;
 
+// The maximum length and distance of a match in Deflate64.
+// The format allows lengths up to 65538 and distances up to 65536, but we limit both to what
+// fits in 16 bits.
+pub const DEFLATE64_MAX_MATCH: u16 = 65535;
+pub const DEFLATE64_MAX_DISTANCE: u16 = 65535;
+
+// In Deflate64, the last length code is followed by 16 extra bits storing the length minus 3,
+// rather than always meaning a length of 258.
+const DEFLATE64_LENGTH_EXTRA_BITS: u8 = 16;
+
 
 // The position in the literal/length table of the end of block symbol
 pub const END_OF_BLOCK_POSITION: usize = 256;
@@ -72,7 +89,7 @@ pub static FIXED_CODE_LENGTHS: [u8; NUM_LITERALS_AND_LENGTHS + 2] =
 
 
 // The number of extra bits for the length codes
-static LENGTH_EXTRA_BITS_LENGTH: [u8; NUM_LENGTH_CODES] =
+pub static LENGTH_EXTRA_BITS_LENGTH: [u8; NUM_LENGTH_CODES] =
     [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
 
 // Table used to get a code from a length value (see get_distance_code_and_extra_bits)
@@ -90,12 +107,12 @@ static LENGTH_CODE: [u8; 256] =
      27, 27, 27, 27, 27, 27, 27, 27, 27, 27, 27, 27, 27, 27, 27, 27, 27, 27, 27, 27, 27, 27, 28];
 
 // Base values to calculate the value of the bits in length codes
-static BASE_LENGTH: [u8; NUM_LENGTH_CODES] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 10, 12, 14, 16, 20, 24,
-                                              28, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192,
-                                              224, 255]; // 258 - MIN_MATCh
+pub static BASE_LENGTH: [u8; NUM_LENGTH_CODES] =
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 10, 12, 14, 16, 20, 24, 28, 32, 40, 48, 56, 64, 80, 96, 112, 128,
+     160, 192, 224, 255]; // 258 - MIN_MATCh
 
 // What number in the literal/length table the lengths start at
-const LENGTH_BITS_START: u16 = 257;
+pub const LENGTH_BITS_START: u16 = 257;
 
 // Lengths for the distance codes in the pre-defined/fixed huffman table
 // (All distance codes are 5 bits long)
@@ -126,13 +143,14 @@ static DISTANCE_CODES: [u8; 512] =
      29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29];
 
 // Number of extra bits following the distance codes
-static DISTANCE_EXTRA_BITS: [u8; NUM_DISTANCE_CODES] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5,
-                                                        6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11,
-                                                        12, 12, 13, 13];
+// The two last codes are only used by Deflate64.
+pub static DISTANCE_EXTRA_BITS: [u8; NUM_DISTANCE_CODES + 2] =
+    [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+     13, 14, 14];
 
-static DISTANCE_BASE: [u16; NUM_DISTANCE_CODES] =
+pub static DISTANCE_BASE: [u16; NUM_DISTANCE_CODES + 2] =
     [0, 1, 2, 3, 4, 6, 8, 12, 16, 24, 32, 48, 64, 96, 128, 192, 256, 384, 512, 768, 1024, 1536,
-     2048, 3072, 4096, 6144, 8192, 12288, 16384, 24576];
+     2048, 3072, 4096, 6144, 8192, 12288, 16384, 24576, 32768, 49152];
 
 /// A struct representing the data needed to generate the bit codes for
 /// a given value and huffman table.
@@ -147,35 +165,55 @@ struct ExtraBits {
     pub value: u16,
 }
 
+/// Get the position in the length table of the code for a stored length.
+///
+/// Lengths above 258 (only allowed in Deflate64) all use the last code.
+fn length_code_number(stored_length: u16) -> u8 {
+    match LENGTH_CODE.get(usize::from(stored_length)) {
+        Some(&n) => n,
+        None => (NUM_LENGTH_CODES - 1) as u8,
+    }
+}
+
 /// Get the length code that corresponds to the length value
 pub fn get_length_code(length: u16) -> Option<usize> {
-    if let Some(c) = Some(usize::from(LENGTH_CODE[(length - MIN_MATCH) as usize])) {
-        Some(c + LENGTH_BITS_START as usize)
-    } else {
-        None
-    }
+    let n = length_code_number(length - MIN_MATCH);
+    Some(usize::from(n) + LENGTH_BITS_START as usize)
 }
 
 /// Get the code for the huffman table and the extra bits for the requested length.
-fn get_length_code_and_extra_bits(length: StoredLength) -> ExtraBits {
+///
+/// If `deflate64` is true, the last length code is encoded the way Deflate64 uses it.
+fn get_length_code_and_extra_bits(length: StoredLength, deflate64: bool) -> ExtraBits {
     // The minimun match length is 3, but length code table starts at 0,
     // so we need to subtract 3 to get the correct code.
-    let n = LENGTH_CODE[length.stored_length() as usize];
+    let n = length_code_number(length.stored_length());
+    let code_number = u16::from(n) + LENGTH_BITS_START;
+
+    if deflate64 && usize::from(n) == NUM_LENGTH_CODES - 1 {
+        return ExtraBits {
+            code_number,
+            num_bits: DEFLATE64_LENGTH_EXTRA_BITS,
+            value: length.stored_length(),
+        };
+    }
 
     // We can then get the base length from the base length table,
     // which we use to calculate the value of the extra bits.
     let base = BASE_LENGTH[n as usize];
     let num_bits = LENGTH_EXTRA_BITS_LENGTH[n as usize];
     ExtraBits {
-        code_number: u16::from(n) + LENGTH_BITS_START,
-        num_bits: num_bits,
-        value: (length.stored_length() - base).into(),
+        code_number,
+        num_bits,
+        value: length.stored_length() - u16::from(base),
     }
 
 }
 
 /// Get the spot in the huffman table for distances `distance` corresponds to
-/// Returns none if the distance is 0, or above 32768
+/// Returns none if the distance is 0
+///
+/// Distances above 32768 use the two extra codes of Deflate64.
 pub fn get_distance_code(distance: u16) -> Option<u8> {
     let distance = distance as usize;
     match distance {
@@ -185,11 +223,15 @@ pub fn get_distance_code(distance: u16) -> Option<u8> {
         // using the top bits to determine the code, rather than having a 32k long table of
         // distance codes.
         257...32768 => Some(DISTANCE_CODES[256 + ((distance - 1) >> 7)]),
+        32769..=49152 => Some(NUM_DISTANCE_CODES as u8),
+        49153..=65535 => Some(NUM_DISTANCE_CODES as u8 + 1),
         _ => None,
     }
 }
 
 
+/// Get the code and extra bits for a distance without the lookup tables in `HuffmanTable`.
+#[cfg(test)]
 fn get_distance_code_and_extra_bits(distance: u16) -> Option<ExtraBits> {
     if let Some(distance_code) = get_distance_code(distance) {
         let extra = DISTANCE_EXTRA_BITS[distance_code as usize];
@@ -235,6 +277,51 @@ impl HuffmanCode {
     }
 }
 
+/// A huffman code followed by its extra bits, in the order they are written, so they can be
+/// written in one go.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct CodeAndExtraBits {
+    pub bits: u32,
+    pub length: u8,
+}
+
+impl CodeAndExtraBits {
+    const fn empty() -> CodeAndExtraBits {
+        CodeAndExtraBits { bits: 0, length: 0 }
+    }
+
+    #[inline]
+    fn new(code: HuffmanCode, extra_bits: u16, num_extra_bits: u8) -> CodeAndExtraBits {
+        CodeAndExtraBits {
+            bits: u32::from(code.code) | u32::from(extra_bits) << code.length,
+            length: code.length + num_extra_bits,
+        }
+    }
+}
+
+/// The huffman code of a distance code, along with what is needed to work out the extra bits
+/// following it.
+#[derive(Copy, Clone)]
+struct DistanceSymbol {
+    code: HuffmanCode,
+    num_extra_bits: u8,
+    // The shortest distance using this code.
+    base: u16,
+}
+
+impl DistanceSymbol {
+    const fn empty() -> DistanceSymbol {
+        DistanceSymbol {
+            code: HuffmanCode {
+                code: 0,
+                length: 0,
+            },
+            num_extra_bits: 0,
+            base: 0,
+        }
+    }
+}
+
 #[cfg(test)]
 pub struct LengthAndDistanceBits {
     pub length_code: HuffmanCode,
@@ -245,9 +332,10 @@ pub struct LengthAndDistanceBits {
 
 /// Counts the number of values of each length.
 /// Returns a tuple containing the longest length value in the table, it's position,
-/// and a vector of lengths.
+/// and an array of the number of values of each length.
 /// Returns an error if `table` is empty, or if any of the lengths exceed 15.
-fn build_length_count_table(table: &[u8]) -> Result<(usize, usize, Vec<u16>), HuffmanError> {
+fn build_length_count_table(table: &[u8])
+                            -> Result<(usize, usize, [u16; MAX_CODE_LENGTH + 1]), HuffmanError> {
     // TODO: Validate the length table properly
     //
     let max_length = match table.iter().max() {
@@ -262,7 +350,7 @@ fn build_length_count_table(table: &[u8]) -> Result<(usize, usize, Vec<u16>), Hu
 
     let mut max_length_pos = 0;
 
-    let mut len_counts = vec![0u16; max_length + 1];
+    let mut len_counts = [0u16; MAX_CODE_LENGTH + 1];
     for (n, &length) in table.iter().enumerate() {
         // TODO: Make sure we don't have more of one length than we can make
         // codes for
@@ -274,13 +362,14 @@ fn build_length_count_table(table: &[u8]) -> Result<(usize, usize, Vec<u16>), Hu
     Ok((max_length, max_length_pos, len_counts))
 }
 
+#[cfg(test)]
 pub fn create_codes(length_table: &[u8]) -> Result<Vec<HuffmanCode>, HuffmanError> {
     let mut codes = vec![HuffmanCode::default(); length_table.len()];
     create_codes_in_place(codes.as_mut_slice(), length_table)?;
     Ok(codes)
 }
 
-/// Generats a vector of huffman codes given a table of bit lengths
+/// Generats huffman codes given a table of bit lengths, without allocating.
 /// Returns an error if any of the lengths are > 15
 pub fn create_codes_in_place(code_table: &mut [HuffmanCode],
                              length_table: &[u8])
@@ -290,11 +379,11 @@ pub fn create_codes_in_place(code_table: &mut [HuffmanCode],
     let (max_length, max_length_pos, lengths) = build_length_count_table(length_table)?;
 
     let mut code = 0u16;
-    let mut next_code = vec![0u16];
+    let mut next_code = [0u16; MAX_CODE_LENGTH + 1];
 
     for bits in 1..max_length + 1 {
         code = (code + lengths[bits - 1]) << 1;
-        next_code.push(code.into());
+        next_code[bits] = code;
     }
 
     for n in 0..max_length_pos + 1 {
@@ -313,15 +402,24 @@ pub fn create_codes_in_place(code_table: &mut [HuffmanCode],
 }
 
 /// A structure containing the tables of huffman codes for lengths, literals and distances
+///
+/// Like in libdeflate, lookup tables giving the code and extra bits of each length and the
+/// code of each distance are built whenever the codes change, so lengths and distances don't
+/// have to be mapped to codes one step at a time for every match.
 pub struct HuffmanTable {
     // Literal, end of block and length codes
     codes: [HuffmanCode; 288],
     // Distance codes
     distance_codes: [HuffmanCode; 32],
+    // The length code and extra bits of each stored length up to 255 (a length of 258), as used
+    // by standard deflate.
+    length_symbols: [CodeAndExtraBits; 256],
+    // The huffman code, number of extra bits and base distance of each distance code.
+    distance_symbols: [DistanceSymbol; 32],
 }
 
 impl HuffmanTable {
-    pub fn empty() -> HuffmanTable {
+    pub const fn empty() -> HuffmanTable {
         HuffmanTable {
             codes: [HuffmanCode {
                 code: 0,
@@ -331,6 +429,8 @@ impl HuffmanTable {
                 code: 0,
                 length: 0,
             }; 32],
+            length_symbols: [CodeAndExtraBits::empty(); 256],
+            distance_symbols: [DistanceSymbol::empty(); 32],
         }
     }
 
@@ -338,13 +438,8 @@ impl HuffmanTable {
     pub fn from_length_tables(literals_and_lengths: &[u8],
                               distances: &[u8])
                               -> Result<HuffmanTable, HuffmanError> {
-        let mut table = HuffmanTable {
-            codes: [HuffmanCode::default(); 288],
-            distance_codes: [HuffmanCode::default(); 32],
-        };
-
-        create_codes_in_place(table.codes.as_mut(), literals_and_lengths)?;
-        create_codes_in_place(table.distance_codes.as_mut(), distances)?;
+        let mut table = HuffmanTable::empty();
+        table.update_from_length_tables(literals_and_lengths, distances)?;
         Ok(table)
     }
 
@@ -356,7 +451,27 @@ impl HuffmanTable {
                                      distances: &[u8])
                                      -> Result<(), HuffmanError> {
         create_codes_in_place(self.codes.as_mut(), literals_and_lengths)?;
-        create_codes_in_place(self.distance_codes.as_mut(), distances)
+        create_codes_in_place(self.distance_codes.as_mut(), distances)?;
+        self.update_symbol_tables();
+        Ok(())
+    }
+
+    /// Build the lookup tables for lengths and distances from the current codes.
+    fn update_symbol_tables(&mut self) {
+        for (stored_length, symbol) in self.length_symbols.iter_mut().enumerate() {
+            let extra_bits = get_length_code_and_extra_bits(StoredLength::new(stored_length as u16),
+                                                            false);
+            *symbol = CodeAndExtraBits::new(self.codes[usize::from(extra_bits.code_number)],
+                                            extra_bits.value,
+                                            extra_bits.num_bits);
+        }
+        for (n, symbol) in self.distance_symbols.iter_mut().enumerate() {
+            *symbol = DistanceSymbol {
+                code: self.distance_codes[n],
+                num_extra_bits: DISTANCE_EXTRA_BITS[n],
+                base: DISTANCE_BASE[n] + 1,
+            };
+        }
     }
 
     /// Create a HuffmanTable using the fixed tables specified in the DEFLATE format specification.
@@ -381,39 +496,42 @@ impl HuffmanTable {
 
     /// Get the huffman code and extra bits for the specified length
     ///
-    /// returns None if the length is larger than MIN_MATCH or smaller than MAX_MATCH
-    pub fn get_length_huffman(&self, length: StoredLength) -> (HuffmanCode, HuffmanCode) {
-
-        let length_data = get_length_code_and_extra_bits(length);
-
-        let length_huffman_code = self.codes[length_data.code_number as usize];
-        (length_huffman_code,
-         HuffmanCode {
-             code: length_data.value,
-             length: length_data.num_bits,
-         })
+    /// If `deflate64` is true, the code is the one used by Deflate64, which differs for lengths
+    /// of 258 and above.
+    #[inline]
+    pub fn get_length_huffman(&self, length: StoredLength, deflate64: bool) -> CodeAndExtraBits {
+        let stored_length = length.stored_length();
+        if !deflate64 || stored_length < MAX_MATCH - MIN_MATCH {
+            if let Some(&symbol) = self.length_symbols.get(usize::from(stored_length)) {
+                return symbol;
+            }
+        }
+        let length_data = get_length_code_and_extra_bits(length, deflate64);
+        CodeAndExtraBits::new(self.codes[usize::from(length_data.code_number)],
+                              length_data.value,
+                              length_data.num_bits)
     }
 
     /// Get the huffman code and extra bits for the specified distance
     ///
-    /// Returns None if distance is 0 or above 32768
-    pub fn get_distance_huffman(&self, distance: u16) -> Option<((HuffmanCode, HuffmanCode))> {
-        if distance < MIN_DISTANCE || distance > MAX_DISTANCE {
+    /// Returns None if distance is 0 or above 32768, unless `deflate64` is true, in which case
+    /// distances up to 65535 are allowed.
+    #[inline]
+    pub fn get_distance_huffman(&self,
+                                distance: u16,
+                                deflate64: bool)
+                                -> Option<CodeAndExtraBits> {
+        let max_distance = if deflate64 {
+            DEFLATE64_MAX_DISTANCE
+        } else {
+            MAX_DISTANCE
+        };
+        if distance < MIN_DISTANCE || distance > max_distance {
             return None;
         }
 
-        let distance_data = match get_distance_code_and_extra_bits(distance) {
-            Some(t) => t,
-            None => return None,
-        };
-
-        let distance_huffman_code = self.distance_codes[distance_data.code_number as usize];
-
-        Some((distance_huffman_code,
-              HuffmanCode {
-                  code: distance_data.value,
-                  length: distance_data.num_bits,
-              }))
+        let symbol = self.distance_symbols[usize::from(get_distance_code(distance)?)];
+        Some(CodeAndExtraBits::new(symbol.code, distance - symbol.base, symbol.num_extra_bits))
     }
 
     #[cfg(test)]
@@ -422,13 +540,20 @@ impl HuffmanTable {
                                     distance: u16)
                                     -> Option<(LengthAndDistanceBits)> {
         assert!(length >= MIN_MATCH && length < MAX_DISTANCE);
-        let l_codes = self.get_length_huffman(StoredLength::from_actual_length(length));
-        let d_codes = self.get_distance_huffman(distance).unwrap();
+        let l_codes = get_length_code_and_extra_bits(StoredLength::from_actual_length(length),
+                                                     false);
+        let d_codes = get_distance_code_and_extra_bits(distance).unwrap();
         Some(LengthAndDistanceBits {
-            length_code: l_codes.0,
-            length_extra_bits: l_codes.1,
-            distance_code: d_codes.0,
-            distance_extra_bits: d_codes.1,
+            length_code: self.codes[usize::from(l_codes.code_number)],
+            length_extra_bits: HuffmanCode {
+                code: l_codes.value,
+                length: l_codes.num_bits,
+            },
+            distance_code: self.distance_codes[usize::from(d_codes.code_number)],
+            distance_extra_bits: HuffmanCode {
+                code: d_codes.value,
+                length: d_codes.num_bits,
+            },
         })
     }
 }
@@ -450,31 +575,51 @@ mod test {
 
     #[test]
     fn test_get_length_code() {
-        let extra_bits = get_length_code_and_extra_bits(l(4));
+        let extra_bits = get_length_code_and_extra_bits(l(4), false);
         assert_eq!(extra_bits.code_number, 258);
         assert_eq!(extra_bits.num_bits, 0);
         assert_eq!(extra_bits.value, 0);
 
-        let extra_bits = get_length_code_and_extra_bits(l(165));
+        let extra_bits = get_length_code_and_extra_bits(l(165), false);
         assert_eq!(extra_bits.code_number, 282);
         assert_eq!(extra_bits.num_bits, 5);
         assert_eq!(extra_bits.value, 2);
 
-        let extra_bits = get_length_code_and_extra_bits(l(257));
+        let extra_bits = get_length_code_and_extra_bits(l(257), false);
         assert_eq!(extra_bits.code_number, 284);
         assert_eq!(extra_bits.num_bits, 5);
         assert_eq!(extra_bits.value, 30);
 
-        let extra_bits = get_length_code_and_extra_bits(l(258));
+        let extra_bits = get_length_code_and_extra_bits(l(258), false);
         assert_eq!(extra_bits.code_number, 285);
         assert_eq!(extra_bits.num_bits, 0);
     }
 
+    #[test]
+    fn deflate64_length_code() {
+        let extra_bits = get_length_code_and_extra_bits(l(165), true);
+        assert_eq!(extra_bits.code_number, 282);
+        assert_eq!(extra_bits.num_bits, 5);
+
+        let extra_bits = get_length_code_and_extra_bits(l(258), true);
+        assert_eq!(extra_bits.code_number, 285);
+        assert_eq!(extra_bits.num_bits, 16);
+        assert_eq!(extra_bits.value, 255);
+
+        let extra_bits = get_length_code_and_extra_bits(l(DEFLATE64_MAX_MATCH), true);
+        assert_eq!(extra_bits.code_number, 285);
+        assert_eq!(extra_bits.num_bits, 16);
+        assert_eq!(extra_bits.value, DEFLATE64_MAX_MATCH - MIN_MATCH);
+        assert_eq!(get_length_code(1000), Some(285));
+    }
+
     #[test]
     fn test_distance_code() {
         assert_eq!(get_distance_code(1).unwrap(), 0);
         assert_eq!(get_distance_code(0), None);
-        assert_eq!(get_distance_code(50000), None);
+        assert_eq!(get_distance_code(32768).unwrap(), 29);
+        assert_eq!(get_distance_code(32769).unwrap(), 30);
+        assert_eq!(get_distance_code(50000).unwrap(), 31);
         assert_eq!(get_distance_code(6146).unwrap(), 25);
         assert_eq!(get_distance_code(256).unwrap(), 15);
     }
@@ -488,6 +633,10 @@ mod test {
         let extra = get_distance_code_and_extra_bits(256).unwrap();
         assert_eq!(extra.code_number, 15);
         assert_eq!(extra.num_bits, 6);
+        let extra = get_distance_code_and_extra_bits(65535).unwrap();
+        assert_eq!(extra.code_number, 31);
+        assert_eq!(extra.num_bits, 14);
+        assert_eq!(extra.value, 16382);
     }
 
     #[test]
@@ -537,4 +686,44 @@ mod test {
         assert_eq!(ld.distance_extra_bits.length, 1);
         assert_eq!(ld.distance_extra_bits.code, 0);
     }
+
+    #[test]
+    fn symbol_tables() {
+        // Some codes of every length, like in a dynamic block.
+        let lengths: Vec<u8> = (0..288).map(|n| (n % 15 + 1) as u8).collect();
+        let distance_lengths: Vec<u8> = (0..32).map(|n| (n % 5 + 3) as u8).collect();
+        for table in &[HuffmanTable::fixed_table(),
+                       HuffmanTable::from_length_tables(&lengths, &distance_lengths).unwrap()] {
+            let check = |code: HuffmanCode, extra: ExtraBits, symbol: CodeAndExtraBits| {
+                let bits = u32::from(code.code) | u32::from(extra.value) << code.length;
+                assert_eq!(symbol,
+                           CodeAndExtraBits {
+                               bits,
+                               length: code.length + extra.num_bits,
+                           });
+            };
+            for &deflate64 in &[false, true] {
+                let max_length = if deflate64 { DEFLATE64_MAX_MATCH } else { MAX_MATCH };
+                for length in MIN_MATCH..=max_length {
+                    let extra = get_length_code_and_extra_bits(l(length), deflate64);
+                    check(table.codes[usize::from(extra.code_number)],
+                          extra,
+                          table.get_length_huffman(l(length), deflate64));
+                }
+                let max_distance = if deflate64 {
+                    DEFLATE64_MAX_DISTANCE
+                } else {
+                    MAX_DISTANCE
+                };
+                for distance in MIN_DISTANCE..=max_distance {
+                    let extra = get_distance_code_and_extra_bits(distance).unwrap();
+                    check(table.distance_codes[usize::from(extra.code_number)],
+                          extra,
+                          table.get_distance_huffman(distance, deflate64).unwrap());
+                }
+                assert_eq!(table.get_distance_huffman(0, deflate64), None);
+            }
+            assert_eq!(table.get_distance_huffman(MAX_DISTANCE + 1, false), None);
+        }
+    }
 }
@@ -1,13 +1,22 @@
-use std::cmp;
+use core::cmp;
+use alloc::boxed::Box;
 
 use chained_hash_table::WINDOW_SIZE;
 use huffman_table;
 
 const MAX_MATCH: usize = huffman_table::MAX_MATCH as usize;
+#[cfg(test)]
 pub const BUFFER_SIZE: usize = (WINDOW_SIZE * 2) + MAX_MATCH;
 
+/// The buffer holding the input data.
+///
+/// It is laid out as a window of earlier data that matches can refer back to, followed by one or
+/// more windows of data to compress, and a lookahead of `MAX_MATCH` bytes so matches at the end
+/// of the last window can be found. When all the windows have been compressed, the last window
+/// and the lookahead are moved down to the start of the buffer to make room for more data.
 pub struct InputBuffer {
-    buffer: Box<[u8; BUFFER_SIZE]>,
+    buffer: Box<[u8]>,
+    window_size: usize,
     current_end: usize,
 }
 
@@ -20,12 +29,51 @@ impl InputBuffer {
     }
 
     pub fn empty() -> InputBuffer {
+        InputBuffer::with_window_size(WINDOW_SIZE)
+    }
+
+    /// Create an empty buffer laid out for windows of `window_size` bytes.
+    ///
+    /// Smaller windows than the default only limit how far back matches can refer to, so the
+    /// buffer is never laid out for windows smaller than that.
+    pub fn with_window_size(window_size: usize) -> InputBuffer {
+        InputBuffer::with_windows(window_size, 1)
+    }
+
+    /// Create an empty buffer laid out for windows of `window_size` bytes, with room for
+    /// `windows` windows of data after the first one.
+    ///
+    /// The data and the positions referring to it have to be moved down every `windows`
+    /// windows of input, so more windows make that happen less often.
+    pub fn with_windows(window_size: usize, windows: usize) -> InputBuffer {
+        let window_size = cmp::max(window_size, WINDOW_SIZE);
         InputBuffer {
-            buffer: Box::new([0; BUFFER_SIZE]),
+            buffer: vec![0; (window_size * (windows + 1)) + MAX_MATCH].into_boxed_slice(),
+            window_size,
             current_end: 0,
         }
     }
 
+    /// The size of the windows the buffer is laid out for.
+    pub fn window_size(&self) -> usize {
+        self.window_size
+    }
+
+    /// The end of the last window in the buffer, which is where the lookahead starts.
+    pub fn windows_end(&self) -> usize {
+        self.buffer.len() - MAX_MATCH
+    }
+
+    /// The number of bytes the data is moved down by in `slide`.
+    pub fn slide_length(&self) -> usize {
+        self.windows_end() - self.window_size
+    }
+
+    /// Whether the buffer is full, i.e contains all the windows and the lookahead.
+    pub fn is_full(&self) -> bool {
+        self.current_end == self.buffer.len()
+    }
+
     /// Add data to the buffer.
     ///
     /// Returns a slice of the data that was not added (including the lookahead if any).
@@ -37,7 +85,7 @@ impl InputBuffer {
                 remaining_buffer.copy_from_slice(&data[..len]);
                 len
             };
-            self.current_end = BUFFER_SIZE;
+            self.current_end = self.buffer.len();
             Some(&data[len..])
         } else {
             self.buffer[self.current_end..self.current_end + data.len()].copy_from_slice(data);
@@ -52,56 +100,48 @@ impl InputBuffer {
 
     /// Slide the input window and add new data.
     ///
+    /// The last window and the lookahead are moved down by `slide_length` bytes, to the start of
+    /// the buffer.
+    ///
     /// Returns a slice containing the data that did not fit, or None if all data was consumed.
     pub fn slide<'a>(&mut self, data: &'a [u8]) -> Option<&'a [u8]> {
         // This should only be used when the buffer is full
-        assert!(self.current_end == BUFFER_SIZE);
-        // Split into lower window and upper window + lookahead
-        let (lower, upper) = self.buffer[..].split_at_mut(WINDOW_SIZE);
-        // Copy the upper window to the lower window
-        lower.copy_from_slice(&upper[..WINDOW_SIZE]);
-        {
-            // Copy the lookahead to the start of the upper window
-            let (upper_2, lookahead) = upper.split_at_mut(WINDOW_SIZE);
-            upper_2[..MAX_MATCH].copy_from_slice(lookahead);
-        }
-
-        // Length of the upper window minus the lookahead bytes
-        let upper_len = upper.len() - MAX_MATCH;
-        let end = cmp::min(data.len(), upper_len);
-        upper[MAX_MATCH..MAX_MATCH + end].copy_from_slice(&data[..end]);
-        self.current_end = lower.len() + MAX_MATCH + end;
-
-        if data.len() > upper_len {
-            // Return a slice of the data that was not added
-            Some(&data[end..])
-        } else {
-            None
-        }
+        debug_assert!(self.is_full());
+        let slide_length = self.slide_length();
+        // Copy the last window and the lookahead to the start of the buffer.
+        self.buffer.copy_within(slide_length.., 0);
+        self.current_end = self.buffer.len() - slide_length;
+        self.add_data(data)
     }
 
 
     /// Slide the buffer such that the current end of the buffer (including lookahead) is moved to
-    /// the position of WINDOW_SIZE, and return the number of bytes slid.
+    /// the position of the window size, and return the number of bytes slid.
     pub fn move_down(&mut self) -> usize {
-        assert!(self.current_end >= WINDOW_SIZE);
-        // Avoid doing anything if the end is already at WINDOW_SIZE.
-        if self.current_end == WINDOW_SIZE {
+        let window_size = self.window_size;
+        debug_assert!(self.current_end >= window_size);
+        // Avoid doing anything if the end is already at the window size.
+        if self.current_end == window_size {
             return 0;
         }
         // We use a naive sliding implementation for now. This may be suboptimal due to using
         // indexing.
-        for i in 0..WINDOW_SIZE {
-            self.buffer[i] = self.buffer[self.current_end - WINDOW_SIZE + i];
+        for i in 0..window_size {
+            self.buffer[i] = self.buffer[self.current_end - window_size + i];
         }
-        let ret = self.current_end - WINDOW_SIZE;
-        self.current_end = WINDOW_SIZE;
+        let ret = self.current_end - window_size;
+        self.current_end = window_size;
         ret
     }
 
     pub fn get_buffer(&mut self) -> &mut [u8] {
         &mut self.buffer[..self.current_end]
     }
+
+    /// Discard all the data in the buffer.
+    pub fn clear(&mut self) {
+        self.current_end = 0;
+    }
 }
 
 #[cfg(test)]
@@ -160,4 +200,30 @@ mod test {
         buf.move_down();
         assert_eq!(*buf.get_buffer().last().unwrap(), 5);
     }
+
+    #[test]
+    fn more_windows() {
+        let mut buf = InputBuffer::with_windows(WINDOW_SIZE, 4);
+        assert_eq!(buf.windows_end(), WINDOW_SIZE * 5);
+        assert_eq!(buf.slide_length(), WINDOW_SIZE * 4);
+        let data: Vec<u8> = (0..(WINDOW_SIZE * 5) + MAX_MATCH + 10).map(|n| n as u8).collect();
+        let rem = buf.add_data(&data).unwrap();
+        assert!(buf.is_full());
+        assert!(buf.slide(rem).is_none());
+        assert!(buf.get_buffer() == &data[WINDOW_SIZE * 4..]);
+    }
+
+    #[test]
+    fn larger_window() {
+        let window_size = WINDOW_SIZE * 2;
+        let mut buf = InputBuffer::with_window_size(window_size);
+        assert_eq!(buf.window_size(), window_size);
+        let data: Vec<u8> = (0..(window_size * 2) + MAX_MATCH).map(|n| n as u8).collect();
+        assert!(buf.add_data(&data).is_none());
+        assert!(buf.is_full());
+        assert!(buf.slide(&[]).is_none());
+        assert!(buf.get_buffer() == &data[window_size..]);
+        // Smaller windows use the default layout.
+        assert_eq!(InputBuffer::with_window_size(1024).window_size(), WINDOW_SIZE);
+    }
 }
@@ -0,0 +1,561 @@
+//! Functionality for inspecting the block structure of DEFLATE and zlib streams.
+//!
+//! This is meant as a debugging aid when tuning `CompressionOptions` or tracking down
+//! regressions in output size. The stream is parsed (but the data is not kept), and for each block
+//! the type, position, huffman table header and a breakdown of where the output bits went is
+//! reported.
+//!
+//! # Examples
+//!
+//! ```
+//! use deflate::deflate_bytes;
+//! use deflate::inspect::inspect_deflate;
+//!
+//! let compressed = deflate_bytes(b"Some data, some more data, and then some data.").unwrap();
+//! let blocks = inspect_deflate(&compressed).unwrap();
+//! for block in &blocks {
+//!     println!("{:?} block at bit {}: {} literals, {} matches",
+//!              block.block_type,
+//!              block.bit_offset,
+//!              block.literals,
+//!              block.matches);
+//! }
+//! assert!(blocks.last().unwrap().final_block);
+//! ```
+
+use std::error;
+use std::fmt;
+
+use huffman_table::{FIXED_CODE_LENGTHS, FIXED_CODE_LENGTHS_DISTANCE, LENGTH_EXTRA_BITS_LENGTH,
+                    BASE_LENGTH, DISTANCE_EXTRA_BITS, DISTANCE_BASE, LENGTH_BITS_START,
+                    NUM_LENGTH_CODES, NUM_DISTANCE_CODES, MAX_CODE_LENGTH, MIN_MATCH,
+                    END_OF_BLOCK_POSITION};
+use huffman_lengths::{HUFFMAN_LENGTH_ORDER, HLIT_BITS, HDIST_BITS, HCLEN_BITS,
+                      MIN_NUM_LITERALS_AND_LENGTHS, MIN_NUM_DISTANCES};
+use length_encode::{COPY_PREVIOUS, REPEAT_ZERO_3_BITS, REPEAT_ZERO_7_BITS};
+
+/// An error that occured while parsing a stream.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum InspectError {
+    /// The stream ended before the final block was finished.
+    UnexpectedEof,
+    /// The zlib header was invalid, or specified a compression method other than DEFLATE.
+    InvalidZlibHeader,
+    /// A block header used the reserved block type `0b11`.
+    InvalidBlockType,
+    /// The length of a stored block did not match its ones complement.
+    InvalidStoredLength,
+    /// The code lengths of a dynamic block did not describe a valid huffman code.
+    InvalidCodeLengths,
+    /// A symbol that does not correspond to any code was encountered.
+    InvalidSymbol,
+    /// A match referred back to before the start of the stream.
+    InvalidDistance,
+}
+
+impl fmt::Display for InspectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            InspectError::UnexpectedEof => "Unexpected end of stream",
+            InspectError::InvalidZlibHeader => "Invalid zlib header",
+            InspectError::InvalidBlockType => "Invalid block type",
+            InspectError::InvalidStoredLength => "Invalid stored block length",
+            InspectError::InvalidCodeLengths => "Invalid huffman code lengths",
+            InspectError::InvalidSymbol => "Invalid huffman symbol",
+            InspectError::InvalidDistance => "Distance too far back",
+        })
+    }
+}
+
+impl error::Error for InspectError {}
+
+/// The type of a DEFLATE block.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BlockType {
+    /// A block of uncompressed data.
+    Stored,
+    /// A block compressed using the huffman codes defined in the DEFLATE specification.
+    Fixed,
+    /// A block compressed using huffman codes described in the block header.
+    Dynamic,
+}
+
+/// The huffman table description found at the start of a dynamic block.
+///
+/// This corresponds to the values output by the encoder in `write_huffman_lengths`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DynamicHeader {
+    /// Number of literal/length codes - 257.
+    pub hlit: u16,
+    /// Number of distance codes - 1.
+    pub hdist: u16,
+    /// Number of code length codes - 4.
+    pub hclen: u16,
+    /// The lengths of the codes used to encode the code lengths, indexed by symbol.
+    pub code_length_lengths: [u8; 19],
+    /// The code lengths of the literal/length alphabet.
+    pub literal_length_lengths: Vec<u8>,
+    /// The code lengths of the distance alphabet.
+    pub distance_lengths: Vec<u8>,
+}
+
+/// Information about a single block in a DEFLATE stream.
+///
+/// The `*_bits` fields describe how many bits in the output were spent on each part of the block,
+/// and add up to `total_bits`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BlockInfo {
+    /// The type of the block.
+    pub block_type: BlockType,
+    /// Whether this block is marked as the final block in the stream.
+    pub final_block: bool,
+    /// The offset of the start of the block in bits from the start of the inspected data.
+    pub bit_offset: u64,
+    /// The huffman table description, if this is a dynamic block.
+    pub header: Option<DynamicHeader>,
+    /// Number of literals in the block (or bytes of data for stored blocks).
+    pub literals: usize,
+    /// Number of length/distance pairs in the block.
+    pub matches: usize,
+    /// Number of uncompressed bytes the block decodes to.
+    pub decoded_bytes: usize,
+    /// Bits used by the block type, the huffman table description in dynamic blocks, and the
+    /// padding and length fields in stored blocks.
+    pub header_bits: u64,
+    /// Bits used by literal codes (or raw bytes in stored blocks).
+    pub literal_bits: u64,
+    /// Bits used by length codes and their extra bits.
+    pub length_bits: u64,
+    /// Bits used by distance codes and their extra bits.
+    pub distance_bits: u64,
+    /// Bits used by the end of block code.
+    pub end_of_block_bits: u64,
+    /// Total size of the block in bits.
+    pub total_bits: u64,
+}
+
+impl BlockInfo {
+    fn new(block_type: BlockType, final_block: bool, bit_offset: u64) -> BlockInfo {
+        BlockInfo {
+            block_type,
+            final_block,
+            bit_offset,
+            header: None,
+            literals: 0,
+            matches: 0,
+            decoded_bytes: 0,
+            header_bits: 0,
+            literal_bits: 0,
+            length_bits: 0,
+            distance_bits: 0,
+            end_of_block_bits: 0,
+            total_bits: 0,
+        }
+    }
+}
+
+/// A simple LSB-first bit reader over a slice that keeps track of the current bit position.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: u64,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8], start_byte: usize) -> BitReader<'a> {
+        BitReader {
+            data,
+            position: start_byte as u64 * 8,
+        }
+    }
+
+    fn read_bit(&mut self) -> Result<u16, InspectError> {
+        let byte = match self.data.get((self.position >> 3) as usize) {
+            Some(&b) => b,
+            None => return Err(InspectError::UnexpectedEof),
+        };
+        let bit = (byte >> (self.position & 7)) & 1;
+        self.position += 1;
+        Ok(u16::from(bit))
+    }
+
+    fn read_bits(&mut self, n: u8) -> Result<u16, InspectError> {
+        debug_assert!(n <= 16);
+        let mut value = 0;
+        for i in 0..n {
+            value |= self.read_bit()? << i;
+        }
+        Ok(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.position = (self.position + 7) & !7;
+    }
+
+    fn byte_position(&self) -> usize {
+        (self.position >> 3) as usize
+    }
+}
+
+/// A canonical huffman decoder built from a table of code lengths.
+struct Decoder {
+    // Number of codes of each length.
+    counts: [u16; MAX_CODE_LENGTH + 1],
+    // Symbols ordered by code.
+    symbols: Vec<u16>,
+}
+
+impl Decoder {
+    /// Create a decoder from a table of code lengths.
+    ///
+    /// Incomplete codes are accepted, as the specification allows a single distance code
+    /// to be defined.
+    fn new(lengths: &[u8]) -> Result<Decoder, InspectError> {
+        let mut counts = [0u16; MAX_CODE_LENGTH + 1];
+        for &l in lengths {
+            if usize::from(l) > MAX_CODE_LENGTH {
+                return Err(InspectError::InvalidCodeLengths);
+            }
+            counts[usize::from(l)] += 1;
+        }
+        counts[0] = 0;
+
+        // Check that the code is not over-subscribed.
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return Err(InspectError::InvalidCodeLengths);
+            }
+        }
+
+        let mut offsets = [0u16; MAX_CODE_LENGTH + 2];
+        for len in 1..MAX_CODE_LENGTH + 1 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; usize::from(offsets[MAX_CODE_LENGTH + 1])];
+        for (symbol, &l) in lengths.iter().enumerate() {
+            if l != 0 {
+                symbols[usize::from(offsets[usize::from(l)])] = symbol as u16;
+                offsets[usize::from(l)] += 1;
+            }
+        }
+
+        Ok(Decoder {
+            counts,
+            symbols,
+        })
+    }
+
+    /// Read one symbol from the reader.
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, InspectError> {
+        // The number of the code currently read, the first code of the current length, and the
+        // index of the first symbol of the current length.
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for &count in &self.counts[1..] {
+            code |= i32::from(reader.read_bit()?);
+            let count = i32::from(count);
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(InspectError::InvalidSymbol)
+    }
+}
+
+/// Read the huffman table description of a dynamic block.
+fn read_dynamic_header(reader: &mut BitReader)
+                       -> Result<(DynamicHeader, Decoder, Decoder), InspectError> {
+    let hlit = reader.read_bits(HLIT_BITS)?;
+    let hdist = reader.read_bits(HDIST_BITS)?;
+    let hclen = reader.read_bits(HCLEN_BITS)?;
+
+    let num_lengths = usize::from(hlit) + MIN_NUM_LITERALS_AND_LENGTHS;
+    let num_distances = usize::from(hdist) + MIN_NUM_DISTANCES;
+    if num_lengths > LENGTH_BITS_START as usize + NUM_LENGTH_CODES ||
+       num_distances > NUM_DISTANCE_CODES {
+        return Err(InspectError::InvalidCodeLengths);
+    }
+
+    let mut code_length_lengths = [0u8; 19];
+    for &n in &HUFFMAN_LENGTH_ORDER[..usize::from(hclen) + 4] {
+        code_length_lengths[usize::from(n)] = reader.read_bits(3)? as u8;
+    }
+    let code_length_decoder = Decoder::new(&code_length_lengths)?;
+
+    let mut lengths = Vec::with_capacity(num_lengths + num_distances);
+    while lengths.len() < num_lengths + num_distances {
+        let symbol = usize::from(code_length_decoder.decode(reader)?);
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            COPY_PREVIOUS => {
+                let prev = match lengths.last() {
+                    Some(&l) => l,
+                    None => return Err(InspectError::InvalidCodeLengths),
+                };
+                (prev, reader.read_bits(2)? + 3)
+            }
+            REPEAT_ZERO_3_BITS => (0, reader.read_bits(3)? + 3),
+            REPEAT_ZERO_7_BITS => (0, reader.read_bits(7)? + 11),
+            _ => return Err(InspectError::InvalidSymbol),
+        };
+        if lengths.len() + usize::from(repeat) > num_lengths + num_distances {
+            return Err(InspectError::InvalidCodeLengths);
+        }
+        for _ in 0..repeat {
+            lengths.push(value);
+        }
+    }
+
+    let distance_lengths = lengths.split_off(num_lengths);
+    if lengths[END_OF_BLOCK_POSITION] == 0 {
+        // A block without an end of block code can't be terminated.
+        return Err(InspectError::InvalidCodeLengths);
+    }
+
+    let literal_decoder = Decoder::new(&lengths)?;
+    let distance_decoder = Decoder::new(&distance_lengths)?;
+
+    Ok((DynamicHeader {
+            hlit,
+            hdist,
+            hclen,
+            code_length_lengths,
+            literal_length_lengths: lengths,
+            distance_lengths,
+        },
+        literal_decoder,
+        distance_decoder))
+}
+
+/// Read the huffman-coded data of a block until the end of block code, updating the counts and
+/// bit totals in `info`.
+///
+/// `total_decoded` is the number of bytes decoded in the stream before this block, and is used to
+/// check that distances are valid.
+fn read_compressed_data(reader: &mut BitReader,
+                        literal_decoder: &Decoder,
+                        distance_decoder: &Decoder,
+                        total_decoded: usize,
+                        info: &mut BlockInfo)
+                        -> Result<(), InspectError> {
+    loop {
+        let start = reader.position;
+        let symbol = literal_decoder.decode(reader)?;
+        if symbol < END_OF_BLOCK_POSITION as u16 {
+            info.literals += 1;
+            info.decoded_bytes += 1;
+            info.literal_bits += reader.position - start;
+        } else if symbol == END_OF_BLOCK_POSITION as u16 {
+            info.end_of_block_bits += reader.position - start;
+            return Ok(());
+        } else {
+            let code = usize::from(symbol - LENGTH_BITS_START);
+            if code >= NUM_LENGTH_CODES {
+                return Err(InspectError::InvalidSymbol);
+            }
+            let extra = reader.read_bits(LENGTH_EXTRA_BITS_LENGTH[code])?;
+            let length = usize::from(BASE_LENGTH[code]) + MIN_MATCH as usize + usize::from(extra);
+            info.length_bits += reader.position - start;
+
+            let start = reader.position;
+            let code = usize::from(distance_decoder.decode(reader)?);
+            if code >= NUM_DISTANCE_CODES {
+                return Err(InspectError::InvalidSymbol);
+            }
+            let extra = reader.read_bits(DISTANCE_EXTRA_BITS[code])?;
+            let distance = usize::from(DISTANCE_BASE[code]) + 1 + usize::from(extra);
+            info.distance_bits += reader.position - start;
+
+            if distance > total_decoded + info.decoded_bytes {
+                return Err(InspectError::InvalidDistance);
+            }
+
+            info.matches += 1;
+            info.decoded_bytes += length;
+        }
+    }
+}
+
+/// Parse the DEFLATE blocks in `data` starting at the byte offset `start`.
+fn inspect_blocks(data: &[u8], start: usize) -> Result<Vec<BlockInfo>, InspectError> {
+    let mut reader = BitReader::new(data, start);
+    let mut blocks = Vec::new();
+    let mut total_decoded = 0;
+
+    let fixed_literal_decoder = Decoder::new(&FIXED_CODE_LENGTHS)?;
+    let fixed_distance_decoder = Decoder::new(&FIXED_CODE_LENGTHS_DISTANCE)?;
+
+    loop {
+        let bit_offset = reader.position;
+        let final_block = reader.read_bits(1)? == 1;
+        let block_type = match reader.read_bits(2)? {
+            0b00 => BlockType::Stored,
+            0b01 => BlockType::Fixed,
+            0b10 => BlockType::Dynamic,
+            _ => return Err(InspectError::InvalidBlockType),
+        };
+        let mut info = BlockInfo::new(block_type, final_block, bit_offset);
+
+        match block_type {
+            BlockType::Stored => {
+                reader.align_to_byte();
+                let length = reader.read_bits(16)?;
+                let n_length = reader.read_bits(16)?;
+                if length != !n_length {
+                    return Err(InspectError::InvalidStoredLength);
+                }
+                info.header_bits = reader.position - bit_offset;
+                let length = usize::from(length);
+                if reader.byte_position() + length > data.len() {
+                    return Err(InspectError::UnexpectedEof);
+                }
+                reader.position += length as u64 * 8;
+                info.literals = length;
+                info.decoded_bytes = length;
+                info.literal_bits = length as u64 * 8;
+            }
+            BlockType::Fixed => {
+                info.header_bits = reader.position - bit_offset;
+                read_compressed_data(&mut reader,
+                                     &fixed_literal_decoder,
+                                     &fixed_distance_decoder,
+                                     total_decoded,
+                                     &mut info)?;
+            }
+            BlockType::Dynamic => {
+                let (header, literal_decoder, distance_decoder) =
+                    read_dynamic_header(&mut reader)?;
+                info.header = Some(header);
+                info.header_bits = reader.position - bit_offset;
+                read_compressed_data(&mut reader,
+                                     &literal_decoder,
+                                     &distance_decoder,
+                                     total_decoded,
+                                     &mut info)?;
+            }
+        }
+
+        info.total_bits = reader.position - bit_offset;
+        total_decoded += info.decoded_bytes;
+        blocks.push(info);
+
+        if final_block {
+            return Ok(blocks);
+        }
+    }
+}
+
+/// Parse a raw DEFLATE stream, returning information about each block in the stream.
+///
+/// Bit offsets are counted from the start of `data`. Any data after the final block is ignored.
+pub fn inspect_deflate(data: &[u8]) -> Result<Vec<BlockInfo>, InspectError> {
+    inspect_blocks(data, 0)
+}
+
+/// Parse a zlib stream, returning information about each DEFLATE block in the stream.
+///
+/// Bit offsets are counted from the start of `data`, so the first block will start after the
+/// zlib header. The trailing checksum is not verified.
+pub fn inspect_zlib(data: &[u8]) -> Result<Vec<BlockInfo>, InspectError> {
+    if data.len() < 2 {
+        return Err(InspectError::UnexpectedEof);
+    }
+    let cmf = data[0];
+    let flg = data[1];
+    if cmf & 0x0F != 8 || (usize::from(cmf) * 256 + usize::from(flg)) % 31 != 0 {
+        return Err(InspectError::InvalidZlibHeader);
+    }
+    // Skip the dictionary id if there is one.
+    let start = if flg & 0b0010_0000 != 0 { 6 } else { 2 };
+    inspect_blocks(data, start)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use compress::compress_data_fixed;
+    use stored_block::compress_data_stored;
+    use test_utils::get_test_data;
+    use {deflate_bytes, deflate_bytes_zlib};
+
+    fn check_totals(blocks: &[BlockInfo], input_len: usize) {
+        let decoded: usize = blocks.iter().map(|b| b.decoded_bytes).sum();
+        assert_eq!(decoded, input_len);
+        assert!(blocks.last().unwrap().final_block);
+        assert_eq!(blocks.iter().filter(|b| b.final_block).count(), 1);
+        for (block, next) in blocks.iter().zip(blocks.iter().skip(1)) {
+            assert_eq!(block.bit_offset + block.total_bits, next.bit_offset);
+        }
+        for b in blocks {
+            assert_eq!(b.header_bits + b.literal_bits + b.length_bits + b.distance_bits +
+                       b.end_of_block_bits,
+                       b.total_bits);
+        }
+    }
+
+    #[test]
+    fn inspect_fixed() {
+        let compressed = compress_data_fixed(b"Deflate late");
+        let blocks = inspect_deflate(&compressed).unwrap();
+        assert_eq!(blocks.len(), 1);
+        let block = &blocks[0];
+        assert_eq!(block.block_type, BlockType::Fixed);
+        assert_eq!(block.literals, 8);
+        assert_eq!(block.matches, 1);
+        assert_eq!(block.header_bits, 3);
+        assert_eq!(block.end_of_block_bits, 7);
+        check_totals(&blocks, 12);
+    }
+
+    #[test]
+    fn inspect_stored() {
+        let data = vec![32u8; 40000];
+        let compressed = compress_data_stored(&data);
+        let blocks = inspect_deflate(&compressed).unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert!(blocks.iter().all(|b| b.block_type == BlockType::Stored));
+        check_totals(&blocks, data.len());
+    }
+
+    #[test]
+    fn inspect_dynamic() {
+        let data = get_test_data();
+        let compressed = deflate_bytes(&data).unwrap();
+        let blocks = inspect_deflate(&compressed).unwrap();
+        assert!(blocks.iter().any(|b| b.block_type == BlockType::Dynamic));
+        check_totals(&blocks, data.len());
+
+        let total_bits: u64 = blocks.iter().map(|b| b.total_bits).sum();
+        assert_eq!((total_bits + 7) / 8, compressed.len() as u64);
+
+        let header = blocks[0].header.as_ref().unwrap();
+        assert_eq!(header.literal_length_lengths.len(),
+                   usize::from(header.hlit) + MIN_NUM_LITERALS_AND_LENGTHS);
+        assert_eq!(header.distance_lengths.len(),
+                   usize::from(header.hdist) + MIN_NUM_DISTANCES);
+    }
+
+    #[test]
+    fn inspect_zlib_stream() {
+        let data = get_test_data();
+        let compressed = deflate_bytes_zlib(&data).unwrap();
+        let blocks = inspect_zlib(&compressed).unwrap();
+        assert_eq!(blocks[0].bit_offset, 16);
+        check_totals(&blocks, data.len());
+
+        assert_eq!(inspect_zlib(&[0x78, 0x00]), Err(InspectError::InvalidZlibHeader));
+    }
+
+    #[test]
+    fn inspect_truncated() {
+        let data = get_test_data();
+        let compressed = deflate_bytes(&data).unwrap();
+        let res = inspect_deflate(&compressed[..compressed.len() / 2]);
+        assert_eq!(res, Err(InspectError::UnexpectedEof));
+    }
+}
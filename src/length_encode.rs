@@ -1,9 +1,13 @@
-use std::iter::Iterator;
-use std::clone::Clone;
+use core::iter::Iterator;
+use core::clone::Clone;
+#[cfg(test)]
+use alloc::vec::Vec;
+
+use huffman_table::{NUM_LITERALS_AND_LENGTHS, NUM_DISTANCE_CODES};
 
 /// An enum representing the different types in the run-length encoded data used to encode
 /// huffman table lengths
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum EncodedLength {
     // An actual length value
     Length(u8),
@@ -25,8 +29,8 @@ impl EncodedLength {
                     EncodedLength::RepeatZero7Bits(repeat)
                 }
             }
-            1...15 => EncodedLength::CopyPrevious(repeat),
-            _ => panic!(),
+            // Lengths above 15 are rejected when creating the codes.
+            _ => EncodedLength::CopyPrevious(repeat),
         }
     }
 }
@@ -37,8 +41,40 @@ pub const REPEAT_ZERO_7_BITS: usize = 18;
 
 const MIN_REPEAT: u8 = 3;
 
+/// The largest number of lengths that are encoded together, which is also the most values
+/// encoding them can result in.
+pub const MAX_ENCODED_LENGTHS: usize = NUM_LITERALS_AND_LENGTHS + NUM_DISTANCE_CODES + 2;
+
+/// Run-length encoded lengths, kept in a fixed size buffer so encoding them doesn't allocate.
+pub struct EncodedLengths {
+    values: [EncodedLength; MAX_ENCODED_LENGTHS],
+    len: usize,
+}
+
+impl EncodedLengths {
+    pub const fn new() -> EncodedLengths {
+        EncodedLengths {
+            values: [EncodedLength::Length(0); MAX_ENCODED_LENGTHS],
+            len: 0,
+        }
+    }
+
+    pub fn as_slice(&self) -> &[EncodedLength] {
+        &self.values[..self.len]
+    }
+
+    pub fn push(&mut self, value: EncodedLength) {
+        self.values[self.len] = value;
+        self.len += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
 fn update_out_and_freq(encoded: EncodedLength,
-                       output: &mut Vec<EncodedLength>,
+                       output: &mut EncodedLengths,
                        frequencies: &mut [u16; 19]) {
     let index = match encoded {
         EncodedLength::Length(l) => usize::from(l),
@@ -62,11 +98,24 @@ fn not_max_repetitions(length_value: u8, repeats: u8) -> bool {
 /// the deflate stream.
 /// Returns a tuple containing a vec of the encoded lengths, and an array describing the frequencies
 /// of the different length codes
+#[cfg(test)]
 pub fn encode_lengths<I>(lengths: I) -> Option<(Vec<EncodedLength>, [u16; 19])>
     where I: Iterator<Item = u8> + Clone
 {
-    let lengths = lengths;
-    let mut out = Vec::with_capacity(lengths.size_hint().0 / 2);
+    let mut out = EncodedLengths::new();
+    encode_lengths_into(lengths, &mut out)
+        .map(|frequencies| (out.as_slice().to_vec(), frequencies))
+}
+
+/// Run-length encode the lengths in `lengths` like `encode_lengths`, storing the encoded lengths
+/// in `out`, and return the frequencies of the different length codes.
+///
+/// There can be at most `MAX_ENCODED_LENGTHS` lengths.
+pub fn encode_lengths_into<I>(lengths: I, out: &mut EncodedLengths) -> Option<[u16; 19]>
+    where I: Iterator<Item = u8> + Clone
+{
+    debug_assert!(lengths.clone().count() <= MAX_ENCODED_LENGTHS);
+    out.clear();
     let mut frequencies = [0u16; 19];
     // Number of repetitions of the current value
     let mut repeat = 0;
@@ -89,13 +138,13 @@ pub fn encode_lengths<I>(lengths: I) -> Option<(Vec<EncodedLength>, [u16; 19])>
                 // The previous value has been repeated enough times to write out a repeat code.
 
                 let val = EncodedLength::from_prev_and_repeat(prev, repeat);
-                update_out_and_freq(val, &mut out, &mut frequencies);
+                update_out_and_freq(val, out, &mut frequencies);
                 repeat = 0;
                 // If we have a new length value, output l unless the last value is 0 or l is the
                 // last byte.
                 if l != prev {
                     if l != 0 || iter.peek().is_none() {
-                        update_out_and_freq(EncodedLength::Length(l), &mut out, &mut frequencies);
+                        update_out_and_freq(EncodedLength::Length(l), out, &mut frequencies);
                         repeat = 0;
                     } else {
                         // If we have a zero, we start repeat at one instead of outputting, as
@@ -128,7 +177,7 @@ pub fn encode_lengths<I>(lengths: I) -> Option<(Vec<EncodedLength>, [u16; 19])>
                 };
 
                 for i in b_iter.take(repeat as usize + extra) {
-                    update_out_and_freq(EncodedLength::Length(i), &mut out, &mut frequencies);
+                    update_out_and_freq(EncodedLength::Length(i), out, &mut frequencies);
                 }
 
                 // If the current byte is zero we start repeat at 1 as we didn't output the literal
@@ -138,224 +187,193 @@ pub fn encode_lengths<I>(lengths: I) -> Option<(Vec<EncodedLength>, [u16; 19])>
         }
         prev = l;
     }
-    Some((out, frequencies))
+    Some(frequencies)
 }
 
-#[cfg(currently_not_in_use)]
-mod bpm {
-
-    type NodeIndex = u16;
-    type WeightType = u32;
-
-    /// A struct representing a node used in the package-merge algorithm
-    #[derive(Copy, Clone, Debug)]
-    struct ChainNode {
-        // The weight of the node, which in this case is the frequency of the symbol in the input
-        // data we are creating huffman codes for
-        weight: WeightType,
-        // Number of leaf nodes to the left of this node
-        // In this case, the count is equal to the symbol in the input data this node represents
-        count: u16,
-        // A pointer to the previous node in this chain, if it exists
-        // As using actual pointers in rust would involve unsafe code, the tail is represented by an
-        // index to the vector containing all the nodes. (This comes with the added benefit of being
-        // able to use a smaller type than a pointer, potentially saving some memory)
-        tail: Option<NodeIndex>,
-    }
-
-    /// A struct representing leaves (same as chainNode, but without the tail)
-    #[derive(Debug)]
-    struct Leaf {
-        weight: WeightType,
-        count: u16,
-    }
-
-    fn advance_lookahead(indexes: &mut [(usize, usize)], index: usize, next: usize) {
-        indexes[index].0 = indexes[index].1;
-        indexes[index].1 = next;
-    }
-
-    /// Implementation of boundary package merge algorithm described by Katajainen/Moffat/Turpin in
-    /// "A Fast and Space-Economical Algorithm for Length-Limited Coding"
-    fn boundary_package_merge(lookahead_indexes: &mut [(usize, usize)],
-                              nodes: &mut Vec<ChainNode>,
-                              leaves: &[Leaf],
-                              index: usize,
-                              last: bool) {
-
-        let count = nodes[lookahead_indexes[index].1].count;
-        let next_count = count + 1;
-
-        if index == 0 && count >= leaves.len() as u16 {
-            return;
-        };
+/// The algorithm used to generate the lengths of the huffman codes.
+///
+/// Deflate limits codes to `15` bits, and the codes used to write the lengths of the codes to
+/// `7` bits, so the lengths can't simply be those of ordinary huffman codes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HuffmanAlgorithm {
+    /// Generate ordinary huffman code lengths in place, and shorten any codes that are too long
+    /// with a heuristic, like miniz does.
+    ///
+    /// The codes are only optimal if none of them had to be shortened, which is usually the
+    /// case, as that needs a very skewed distribution of symbols.
+    InPlace,
+    /// Generate optimal length-limited codes using the package-merge algorithm of Larmore and
+    /// Hirschberg.
+    ///
+    /// The codes never take more bits to encode the block than the ones from `InPlace`, and take
+    /// fewer if some codes would have been too long. This is somewhat slower, though still fast
+    /// compared to finding matches.
+    PackageMerge,
+}
 
-        if index == 0 {
-            // If we are at index 0, we need to move the lookahead to the next leaf node.
-            advance_lookahead(lookahead_indexes, index, nodes.len());
+mod package_merge {
+    use core::mem;
+    use super::{MAX_SYMBOLS, validate_lengths};
 
-            let new_weight = leaves[count as usize].weight;
+    type WeightType = u32;
 
-            // Add the new leaf node.
-            nodes.push(ChainNode {
-                weight: new_weight,
-                count: next_count,
-                tail: None,
-            });
+    /// The longest code length supported.
+    const MAX_LEN: usize = 15;
+    /// The largest number of items in a list. Only the first `2n - 2` items of each list can be
+    /// used, where `n` is the number of symbols with a code.
+    const MAX_ITEMS: usize = 2 * MAX_SYMBOLS - 2;
 
-            return;
+    /// Generate optimal code lengths of at most `max_len` bits, using the package-merge
+    /// algorithm described by Larmore and Hirschberg in
+    /// "A Fast Algorithm for Optimal Length-Limited Huffman Codes".
+    ///
+    /// Seen as the coin collector's problem, every symbol has a coin for each allowed code
+    /// length, with the frequency of the symbol as its value. The list of coins for the longest
+    /// length is the symbols sorted by frequency. The list for each shorter length is made by
+    /// merging the symbols with packages of two coins each from the previous list. The length of
+    /// the code of a symbol is the number of its coins among the cheapest `2n - 2` items of the
+    /// last list, counting the coins that are inside packages too.
+    ///
+    /// Which coins of a symbol are used doesn't matter, only how many, and the coins of
+    /// the cheapest symbols come first in every list. So instead of keeping track of what the
+    /// packages contain, we only note which items in each list are packages, and then walk
+    /// back through the lists to count how many of the symbols are used at each length.
+    ///
+    /// The lengths are stored in `ret`, which has to be as long as `frequencies`. This doesn't
+    /// allocate.
+    pub fn package_merge_lengths(frequencies: &[u16], max_len: usize, ret: &mut [u8]) {
+        debug_assert!(frequencies.len() <= MAX_SYMBOLS);
+        debug_assert_eq!(frequencies.len(), ret.len());
+        debug_assert!(max_len > 0 && max_len <= MAX_LEN);
+
+        for l in ret.iter_mut() {
+            *l = 0;
         }
 
-        let sum = {
-            let la = lookahead_indexes[index - 1];
-            nodes[la.0].weight + nodes[la.1].weight
-        };
-
-        // If the sum of the two lookahead nodes in the previous list is smaller than the next leaf
-        // node, we add a new package, otherwise, we add another leaf node.
-        if count < leaves.len() as u16 && sum > leaves[count as usize].weight {
-            // Record the tail of the current lookahead first to avoid the borrow checker.
-            let y = nodes[lookahead_indexes[index].1].tail;
-
-            let next_weight = leaves[count as usize].weight;
-
-            advance_lookahead(lookahead_indexes, index, nodes.len());
-
-            // Add a leaf node.
-            nodes.push(ChainNode {
-                weight: next_weight,
-                count: next_count,
-                tail: y,
-            });
-        } else {
-            {
-                advance_lookahead(lookahead_indexes, index, nodes.len());
-
-                // Add a package containing the sum of the lookaheads in the previous list.
-                nodes.push(ChainNode {
-                    weight: sum,
-                    count: count,
-                    tail: Some(lookahead_indexes[index - 1].1 as NodeIndex),
-                });
-            }
-            if !last {
-                // If we add a package, we need to run boundary_pm on the previous lists to look for
-                // more leaf nodes
-                // We might want to avoid using recursion here, though we won't ever go more than 15
-                // levels in as that is the maximum code length allowed by the deflate spec.
-                boundary_package_merge(lookahead_indexes, nodes, leaves, index - 1, false);
-                boundary_package_merge(lookahead_indexes, nodes, leaves, index - 1, false);
+        // Sort the symbols that are used by frequency, and by symbol for equal frequencies.
+        let mut leaf_buffer = [(0 as WeightType, 0u16); MAX_SYMBOLS];
+        let mut num_leaves = 0;
+        for (n, &f) in frequencies.iter().enumerate() {
+            if f > 0 {
+                leaf_buffer[num_leaves] = (WeightType::from(f), n as u16);
+                num_leaves += 1;
             }
         }
-    }
+        let leaves = &mut leaf_buffer[..num_leaves];
 
-    pub fn _huffman_lengths_from_frequency_bpm(frequencies: &[u16], max_len: usize) -> Vec<u8> {
-        // Make sure the number of frequencies is sensible since we use u16 to index.
-        assert!(max_len > 1 && max_len < 16);
-
-        let mut lengths = vec![0; frequencies.len()];
-
-        // Create a vector of nodes used by the package merge algorithm
-        // We start by adding a leaf node for each nonzero frequency, and subsequently
-        // sorting them by weight.
-        let mut leaves: Vec<_> = frequencies.iter()
-            .enumerate()
-            .filter_map(|(n, f)| {
-                if *f > 0 {
-                    Some(Leaf {
-                        weight: *f as WeightType,
-                        count: n as u16,
-                    })
-                } else {
-                    None
-                }
-            })
-            .collect();
-        // NOTE: We might want to consider normalising the
-        // frequencies if we are going to use very large blocks as large freq values
-        // result in a large number of nodes
-
-        // Special case with zero or 1 value having a non-zero frequency
-        // (this will break the package merge otherwise)
+        // Special cases with zero or 1 value having a non-zero frequency
         if leaves.len() == 1 {
-            lengths[leaves[0].count as usize] += 1;
-            return lengths;
+            ret[usize::from(leaves[0].1)] = 1;
+            return;
         } else if leaves.is_empty() {
-            return lengths;
+            return;
+        }
+        debug_assert!(leaves.len() <= 1 << max_len,
+                      "Too many symbols to give them codes of at most max_len bits!");
+
+        leaves.sort_unstable();
+
+        let num_items = 2 * leaves.len() - 2;
+        // Whether each item in the list for each length is a package.
+        let mut is_package = [[false; MAX_ITEMS]; MAX_LEN];
+        let mut list_lengths = [0; MAX_LEN];
+        let mut weights = [0 as WeightType; MAX_ITEMS];
+        let mut prev_weights = [0 as WeightType; MAX_ITEMS];
+
+        // The list for the longest length only has the symbols themselves.
+        list_lengths[0] = leaves.len();
+        for (w, leaf) in weights.iter_mut().zip(leaves.iter()) {
+            *w = leaf.0;
         }
 
-        leaves.sort_by(|a, b| a.weight.cmp(&b.weight));
-
-        // We create the two first lookahead nodes from the two first leaves, with counts 1 and 2
-        // TODO: Find an algorhithm to approximate the number of nodes we will get
-        let mut nodes = Vec::with_capacity(8 * leaves.len());
-        nodes.extend(leaves.iter()
-            .take(2)
-            .enumerate()
-            .map(|(n, f)| {
-                ChainNode {
-                    weight: f.weight,
-                    count: n as u16 + 1,
-                    tail: None,
+        for list in 1..max_len {
+            mem::swap(&mut weights, &mut prev_weights);
+            let num_packages = list_lengths[list - 1] / 2;
+            let (mut leaf, mut package) = (0, 0);
+            let mut len = 0;
+            while len < num_items && (leaf < leaves.len() || package < num_packages) {
+                let package_weight = if package < num_packages {
+                    prev_weights[2 * package] + prev_weights[2 * package + 1]
+                } else {
+                    WeightType::MAX
+                };
+                if leaf < leaves.len() && leaves[leaf].0 <= package_weight {
+                    weights[len] = leaves[leaf].0;
+                    leaf += 1;
+                } else {
+                    weights[len] = package_weight;
+                    is_package[list][len] = true;
+                    package += 1;
                 }
-            }));
-
-        // Indexes to the current lookahead nodes in each list.
-        // The lookahead indexes in each list start out pointing to the first two leaves.
-        let mut lookahead_ptrs = vec![(0, 1); max_len];
-
-        // The boundary package-merge algorhithm is run repeatedly until we have 2n - 2 nodes in the
-        // last list, which tells us how many active nodes there are in each list.
-        // As we have already added the two first nodes in each list, we need to run
-        // 2n - 2 - 2 times more to get the needed number of nodes in the last list.
-        let num_runs = (2 * leaves.len()) - 2 - 2;
-        for i in 0..num_runs {
-            let last = i == num_runs - 1;
-            boundary_package_merge(&mut lookahead_ptrs, &mut nodes, &leaves, max_len - 1, last);
+                len += 1;
+            }
+            list_lengths[list] = len;
         }
 
+        // Walk back from the last list, adding one to the length of each symbol used in each
+        // list. The packages among the items used from a list are made from twice as many items
+        // of the previous one.
+        let mut used = num_items;
+        for list in (0..max_len).rev() {
+            debug_assert!(used <= list_lengths[list]);
+            let packages = is_package[list][..used].iter().filter(|&&p| p).count();
+            for leaf in &leaves[..used - packages] {
+                ret[usize::from(leaf.1)] += 1;
+            }
+            used = 2 * packages;
+        }
 
-        let head = lookahead_ptrs.last().unwrap().1;
-
-        // Generate the lengths from the trees generated by the package merge algorithm.
-        let mut next_node = head;
-        loop {
-            let node = nodes[next_node];
+        debug_assert_eq!(used, 0);
+        debug_assert!(validate_lengths(ret),
+                      "The generated length codes were not valid!");
+    }
+}
 
-            for item in leaves.iter().take(node.count as usize) {
-                lengths[item.count as usize] += 1;
-            }
+#[cfg(test)]
+pub fn huffman_lengths_from_frequency(frequencies: &[u16],
+                                      max_len: usize,
+                                      algorithm: HuffmanAlgorithm)
+                                      -> Vec<u8> {
+    let mut lengths = vec![0; frequencies.len()];
+    huffman_lengths_from_frequency_into(frequencies, max_len, algorithm, &mut lengths);
+    lengths
+}
 
-            if let Some(n) = node.tail {
-                next_node = n as usize;
-            } else {
-                break;
-            }
+/// Generate huffman code lengths of at most `max_len` bits for the symbols with the frequencies
+/// in `frequencies` using `algorithm`, storing them in the start of `lengths`.
+///
+/// There can be at most `MAX_SYMBOLS` symbols, and `max_len` can be at most `15`. This doesn't
+/// allocate.
+pub fn huffman_lengths_from_frequency_into(frequencies: &[u16],
+                                           max_len: usize,
+                                           algorithm: HuffmanAlgorithm,
+                                           lengths: &mut [u8]) {
+    let lengths = &mut lengths[..frequencies.len()];
+    match algorithm {
+        HuffmanAlgorithm::InPlace => in_place::in_place_lengths(frequencies, max_len, lengths),
+        HuffmanAlgorithm::PackageMerge => {
+            package_merge::package_merge_lengths(frequencies, max_len, lengths)
         }
-        lengths
     }
 }
 
-pub fn huffman_lengths_from_frequency(frequencies: &[u16], max_len: usize) -> Vec<u8> {
-    in_place::in_place_lengths(frequencies, max_len)
-    // huffman_lengths_from_frequency_bpm(frequencies, max_len)
+/// The largest number of symbols huffman code lengths are generated for at once.
+pub const MAX_SYMBOLS: usize = NUM_LITERALS_AND_LENGTHS + 2;
+
+/// Check that the lengths are valid, i.e that the sum of 2^-length over the codes is at most 1.
+fn validate_lengths(lengths: &[u8]) -> bool {
+    // The sum is scaled by 2^32 to work with integers, which also avoids needing float
+    // functions from `std`.
+    let v = lengths.iter().fold(0u64, |acc, &n| {
+        acc + if n != 0 && n <= 32 { 1u64 << (32 - n) } else { 0 }
+    });
+    v <= 1u64 << 32
 }
 
 mod in_place {
-    type WeightType = u32;
+    use super::{MAX_SYMBOLS, validate_lengths};
 
-    fn validate_lengths(lengths: &[u8]) -> bool {
-        let v = lengths.iter().fold(0f64, |acc, &n| {
-            acc + if n != 0 { 2f64.powi(-(n as i32)) } else { 0f64 }
-        });
-        if v > 1.0 {
-            println!("Sum greater than 1.0: ({})", v);
-            false
-        } else {
-            // println!("Sum ok: ({})", v);
-            true
-        }
-    }
+    type WeightType = u32;
 
     #[derive(Eq, Ord, PartialEq, PartialOrd, Debug, Clone, Copy, Default)]
     pub struct Node {
@@ -483,45 +501,50 @@ mod in_place {
     /// tables that are better compressible than the algorithm used previously. The downside of this
     /// algorithm is that it's not length-limited, so if too long code lengths are generated,
     /// it might result in a sub-optimal tables as the length-restricting function isn't optimal.
-    pub fn in_place_lengths(frequencies: &[u16], max_len: usize) -> Vec<u8> {
+    ///
+    /// The lengths are stored in `ret`, which has to be as long as `frequencies`.
+    pub fn in_place_lengths(frequencies: &[u16], max_len: usize, ret: &mut [u8]) {
+        debug_assert!(frequencies.len() <= MAX_SYMBOLS);
+        debug_assert_eq!(frequencies.len(), ret.len());
         // Discard zero length nodes as they won't be given a code and thus don't need to
-        // participate in code length generation and create a new vec of the remaining
-        // symbols and weights.
-        let mut leaves: Vec<Node> = frequencies.iter()
-            .enumerate()
-            .filter_map(|(n, f)| {
-                if *f > 0 {
-                    Some(Node {
-                        value: *f as WeightType,
-                        symbol: n as u16,
-                    })
-                } else {
-                    None
-                }
-            })
-            .collect();
+        // participate in code length generation, and put the remaining symbols and weights
+        // in a buffer on the stack.
+        let mut leaf_buffer = [Node::default(); MAX_SYMBOLS];
+        let mut num_leaves = 0;
+        for (n, &f) in frequencies.iter().enumerate() {
+            if f > 0 {
+                leaf_buffer[num_leaves] = Node {
+                    value: f as WeightType,
+                    symbol: n as u16,
+                };
+                num_leaves += 1;
+            }
+        }
+        let leaves = &mut leaf_buffer[..num_leaves];
 
-        let mut ret = vec![0u8; frequencies.len()];
+        for l in ret.iter_mut() {
+            *l = 0;
+        }
 
         // Special cases with zero or 1 value having a non-zero frequency
         if leaves.len() == 1 {
             ret[leaves[0].symbol as usize] = 1;
-            return ret;
+            return;
         } else if leaves.is_empty() {
-            return ret;
+            return;
         }
 
-        // Sort the leaves by value. As the sort in the standard library is stable, we don't
-        // have to worry about the symbol code here.
-        leaves.sort_by(|&a, &b| a.value.cmp(&b.value));
+        // Sort the leaves by value, and by symbol for equal values, which gives the same order
+        // as a stable sort by value without needing to allocate.
+        leaves.sort_unstable();
 
-        step_1(&mut leaves);
-        step_2(&mut leaves);
+        step_1(leaves);
+        step_2(leaves);
 
         // Count how many codes of each length used, for usage in the next section.
         let mut num_codes = {
             let mut num_codes = [0u16; NUM_CODES_LENGTH];
-            for l in &leaves {
+            for l in leaves.iter() {
                 num_codes[l.value as usize] += 1;
             }
             num_codes
@@ -534,16 +557,14 @@ mod in_place {
         // Output the actual lengths
         let mut leaf_it = leaves.iter().rev();
         for (&n_codes, i) in num_codes[1..max_len + 1].iter().zip(1..(max_len as u8) + 1) {
-            for _ in 0..n_codes {
-                ret[leaf_it.next().unwrap().symbol as usize] = i;
+            for leaf in leaf_it.by_ref().take(usize::from(n_codes)) {
+                ret[leaf.symbol as usize] = i;
             }
         }
 
         debug_assert_eq!(leaf_it.next(), None);
-        debug_assert!(validate_lengths(&ret),
+        debug_assert!(validate_lengths(ret),
                       "The generated length codes were not valid!");
-
-        ret
     }
 
 
@@ -651,49 +672,51 @@ mod test {
 
     #[test]
     fn test_lengths_from_frequencies() {
-        let frequencies = [1, 1, 5, 7, 10, 14];
+        for &algorithm in &[HuffmanAlgorithm::InPlace, HuffmanAlgorithm::PackageMerge] {
+            let frequencies = [1, 1, 5, 7, 10, 14];
 
-        let expected = [4, 4, 3, 2, 2, 2];
-        let res = huffman_lengths_from_frequency(&frequencies, 4);
+            let expected = [4, 4, 3, 2, 2, 2];
+            let res = huffman_lengths_from_frequency(&frequencies, 4, algorithm);
 
-        assert_eq!(expected, res.as_slice());
+            assert_eq!(expected, res.as_slice());
 
-        let frequencies = [1, 5, 1, 7, 10, 14];
-        let expected = [4, 3, 4, 2, 2, 2];
+            let frequencies = [1, 5, 1, 7, 10, 14];
+            let expected = [4, 3, 4, 2, 2, 2];
 
-        let res = huffman_lengths_from_frequency(&frequencies, 4);
+            let res = huffman_lengths_from_frequency(&frequencies, 4, algorithm);
 
-        assert_eq!(expected, res.as_slice());
+            assert_eq!(expected, res.as_slice());
 
-        let frequencies = [0, 25, 0, 10, 2, 4];
+            let frequencies = [0, 25, 0, 10, 2, 4];
 
-        let res = huffman_lengths_from_frequency(&frequencies, 4);
-        assert_eq!(res[0], 0);
-        assert_eq!(res[2], 0);
-        assert!(res[1] < 4);
+            let res = huffman_lengths_from_frequency(&frequencies, 4, algorithm);
+            assert_eq!(res[0], 0);
+            assert_eq!(res[2], 0);
+            assert!(res[1] < 4);
 
-        // Only one value
-        let frequencies = [0, 0, 0, 0, 0, 0, 0, 0, 55, 0, 0, 0];
-        let res = huffman_lengths_from_frequency(&frequencies, 5);
-        let expected = [0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0];
-        assert_eq!(expected, res.as_slice());
+            // Only one value
+            let frequencies = [0, 0, 0, 0, 0, 0, 0, 0, 55, 0, 0, 0];
+            let res = huffman_lengths_from_frequency(&frequencies, 5, algorithm);
+            let expected = [0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0];
+            assert_eq!(expected, res.as_slice());
 
-        // No values
-        let frequencies = [0; 30];
-        let res = huffman_lengths_from_frequency(&frequencies, 5);
-        for (a, b) in frequencies.iter().zip(res.iter()) {
-            assert_eq!(*a, (*b).into());
-        }
-        // assert_eq!(frequencies, res.as_slice());
+            // No values
+            let frequencies = [0; 30];
+            let res = huffman_lengths_from_frequency(&frequencies, 5, algorithm);
+            for (a, b) in frequencies.iter().zip(res.iter()) {
+                assert_eq!(*a, (*b).into());
+            }
+            // assert_eq!(frequencies, res.as_slice());
 
-        let mut frequencies = vec![3; NUM_LITERALS_AND_LENGTHS];
-        frequencies[55] = u16::MAX / 3;
-        frequencies[125] = u16::MAX / 3;
+            let mut frequencies = vec![3; NUM_LITERALS_AND_LENGTHS];
+            frequencies[55] = u16::MAX / 3;
+            frequencies[125] = u16::MAX / 3;
 
-        let res = huffman_lengths_from_frequency(&frequencies, 15);
-        assert_eq!(res.len(), NUM_LITERALS_AND_LENGTHS);
-        assert!(res[55] < 3);
-        assert!(res[125] < 3);
+            let res = huffman_lengths_from_frequency(&frequencies, 15, algorithm);
+            assert_eq!(res.len(), NUM_LITERALS_AND_LENGTHS);
+            assert!(res[55] < 3);
+            assert!(res[125] < 3);
+        }
     }
 
     #[test]
@@ -714,7 +737,7 @@ mod test {
                      5, 1, 1];
 
 
-        let lens = huffman_lengths_from_frequency(&freqs, 15);
+        let lens = huffman_lengths_from_frequency(&freqs, 15, HuffmanAlgorithm::InPlace);
 
         // Lengths produced by miniz for this frequency table for comparison
         // the number of total bits encoded with these huffman codes is 7701
@@ -741,6 +764,72 @@ mod test {
 
         let num_bits = lens.iter().zip(freqs.iter()).fold(0, |a, (&f, &l)| a + (f as u16 * l));
         assert_eq!(num_bits, 7701);
+
+        let lens = huffman_lengths_from_frequency(&freqs, 15, HuffmanAlgorithm::PackageMerge);
+        assert_eq!(num_bits_of(&lens, &freqs), 7701);
+    }
+
+    fn num_bits_of(lengths: &[u8], frequencies: &[u16]) -> u64 {
+        lengths.iter().zip(frequencies).map(|(&l, &f)| u64::from(l) * u64::from(f)).sum()
+    }
+
+    #[test]
+    // Test that the package-merge lengths are valid and never take more bits than the in-place
+    // ones, and take fewer when the in-place lengths had to be limited.
+    fn package_merge_not_worse() {
+        let check = |frequencies: &[u16], max_len: usize| {
+            let in_place = huffman_lengths_from_frequency(frequencies,
+                                                          max_len,
+                                                          HuffmanAlgorithm::InPlace);
+            let package_merge = huffman_lengths_from_frequency(frequencies,
+                                                               max_len,
+                                                               HuffmanAlgorithm::PackageMerge);
+            assert!(validate_lengths(&package_merge));
+            for (&l, &f) in package_merge.iter().zip(frequencies) {
+                assert!(usize::from(l) <= max_len);
+                assert_eq!(l == 0, f == 0);
+            }
+            let in_place_bits = num_bits_of(&in_place, frequencies);
+            let package_merge_bits = num_bits_of(&package_merge, frequencies);
+            assert!(package_merge_bits <= in_place_bits);
+            package_merge_bits < in_place_bits
+        };
+
+        // Frequencies following the fibonacci sequence give the longest possible codes, so
+        // these need limiting.
+        let mut fibonacci = [0u16; 23];
+        fibonacci[0] = 1;
+        fibonacci[1] = 1;
+        for n in 2..fibonacci.len() {
+            fibonacci[n] = fibonacci[n - 1] + fibonacci[n - 2];
+        }
+        let mut better = 0;
+        for n in 2..fibonacci.len() + 1 {
+            for &max_len in &[7, 15] {
+                if check(&fibonacci[..n], max_len) {
+                    better += 1;
+                }
+            }
+        }
+        assert!(better > 0);
+
+        // Skewed pseudo-random frequencies with some zeroes, for tables the size of each
+        // alphabet.
+        let mut seed = 0x9E3779B9u32;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        for &(len, max_len) in &[(NUM_LITERALS_AND_LENGTHS, 15), (30, 15), (19, 7), (2, 1)] {
+            for _ in 0..200 {
+                let frequencies: Vec<u16> = (0..len)
+                    .map(|_| if next() % 4 == 0 { 0 } else { (next() as u16) >> (next() % 16) })
+                    .collect();
+                check(&frequencies, max_len);
+            }
+        }
     }
 
 }
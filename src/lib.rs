@@ -12,7 +12,7 @@
 //! use deflate::deflate_bytes;
 //!
 //! let data = b"Some data";
-//! let compressed = deflate_bytes(data);
+//! let compressed = deflate_bytes(data).unwrap();
 //! ```
 //!
 //! ## Using a writer:
@@ -28,166 +28,282 @@
 //! let compressed_data = encoder.finish().unwrap();
 //! # let _ = compressed_data;
 //! ```
+//!
+//! # `no_std` support
+//! Without the default `std` feature, the crate only needs `alloc`. Raw DEFLATE data can then
+//! be compressed with `deflate_bytes` and friends, or streamed to any `Sink` with
+//! `SinkEncoder`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(not(any(feature = "simd", feature = "capi")), forbid(unsafe_code))]
 
 #[cfg(test)]
 extern crate flate2;
+#[cfg(test)]
+extern crate deflate64;
 // #[cfg(test)]
 // extern crate inflate;
 
+#[cfg(feature = "std")]
 extern crate adler32;
+#[cfg(feature = "std")]
 extern crate byteorder;
+#[cfg(feature = "std")]
+extern crate core;
+#[macro_use]
+extern crate alloc;
 
 mod compression_options;
 mod huffman_table;
 mod lz77;
 mod lzvalue;
 mod chained_hash_table;
+mod binary_tree;
 mod length_encode;
 mod output_writer;
 mod stored_block;
 mod huffman_lengths;
+#[cfg(feature = "std")]
 mod zlib;
+#[cfg(feature = "std")]
+mod gzip;
+#[cfg(feature = "std")]
+mod framing;
+#[cfg(feature = "std")]
 mod checksum;
 mod bit_reverse;
 mod bitstream;
 mod encoder_state;
 mod matching;
+#[cfg(feature = "simd")]
+mod simd;
 mod input_buffer;
 mod deflate_state;
 mod compress;
+#[cfg(feature = "std")]
 mod writer;
+mod stats;
+mod rsyncable;
+mod zlib_compat;
+mod error;
+mod sink;
+mod static_encoder;
+#[cfg(feature = "std")]
+pub mod inspect;
+#[cfg(feature = "std")]
+pub mod zip;
+#[cfg(feature = "std")]
+pub mod websocket;
+#[cfg(feature = "std")]
+pub mod png;
+#[cfg(feature = "capi")]
+pub mod capi;
 #[cfg(test)]
 mod test_utils;
 
+#[cfg(feature = "std")]
 use std::io::Write;
+#[cfg(feature = "std")]
 use std::io;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 
-use byteorder::BigEndian;
-
-use checksum::RollingChecksum;
 use deflate_state::DeflateState;
 use compress::compress_data_dynamic_n;
 
 #[doc(hidden)]
 pub use lz77::lz77_compress;
 
-pub use compression_options::{CompressionOptions, SpecialOptions, Compression};
+pub use compression_options::{CompressionOptions, SpecialOptions, Compression, ZlibCompat,
+                              ZlibStrategy};
+#[cfg(feature = "std")]
+pub use framing::Format;
+#[cfg(feature = "std")]
+use framing::{Framing, ZlibFraming, GzipFraming};
 use compress::Flush;
-pub use lz77::MatchingType;
+pub use lz77::{MatchingType, MatchFinder};
+pub use length_encode::HuffmanAlgorithm;
+pub use stats::CompressionStats;
+pub use error::Error;
+pub use sink::{Sink, SinkError, SinkEncoder};
+pub use static_encoder::{StaticBuffers, StaticEncoder};
 
 /// Encoders implementing a `Write` interface.
+#[cfg(feature = "std")]
 pub mod write {
-    pub use writer::{DeflateEncoder, ZlibEncoder};
+    pub use writer::{DeflateEncoder, ZlibEncoder, GzipEncoder, Encoder};
 }
 
-fn compress_data_dynamic<RC: RollingChecksum, W: Write>(input: &[u8],
-                                                        writer: &mut W,
-                                                        mut checksum: RC,
-                                                        compression_options: CompressionOptions)
-                                                        -> io::Result<usize> {
-    checksum.update_from_slice(input);
-    // We use a box here to avoid putting the buffers on the stack
-    // It's done here rather than in the structs themselves for now to
-    // keep the data close in memory.
-    let mut deflate_state = Box::new(DeflateState::new(compression_options, writer));
-    compress_data_dynamic_n(input, &mut deflate_state, Flush::Finish)
+#[cfg(feature = "std")]
+fn compress_data_framed<F: Framing, W: Write>(input: &[u8],
+                                               writer: &mut W,
+                                               mut framing: F,
+                                               compression_options: CompressionOptions)
+                                               -> io::Result<CompressionStats> {
+    framing.write_header(writer, &compression_options)?;
+    framing.update(input);
+    let stats = {
+        // We use a box here to avoid putting the buffers on the stack
+        // It's done here rather than in the structs themselves for now to
+        // keep the data close in memory.
+        let mut deflate_state = Box::new(DeflateState::new(compression_options, &mut *writer));
+        compress_data_dynamic_n(input, &mut deflate_state, Flush::Finish)?;
+        deflate_state.encoder_state.writer.write_pending()?;
+        deflate_state.stats()
+    };
+    framing.write_trailer(writer, input.len() as u64)?;
+    Ok(stats)
 }
 
 /// Compress the given slice of bytes with DEFLATE compression.
 ///
-/// Returns a Vec<u8> of the compressed data.
+/// Returns the compressed data, or an error if one of the internal checks fails.
 ///
 /// # Examples
 ///
 /// ```
 /// use deflate::{deflate_bytes_conf, Compression};
 /// let data = b"This is some test data";
-/// let compressed_data = deflate_bytes_conf(data, Compression::Best);
+/// let compressed_data = deflate_bytes_conf(data, Compression::Best).unwrap();
 /// # let _ = compressed_data;
 /// ```
-pub fn deflate_bytes_conf<O: Into<CompressionOptions>>(input: &[u8], options: O) -> Vec<u8> {
-    let mut writer = Vec::with_capacity(input.len() / 3);
-    compress_data_dynamic(input,
-                          &mut writer,
-                          checksum::NoChecksum::new(),
-                          options.into())
-        .expect("Write error!");
-    writer
+pub fn deflate_bytes_conf<O: Into<CompressionOptions>>(input: &[u8],
+                                                      options: O)
+                                                      -> Result<Vec<u8>, Error> {
+    deflate_bytes_with_stats(input, options).map(|(output, _)| output)
+}
+
+/// Compress the given slice of bytes with DEFLATE compression, and return statistics about the
+/// compression process along with the compressed data.
+///
+/// # Examples
+///
+/// ```
+/// use deflate::{deflate_bytes_with_stats, Compression};
+/// let data = b"This is some test data, some test data";
+/// let (compressed_data, stats) = deflate_bytes_with_stats(data, Compression::Default).unwrap();
+/// assert_eq!(stats.bytes_in, data.len() as u64);
+/// assert_eq!(stats.bytes_out, compressed_data.len() as u64);
+/// ```
+pub fn deflate_bytes_with_stats<O: Into<CompressionOptions>>
+    (input: &[u8],
+     options: O)
+     -> Result<(Vec<u8>, CompressionStats), Error> {
+    // Raw DEFLATE data has no header or trailer, so this is done without the framing, which
+    // isn't available without `std`.
+    let writer = Vec::with_capacity(input.len() / 3);
+    let mut deflate_state = Box::new(DeflateState::new(options.into(), writer));
+    compress_data_dynamic_n(input, &mut deflate_state, Flush::Finish)?;
+    deflate_state.encoder_state.writer.write_pending()?;
+    let stats = deflate_state.stats();
+    Ok((deflate_state.encoder_state.writer.w, stats))
 }
 
 /// Compress the given slice of bytes with DEFLATE compression using the default compression
 /// level.
 ///
-/// Returns a Vec<u8> of the compressed data.
+/// Returns the compressed data, or an error if one of the internal checks fails.
 ///
 /// # Examples
 ///
 /// ```
 /// use deflate::deflate_bytes;
 /// let data = b"This is some test data";
-/// let compressed_data = deflate_bytes(data);
+/// let compressed_data = deflate_bytes(data).unwrap();
 /// # let _ = compressed_data;
 /// ```
-pub fn deflate_bytes(input: &[u8]) -> Vec<u8> {
+pub fn deflate_bytes(input: &[u8]) -> Result<Vec<u8>, Error> {
     deflate_bytes_conf(input, Compression::Default)
 }
 
+#[cfg(feature = "std")]
 /// Compress the given slice of bytes with DEFLATE compression, including a zlib header and trailer.
 ///
-/// Returns a Vec<u8> of the compressed data.
+/// Returns the compressed data, or an error if one of the internal checks fails.
 ///
-/// Zlib dictionaries are not yet suppored.
+/// To use a preset dictionary, see [`ZlibEncoder::set_dictionary`]
+/// (write/struct.ZlibEncoder.html#method.set_dictionary).
 ///
 /// # Examples
 ///
 /// ```
 /// use deflate::{deflate_bytes_zlib_conf, Compression};
 /// let data = b"This is some test data";
-/// let compressed_data = deflate_bytes_zlib_conf(data, Compression::Best);
+/// let compressed_data = deflate_bytes_zlib_conf(data, Compression::Best).unwrap();
 /// # let _ = compressed_data;
 /// ```
-pub fn deflate_bytes_zlib_conf<O: Into<CompressionOptions>>(input: &[u8], options: O) -> Vec<u8> {
-    use byteorder::WriteBytesExt;
+pub fn deflate_bytes_zlib_conf<O: Into<CompressionOptions>>(input: &[u8],
+                                                           options: O)
+                                                           -> Result<Vec<u8>, Error> {
     let mut writer = Vec::with_capacity(input.len() / 3);
-    // Write header
-    zlib::write_zlib_header(&mut writer, zlib::CompressionLevel::Default)
-        .expect("Write error when writing zlib header!");
-
-    let mut checksum = checksum::Adler32Checksum::new();
-    compress_data_dynamic(input, &mut writer, &mut checksum, options.into())
-        .expect("Write error when writing compressed data!");
-
-    let hash = checksum.current_hash();
-
-    writer.write_u32::<BigEndian>(hash).expect("Write error when writing checksum!");
-    writer
+    compress_data_framed(input, &mut writer, ZlibFraming::new(), options.into())?;
+    Ok(writer)
 }
 
+#[cfg(feature = "std")]
 /// Compress the given slice of bytes with DEFLATE compression, including a zlib header and trailer,
 /// using the default compression level.
 ///
-/// Returns a Vec<u8> of the compressed data.
-///
-/// Zlib dictionaries are not yet suppored.
+/// Returns the compressed data, or an error if one of the internal checks fails.
 ///
 /// # Examples
 ///
 /// ```
 /// use deflate::deflate_bytes_zlib;
 /// let data = b"This is some test data";
-/// let compressed_data = deflate_bytes_zlib(data);
+/// let compressed_data = deflate_bytes_zlib(data).unwrap();
 /// # let _ = compressed_data;
 /// ```
-pub fn deflate_bytes_zlib(input: &[u8]) -> Vec<u8> {
+pub fn deflate_bytes_zlib(input: &[u8]) -> Result<Vec<u8>, Error> {
     deflate_bytes_zlib_conf(input, Compression::Default)
 }
 
+#[cfg(feature = "std")]
+/// Compress the given slice of bytes with DEFLATE compression, including a gzip header and
+/// trailer.
+///
+/// Returns the compressed data, or an error if one of the internal checks fails.
+///
+/// # Examples
+///
+/// ```
+/// use deflate::{deflate_bytes_gzip_conf, Compression};
+/// let data = b"This is some test data";
+/// let compressed_data = deflate_bytes_gzip_conf(data, Compression::Best).unwrap();
+/// # let _ = compressed_data;
+/// ```
+pub fn deflate_bytes_gzip_conf<O: Into<CompressionOptions>>(input: &[u8],
+                                                           options: O)
+                                                           -> Result<Vec<u8>, Error> {
+    let mut writer = Vec::with_capacity(input.len() / 3);
+    compress_data_framed(input, &mut writer, GzipFraming::new(), options.into())?;
+    Ok(writer)
+}
+
+#[cfg(feature = "std")]
+/// Compress the given slice of bytes with DEFLATE compression, including a gzip header and
+/// trailer, using the default compression level.
+///
+/// Returns the compressed data, or an error if one of the internal checks fails.
+///
+/// # Examples
+///
+/// ```
+/// use deflate::deflate_bytes_gzip;
+/// let data = b"This is some test data";
+/// let compressed_data = deflate_bytes_gzip(data).unwrap();
+/// # let _ = compressed_data;
+/// ```
+pub fn deflate_bytes_gzip(input: &[u8]) -> Result<Vec<u8>, Error> {
+    deflate_bytes_gzip_conf(input, Compression::Default)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use std::io::Write;
 
-    use test_utils::{get_test_data, decompress_to_end, decompress_zlib};
+    use test_utils::{get_test_data, decompress_to_end, decompress_zlib, decompress_gzip};
 
     /// Write data to the writer in chunks of chunk_size.
     fn chunked_write<W: Write>(mut writer: W, data: &[u8], chunk_size: usize) {
@@ -201,7 +317,7 @@ mod test {
     fn dynamic_string_mem() {
         use std::str;
         let test_data = String::from("                    GNU GENERAL PUBLIC LICENSE").into_bytes();
-        let compressed = deflate_bytes(&test_data);
+        let compressed = deflate_bytes(&test_data).unwrap();
 
         assert!(compressed.len() < test_data.len());
 
@@ -213,7 +329,7 @@ mod test {
     fn dynamic_string_file() {
         use std::str;
         let input = get_test_data();
-        let compressed = deflate_bytes(&input);
+        let compressed = deflate_bytes(&input).unwrap();
 
         println!("dynamic_string_file compressed(default) len: {}",
                  compressed.len());
@@ -229,7 +345,7 @@ mod test {
     fn file_zlib() {
         let test_data = get_test_data();
 
-        let compressed = deflate_bytes_zlib(&test_data);
+        let compressed = deflate_bytes_zlib(&test_data).unwrap();
         // {
         //     use std::fs::File;
         //     use std::io::Write;
@@ -248,7 +364,7 @@ mod test {
     #[test]
     fn zlib_short() {
         let test_data = [10, 20, 30, 40, 55];
-        let compressed = deflate_bytes_zlib(&test_data);
+        let compressed = deflate_bytes_zlib(&test_data).unwrap();
 
 
 
@@ -260,7 +376,7 @@ mod test {
     fn zlib_last_block() {
         let mut test_data = vec![22; 32768];
         test_data.extend(&[5, 2, 55, 11, 12]);
-        let compressed = deflate_bytes_zlib(&test_data);
+        let compressed = deflate_bytes_zlib(&test_data).unwrap();
         // {
         // use std::fs::File;
         // use std::io::Write;
@@ -273,22 +389,198 @@ mod test {
     }
 
     fn chunk_test(chunk_size: usize) {
+        chunk_test_with_options(chunk_size, CompressionOptions::high());
+    }
+
+    fn chunk_test_with_options(chunk_size: usize, options: CompressionOptions) {
         let mut compressed = Vec::with_capacity(32000);
         let data = get_test_data();
         {
-            let mut compressor = write::ZlibEncoder::new(&mut compressed,
-                                                         CompressionOptions::high());
+            let mut compressor = write::ZlibEncoder::new(&mut compressed, options);
             chunked_write(&mut compressor, &data, chunk_size);
             compressor.finish().unwrap();
         }
-        let compressed2 = deflate_bytes_zlib_conf(&data, CompressionOptions::high());
+        let compressed2 = deflate_bytes_zlib_conf(&data, options).unwrap();
         let res = decompress_zlib(&compressed);
         assert!(res == data);
         assert_eq!(compressed.len(), compressed2.len());
         assert!(compressed == compressed2);
     }
 
-    #[ignore]
+    #[test]
+    fn stats() {
+        let data = get_test_data();
+        let (compressed, stats) = deflate_bytes_with_stats(&data, Compression::Default).unwrap();
+        assert_eq!(stats.bytes_in, data.len() as u64);
+        assert_eq!(stats.bytes_out, compressed.len() as u64);
+        assert!(stats.dynamic_blocks > 0);
+        assert!(stats.hash_probes > 0);
+        assert_eq!(stats.literals + stats.total_match_length, data.len() as u64);
+        assert!(stats.average_match_length() >= 3.0);
+        assert!(stats.average_match_distance() >= 1.0);
+
+        // Check the block counts against what the stream actually contains.
+        let blocks = inspect::inspect_deflate(&compressed).unwrap();
+        assert_eq!(stats.blocks(), blocks.len() as u64);
+        let matches: usize = blocks.iter().map(|b| b.matches).sum();
+        assert_eq!(stats.matches, matches as u64);
+    }
+
+    #[test]
+    fn force_stored() {
+        let data = get_test_data();
+        let options = CompressionOptions::from_level(0);
+        let (compressed, stats) = deflate_bytes_with_stats(&data, options).unwrap();
+        assert!(decompress_to_end(&compressed) == data);
+        assert_eq!(stats.blocks(), stats.stored_blocks);
+        assert_eq!(stats.stored_blocks, (data.len() as u64 + 65534) / 65535);
+
+        // Check that syncing works when only using stored blocks.
+        let mut compressor = write::DeflateEncoder::new(Vec::new(), options);
+        compressor.write_all(&data[..1000]).unwrap();
+        compressor.flush().unwrap();
+        compressor.write_all(&data[1000..]).unwrap();
+        let compressed = compressor.finish().unwrap();
+        assert!(decompress_to_end(&compressed) == data);
+
+        assert_eq!(deflate_bytes_conf(&[], options).unwrap(), [1, 0, 0, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn empty_input() {
+        assert!(decompress_to_end(&deflate_bytes(&[]).unwrap()).is_empty());
+        assert!(decompress_zlib(&deflate_bytes_zlib(&[]).unwrap()).is_empty());
+        assert!(decompress_gzip(&deflate_bytes_gzip(&[]).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn force_fixed() {
+        let data = get_test_data();
+        let options = CompressionOptions {
+            special: SpecialOptions::ForceFixed,
+            ..Default::default()
+        };
+        let (compressed, stats) = deflate_bytes_with_stats(&data, options).unwrap();
+        assert!(decompress_to_end(&compressed) == data);
+        assert!(stats.fixed_blocks > 1);
+        assert_eq!(stats.blocks(), stats.fixed_blocks);
+    }
+
+    #[test]
+    fn window_bits() {
+        let data = get_test_data();
+        let options = CompressionOptions {
+            window_bits: 9,
+            ..Default::default()
+        };
+        let compressed = deflate_bytes_zlib_conf(&data, options).unwrap();
+        // CINFO should indicate a 512 byte window.
+        assert_eq!(compressed[0], 0x18);
+        assert!(decompress_zlib(&compressed) == data);
+        assert!(compressed.len() > deflate_bytes_zlib(&data).unwrap().len());
+    }
+
+    #[test]
+    fn levels() {
+        let data = get_test_data();
+        for level in 0..10 {
+            let compressed =
+                deflate_bytes_gzip_conf(&data, CompressionOptions::from_level(level)).unwrap();
+            assert!(decompress_gzip(&compressed) == data);
+        }
+        assert_eq!(deflate_bytes_conf(&data, CompressionOptions::from_level(6)).unwrap(),
+                   deflate_bytes(&data).unwrap());
+    }
+
+    #[test]
+    fn deflate64() {
+        use test_utils::decompress_deflate64;
+        // Random data repeated further back than the 32k window of standard deflate, followed by
+        // a run longer than the longest match standard deflate allows.
+        let mut state = 0x1234_5678u32;
+        let mut data: Vec<u8> = (0..50000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        data.extend_from_within(..);
+        data.extend(vec![7; 200000]);
+        data.extend(get_test_data());
+
+        let options = CompressionOptions {
+            deflate64: true,
+            ..Default::default()
+        };
+        let compressed = deflate_bytes_conf(&data, options).unwrap();
+        assert!(decompress_deflate64(&compressed) == data);
+        // Only Deflate64 can refer back to the first copy of the random data.
+        assert!(compressed.len() < deflate_bytes(&data).unwrap().len() - 40000);
+
+        for &level in &[0, 1, 3, 9] {
+            let options = CompressionOptions {
+                deflate64: true,
+                ..CompressionOptions::from_level(level)
+            };
+            assert!(decompress_deflate64(&deflate_bytes_conf(&data, options).unwrap()) == data);
+        }
+        let fixed = CompressionOptions {
+            special: SpecialOptions::ForceFixed,
+            ..options
+        };
+        assert!(decompress_deflate64(&deflate_bytes_conf(&data, fixed).unwrap()) == data);
+
+        // Write in chunks, with a sync flush in the middle.
+        let mut encoder = write::DeflateEncoder::new(Vec::new(), options);
+        chunked_write(&mut encoder, &data[..70000], 3000);
+        encoder.flush().unwrap();
+        chunked_write(&mut encoder, &data[70000..], 20000);
+        let compressed = encoder.finish().unwrap();
+        assert!(decompress_deflate64(&compressed) == data);
+    }
+
+    #[test]
+    fn rsyncable() {
+        let data = get_test_data();
+        let options = CompressionOptions {
+            rsyncable: true,
+            ..Default::default()
+        };
+        let compressed = deflate_bytes_conf(&data, options).unwrap();
+        assert!(decompress_to_end(&compressed) == data);
+        // Each full flush costs some compression, as matches can't refer back past it.
+        let normal = deflate_bytes(&data).unwrap();
+        assert!(compressed.len() > normal.len());
+        assert!(compressed.len() < normal.len() + normal.len() / 4);
+
+        // Writing in chunks gives the same output.
+        let mut encoder = write::DeflateEncoder::new(Vec::new(), options);
+        chunked_write(&mut encoder, &data, 3000);
+        assert!(encoder.finish().unwrap() == compressed);
+
+        // A small edit near the start only changes the start of the output.
+        let mut edited = data.clone();
+        edited[2000..2010].copy_from_slice(b"0123456789");
+        let edited_compressed = deflate_bytes_conf(&edited, options).unwrap();
+        assert!(decompress_to_end(&edited_compressed) == edited);
+        let common_suffix = compressed.iter()
+            .rev()
+            .zip(edited_compressed.iter().rev())
+            .take_while(|&(a, b)| a == b)
+            .count();
+        assert!(common_suffix > compressed.len() * 9 / 10);
+
+        for &level in &[0, 1, 9] {
+            let options = CompressionOptions {
+                rsyncable: true,
+                ..CompressionOptions::from_level(level)
+            };
+            assert!(decompress_to_end(&deflate_bytes_conf(&data, options).unwrap()) == data);
+        }
+    }
+
     #[test]
     /// Test the writer by inputing data in one chunk at the time.
     fn zlib_writer_chunks() {
@@ -301,4 +593,33 @@ mod test {
         chunk_test(50000);
         chunk_test((32768 * 2) + 258);
     }
+
+    #[test]
+    /// Test that the output doesn't depend on how the input is split between writes with the
+    /// other compression modes either.
+    fn writer_chunks_options() {
+        use input_buffer::BUFFER_SIZE;
+        let options = [CompressionOptions::default(),
+                       CompressionOptions::fast(),
+                       CompressionOptions::from_level(0),
+                       CompressionOptions::huffman_only(),
+                       CompressionOptions {
+                           special: SpecialOptions::ForceFixed,
+                           ..Default::default()
+                       },
+                       CompressionOptions {
+                           rsyncable: true,
+                           ..Default::default()
+                       },
+                       CompressionOptions {
+                           window_bits: 9,
+                           ..Default::default()
+                       },
+                       CompressionOptions::zlib_compatible(6, ZlibStrategy::Default)];
+        for &options in &options {
+            for &chunk_size in &[1, 259, 32768, BUFFER_SIZE + 1] {
+                chunk_test_with_options(chunk_size, options);
+            }
+        }
+    }
 }
@@ -1,13 +1,16 @@
 //! This module contains functionality for doing lz77 compression of data.
-use std::cmp;
-use std::ops::Range;
+use core::cmp;
+use core::ops::Range;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 
 use input_buffer::InputBuffer;
 use matching::longest_match;
 use lzvalue::LZValue;
 use huffman_table;
 use chained_hash_table::ChainedHashTable;
-use compression_options::{HIGH_MAX_HASH_CHECKS, HIGH_LAZY_IF_LESS_THAN};
+use binary_tree::BinaryTree;
+use compression_options::{HIGH_MAX_HASH_CHECKS, HIGH_LAZY_IF_LESS_THAN, DEFLATE64_WINDOW_SIZE};
 use output_writer::{OutputWriter, FixedWriter};
 use compress::Flush;
 
@@ -23,24 +26,56 @@ pub enum MatchingType {
     /// Use greedy matching: the matching algorithm simply uses a match right away
     /// if found.
     Greedy,
+    /// Like lazy matching, but if there is no better match at the next byte, the byte after
+    /// that is also checked when the current match is shorter than `good_length`, like the
+    /// `lazy2` parser in libdeflate.
+    ///
+    /// A match there has to be at least two bytes longer to be used, as it needs two literals
+    /// before it. This compresses slightly better than `Lazy`, but is slower.
+    Lazy2,
+}
+
+/// An enum describing the data structure used to find matches.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MatchFinder {
+    /// Walk the chain of previous positions with the same hash, checking up to
+    /// `max_hash_checks` of them.
+    HashChains,
+    /// Search a binary tree of the previous positions with the same hash, sorted by the data
+    /// at them, like 7-zip and libdeflate do. `max_hash_checks` limits the depth of the search.
+    ///
+    /// The tree finds the longest matches with far fewer checks than hash chains when many
+    /// earlier positions share a hash, as in data with a small alphabet, where it is several
+    /// times faster than hash chains with high values of `max_hash_checks`. As every position
+    /// is added to the tree, including the ones inside matches, it is slower on data like text,
+    /// where the hash chains are short. It uses an extra 512 KiB of memory, or 768 KiB with
+    /// Deflate64.
+    BinaryTree,
 }
 
 /// A struct that contains the hash table, and keeps track of where we are in the input data
 pub struct LZ77State {
     /// Struct containing hash chains that will be used to find matches.
     hash_table: ChainedHashTable,
+    /// Binary trees used to find matches instead of the hash chains, if enabled.
+    binary_tree: Option<Box<BinaryTree>>,
     /// True if this is the first window that is being processed.
     is_first_window: bool,
     /// Set to true when the last block has been processed.
     is_last_block: bool,
     /// How many bytes the last match in the previous window extended into the current one.
     overlap: usize,
-    /// The maximum number of hash entries to search.
-    max_hash_checks: u16,
-    /// Only lazy match if we have a match length less than this.
-    lazy_if_less_than: u16,
-    /// Whether to use greedy or lazy parsing
-    matching_type: MatchingType,
+    /// The settings used when searching for matches.
+    params: MatchParams,
+    /// The number of hash chain positions checked so far.
+    hash_probes: u64,
+    /// The number of bytes at the start of the input buffer that are a preset dictionary
+    /// rather than input data.
+    dictionary_length: usize,
+    /// Where to continue processing in the first window after a sync flush.
+    first_window_start: usize,
+    /// Where the next window to process after the first one starts in the input buffer.
+    next_window_start: usize,
 }
 
 impl LZ77State {
@@ -48,16 +83,31 @@ impl LZ77State {
                             b1: u8,
                             max_hash_checks: u16,
                             lazy_if_less_than: u16,
-                            matching_type: MatchingType)
+                            matching_type: MatchingType,
+                            window_size: usize)
                             -> LZ77State {
+        // The hash table and input buffer are laid out using at least the default window size.
+        let layout_window_size = cmp::max(window_size, DEFAULT_WINDOW_SIZE);
         LZ77State {
-            hash_table: ChainedHashTable::from_starting_values(b0, b1),
+            hash_table: ChainedHashTable::with_window_size(layout_window_size, b0, b1),
+            binary_tree: None,
             is_first_window: true,
             is_last_block: false,
             overlap: 0,
-            max_hash_checks: max_hash_checks,
-            lazy_if_less_than: lazy_if_less_than,
-            matching_type: matching_type,
+            params: MatchParams {
+                max_hash_checks,
+                lazy_if_less_than: lazy_if_less_than as usize,
+                matching_type,
+                window_size,
+                min_match_length: MIN_MATCH,
+                max_match_length: MAX_MATCH,
+                good_length: DEFAULT_GOOD_LENGTH,
+                nice_length: MAX_MATCH,
+            },
+            hash_probes: 0,
+            dictionary_length: 0,
+            first_window_start: 0,
+            next_window_start: 0,
         }
     }
 
@@ -66,29 +116,110 @@ impl LZ77State {
     pub fn _new_warmup(data: &[u8],
                        max_hash_checks: u16,
                        lazy_if_less_than: u16,
-                       matching_type: MatchingType)
+                       matching_type: MatchingType,
+                       window_size: usize)
                        -> LZ77State {
         LZ77State::from_starting_values(data[0],
                                         data[1],
                                         max_hash_checks,
                                         lazy_if_less_than,
-                                        matching_type)
+                                        matching_type,
+                                        window_size)
     }
 
     /// Creates a new LZ77 state
+    ///
+    /// `window_size` is the maximum distance back matches can refer to, and should be a power of
+    /// two no larger than 32k, or 64k for Deflate64.
+    ///
+    /// The input buffer used with the state has to be laid out for the same window size.
     pub fn new(max_hash_checks: u16,
                lazy_if_less_than: u16,
-               matching_type: MatchingType)
+               matching_type: MatchingType,
+               window_size: usize)
                -> LZ77State {
-        LZ77State::from_starting_values(55, 23, max_hash_checks, lazy_if_less_than, matching_type)
+        debug_assert!(window_size <= DEFLATE64_WINDOW_SIZE);
+        LZ77State::from_starting_values(55,
+                                        23,
+                                        max_hash_checks,
+                                        lazy_if_less_than,
+                                        matching_type,
+                                        window_size)
     }
 
     /// Resets the state excluding max_hash_checks and lazy_if_less_than
     pub fn reset(&mut self) {
+        self.clear_history();
+        self.hash_probes = 0;
+    }
+
+    /// Resets the hash chains and window position so the next input starts from scratch,
+    /// keeping the options and statistics.
+    pub fn clear_history(&mut self) {
         self.hash_table.reset();
+        if let Some(ref mut binary_tree) = self.binary_tree {
+            binary_tree.reset();
+        }
         self.is_first_window = true;
         self.is_last_block = false;
         self.overlap = 0;
+        self.dictionary_length = 0;
+        self.first_window_start = 0;
+    }
+
+    /// Note that the first `length` bytes in the input buffer are a preset dictionary.
+    ///
+    /// These bytes will be added to the hash table so that matches can refer to them, but
+    /// will not be output themselves.
+    pub fn set_dictionary_length(&mut self, length: usize) {
+        debug_assert!(self.is_first_window);
+        self.dictionary_length = length;
+    }
+
+    /// Only use matches of at least `length` bytes, outputting shorter ones as literals.
+    ///
+    /// Values lower than the minimum match length allowed by the format have no effect.
+    pub fn set_min_match_length(&mut self, length: usize) {
+        self.params.min_match_length = cmp::max(length, MIN_MATCH);
+    }
+
+    /// Allow matches of up to `length` bytes, which can be longer than `MAX_MATCH` for Deflate64.
+    pub fn set_max_match_length(&mut self, length: usize) {
+        self.params.max_match_length = cmp::max(length, MIN_MATCH);
+    }
+
+    /// When lazy matching, only check a quarter of `max_hash_checks` positions for a better
+    /// match if the previous match is at least `length` bytes long.
+    pub fn set_good_length(&mut self, length: usize) {
+        self.params.good_length = length;
+    }
+
+    /// Stop searching for matches once a match of at least `length` bytes is found.
+    pub fn set_nice_length(&mut self, length: usize) {
+        self.params.nice_length = cmp::max(length, MIN_MATCH);
+    }
+
+    /// Use `match_finder` to find matches.
+    pub fn set_match_finder(&mut self, match_finder: MatchFinder) {
+        self.binary_tree = match match_finder {
+            MatchFinder::HashChains => None,
+            MatchFinder::BinaryTree => {
+                let layout_window_size = cmp::max(self.params.window_size, DEFAULT_WINDOW_SIZE);
+                Some(Box::new(BinaryTree::new(layout_window_size)))
+            }
+        };
+    }
+
+    /// Slide the hash chains and binary trees down by `bytes`.
+    fn slide(&mut self, bytes: usize) {
+        self.hash_table.slide(bytes);
+        if let Some(ref mut binary_tree) = self.binary_tree {
+            binary_tree.slide(bytes);
+        }
+    }
+
+    pub fn has_dictionary(&self) -> bool {
+        self.dictionary_length > 0
     }
 
     pub fn set_last(&mut self) {
@@ -102,43 +233,152 @@ impl LZ77State {
     pub fn is_first_window(&self) -> bool {
         self.is_first_window
     }
+
+    /// The number of hash chain positions checked since the state was created or reset.
+    pub fn hash_probes(&self) -> u64 {
+        self.hash_probes
+    }
 }
 
 const DEFAULT_WINDOW_SIZE: usize = 32768;
+/// The `good_length` used unless another one is set.
+const DEFAULT_GOOD_LENGTH: usize = 32;
+
+/// The settings used when searching for matches.
+#[derive(Clone, Copy, Debug)]
+struct MatchParams {
+    /// The maximum number of hash entries to search.
+    max_hash_checks: u16,
+    /// Only lazy match if we have a match length less than this.
+    lazy_if_less_than: usize,
+    /// Whether to use greedy or lazy parsing
+    matching_type: MatchingType,
+    /// The maximum distance back a match can refer to.
+    window_size: usize,
+    /// Matches shorter than this are output as literals instead.
+    min_match_length: usize,
+    /// The longest match allowed by the output format.
+    max_match_length: usize,
+    /// Search less for a better match when lazy matching if the previous match is this long.
+    good_length: usize,
+    /// Stop searching once a match this long is found.
+    nice_length: usize,
+}
 
 fn process_chunk<W: OutputWriter>(data: &[u8],
                                   iterated_data: Range<usize>,
                                   hash_table: &mut ChainedHashTable,
+                                  binary_tree: Option<&mut BinaryTree>,
                                   writer: &mut W,
-                                  max_hash_checks: u16,
-                                  lazy_if_less_than: usize,
-                                  matching_type: MatchingType)
+                                  params: &MatchParams,
+                                  hash_probes: &mut u64)
                                   -> usize {
-    match matching_type {
+    match params.matching_type {
         MatchingType::Greedy => {
-            process_chunk_greedy(data, iterated_data, hash_table, writer, max_hash_checks)
+            process_chunk_greedy(data,
+                                 iterated_data,
+                                 hash_table,
+                                 binary_tree,
+                                 writer,
+                                 params,
+                                 hash_probes)
         }
         MatchingType::Lazy => {
             process_chunk_lazy(data,
                                iterated_data,
                                hash_table,
+                               binary_tree,
                                writer,
-                               max_hash_checks,
-                               lazy_if_less_than)
+                               params,
+                               hash_probes)
+        }
+        MatchingType::Lazy2 => {
+            process_chunk_lazy2(data,
+                                iterated_data,
+                                hash_table,
+                                binary_tree,
+                                writer,
+                                params,
+                                hash_probes)
         }
     }
 }
 
+/// Find the longest match at `position` using the binary trees if they are enabled, or the hash
+/// chains otherwise.
+///
+/// [See `matching::longest_match`](../matching/fn.longest_match.html)
+#[allow(clippy::too_many_arguments)]
+fn find_match(data: &[u8],
+              hash_table: &ChainedHashTable,
+              binary_tree: Option<&mut BinaryTree>,
+              position: usize,
+              prev_length: usize,
+              max_hash_checks: u16,
+              window_size: usize,
+              max_match_length: usize,
+              nice_length: usize,
+              hash_probes: &mut u64)
+              -> (usize, usize) {
+    match binary_tree {
+        Some(binary_tree) => {
+            binary_tree.longest_match(data,
+                                      position,
+                                      prev_length,
+                                      max_hash_checks,
+                                      window_size,
+                                      max_match_length,
+                                      nice_length,
+                                      hash_probes)
+        }
+        None => {
+            longest_match(data,
+                          hash_table,
+                          position,
+                          prev_length,
+                          max_hash_checks,
+                          window_size,
+                          max_match_length,
+                          nice_length,
+                          hash_probes)
+        }
+    }
+}
+
+/// The number of checks to make when looking for a match that is better than one of
+/// `prev_length` bytes at the previous position.
+///
+/// Only a quarter of `max_hash_checks` are made if the previous match is at least `good_length`
+/// bytes long.
+fn lazy_hash_checks(max_hash_checks: u16, prev_length: usize, good_length: usize) -> u16 {
+    if prev_length >= good_length {
+        max_hash_checks >> 2
+    } else {
+        max_hash_checks
+    }
+}
+
 fn process_chunk_lazy<W: OutputWriter>(data: &[u8],
                                        iterated_data: Range<usize>,
                                        hash_table: &mut ChainedHashTable,
+                                       mut binary_tree: Option<&mut BinaryTree>,
                                        writer: &mut W,
-                                       max_hash_checks: u16,
-                                       lazy_if_less_than: usize)
+                                       params: &MatchParams,
+                                       hash_probes: &mut u64)
                                        -> usize {
+    let MatchParams { max_hash_checks,
+                      lazy_if_less_than,
+                      window_size,
+                      min_match_length,
+                      max_match_length,
+                      good_length,
+                      nice_length,
+                      .. } = *params;
     let end = cmp::min(data.len(), iterated_data.end);
     let start = iterated_data.start;
     let current_chunk = &data[start..end];
+    // The binary trees only compare strings up to the nice length.
+    let tree_length = cmp::min(nice_length, max_match_length);
 
     let mut insert_it = current_chunk.iter().enumerate();
     let mut hash_it = {
@@ -181,15 +421,21 @@ fn process_chunk_lazy<W: OutputWriter>(data: &[u8],
                 let (match_len, match_dist) = {
                     // If there already was a decent match at the previous byte
                     // and we are lazy matching, do less match checks in this step.
-                    let max_hash_checks = if prev_length >= 32 {
-                        max_hash_checks >> 2
-                    } else {
-                        max_hash_checks
-                    };
+                    let max_hash_checks =
+                        lazy_hash_checks(max_hash_checks, prev_length, good_length);
 
                     // Check if we can find a better match here than the one we had at
                     // the previous byte.
-                    longest_match(data, hash_table, position, prev_length, max_hash_checks)
+                    find_match(data,
+                               hash_table,
+                               binary_tree.as_deref_mut(),
+                               position,
+                               prev_length,
+                               max_hash_checks,
+                               window_size,
+                               max_match_length,
+                               nice_length,
+                               hash_probes)
                 };
                 if match_len > lazy_if_less_than {
                     // We found a decent match, so we won't check for a better one at the next byte.
@@ -198,10 +444,18 @@ fn process_chunk_lazy<W: OutputWriter>(data: &[u8],
                 (match_len, match_dist)
             } else {
                 // We already had a decent match, so we don't bother checking for another one.
+                if let Some(ref mut binary_tree) = binary_tree {
+                    binary_tree.skip(data,
+                                     position,
+                                     max_hash_checks,
+                                     window_size,
+                                     tree_length,
+                                     hash_probes);
+                }
                 (NO_LENGTH, 0)
             };
 
-            if prev_length >= match_len && prev_length >= MIN_MATCH as usize && prev_distance > 0 {
+            if prev_length >= match_len && prev_length >= min_match_length && prev_distance > 0 {
                 // The previous match was better so we add it
                 // Casting note: length and distance is already bounded by the longest match
                 // function. Usize is just used for convenience
@@ -219,6 +473,14 @@ fn process_chunk_lazy<W: OutputWriter>(data: &[u8],
                 for (ipos, _) in taker {
                     if let Some(&i_hash_byte) = hash_taker.next() {
                         hash_table.add_hash_value(ipos + start, i_hash_byte);
+                        if let Some(ref mut binary_tree) = binary_tree {
+                            binary_tree.skip(data,
+                                             ipos + start,
+                                             max_hash_checks,
+                                             window_size,
+                                             tree_length,
+                                             hash_probes);
+                        }
                     }
                 }
 
@@ -262,15 +524,161 @@ fn process_chunk_lazy<W: OutputWriter>(data: &[u8],
     overlap
 }
 
+/// Like `process_chunk_lazy`, but also checks for a better match two bytes ahead.
+///
+/// Every position before `position` has been added to the hash chains (and binary trees) at
+/// the start of each step, and the ones after it haven't.
+fn process_chunk_lazy2<W: OutputWriter>(data: &[u8],
+                                        iterated_data: Range<usize>,
+                                        hash_table: &mut ChainedHashTable,
+                                        mut binary_tree: Option<&mut BinaryTree>,
+                                        writer: &mut W,
+                                        params: &MatchParams,
+                                        hash_probes: &mut u64)
+                                        -> usize {
+    let MatchParams { max_hash_checks,
+                      lazy_if_less_than,
+                      window_size,
+                      min_match_length,
+                      max_match_length,
+                      good_length,
+                      nice_length,
+                      .. } = *params;
+    let end = cmp::min(data.len(), iterated_data.end);
+    let start = iterated_data.start;
+    // Positions from here on don't have the three bytes needed to hash them, so they are output
+    // as literals.
+    let hash_end = if end - start > 2 {
+        cmp::min(end, data.len() - 2)
+    } else {
+        start
+    };
+    // The binary trees only compare strings up to the nice length.
+    let tree_length = cmp::min(nice_length, max_match_length);
+
+    const NO_LENGTH: usize = MIN_MATCH - 1;
+
+    // Add a position to the hash chains, and search for a match longer than `prev_length` at it
+    // if `search` is set.
+    let mut add_position = |position: usize, prev_length: usize, search: bool| {
+        hash_table.add_hash_value(position, data[position + 2]);
+        if search {
+            let max_hash_checks = lazy_hash_checks(max_hash_checks, prev_length, good_length);
+            find_match(data,
+                       hash_table,
+                       binary_tree.as_deref_mut(),
+                       position,
+                       prev_length,
+                       max_hash_checks,
+                       window_size,
+                       max_match_length,
+                       nice_length,
+                       hash_probes)
+        } else {
+            if let Some(ref mut binary_tree) = binary_tree {
+                binary_tree.skip(data,
+                                 position,
+                                 max_hash_checks,
+                                 window_size,
+                                 tree_length,
+                                 hash_probes);
+            }
+            (NO_LENGTH, 0)
+        }
+    };
+
+    // The number of bytes past end that was added due to finding a match that extends into
+    // the lookahead window.
+    let mut overlap = 0;
+    let mut position = start;
+    while position < end {
+        if position >= hash_end {
+            writer.write_literal(data[position]);
+            position += 1;
+            continue;
+        }
+
+        let (mut length, mut distance) = add_position(position, NO_LENGTH, true);
+        if length < min_match_length || distance == 0 {
+            writer.write_literal(data[position]);
+            position += 1;
+            continue;
+        }
+
+        // The next position that hasn't been added to the hash chains.
+        let mut next = position + 1;
+        // Look for a better match at the next two bytes, unless the current one is good enough.
+        while length <= lazy_if_less_than && position + 2 < hash_end {
+            let (next_length, next_distance) = add_position(position + 1, length, true);
+            next = position + 2;
+            if next_length > length {
+                writer.write_literal(data[position]);
+                position += 1;
+                length = next_length;
+                distance = next_distance;
+                continue;
+            }
+
+            // Only look two bytes ahead if the current match is short. A longer match is rarely
+            // beaten there, and the search would use fewer checks, which with binary trees also
+            // drops positions from them.
+            if length >= good_length {
+                break;
+            }
+            // The match two bytes ahead has to make up for the extra literal.
+            let (next_length, next_distance) = add_position(position + 2, length + 1, true);
+            next = position + 3;
+            if next_length > length + 1 {
+                writer.write_literal(data[position]);
+                writer.write_literal(data[position + 1]);
+                position += 2;
+                length = next_length;
+                distance = next_distance;
+            } else {
+                break;
+            }
+        }
+
+        // Casting note: length and distance is already bounded by the longest match
+        // function. Usize is just used for convenience
+        writer.write_length_distance(length as u16, distance as u16);
+
+        // Add the positions we jump over to the hash table.
+        let match_end = position + length;
+        for skipped in next..cmp::min(match_end, hash_end) {
+            add_position(skipped, NO_LENGTH, false);
+        }
+
+        // If the match is longer than the current window, we have note how many bytes we
+        // overlap, since we don't need to do any matching on these bytes in the next call of
+        // this function.
+        if match_end > end {
+            overlap = match_end - end;
+        }
+        position = match_end;
+    }
+    overlap
+}
+
 fn process_chunk_greedy<W: OutputWriter>(data: &[u8],
                                          iterated_data: Range<usize>,
                                          hash_table: &mut ChainedHashTable,
+                                         mut binary_tree: Option<&mut BinaryTree>,
                                          writer: &mut W,
-                                         max_hash_checks: u16)
+                                         params: &MatchParams,
+                                         hash_probes: &mut u64)
                                          -> usize {
+    let MatchParams { max_hash_checks,
+                      window_size,
+                      min_match_length,
+                      max_match_length,
+                      nice_length,
+                      .. } = *params;
     let end = cmp::min(data.len(), iterated_data.end);
     let start = iterated_data.start;
     let current_chunk = &data[start..end];
+    // The binary trees only compare strings up to the nice length.
+    let tree_length = cmp::min(nice_length, max_match_length);
 
     let mut insert_it = current_chunk.iter().enumerate();
     let mut hash_it = {
@@ -301,10 +709,19 @@ fn process_chunk_greedy<W: OutputWriter>(data: &[u8],
 
             // TODO: This should be cleaned up a bit
             let (match_len, match_dist) = {
-                longest_match(data, hash_table, position, NO_LENGTH, max_hash_checks)
+                find_match(data,
+                           hash_table,
+                           binary_tree.as_deref_mut(),
+                           position,
+                           NO_LENGTH,
+                           max_hash_checks,
+                           window_size,
+                           max_match_length,
+                           nice_length,
+                           hash_probes)
             };
 
-            if match_len >= MIN_MATCH as usize && match_dist > 0 {
+            if match_len >= min_match_length && match_dist > 0 {
                 // Casting note: length and distance is already bounded by the longest match
                 // function. Usize is just used for convenience
                 writer.write_length_distance(match_len as u16, match_dist as u16);
@@ -321,6 +738,14 @@ fn process_chunk_greedy<W: OutputWriter>(data: &[u8],
                 for (ipos, _) in taker {
                     if let Some(&i_hash_byte) = hash_taker.next() {
                         hash_table.add_hash_value(ipos + start, i_hash_byte);
+                        if let Some(ref mut binary_tree) = binary_tree {
+                            binary_tree.skip(data,
+                                             ipos + start,
+                                             max_hash_checks,
+                                             window_size,
+                                             tree_length,
+                                             hash_probes);
+                        }
                     }
                 }
 
@@ -376,7 +801,8 @@ pub fn lz77_compress_block_finish<W: OutputWriter>(data: &[u8],
 /// Compress a slice with lz77 compression.
 ///
 /// This function processes one window at a time, and returns when there is no input left,
-/// or it determines it's time to end a block.
+/// or it determines it's time to end a block. The buffer and the match finder are only slid
+/// once all the windows in the buffer have been processed.
 ///
 /// Returns the number of bytes of the input that were not processed, and a status describing
 /// whether there is no input, it's time to finish, or it's time to end the block.
@@ -386,8 +812,10 @@ pub fn lz77_compress_block<W: OutputWriter>(data: &[u8],
                                             mut writer: &mut W,
                                             flush: Flush)
                                             -> (usize, LZ77Status) {
-    // Currently we only support the maximum window size
-    let window_size = DEFAULT_WINDOW_SIZE;
+    // The buffer is laid out using at least the default window size, smaller windows only limit
+    // how far back matches can refer to.
+    let window_size = buffer.window_size();
+    debug_assert_eq!(cmp::max(state.params.window_size, DEFAULT_WINDOW_SIZE), window_size);
 
     let finish = flush == Flush::Finish || flush == Flush::Sync;
     let sync = flush == Flush::Sync;
@@ -395,73 +823,116 @@ pub fn lz77_compress_block<W: OutputWriter>(data: &[u8],
     let mut status = LZ77Status::EndBlock;
     let mut remaining_data = buffer.add_data(data);
 
-    while writer.buffer_length() < (window_size * 2) {
+    // The block size doesn't grow with the window, so the symbol frequencies still fit in the
+    // frequency type.
+    while writer.buffer_length() < (DEFAULT_WINDOW_SIZE * 2) {
         if state.is_first_window {
             // Don't do anything until we are either flushing, or we have at least one window of
             // data.
-            if buffer.current_end() >= (window_size * 2) + MAX_MATCH || finish {
+            if buffer.is_full() || finish {
+
+                let dictionary_length = state.dictionary_length;
+                if state.first_window_start == 0 {
+                    if buffer.get_buffer().len() > 2 {
+                        let b = buffer.get_buffer();
+                        // Warm up the hash with the two first values, so we can match against
+                        // index 0.
+                        state.hash_table.add_initial_hash_values(b[0], b[1]);
+                    }
 
-                if buffer.get_buffer().len() > 2 {
-                    let b = buffer.get_buffer();
-                    // Warm up the hash with the two first values, so we can match against
-                    // index 0.
-                    state.hash_table.add_initial_hash_values(b[0], b[1]);
+                    // If there is a preset dictionary at the start of the buffer, add it to the
+                    // hash table so we can match against it, and start processing after it.
+                    let data = &*buffer.get_buffer();
+                    let tree_length = cmp::min(state.params.nice_length,
+                                               state.params.max_match_length);
+                    for (n, &b) in data.iter().skip(2).take(dictionary_length).enumerate() {
+                        state.hash_table.add_hash_value(n, b);
+                        if let Some(ref mut binary_tree) = state.binary_tree {
+                            binary_tree.skip(data,
+                                             n,
+                                             state.params.max_hash_checks,
+                                             state.params.window_size,
+                                             tree_length,
+                                             &mut state.hash_probes);
+                        }
+                    }
                 }
-
-                let first_chunk_end = if finish && remaining_data.is_none() {
-                    // If we are finishing, make sure we include data in the lookahead area
+                // If we synced earlier in the first window, continue after the data processed
+                // then.
+                let first_chunk_start = cmp::max(dictionary_length, state.first_window_start);
+
+                // If we are finishing, make sure we include data in the lookahead area, unless
+                // there is more than a window of data left after the first one.
+                let last_chunk = finish && remaining_data.is_none() &&
+                                 buffer.current_end() <= window_size * 2 + MAX_MATCH;
+                let first_chunk_end = if last_chunk {
                     buffer.current_end()
                 } else {
                     cmp::min(window_size, buffer.current_end())
                 };
 
                 state.overlap = process_chunk::<W>(buffer.get_buffer(),
-                                                   0..first_chunk_end,
+                                                   first_chunk_start..first_chunk_end,
                                                    &mut state.hash_table,
+                                                   state.binary_tree.as_deref_mut(),
                                                    &mut writer,
-                                                   state.max_hash_checks,
-                                                   state.lazy_if_less_than as usize,
-                                                   state.matching_type);
+                                                   &state.params,
+                                                   &mut state.hash_probes);
 
                 // We are at the first window so we don't need to slide the hash table yet,
 
-                if first_chunk_end >= data.len() && finish {
+                state.is_first_window = false;
+                state.next_window_start = window_size;
+                if last_chunk {
                     if !sync {
                         state.set_last();
+                    } else if buffer.current_end() >= window_size {
+                        // As when syncing in later windows, slide the buffer and hash chains so
+                        // that the next call to this function starts at the right place.
+                        state.overlap = 0;
+                        let n = buffer.move_down();
+                        state.slide(n);
+                    } else {
+                        // There is less than a window of data, so we stay in the first window
+                        // and continue where we stopped when more data arrives.
+                        state.first_window_start = first_chunk_end;
+                        state.is_first_window = true;
                     }
                     status = LZ77Status::Finished;
                 } else {
                     status = LZ77Status::EndBlock;
                 }
-                state.is_first_window = false;
                 break;
             } else {
                 status = LZ77Status::NeedInput;
                 break;
             }
-        } else if buffer.current_end() >= (window_size * 2) + MAX_MATCH || finish {
-            // This isn't the first chunk, so we start reading at one window in in them
-            // buffer plus any additional overlap from earlier.
-            let start = window_size + state.overlap;
-
-            // Determine where we have to stop iterating to slide the buffer and hash,
-            // or stop because we are at the end of the input data.
-            let end = if remaining_data.is_none() && finish {
+        } else if buffer.is_full() || finish {
+            // This isn't the first chunk, so we start reading at the next window in the buffer
+            // plus any additional overlap from earlier.
+            let window_start = state.next_window_start;
+            let start = window_start + state.overlap;
+
+            // Determine where we have to stop iterating to move on to the next window, or stop
+            // because we are at the end of the input data.
+            let last_chunk = finish && remaining_data.is_none() &&
+                             buffer.current_end() <= window_start + window_size + MAX_MATCH;
+            let end = if last_chunk {
                 // If we are finishing, make sure we include the lookahead data
                 buffer.current_end()
             } else {
                 // Otherwise we process one window size of data.
-                cmp::min(window_size * 2, buffer.current_end())
+                cmp::min(window_start + window_size, buffer.current_end())
             };
 
             state.overlap = process_chunk::<W>(buffer.get_buffer(),
                                                start..end,
                                                &mut state.hash_table,
+                                               state.binary_tree.as_deref_mut(),
                                                &mut writer,
-                                               state.max_hash_checks,
-                                               state.lazy_if_less_than as usize,
-                                               state.matching_type);
-            if remaining_data.is_none() && finish {
+                                               &state.params,
+                                               &mut state.hash_probes);
+            if last_chunk {
                 // We stopped before or at the window size, so we are at the end.
                 if !sync {
                     state.set_last();
@@ -470,19 +941,26 @@ pub fn lz77_compress_block<W: OutputWriter>(data: &[u8],
                     // next call to this function starts at the right place.
                     state.overlap = 0;
                     let n = buffer.move_down();
-                    state.hash_table.slide(n);
+                    state.slide(n);
+                    state.next_window_start = window_size;
                 }
                 status = LZ77Status::Finished;
                 break;
             } else {
-                // We are not at the end, so slide and continue
-                // We slide the hash table back to make space for new hash values
-                // We only need to remember 32k bytes back (the maximum distance allowed by the
-                // deflate spec)
-                state.hash_table.slide(window_size);
-
-                // Slide the buffer
-                remaining_data = buffer.slide(remaining_data.unwrap_or(&[]));
+                state.next_window_start = end;
+                if end >= buffer.windows_end() {
+                    // We are not at the end, but all the windows in the buffer have been
+                    // processed, so slide and continue.
+                    // We slide the hash table back to make space for new hash values
+                    // We only need to remember one window back (the maximum distance allowed by
+                    // the format)
+                    let slide_length = buffer.slide_length();
+                    state.slide(slide_length);
+                    state.next_window_start -= slide_length;
+
+                    // Slide the buffer
+                    remaining_data = buffer.slide(remaining_data.unwrap_or(&[]));
+                }
 
                 status = LZ77Status::EndBlock;
             }
@@ -509,7 +987,8 @@ impl TestStruct {
         TestStruct {
             state: LZ77State::new(HIGH_MAX_HASH_CHECKS,
                                   HIGH_LAZY_IF_LESS_THAN,
-                                  MatchingType::Lazy),
+                                  MatchingType::Lazy,
+                                  DEFAULT_WINDOW_SIZE),
             buffer: InputBuffer::empty(),
             writer: FixedWriter::new(),
         }
@@ -561,7 +1040,12 @@ mod test {
     use test_utils::get_test_data;
 
     fn decompress_lz77(input: &[LZValue]) -> Vec<u8> {
-        let mut output = Vec::new();
+        decompress_lz77_with_dictionary(input, &[])
+    }
+
+    /// Decompress the lz77 data, with `dictionary` preceding the output.
+    fn decompress_lz77_with_dictionary(input: &[LZValue], dictionary: &[u8]) -> Vec<u8> {
+        let mut output = dictionary.to_vec();
         for p in input {
             match p.value() {
                 LZType::Literal(l) => output.push(l),
@@ -709,7 +1193,10 @@ mod test {
         let mut writer = FixedWriter::new();
 
         let mut buffer = InputBuffer::empty();
-        let mut state = LZ77State::new(4096, DEFAULT_LAZY_IF_LESS_THAN, MatchingType::Lazy);
+        let mut state = LZ77State::new(4096,
+                                       DEFAULT_LAZY_IF_LESS_THAN,
+                                       MatchingType::Lazy,
+                                       WINDOW_SIZE);
         let status = lz77_compress_block_finish(data, &mut state, &mut buffer, &mut writer);
         assert_eq!(status.1, LZ77Status::Finished);
         assert!(&buffer.get_buffer()[..data.len()] == data);
@@ -726,7 +1213,10 @@ mod test {
         let mut writer = FixedWriter::new();
 
         let mut buffer = InputBuffer::empty();
-        let mut state = LZ77State::new(0, DEFAULT_LAZY_IF_LESS_THAN, MatchingType::Lazy);
+        let mut state = LZ77State::new(0,
+                                       DEFAULT_LAZY_IF_LESS_THAN,
+                                       MatchingType::Lazy,
+                                       WINDOW_SIZE);
         let (bytes_consumed, status) =
             lz77_compress_block_finish(&data, &mut state, &mut buffer, &mut writer);
         assert_eq!(buffer.get_buffer().len(),
@@ -764,4 +1254,210 @@ mod test {
         };
         assert!(comp1 == comp2);
     }
+
+    /// Compress all of `data` using the provided state and buffer.
+    fn compress_with_state(data: &[u8],
+                           state: &mut LZ77State,
+                           buffer: &mut InputBuffer)
+                           -> Vec<LZValue> {
+        use output_writer::OutputWriter;
+        let mut writer = FixedWriter::new();
+        let mut out = Vec::new();
+        let mut slice = data;
+        while !state.is_last_block {
+            let bytes_written = lz77_compress_block_finish(slice, state, buffer, &mut writer).0;
+            slice = &slice[bytes_written..];
+            out.extend(writer.get_buffer());
+            writer.clear_buffer();
+        }
+        out
+    }
+
+    #[test]
+    fn window_limit() {
+        let data = get_test_data();
+        let window_size = 1024;
+        let mut state = LZ77State::new(HIGH_MAX_HASH_CHECKS,
+                                       HIGH_LAZY_IF_LESS_THAN,
+                                       MatchingType::Lazy,
+                                       window_size);
+        let compressed = compress_with_state(&data, &mut state, &mut InputBuffer::empty());
+        let max_distance = compressed.iter()
+            .filter_map(|v| match v.value() {
+                LZType::StoredLengthDistance(_, d) => Some(d as usize),
+                LZType::Literal(_) => None,
+            })
+            .max();
+        assert_eq!(max_distance, Some(window_size));
+        assert!(decompress_lz77(&compressed) == data);
+    }
+
+    #[test]
+    fn deflate64_matches() {
+        let mut data = get_test_data();
+        data.extend(vec![0; 100000]);
+        data.extend_from_within(..40000);
+        let mut state = LZ77State::new(HIGH_MAX_HASH_CHECKS,
+                                       HIGH_LAZY_IF_LESS_THAN,
+                                       MatchingType::Lazy,
+                                       DEFLATE64_WINDOW_SIZE);
+        state.set_max_match_length(usize::from(huffman_table::DEFLATE64_MAX_MATCH));
+        let mut buffer = InputBuffer::with_window_size(DEFLATE64_WINDOW_SIZE);
+        let compressed = compress_with_state(&data, &mut state, &mut buffer);
+        let matches = || {
+            compressed.iter().filter_map(|v| match v.value() {
+                LZType::StoredLengthDistance(l, d) => Some((l.actual_length(), d)),
+                LZType::Literal(_) => None,
+            })
+        };
+        assert!(matches().map(|(l, _)| l).max().unwrap() > MAX_MATCH as u16);
+        assert!(matches().map(|(_, d)| d).max().unwrap() > 32768);
+        assert!(decompress_lz77(&compressed) == data);
+    }
+
+    #[test]
+    fn lazy2() {
+        let mut data = get_test_data();
+        data.extend(vec![0; 100000]);
+        for &(window_size, deflate64) in &[(1024, false),
+                                           (WINDOW_SIZE, false),
+                                           (DEFLATE64_WINDOW_SIZE, true)] {
+            let mut state = LZ77State::new(HIGH_MAX_HASH_CHECKS,
+                                           HIGH_LAZY_IF_LESS_THAN,
+                                           MatchingType::Lazy2,
+                                           window_size);
+            let mut buffer = if deflate64 {
+                state.set_max_match_length(usize::from(huffman_table::DEFLATE64_MAX_MATCH));
+                InputBuffer::with_window_size(DEFLATE64_WINDOW_SIZE)
+            } else {
+                InputBuffer::empty()
+            };
+            let compressed = compress_with_state(&data, &mut state, &mut buffer);
+            let max_distance = compressed.iter()
+                .filter_map(|v| match v.value() {
+                    LZType::StoredLengthDistance(_, d) => Some(d as usize),
+                    LZType::Literal(_) => None,
+                })
+                .max();
+            assert!(max_distance.unwrap() <= window_size);
+            assert!(decompress_lz77(&compressed) == data);
+        }
+    }
+
+    #[test]
+    fn dictionary() {
+        let dictionary = b"This is the preset dictionary containing badgers and mushrooms.";
+        let data = b"Badgers and mushrooms, mushrooms and badgers.";
+        let mut state = LZ77State::new(HIGH_MAX_HASH_CHECKS,
+                                       HIGH_LAZY_IF_LESS_THAN,
+                                       MatchingType::Lazy,
+                                       WINDOW_SIZE);
+        let mut buffer = InputBuffer::empty();
+        buffer.add_data(dictionary);
+        state.set_dictionary_length(dictionary.len());
+        let compressed = compress_with_state(data, &mut state, &mut buffer);
+        // The first match has to refer back to the dictionary.
+        let first_match = compressed.iter().position(|v| match v.value() {
+            LZType::StoredLengthDistance(..) => true,
+            LZType::Literal(_) => false,
+        });
+        assert!(first_match.unwrap() < 4);
+
+        let mut with_dictionary = dictionary.to_vec();
+        with_dictionary.extend_from_slice(data);
+        let decompressed = decompress_lz77_with_dictionary(&compressed, dictionary);
+        assert_eq!(decompressed, with_dictionary);
+    }
+
+    #[test]
+    fn binary_tree() {
+        let data = get_test_data();
+        let compress = |matching_type, match_finder, window_size, deflate64| {
+            let mut state = LZ77State::new(HIGH_MAX_HASH_CHECKS,
+                                           HIGH_LAZY_IF_LESS_THAN,
+                                           matching_type,
+                                           window_size);
+            state.set_match_finder(match_finder);
+            let mut buffer = if deflate64 {
+                state.set_max_match_length(usize::from(huffman_table::DEFLATE64_MAX_MATCH));
+                InputBuffer::with_window_size(DEFLATE64_WINDOW_SIZE)
+            } else {
+                InputBuffer::empty()
+            };
+            let compressed = compress_with_state(&data, &mut state, &mut buffer);
+            assert!(decompress_lz77(&compressed) == data);
+            compressed.len()
+        };
+
+        for &matching_type in &[MatchingType::Lazy, MatchingType::Greedy, MatchingType::Lazy2] {
+            for &window_size in &[1024, WINDOW_SIZE] {
+                let chains = compress(matching_type, MatchFinder::HashChains, window_size, false);
+                let tree = compress(matching_type, MatchFinder::BinaryTree, window_size, false);
+                assert!(tree <= chains);
+            }
+        }
+        compress(MatchingType::Lazy, MatchFinder::BinaryTree, DEFLATE64_WINDOW_SIZE, true);
+
+        let dictionary = &data[..1000];
+        let mut state = LZ77State::new(HIGH_MAX_HASH_CHECKS,
+                                       HIGH_LAZY_IF_LESS_THAN,
+                                       MatchingType::Lazy,
+                                       WINDOW_SIZE);
+        state.set_match_finder(MatchFinder::BinaryTree);
+        let mut buffer = InputBuffer::empty();
+        buffer.add_data(dictionary);
+        state.set_dictionary_length(dictionary.len());
+        let compressed = compress_with_state(dictionary, &mut state, &mut buffer);
+        // The data is the same as the dictionary, so it is a few long matches.
+        assert!(compressed.len() < 10);
+        assert!(decompress_lz77_with_dictionary(&compressed, dictionary)[1000..] == dictionary[..]);
+    }
+
+    #[test]
+    fn good_and_nice_length() {
+        let data = get_test_data();
+        let compress = |match_finder, good_length, nice_length| {
+            let mut state = LZ77State::new(16,
+                                           HIGH_LAZY_IF_LESS_THAN,
+                                           MatchingType::Lazy,
+                                           WINDOW_SIZE);
+            state.set_match_finder(match_finder);
+            state.set_good_length(good_length);
+            state.set_nice_length(nice_length);
+            let compressed = compress_with_state(&data, &mut state, &mut InputBuffer::empty());
+            assert!(decompress_lz77(&compressed) == data);
+            state.hash_probes()
+        };
+
+        for &match_finder in &[MatchFinder::HashChains, MatchFinder::BinaryTree] {
+            let probes = compress(match_finder, 32, 258);
+            assert!(compress(match_finder, 8, 258) < probes);
+            assert!(compress(match_finder, 32, 16) < probes);
+        }
+    }
+
+    #[test]
+    fn min_match_length() {
+        let data = get_test_data();
+        let shortest_match = |matching_type, min_match_length| {
+            let mut state = LZ77State::new(HIGH_MAX_HASH_CHECKS,
+                                           HIGH_LAZY_IF_LESS_THAN,
+                                           matching_type,
+                                           WINDOW_SIZE);
+            state.set_min_match_length(min_match_length);
+            let compressed = compress_with_state(&data, &mut state, &mut InputBuffer::empty());
+            assert!(decompress_lz77(&compressed) == data);
+            compressed.iter()
+                .filter_map(|v| match v.value() {
+                    LZType::StoredLengthDistance(l, _) => Some(l.actual_length()),
+                    LZType::Literal(_) => None,
+                })
+                .min()
+        };
+
+        for &matching_type in &[MatchingType::Lazy, MatchingType::Greedy, MatchingType::Lazy2] {
+            assert_eq!(shortest_match(matching_type, 0), Some(3));
+            assert_eq!(shortest_match(matching_type, 6), Some(6));
+        }
+    }
 }
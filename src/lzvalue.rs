@@ -1,30 +1,27 @@
-use huffman_table::{MAX_DISTANCE, MIN_MATCH};
-#[cfg(test)]
-use huffman_table::MAX_MATCH;
+use huffman_table::MIN_MATCH;
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct StoredLength {
-    length: u8,
+    length: u16,
 }
 
 impl StoredLength {
     #[cfg(test)]
     pub fn from_actual_length(length: u16) -> StoredLength {
-        assert!(length <= MAX_MATCH && length >= MIN_MATCH);
-        StoredLength { length: (length - MIN_MATCH) as u8 }
+        assert!(length >= MIN_MATCH);
+        StoredLength { length: length - MIN_MATCH }
     }
 
-    pub fn new(stored_length: u8) -> StoredLength {
+    pub fn new(stored_length: u16) -> StoredLength {
         StoredLength { length: stored_length }
     }
 
-    pub fn stored_length(&self) -> u8 {
+    pub fn stored_length(&self) -> u16 {
         self.length
     }
 
-    #[cfg(test)]
     pub fn actual_length(&self) -> u16 {
-        u16::from(self.length) + MIN_MATCH
+        self.length + MIN_MATCH
     }
 }
 
@@ -36,23 +33,27 @@ pub enum LZType {
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct LZValue {
-    litlen: u8,
+    // The literal, or the length minus `MIN_MATCH` if this is a match.
+    // Lengths can be longer than 258 in Deflate64, so this can't be a u8.
+    litlen: u16,
     distance: u16,
 }
 
 impl LZValue {
     #[inline]
-    pub fn literal(value: u8) -> LZValue {
+    pub const fn literal(value: u8) -> LZValue {
         LZValue {
-            litlen: value,
+            litlen: value as u16,
             distance: 0,
         }
     }
 
     #[inline]
     pub fn length_distance(length: u16, distance: u16) -> LZValue {
-        assert!(distance > 0 && distance <= MAX_DISTANCE);
-        let stored_length = (length - MIN_MATCH) as u8;
+        // Any non-zero distance is valid for Deflate64. Invalid distances are rejected when the
+        // value is written.
+        debug_assert!(distance > 0);
+        let stored_length = length - MIN_MATCH;
         LZValue {
             litlen: stored_length,
             distance: distance,
@@ -64,7 +65,7 @@ impl LZValue {
         if self.distance != 0 {
             LZType::StoredLengthDistance(StoredLength::new(self.litlen), self.distance)
         } else {
-            LZType::Literal(self.litlen)
+            LZType::Literal(self.litlen as u8)
         }
     }
 }
@@ -72,7 +73,7 @@ impl LZValue {
 #[cfg(test)]
 mod test {
     use super::*;
-    use huffman_table::{MIN_MATCH, MIN_DISTANCE, MAX_MATCH, MAX_DISTANCE};
+    use huffman_table::{MIN_MATCH, MIN_DISTANCE, DEFLATE64_MAX_MATCH, DEFLATE64_MAX_DISTANCE};
     #[test]
     fn lzvalue() {
         for i in 0..255 as usize + 1 {
@@ -84,7 +85,7 @@ mod test {
             }
         }
 
-        for i in MIN_MATCH..MAX_MATCH + 1 {
+        for i in MIN_MATCH..=DEFLATE64_MAX_MATCH {
             let v = LZValue::length_distance(i, 5);
             if let LZType::StoredLengthDistance(l, _) = v.value() {
                 assert_eq!(l.actual_length(), i);
@@ -93,7 +94,7 @@ mod test {
             }
         }
 
-        for i in MIN_DISTANCE..MAX_DISTANCE + 1 {
+        for i in MIN_DISTANCE..=DEFLATE64_MAX_DISTANCE {
             let v = LZValue::length_distance(5, i);
 
             if let LZType::StoredLengthDistance(_, d) = v.value() {
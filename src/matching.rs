@@ -1,52 +1,69 @@
-use std::cmp;
+use core::cmp;
+use core::convert::TryInto;
 
-use chained_hash_table::{ChainedHashTable, WINDOW_SIZE};
+use chained_hash_table::ChainedHashTable;
+#[cfg(test)]
 use huffman_table;
 
+#[cfg(test)]
 const MAX_MATCH: usize = huffman_table::MAX_MATCH as usize;
 #[cfg(test)]
 const MIN_MATCH: usize = huffman_table::MIN_MATCH as usize;
 
 /// Get the length of the checked match
 /// The function returns number of bytes at and including `current_pos` that are the same as the
-/// ones at `pos_to_check`
-fn get_match_length(data: &[u8], current_pos: usize, pos_to_check: usize) -> usize {
-    // Unsafe version for comparison
-    // This doesn't actually make it much faster
-
-    // use std::mem::transmute_copy;
-
-    // let mut counter = 0;
-    // let max = cmp::min(data.len() - current_pos, MAX_MATCH);
-
-    // unsafe {
-    //     let mut cur = data.as_ptr().offset(current_pos as isize);
-    //     let mut tc = data.as_ptr().offset(pos_to_check as isize);
-    //     while (counter < max) &&
-    //           (transmute_copy::<u8, u32>(&*cur) == transmute_copy::<u8, u32>(&*tc)) {
-    //         counter += 4;
-    //         cur = cur.offset(4);
-    //         tc = tc.offset(4);
-    //     }
-    //     if counter > 3 {
-    //         cur = cur.offset(-4);
-    //         tc = tc.offset(-4);
-    //         counter -= 4;
-    //     }
-    //     while counter < max && *cur == *tc {
-    //         counter += 1;
-    //         cur = cur.offset(1);
-    //         tc = tc.offset(1);
-    //     }
-    // }
-
-    //    counter
-    data[current_pos..]
-        .iter()
-        .zip(data[pos_to_check..].iter())
-        .take(MAX_MATCH)
-        .take_while(|&(&a, &b)| a == b)
-        .count()
+/// ones at `pos_to_check`, up to `max_match`.
+#[inline]
+pub fn get_match_length(data: &[u8],
+                        current_pos: usize,
+                        pos_to_check: usize,
+                        max_match: usize)
+                        -> usize {
+    let current = &data[current_pos..];
+    let to_check = &data[pos_to_check..];
+    let max = cmp::min(max_match, cmp::min(current.len(), to_check.len()));
+    common_prefix_length(&current[..max], &to_check[..max])
+}
+
+#[cfg(feature = "simd")]
+use simd::common_prefix_length;
+
+/// Get the number of bytes at the start of `a` and `b`, which have the same length, that are
+/// equal.
+#[cfg(not(feature = "simd"))]
+#[inline]
+fn common_prefix_length(a: &[u8], b: &[u8]) -> usize {
+    common_prefix_length_words(a, b)
+}
+
+/// Read eight bytes as a little-endian word, so the first byte is the least significant one.
+#[inline]
+fn read_word(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes.try_into().unwrap())
+}
+
+/// `common_prefix_length` comparing eight bytes at a time.
+///
+/// The position of the lowest set bit of the XOR of two words is in the first byte that
+/// differs, as the words are little-endian.
+#[inline]
+pub fn common_prefix_length_words(a: &[u8], b: &[u8]) -> usize {
+    debug_assert_eq!(a.len(), b.len());
+    let mut length = 0;
+    for (a_word, b_word) in a.chunks_exact(8).zip(b.chunks_exact(8)) {
+        let diff = read_word(a_word) ^ read_word(b_word);
+        if diff != 0 {
+            return length + (diff.trailing_zeros() / 8) as usize;
+        }
+        length += 8;
+    }
+    length + common_prefix_length_bytes(&a[length..], &b[length..])
+}
+
+/// `common_prefix_length` comparing one byte at a time.
+#[inline]
+pub fn common_prefix_length_bytes(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|&(a, b)| a == b).count()
 }
 
 /// Try finding the position and length of the longest match in the input data.
@@ -61,29 +78,34 @@ fn get_match_length(data: &[u8], current_pos: usize, pos_to_check: usize) -> usi
 /// `position`: The position in the data to match against.
 /// `prev_length`: The length of the previous `longest_match` check to compare against.
 /// `max_hash_checks`: The maximum number of matching hash chain positions to check.
-#[allow(unused_assignments)]
+/// `window_size`: The maximum distance back a match can be found.
+/// `max_match`: The longest match allowed.
+/// `nice_length`: Stop searching once a match at least this long is found.
+/// `hash_probes`: Incremented by the number of hash chain positions that were checked.
+#[allow(unused_assignments, clippy::too_many_arguments)]
 pub fn longest_match(data: &[u8],
                      hash_table: &ChainedHashTable,
                      position: usize,
                      prev_length: usize,
-                     max_hash_checks: u16)
+                     max_hash_checks: u16,
+                     window_size: usize,
+                     max_match: usize,
+                     nice_length: usize,
+                     hash_probes: &mut u64)
                      -> (usize, usize) {
 
     // debug_assert_eq!(position, hash_table.current_head() as usize);
 
     // If we are at the start, we already have a match at the maximum length,
     // or we can't grow further, we stop here.
-    if position == 0 || prev_length >= MAX_MATCH || position + prev_length >= data.len() {
+    if position == 0 || prev_length >= max_match || position + prev_length >= data.len() {
         return (2, 0);
     }
 
-    let limit = if position > WINDOW_SIZE {
-        position - WINDOW_SIZE
-    } else {
-        0
-    };
+    let limit = position.saturating_sub(window_size);
 
-    let max_length = cmp::min((data.len() - position), MAX_MATCH);
+    let max_length = cmp::min(data.len() - position, max_match);
+    let nice_length = cmp::min(nice_length, max_length);
 
     // The position in the hash chain we are currently checking.
     let mut current_head = hash_table.get_prev(position) as usize;
@@ -96,21 +118,24 @@ pub fn longest_match(data: &[u8],
     let mut best_distance = 0;
 
     let mut iters = 0;
+    // The number of chain positions checked.
+    let mut probes = 0;
 
     // The position of the previous value in the hash chain.
     let mut prev_head = 0;
 
     while current_head >= limit && iters < max_hash_checks {
+        probes += 1;
         // We only check further if the match length can actually increase
         if data[position + best_length - 1..position + best_length + 1] ==
            data[current_head + best_length - 1..current_head + best_length + 1] {
-            let length = get_match_length(data, position, current_head);
+            let length = get_match_length(data, position, current_head, max_length);
             if length > best_length {
                 best_length = length;
                 best_distance = position - current_head;
-                if length == max_length {
-                    // We are at the max length, so there is no point
-                    // searching any longer
+                if length >= nice_length {
+                    // The match is good enough, or we are at the max length, so there is no
+                    // point searching any longer
                     break;
                 }
             }
@@ -126,6 +151,7 @@ pub fn longest_match(data: &[u8],
         }
         iters += 1;
     }
+    *hash_probes += probes;
 
     let r = if best_length > prev_length {
         best_length
@@ -141,28 +167,61 @@ pub fn longest_match(data: &[u8],
 #[cfg(test)]
 pub fn longest_match_current(data: &[u8], hash_table: &ChainedHashTable) -> (usize, usize) {
     use compression_options::MAX_HASH_CHECKS;
+    use chained_hash_table::WINDOW_SIZE;
     longest_match(data,
                   hash_table,
                   hash_table.current_position(),
                   MIN_MATCH as usize - 1,
-                  MAX_HASH_CHECKS)
+                  MAX_HASH_CHECKS,
+                  WINDOW_SIZE,
+                  MAX_MATCH,
+                  MAX_MATCH,
+                  &mut 0)
 }
 
 #[cfg(test)]
 mod test {
     use chained_hash_table::{filled_hash_table, HASH_BYTES, ChainedHashTable};
-    use super::{get_match_length, longest_match};
+    use super::{get_match_length, longest_match, common_prefix_length_words,
+                common_prefix_length_bytes, MAX_MATCH};
+    use core::cmp;
+    use alloc::vec::Vec;
 
     /// Test that match lengths are calculated correctly
     #[test]
     fn match_length() {
         let test_arr = [5u8, 5, 5, 5, 5, 9, 9, 2, 3, 5, 5, 5, 5, 5];
-        let l = get_match_length(&test_arr, 9, 0);
+        let l = get_match_length(&test_arr, 9, 0, MAX_MATCH);
         assert_eq!(l, 5);
-        let l2 = get_match_length(&test_arr, 9, 7);
+        let l2 = get_match_length(&test_arr, 9, 7, MAX_MATCH);
         assert_eq!(l2, 0);
-        let l3 = get_match_length(&test_arr, 10, 0);
+        let l3 = get_match_length(&test_arr, 10, 0, MAX_MATCH);
         assert_eq!(l3, 4);
+        let l4 = get_match_length(&test_arr, 9, 0, 3);
+        assert_eq!(l4, 3);
+    }
+
+    /// Compare the word-at-a-time and byte-at-a-time versions on matches of every length up to
+    /// the maximum, at every alignment.
+    #[test]
+    fn match_length_words_matches_bytes() {
+        let offset = 7 * 40;
+        let mut data: Vec<u8> = (0..offset + MAX_MATCH + 32).map(|n| (n % 7) as u8).collect();
+        for start in 0..16 {
+            for length in 0..MAX_MATCH + 8 {
+                // Make the data differ after `length` bytes.
+                let end = start + offset + length;
+                data[end] = 0xFF;
+                let a = &data[start + offset..];
+                let b = &data[start..start + a.len()];
+                let expected = common_prefix_length_bytes(a, b);
+                assert_eq!(expected, length);
+                assert_eq!(common_prefix_length_words(a, b), expected);
+                assert_eq!(get_match_length(&data, start + offset, start, MAX_MATCH),
+                           cmp::min(expected, MAX_MATCH));
+                data[end] = (end % 7) as u8;
+            }
+        }
     }
 
     /// Test that we get the longest of the matches
@@ -184,6 +243,23 @@ mod test {
         assert_eq!(length, 4);
     }
 
+    /// Test that the search stops at the first match that is at least `nice_length` long.
+    #[test]
+    fn nice_length_stops_search() {
+        let test_data = b"xTest data, Test_data,zTest data";
+        let hash_table = filled_hash_table(&test_data[..23 + 1 + HASH_BYTES - 1]);
+        let position = hash_table.current_position();
+
+        let (length, distance) =
+            longest_match(test_data, &hash_table, position, 2, 4096, 32768, MAX_MATCH, 4, &mut 0);
+        // The closer, shorter match is checked first.
+        assert_eq!((length, distance), (4, 11));
+
+        let (length, distance) =
+            longest_match(test_data, &hash_table, position, 2, 4096, 32768, MAX_MATCH, 5, &mut 0);
+        assert_eq!((length, distance), (9, 22));
+    }
+
     /// Make sure we can get a match at index zero
     #[test]
     fn match_index_zero() {
@@ -194,9 +270,31 @@ mod test {
             hash_table.add_hash_value(n, b);
         }
 
-        let (match_length, match_dist) = longest_match(test_data, &hash_table, 2, 0, 4096);
+        let mut probes = 0;
+        let (match_length, match_dist) =
+            longest_match(test_data, &hash_table, 2, 0, 4096, 32768, MAX_MATCH, MAX_MATCH,
+                          &mut probes);
 
         assert_eq!(match_dist, 1);
         assert!(match_length > 2);
+        assert!(probes > 0);
+    }
+
+    #[test]
+    fn match_outside_window() {
+        let test_data = b"abcdefghabcdefgh";
+
+        let mut hash_table = ChainedHashTable::from_starting_values(test_data[0], test_data[1]);
+        for (n, &b) in test_data[2..11].iter().enumerate() {
+            hash_table.add_hash_value(n, b);
+        }
+
+        let (match_length, match_dist) =
+            longest_match(test_data, &hash_table, 8, 2, 4096, 8, MAX_MATCH, MAX_MATCH, &mut 0);
+        assert_eq!((match_length, match_dist), (8, 8));
+
+        let (match_length, match_dist) =
+            longest_match(test_data, &hash_table, 8, 2, 4096, 7, MAX_MATCH, MAX_MATCH, &mut 0);
+        assert_eq!((match_length, match_dist), (2, 0));
     }
 }
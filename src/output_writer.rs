@@ -1,3 +1,4 @@
+use alloc::vec::Vec;
 use lzvalue::LZValue;
 use huffman_table::{NUM_LITERALS_AND_LENGTHS, NUM_DISTANCE_CODES, END_OF_BLOCK_POSITION,
                     get_distance_code, get_length_code};
@@ -78,7 +79,9 @@ pub struct DynamicWriter {
     // The two last length codes are not actually used, but only participates in code construction
     // Therefore, we ignore them to get the correct number of lengths
     frequencies: [FrequencyType; NUM_LITERALS_AND_LENGTHS],
-    distance_frequencies: [FrequencyType; NUM_DISTANCE_CODES],
+    // The two last distance codes are only used by Deflate64, and will have a frequency of 0
+    // otherwise.
+    distance_frequencies: [FrequencyType; NUM_DISTANCE_CODES + 2],
 }
 
 impl OutputWriter for DynamicWriter {
@@ -89,11 +92,13 @@ impl OutputWriter for DynamicWriter {
 
     fn write_length_distance(&mut self, length: u16, distance: u16) {
         self.fixed_writer.write_length_distance(length, distance);
-        let l_code_num = get_length_code(length).expect("Invalid length!");
-        self.frequencies[l_code_num] += 1;
-        let d_code_num = get_distance_code(distance)
-            .expect("Tried to get a distance code which was out of range!");
-        self.distance_frequencies[usize::from(d_code_num)] += 1;
+        // Invalid values are rejected when they are written, so they can be skipped here.
+        if let Some(l_code_num) = get_length_code(length) {
+            self.frequencies[l_code_num] += 1;
+        }
+        if let Some(d_code_num) = get_distance_code(distance) {
+            self.distance_frequencies[usize::from(d_code_num)] += 1;
+        }
     }
 
     fn buffer_length(&self) -> usize {
@@ -115,7 +120,7 @@ impl DynamicWriter {
         let mut w = DynamicWriter {
             fixed_writer: FixedWriter::new(),
             frequencies: [0; NUM_LITERALS_AND_LENGTHS],
-            distance_frequencies: [0; NUM_DISTANCE_CODES],
+            distance_frequencies: [0; NUM_DISTANCE_CODES + 2],
         };
         // This will always be 1,
         // since there will always only be one end of block marker in each block
@@ -129,7 +134,7 @@ impl DynamicWriter {
 
     pub fn clear_frequencies(&mut self) {
         self.frequencies = [0; NUM_LITERALS_AND_LENGTHS];
-        self.distance_frequencies = [0; NUM_DISTANCE_CODES];
+        self.distance_frequencies = [0; NUM_DISTANCE_CODES + 2];
         self.frequencies[END_OF_BLOCK_POSITION] = 1;
     }
 
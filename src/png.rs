@@ -0,0 +1,378 @@
+//! Writing the image data of [PNG](https://www.w3.org/TR/PNG/) files.
+//!
+//! The image data of a PNG file is a single zlib stream, split over one or more `IDAT` chunks
+//! that each start with their length and type and end with a CRC-32 of the type and data. Each
+//! row of the image is preceded by a byte giving the filter applied to it, which makes the data
+//! easier to compress.
+//!
+//! `IdatWriter` takes care of the filtering, compression and chunking, so only the chunks
+//! before and after the image data have to be written separately, e.g using `write_chunk`.
+//! Compressing with `SpecialOptions::Filtered` usually works better than the default for
+//! filtered image data.
+//!
+//! # Examples
+//!
+//! ```
+//! use deflate::{CompressionOptions, SpecialOptions};
+//! use deflate::png::{IdatWriter, FilterType};
+//!
+//! let options = CompressionOptions {
+//!     special: SpecialOptions::Filtered,
+//!     ..Default::default()
+//! };
+//! // An 8-bit RGB image, so 3 bytes per pixel.
+//! let mut writer = IdatWriter::new(Vec::new(), 3, options);
+//! writer.write_row(FilterType::None, &[255, 0, 0, 0, 255, 0]).unwrap();
+//! writer.write_row(FilterType::Up, &[0, 0, 255, 255, 255, 255]).unwrap();
+//! let idat_chunks = writer.finish().unwrap();
+//! # let _ = idat_chunks;
+//! ```
+
+use std::io::{self, Write};
+use std::thread;
+
+use byteorder::{WriteBytesExt, BigEndian};
+
+use checksum::{Crc32Checksum, RollingChecksum};
+use compression_options::CompressionOptions;
+use stats::CompressionStats;
+use writer::ZlibEncoder;
+use error::Error;
+
+/// The eight bytes every PNG file starts with.
+pub const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// The default size of the data of each `IDAT` chunk, which is the same as the one used by
+/// libpng.
+pub const DEFAULT_CHUNK_SIZE: usize = 8192;
+
+/// The largest chunk length allowed by the format.
+const MAX_CHUNK_SIZE: usize = 0x7FFF_FFFF;
+
+/// The filter applied to a row of the image before compressing it.
+///
+/// Each byte is predicted from the corresponding bytes of the pixel to the left (`a`), the pixel
+/// above (`b`) and the pixel above and to the left (`c`), and the difference from the
+/// prediction is stored.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum FilterType {
+    /// Store the row unchanged.
+    None = 0,
+    /// Predict each byte from `a`.
+    Sub = 1,
+    /// Predict each byte from `b`.
+    Up = 2,
+    /// Predict each byte from the average of `a` and `b`.
+    Average = 3,
+    /// Predict each byte from whichever of `a`, `b` or `c` is closest to `a + b - c`.
+    Paeth = 4,
+}
+
+fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let pa = (p - i16::from(a)).abs();
+    let pb = (p - i16::from(b)).abs();
+    let pc = (p - i16::from(c)).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Filter `row` given the previous row, appending the filter type and the filtered bytes to
+/// `output`.
+fn filter_row(filter: FilterType,
+              bytes_per_pixel: usize,
+              previous: &[u8],
+              row: &[u8],
+              output: &mut Vec<u8>) {
+    debug_assert_eq!(previous.len(), row.len());
+    output.push(filter as u8);
+    for (i, (&x, &b)) in row.iter().zip(previous).enumerate() {
+        let (a, c) = if i >= bytes_per_pixel {
+            (row[i - bytes_per_pixel], previous[i - bytes_per_pixel])
+        } else {
+            (0, 0)
+        };
+        let prediction = match filter {
+            FilterType::None => 0,
+            FilterType::Sub => a,
+            FilterType::Up => b,
+            FilterType::Average => ((u16::from(a) + u16::from(b)) / 2) as u8,
+            FilterType::Paeth => paeth_predictor(a, b, c),
+        };
+        output.push(x.wrapping_sub(prediction));
+    }
+}
+
+/// Write a PNG chunk with the given type and data, along with its length and CRC-32.
+pub fn write_chunk<W: Write>(writer: &mut W, chunk_type: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    if data.len() > MAX_CHUNK_SIZE {
+        return Err(Error::InvalidInput("The chunk is too large!").into());
+    }
+    let mut checksum = Crc32Checksum::new();
+    checksum.update_from_slice(chunk_type);
+    checksum.update_from_slice(data);
+
+    writer.write_u32::<BigEndian>(data.len() as u32)?;
+    writer.write_all(chunk_type)?;
+    writer.write_all(data)?;
+    writer.write_u32::<BigEndian>(checksum.current_hash())
+}
+
+/// A writer splitting the data written to it into `IDAT` chunks.
+struct ChunkWriter<W: Write> {
+    inner: W,
+    buffer: Vec<u8>,
+    chunk_size: usize,
+}
+
+impl<W: Write> ChunkWriter<W> {
+    /// Write the buffered data as a chunk, even if it is smaller than the chunk size.
+    fn write_buffered(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            write_chunk(&mut self.inner, b"IDAT", &self.buffer)?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ChunkWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = (self.chunk_size - self.buffer.len()).min(buf.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        if self.buffer.len() == self.chunk_size {
+            self.write_buffered()?;
+        }
+        Ok(n)
+    }
+
+    /// Flush the underlying writer.
+    ///
+    /// The buffered data is not written, as the encoder flushes its writer when finishing, so
+    /// this would end up writing a short chunk before the last one.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A writer compressing PNG image data, and writing it as `IDAT` chunks.
+///
+/// Rows can either be written with `write_row`, which applies the given filter, or as already
+/// filtered data, including the filter type byte of each row, using the `Write` interface.
+/// `write_row` keeps track of the previous row, so the two should not be mixed within a pass of
+/// the image.
+///
+/// All chunks except the last one contain exactly the chunk size bytes of data.
+pub struct IdatWriter<W: Write> {
+    // Option is used to allow us to implement `Drop` and `finish()` at the same time.
+    encoder: Option<ZlibEncoder<ChunkWriter<W>>>,
+    bytes_per_pixel: usize,
+    previous_row: Vec<u8>,
+    filtered_row: Vec<u8>,
+}
+
+impl<W: Write> IdatWriter<W> {
+    /// Create a new writer using the default chunk size.
+    ///
+    /// `bytes_per_pixel` is the number of bytes per complete pixel, rounded up to one for
+    /// bit depths below 8, and is used by the filters.
+    pub fn new<O: Into<CompressionOptions>>(writer: W,
+                                            bytes_per_pixel: usize,
+                                            options: O)
+                                            -> IdatWriter<W> {
+        IdatWriter::with_chunk_size(writer, bytes_per_pixel, DEFAULT_CHUNK_SIZE, options)
+    }
+
+    /// Create a new writer splitting the data into chunks of `chunk_size` bytes.
+    ///
+    /// `chunk_size` is clamped to the range allowed by the format, from 1 to `2^31 - 1`.
+    pub fn with_chunk_size<O: Into<CompressionOptions>>(writer: W,
+                                                        bytes_per_pixel: usize,
+                                                        chunk_size: usize,
+                                                        options: O)
+                                                        -> IdatWriter<W> {
+        let chunk_size = chunk_size.clamp(1, MAX_CHUNK_SIZE);
+        let chunk_writer = ChunkWriter {
+            inner: writer,
+            buffer: Vec::with_capacity(chunk_size.min(DEFAULT_CHUNK_SIZE)),
+            chunk_size,
+        };
+        IdatWriter {
+            encoder: Some(ZlibEncoder::new(chunk_writer, options)),
+            bytes_per_pixel: bytes_per_pixel.max(1),
+            previous_row: Vec::new(),
+            filtered_row: Vec::new(),
+        }
+    }
+
+    /// Filter a row of the image using `filter`, and write it along with the filter type.
+    ///
+    /// A row with a different length than the previous one starts a new pass, as does calling
+    /// `start_pass`.
+    pub fn write_row(&mut self, filter: FilterType, row: &[u8]) -> io::Result<()> {
+        if row.len() != self.previous_row.len() {
+            self.start_pass();
+            self.previous_row.resize(row.len(), 0);
+        }
+        self.filtered_row.clear();
+        filter_row(filter,
+                   self.bytes_per_pixel,
+                   &self.previous_row,
+                   row,
+                   &mut self.filtered_row);
+        self.encoder.as_mut().unwrap().write_all(&self.filtered_row)?;
+        self.previous_row.copy_from_slice(row);
+        Ok(())
+    }
+
+    /// Start a new pass of the image, so the next row is filtered as the first row.
+    ///
+    /// This is needed between the passes of an interlaced image.
+    pub fn start_pass(&mut self) {
+        self.previous_row.clear();
+    }
+
+    /// Get statistics about the data compressed so far.
+    pub fn stats(&self) -> CompressionStats {
+        self.encoder.as_ref().unwrap().stats()
+    }
+
+    /// Compress all remaining data and write the last chunk, returning the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        // We have to move the encoder out, and replace it with `None` to let the `IdatWriter`
+        // drop safely.
+        let mut chunk_writer = self.encoder.take().unwrap().finish()?;
+        chunk_writer.write_buffered()?;
+        Ok(chunk_writer.inner)
+    }
+}
+
+impl<W: Write> io::Write for IdatWriter<W> {
+    /// Write image data that is already filtered.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.encoder.as_mut().unwrap().write(buf)
+    }
+
+    /// Flush the compressed data, ending the current chunk early.
+    fn flush(&mut self) -> io::Result<()> {
+        let encoder = self.encoder.as_mut().unwrap();
+        encoder.flush()?;
+        let chunk_writer = encoder.get_mut();
+        chunk_writer.write_buffered()?;
+        chunk_writer.flush()
+    }
+}
+
+impl<W: Write> Drop for IdatWriter<W> {
+    /// When the writer is dropped, finish the compressed stream and write the last chunk.
+    ///
+    /// WARNING: This may silently fail if writing fails, so using this to finish the image data
+    /// for writers where writing might fail is not recommended, for that call finish() instead.
+    fn drop(&mut self) {
+        if let Some(encoder) = self.encoder.take() {
+            if !thread::panicking() {
+                if let Ok(mut chunk_writer) = encoder.finish() {
+                    let _ = chunk_writer.write_buffered();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use byteorder::{ReadBytesExt, BigEndian};
+    use compression_options::SpecialOptions;
+    use test_utils::decompress_to_end;
+
+    /// Split the output into chunks, checking their types and CRCs.
+    fn read_chunks(mut data: &[u8]) -> Vec<Vec<u8>> {
+        let mut chunks = Vec::new();
+        while !data.is_empty() {
+            let length = data.read_u32::<BigEndian>().unwrap() as usize;
+            assert_eq!(&data[..4], b"IDAT");
+            let mut checksum = Crc32Checksum::new();
+            checksum.update_from_slice(&data[..4 + length]);
+            chunks.push(data[4..4 + length].to_vec());
+            data = &data[4 + length..];
+            assert_eq!(data.read_u32::<BigEndian>().unwrap(), checksum.current_hash());
+        }
+        chunks
+    }
+
+    #[test]
+    fn chunk_sizes() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8 ^ (i >> 9) as u8).collect();
+        let mut writer =
+            IdatWriter::with_chunk_size(Vec::new(), 1, 1000, CompressionOptions::fast());
+        writer.write_all(&data).unwrap();
+        let chunks = read_chunks(&writer.finish().unwrap());
+
+        assert!(chunks.len() > 1);
+        let (last, rest) = chunks.split_last().unwrap();
+        assert!(rest.iter().all(|c| c.len() == 1000));
+        assert!(!last.is_empty() && last.len() <= 1000);
+        assert!(decompress_to_end(&chunks.concat()[2..]) == data);
+    }
+
+    #[test]
+    fn filters() {
+        let rows: Vec<Vec<u8>> = (0..8u8)
+            .map(|y| (0..24u8).map(|x| x.wrapping_mul(y).wrapping_add(x ^ 0x5A)).collect())
+            .collect();
+        let filters = [FilterType::None,
+                       FilterType::Sub,
+                       FilterType::Up,
+                       FilterType::Average,
+                       FilterType::Paeth];
+
+        let options = CompressionOptions {
+            special: SpecialOptions::Filtered,
+            ..Default::default()
+        };
+        let mut writer = IdatWriter::new(Vec::new(), 3, options);
+        for (row, &filter) in rows.iter().zip(filters.iter().cycle()) {
+            writer.write_row(filter, row).unwrap();
+        }
+        let chunks = read_chunks(&writer.finish().unwrap());
+        assert_eq!(chunks.len(), 1);
+        let filtered = decompress_to_end(&chunks[0][2..]);
+
+        // Undo the filters.
+        let mut previous = vec![0u8; 24];
+        for (row, filtered_row) in rows.iter().zip(filtered.chunks(25)) {
+            let filter = filtered_row[0];
+            let mut current = vec![0u8; 24];
+            for i in 0..24 {
+                let a = if i >= 3 { current[i - 3] } else { 0 };
+                let c = if i >= 3 { previous[i - 3] } else { 0 };
+                let b = previous[i];
+                let prediction = match filter {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((u16::from(a) + u16::from(b)) / 2) as u8,
+                    4 => paeth_predictor(a, b, c),
+                    _ => panic!("Invalid filter type {}", filter),
+                };
+                current[i] = filtered_row[i + 1].wrapping_add(prediction);
+            }
+            assert_eq!(&current, row);
+            previous = current;
+        }
+    }
+
+    #[test]
+    fn paeth() {
+        assert_eq!(paeth_predictor(10, 20, 10), 20);
+        assert_eq!(paeth_predictor(20, 10, 10), 20);
+        assert_eq!(paeth_predictor(10, 10, 20), 10);
+        assert_eq!(paeth_predictor(0, 255, 255), 0);
+    }
+}
@@ -0,0 +1,111 @@
+//! Content-defined sync points for rsyncable output.
+//!
+//! Like `gzip --rsyncable`, a rolling sum of the last `RSYNC_WINDOW` input bytes is kept, and a
+//! sync point is placed after each byte where the sum is a multiple of the window size. As the
+//! points only depend on the input bytes right before them, an edit to the input only moves the
+//! points close to it, and the compressed output after the next unaffected point stays the same.
+
+use core::mem;
+use alloc::boxed::Box;
+
+/// The number of bytes the rolling sum is calculated over.
+pub const RSYNC_WINDOW: usize = 4096;
+
+/// A rolling sum over the last `RSYNC_WINDOW` bytes of input.
+pub struct RsyncChecksum {
+    // The last `RSYNC_WINDOW` bytes of input, used as a ring buffer.
+    window: Box<[u8; RSYNC_WINDOW]>,
+    // The position in `window` of the oldest byte.
+    position: usize,
+    sum: u32,
+    // The number of bytes added since the last sync point.
+    since_sync: usize,
+}
+
+impl RsyncChecksum {
+    pub fn new() -> RsyncChecksum {
+        RsyncChecksum {
+            window: Box::new([0; RSYNC_WINDOW]),
+            position: 0,
+            sum: 0,
+            since_sync: 0,
+        }
+    }
+
+    /// Add bytes from `data` to the sum until the end of `data` or a sync point is reached.
+    ///
+    /// Returns the number of bytes up to and including the byte ending the chunk if a sync point
+    /// was found, in which case the bytes after it have not been added yet.
+    ///
+    /// Sync points are at least `RSYNC_WINDOW` bytes apart, which avoids placing one after every
+    /// byte in runs of zeroes.
+    pub fn find_sync_point(&mut self, data: &[u8]) -> Option<usize> {
+        for (n, &b) in data.iter().enumerate() {
+            let old = mem::replace(&mut self.window[self.position], b);
+            self.position = (self.position + 1) % RSYNC_WINDOW;
+            self.sum = self.sum.wrapping_add(u32::from(b)).wrapping_sub(u32::from(old));
+            self.since_sync += 1;
+            // `RSYNC_WINDOW` is a power of two, so this checks if the sum is a multiple of it.
+            if self.since_sync >= RSYNC_WINDOW && self.sum & (RSYNC_WINDOW as u32 - 1) == 0 {
+                self.since_sync = 0;
+                return Some(n + 1);
+            }
+        }
+        None
+    }
+
+    pub fn reset(&mut self) {
+        *self.window = [0; RSYNC_WINDOW];
+        self.position = 0;
+        self.sum = 0;
+        self.since_sync = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_utils::get_test_data;
+
+    /// Find all the sync points in `data`, adding it in chunks of `chunk_size` bytes.
+    fn sync_points(data: &[u8], chunk_size: usize) -> Vec<usize> {
+        let mut checksum = RsyncChecksum::new();
+        let mut points = Vec::new();
+        let mut offset = 0;
+        for chunk in data.chunks(chunk_size) {
+            let mut chunk = chunk;
+            while let Some(n) = checksum.find_sync_point(chunk) {
+                offset += n;
+                points.push(offset);
+                chunk = &chunk[n..];
+            }
+            offset += chunk.len();
+        }
+        points
+    }
+
+    #[test]
+    fn content_defined() {
+        let data = get_test_data();
+        let points = sync_points(&data, data.len());
+        assert!(points.len() > 5);
+        assert!(points.windows(2).all(|p| p[1] - p[0] >= RSYNC_WINDOW));
+        assert_eq!(sync_points(&data, 1000), points);
+
+        // Inserting bytes near the start only moves the points close to the insertion.
+        let mut edited = data[..3000].to_vec();
+        edited.extend_from_slice(b"Some inserted text");
+        edited.extend_from_slice(&data[3000..]);
+        let after_edit = |points: &[usize]| -> Vec<usize> {
+            points.iter().cloned().filter(|&p| p > 3000 + RSYNC_WINDOW * 2).collect()
+        };
+        let shifted: Vec<_> = points.iter().map(|p| p + 18).collect();
+        assert_eq!(after_edit(&sync_points(&edited, edited.len())), after_edit(&shifted));
+    }
+
+    #[test]
+    fn zeroes() {
+        let points = sync_points(&[0; RSYNC_WINDOW * 10], 5000);
+        assert_eq!(points.len(), 10);
+    }
+}
@@ -0,0 +1,182 @@
+//! SIMD versions of the match length comparison.
+//!
+//! This module is only available with the `simd` feature, and is the only place outside of the
+//! C API using unsafe code. The vector instructions are only used when the CPU is detected to
+//! support them at runtime, and the word-at-a-time comparison is used otherwise.
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+#[cfg(all(target_arch = "aarch64", target_endian = "little"))]
+use std::arch::aarch64::*;
+
+use matching::common_prefix_length_words;
+
+/// Matches shorter than this are compared a word at a time, as loading vectors and detecting
+/// the CPU features isn't worth it for them.
+const MIN_VECTOR_LENGTH: usize = 32;
+
+/// Get the number of bytes at the start of `a` and `b`, which have the same length, that are
+/// equal, using the widest vectors the CPU supports.
+#[inline]
+pub fn common_prefix_length(a: &[u8], b: &[u8]) -> usize {
+    debug_assert_eq!(a.len(), b.len());
+    if a.len() < MIN_VECTOR_LENGTH {
+        return common_prefix_length_words(a, b);
+    }
+    // Most of the matches checked are short, so check the first word on its own.
+    let first = common_prefix_length_words(&a[..8], &b[..8]);
+    if first < 8 {
+        return first;
+    }
+
+    // The functions using vector instructions are only called if the CPU supports them, which
+    // is all they need.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { common_prefix_length_avx2(a, b) };
+        }
+        if is_x86_feature_detected!("sse2") {
+            return unsafe { common_prefix_length_sse2(a, b) };
+        }
+    }
+    #[cfg(all(target_arch = "aarch64", target_endian = "little"))]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            return unsafe { common_prefix_length_neon(a, b) };
+        }
+    }
+    common_prefix_length_words(a, b)
+}
+
+/// `common_prefix_length` comparing 16 bytes at a time with SSE2.
+///
+/// The CPU has to support SSE2.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+unsafe fn common_prefix_length_sse2(a: &[u8], b: &[u8]) -> usize {
+    let len = a.len().min(b.len());
+    let mut length = 0;
+    while length + 16 <= len {
+        // The loads are unaligned, and both slices have at least 16 bytes left.
+        let x = _mm_loadu_si128(a.as_ptr().add(length) as *const __m128i);
+        let y = _mm_loadu_si128(b.as_ptr().add(length) as *const __m128i);
+        // One bit for each byte, set if the bytes are equal.
+        let equal = _mm_movemask_epi8(_mm_cmpeq_epi8(x, y)) as u32;
+        if equal != 0xFFFF {
+            return length + (!equal).trailing_zeros() as usize;
+        }
+        length += 16;
+    }
+    length + common_prefix_length_words(&a[length..len], &b[length..len])
+}
+
+/// `common_prefix_length` comparing 32 bytes at a time with AVX2.
+///
+/// The CPU has to support AVX2.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn common_prefix_length_avx2(a: &[u8], b: &[u8]) -> usize {
+    let len = a.len().min(b.len());
+    let mut length = 0;
+    while length + 32 <= len {
+        let x = _mm256_loadu_si256(a.as_ptr().add(length) as *const __m256i);
+        let y = _mm256_loadu_si256(b.as_ptr().add(length) as *const __m256i);
+        let equal = _mm256_movemask_epi8(_mm256_cmpeq_epi8(x, y)) as u32;
+        if equal != u32::MAX {
+            return length + (!equal).trailing_zeros() as usize;
+        }
+        length += 32;
+    }
+    length + common_prefix_length_words(&a[length..len], &b[length..len])
+}
+
+/// `common_prefix_length` comparing 16 bytes at a time with NEON.
+///
+/// The CPU has to support NEON. NEON has no movemask instruction, so the comparison result is
+/// checked as two little-endian words, where each byte is 0xFF if the bytes were equal.
+#[cfg(all(target_arch = "aarch64", target_endian = "little"))]
+#[target_feature(enable = "neon")]
+unsafe fn common_prefix_length_neon(a: &[u8], b: &[u8]) -> usize {
+    let len = a.len().min(b.len());
+    let mut length = 0;
+    while length + 16 <= len {
+        let x = vld1q_u8(a.as_ptr().add(length));
+        let y = vld1q_u8(b.as_ptr().add(length));
+        let equal = vreinterpretq_u64_u8(vceqq_u8(x, y));
+        let low = vgetq_lane_u64(equal, 0);
+        if low != u64::MAX {
+            return length + ((!low).trailing_zeros() / 8) as usize;
+        }
+        let high = vgetq_lane_u64(equal, 1);
+        if high != u64::MAX {
+            return length + 8 + ((!high).trailing_zeros() / 8) as usize;
+        }
+        length += 16;
+    }
+    length + common_prefix_length_words(&a[length..len], &b[length..len])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use matching::common_prefix_length_bytes;
+    use test_utils::get_test_data;
+
+    /// Check `prefix_length` against the byte-at-a-time comparison on matches of every length
+    /// up to 300 bytes, at every alignment, and on the matches in the test data.
+    fn check_against_bytes<F: Fn(&[u8], &[u8]) -> usize>(prefix_length: F) {
+        let mut a = vec![0x55u8; 400];
+        let b = a.clone();
+        for start in 0..32 {
+            for length in 0..300 {
+                a[start + length] = 0xAA;
+                let (x, y) = (&a[start..], &b[start..]);
+                assert_eq!(prefix_length(x, y), common_prefix_length_bytes(x, y));
+                assert_eq!(prefix_length(x, y), length);
+                // The match can also be cut off by the end of the data.
+                let (x, y) = (&x[..length / 2], &y[..length / 2]);
+                assert_eq!(prefix_length(x, y), length / 2);
+                a[start + length] = 0x55;
+            }
+        }
+
+        let data = get_test_data();
+        let data = &data[..20000];
+        for position in (1000..data.len()).step_by(7) {
+            for distance in 1..64 {
+                let end = (position + 258).min(data.len());
+                let x = &data[position..end];
+                let y = &data[position - distance..end - distance];
+                assert_eq!(prefix_length(x, y), common_prefix_length_bytes(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn simd_matches_bytes() {
+        check_against_bytes(common_prefix_length);
+        check_against_bytes(common_prefix_length_words);
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn x86_matches_bytes() {
+        if is_x86_feature_detected!("sse2") {
+            check_against_bytes(|a, b| unsafe { common_prefix_length_sse2(a, b) });
+        }
+        if is_x86_feature_detected!("avx2") {
+            check_against_bytes(|a, b| unsafe { common_prefix_length_avx2(a, b) });
+        }
+    }
+
+    #[test]
+    #[cfg(all(target_arch = "aarch64", target_endian = "little"))]
+    fn neon_matches_bytes() {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            check_against_bytes(|a, b| unsafe { common_prefix_length_neon(a, b) });
+        }
+    }
+}
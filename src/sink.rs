@@ -0,0 +1,305 @@
+//! The output interface used by the core of the encoder, so it works without the standard
+//! library, and a raw DEFLATE encoder using it.
+//!
+//! With the `std` feature, `Sink` is implemented for every `std::io::Write` type and
+//! `SinkError` is `std::io::Error`. Without it, `Sink` is implemented for `Vec<u8>`, and can be
+//! implemented for other outputs, like a UART or a fixed buffer.
+
+use alloc::boxed::Box;
+
+use compress::{compress_data_dynamic_n, Flush};
+use compression_options::CompressionOptions;
+use deflate_state::DeflateState;
+use error::Error;
+use stats::CompressionStats;
+
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+#[cfg(not(feature = "std"))]
+use core::fmt;
+
+/// The error returned by a `Sink`.
+#[cfg(feature = "std")]
+pub type SinkError = io::Error;
+
+/// The error returned by a `Sink`.
+#[cfg(not(feature = "std"))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SinkError {
+    /// The sink can't take any more data.
+    Full,
+    /// Writing failed for another reason.
+    Other(&'static str),
+}
+
+#[cfg(not(feature = "std"))]
+impl fmt::Display for SinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SinkError::Full => f.write_str("The sink is full"),
+            SinkError::Other(message) => f.write_str(message),
+        }
+    }
+}
+
+/// A destination for compressed output.
+///
+/// The methods are named differently from the ones in `std::io::Write`, so both traits can be
+/// in scope at the same time.
+pub trait Sink {
+    /// Write some of the data in `buf`, returning how many bytes were written.
+    ///
+    /// Writing 0 bytes of a non-empty buffer is treated as the sink being full.
+    fn write_output(&mut self, buf: &[u8]) -> Result<usize, SinkError>;
+
+    /// Make sure everything written so far has reached its destination.
+    fn flush_output(&mut self) -> Result<(), SinkError>;
+}
+
+#[cfg(feature = "std")]
+impl<W: io::Write + ?Sized> Sink for W {
+    fn write_output(&mut self, buf: &[u8]) -> Result<usize, SinkError> {
+        io::Write::write(self, buf)
+    }
+
+    fn flush_output(&mut self) -> Result<(), SinkError> {
+        io::Write::flush(self)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl Sink for Vec<u8> {
+    fn write_output(&mut self, buf: &[u8]) -> Result<usize, SinkError> {
+        self.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush_output(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<'a, S: Sink + ?Sized> Sink for &'a mut S {
+    fn write_output(&mut self, buf: &[u8]) -> Result<usize, SinkError> {
+        (**self).write_output(buf)
+    }
+
+    fn flush_output(&mut self) -> Result<(), SinkError> {
+        (**self).flush_output()
+    }
+}
+
+/// The error returned when a sink doesn't accept any more data.
+#[cfg(feature = "std")]
+pub fn write_zero_error() -> SinkError {
+    io::Error::new(io::ErrorKind::WriteZero, "failed to write the pending output")
+}
+
+/// The error returned when a sink doesn't accept any more data.
+#[cfg(not(feature = "std"))]
+pub fn write_zero_error() -> SinkError {
+    SinkError::Full
+}
+
+/// Whether writing should be retried after `err`.
+#[cfg(feature = "std")]
+pub fn is_interrupted(err: &SinkError) -> bool {
+    err.kind() == io::ErrorKind::Interrupted
+}
+
+/// Whether writing should be retried after `err`.
+#[cfg(not(feature = "std"))]
+pub fn is_interrupted(_: &SinkError) -> bool {
+    false
+}
+
+/// A raw DEFLATE encoder writing to a `Sink`, available without the standard library.
+///
+/// Unlike the encoders in `write`, errors from the sink are returned as they are, and the
+/// encoder can't be used any more after one.
+///
+/// # Examples
+///
+/// ```
+/// use deflate::{Compression, SinkEncoder};
+///
+/// let mut encoder = SinkEncoder::new(Vec::new(), Compression::Default);
+/// encoder.write(b"Some telemetry").unwrap();
+/// encoder.write(b"Some more telemetry").unwrap();
+/// let compressed_data = encoder.finish().unwrap();
+/// # let _ = compressed_data;
+/// ```
+pub struct SinkEncoder<S: Sink> {
+    // Boxed to avoid putting the buffers on the stack.
+    deflate_state: Box<DeflateState<S>>,
+    // Set when writing fails, as the state may be inconsistent after that.
+    failed: bool,
+}
+
+impl<S: Sink> SinkEncoder<S> {
+    /// Creates a new encoder writing to `sink` using the provided compression options.
+    pub fn new<O: Into<CompressionOptions>>(sink: S, options: O) -> SinkEncoder<S> {
+        SinkEncoder {
+            deflate_state: Box::new(DeflateState::new(options.into(), sink)),
+            failed: false,
+        }
+    }
+
+    /// Run `f` and write out the output, refusing to do anything if writing has failed before,
+    /// and remembering if it fails.
+    fn check_failure<F>(&mut self, f: F) -> Result<(), Error>
+        where F: FnOnce(&mut DeflateState<S>) -> Result<(), Error>
+    {
+        if self.failed {
+            return Err(Error::InvalidInput("The encoder can't be used after writing failed!"));
+        }
+        let result = f(&mut self.deflate_state)
+            .and_then(|()| self.deflate_state.encoder_state.writer.write_pending());
+        self.failed = result.is_err();
+        result
+    }
+
+    /// Compress `data`, writing any output that is ready to the sink.
+    ///
+    /// Data is buffered until there is enough to compress, so the output lags behind the
+    /// input unless the encoder is flushed.
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.check_failure(|state| compress_data_dynamic_n(data, state, Flush::None).map(|_| ()))
+    }
+
+    /// Write out all the data compressed so far, followed by an empty stored block, like the
+    /// Sync flush method from zlib.
+    ///
+    /// The output so far can then be decompressed on its own.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.check_failure(|state| compress_data_dynamic_n(&[], state, Flush::Sync).map(|_| ()))?;
+        self.deflate_state.encoder_state.writer.w.flush_output().map_err(Error::from)
+    }
+
+    /// Compress the remaining data and end the stream, returning the sink.
+    pub fn finish(mut self) -> Result<S, Error> {
+        self.check_failure(|state| compress_data_dynamic_n(&[], state, Flush::Finish).map(|_| ()))?;
+        self.deflate_state.encoder_state.writer.w.flush_output()?;
+        Ok(self.deflate_state.encoder_state.writer.w)
+    }
+
+    /// Use the provided data as a preset dictionary, which has to be done before writing any
+    /// data.
+    ///
+    /// The decompressor needs the same dictionary to decompress the output.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<(), Error> {
+        self.check_failure(|state| state.set_dictionary(dictionary))
+    }
+
+    /// Finish the current stream and start a new one writing to `sink`, returning the old sink.
+    ///
+    /// The compression options are kept, and the new stream doesn't refer back to the old one.
+    pub fn reset(&mut self, sink: S) -> Result<S, Error> {
+        self.check_failure(|state| compress_data_dynamic_n(&[], state, Flush::Finish).map(|_| ()))?;
+        self.deflate_state.encoder_state.writer.w.flush_output()?;
+        let old_sink = self.deflate_state.reset(sink)?;
+        Ok(old_sink)
+    }
+
+    /// Get statistics about the data compressed so far.
+    pub fn stats(&self) -> CompressionStats {
+        self.deflate_state.stats()
+    }
+
+    /// Get a reference to the sink.
+    pub fn get_ref(&self) -> &S {
+        &self.deflate_state.encoder_state.writer.w
+    }
+
+    /// Get a mutable reference to the sink.
+    ///
+    /// Writing to the sink directly will corrupt the compressed stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.deflate_state.encoder_state.writer.w
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::cmp;
+    use compression_options::Compression;
+    use test_utils::{get_test_data, decompress_to_end, decompress_with_dictionary};
+
+    /// A sink that only has room for a fixed amount of data, like a buffer in an embedded
+    /// device.
+    struct FixedSink {
+        buffer: [u8; 64],
+        len: usize,
+    }
+
+    impl Sink for FixedSink {
+        fn write_output(&mut self, buf: &[u8]) -> Result<usize, SinkError> {
+            let n = cmp::min(buf.len(), self.buffer.len() - self.len);
+            self.buffer[self.len..self.len + n].copy_from_slice(&buf[..n]);
+            self.len += n;
+            Ok(n)
+        }
+
+        fn flush_output(&mut self) -> Result<(), SinkError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sink_encoder() {
+        let data = get_test_data();
+        let mut encoder = SinkEncoder::new(Vec::new(), Compression::Default);
+        let (first, second) = data.split_at(data.len() / 2);
+        encoder.write(first).unwrap();
+        encoder.flush().unwrap();
+        {
+            // The data so far can be decompressed after a flush.
+            use flate2::read::DeflateDecoder;
+            use std::io::Read;
+            let mut decompressed = vec![0; first.len()];
+            DeflateDecoder::new(&encoder.get_ref()[..]).read_exact(&mut decompressed).unwrap();
+            assert!(decompressed == first);
+        }
+        encoder.write(second).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert!(decompress_to_end(&compressed) == data);
+    }
+
+    #[test]
+    fn sink_encoder_reset_and_dictionary() {
+        let dictionary = b"Some telemetry, ";
+        let data = b"Some telemetry, some more telemetry";
+        let mut encoder = SinkEncoder::new(Vec::new(), Compression::Fast);
+        encoder.write(data).unwrap();
+        let first = encoder.reset(Vec::new()).unwrap();
+        assert_eq!(decompress_to_end(&first), &data[..]);
+
+        encoder.set_dictionary(dictionary).unwrap();
+        encoder.write(data).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(decompress_with_dictionary(&compressed, dictionary)[dictionary.len()..],
+                   data[..]);
+    }
+
+    #[test]
+    fn sink_encoder_full() {
+        let data = get_test_data();
+        let mut encoder = SinkEncoder::new(FixedSink {
+                                               buffer: [0; 64],
+                                               len: 0,
+                                           },
+                                           Compression::Default);
+        let result = encoder.write(&data).and_then(|()| encoder.flush());
+        assert!(result.is_err());
+        assert_eq!(encoder.get_ref().len, 64);
+        // The encoder can't be used after an error.
+        match encoder.write(b"More data") {
+            Err(Error::InvalidInput(_)) => (),
+            other => panic!("Unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! A raw DEFLATE encoder that keeps all its buffers in a caller-provided struct, so it never
+//! allocates.
+
+use bitstream::FixedBuffer;
+use compress::Flush;
+use compression_options::{ZlibCompat, MAX_WINDOW_BITS};
+use encoder_state::EncoderState;
+use error::Error;
+use huffman_table::HuffmanTable;
+use sink::Sink;
+use stats::CompressionStats;
+use zlib_compat::ZlibState;
+
+/// The size of the buffer for output that hasn't been written to the sink yet.
+const OUTPUT_BUFFER_SIZE: usize = 4096;
+
+/// The buffers used by a `StaticEncoder`: the window, the hash tables, the buffered literals and
+/// matches of the current block, the huffman trees and the output buffer.
+///
+/// The size is fixed, about 285 KiB on 64-bit targets (see `core::mem::size_of`), and doesn't
+/// depend on the compression level. As `new` is a `const fn`, the buffers can be placed in a
+/// `static`. They should not be put on the stack on targets with small stacks.
+pub struct StaticBuffers {
+    state: ZlibState,
+    output: [u8; OUTPUT_BUFFER_SIZE],
+}
+
+impl StaticBuffers {
+    /// Create a new set of buffers.
+    pub const fn new() -> StaticBuffers {
+        StaticBuffers {
+            state: ZlibState::empty(),
+            output: [0; OUTPUT_BUFFER_SIZE],
+        }
+    }
+}
+
+impl Default for StaticBuffers {
+    fn default() -> StaticBuffers {
+        StaticBuffers::new()
+    }
+}
+
+/// A raw DEFLATE encoder using buffers provided by the caller, for targets where allocating
+/// isn't possible or allowed.
+///
+/// The encoder doesn't allocate at all, and it compresses the same way as the zlib compatible
+/// mode, so the output is identical to zlib's raw DEFLATE output with the same level and
+/// strategy.
+///
+/// Like `SinkEncoder`, errors from the sink are returned as they are, and the encoder can't be
+/// used any more after one. The buffers can be reused for a new encoder afterwards.
+///
+/// # Examples
+///
+/// ```
+/// use deflate::{StaticBuffers, StaticEncoder, ZlibCompat, ZlibStrategy};
+///
+/// // On a target without a heap, this would usually be a `static`.
+/// let mut buffers = Box::new(StaticBuffers::new());
+/// let compat = ZlibCompat { level: 6, strategy: ZlibStrategy::Default };
+/// let mut encoder = StaticEncoder::new(&mut buffers, Vec::new(), compat);
+/// encoder.write(b"Some telemetry").unwrap();
+/// encoder.write(b"Some more telemetry").unwrap();
+/// let compressed_data = encoder.finish().unwrap();
+/// # let _ = compressed_data;
+/// ```
+pub struct StaticEncoder<'a, S: Sink> {
+    zlib_state: &'a mut ZlibState,
+    encoder_state: EncoderState<S, FixedBuffer<'a>>,
+    stats: CompressionStats,
+    // Set when writing fails, as the state may be inconsistent after that.
+    failed: bool,
+}
+
+impl<'a, S: Sink> StaticEncoder<'a, S> {
+    /// Creates a new encoder writing to `sink` using `buffers`, compressing the way zlib does
+    /// with the given level and strategy.
+    pub fn new(buffers: &'a mut StaticBuffers,
+               sink: S,
+               compat: ZlibCompat)
+               -> StaticEncoder<'a, S> {
+        let StaticBuffers { ref mut state, ref mut output } = *buffers;
+        state.init(compat, MAX_WINDOW_BITS);
+        StaticEncoder {
+            zlib_state: state,
+            encoder_state: EncoderState::with_buffer(HuffmanTable::empty(),
+                                                     sink,
+                                                     FixedBuffer::new(output)),
+            stats: CompressionStats::default(),
+            failed: false,
+        }
+    }
+
+    /// Compress `data` with the given flush mode and write out the pending output, refusing to
+    /// do anything if writing has failed before, and remembering if it fails.
+    fn deflate(&mut self, data: &[u8], flush: Flush) -> Result<(), Error> {
+        if self.failed {
+            return Err(Error::InvalidInput("The encoder can't be used after writing failed!"));
+        }
+        let result = self.zlib_state
+            .deflate(data, flush, &mut self.encoder_state, &mut self.stats)
+            .and_then(|()| self.encoder_state.writer.write_pending());
+        self.failed = result.is_err();
+        self.stats.bytes_in += data.len() as u64;
+        result
+    }
+
+    /// Compress `data`, writing any output that is ready to the sink.
+    ///
+    /// Data is buffered until there is enough to compress, so the output lags behind the
+    /// input unless the encoder is flushed.
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.deflate(data, Flush::None)
+    }
+
+    /// Write out all the data compressed so far, followed by an empty stored block, like the
+    /// Sync flush method from zlib.
+    ///
+    /// The output so far can then be decompressed on its own.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.deflate(&[], Flush::Sync)?;
+        self.encoder_state.writer.w.flush_output().map_err(Error::from)
+    }
+
+    /// Compress the remaining data and end the stream, returning the sink.
+    pub fn finish(mut self) -> Result<S, Error> {
+        self.deflate(&[], Flush::Finish)?;
+        self.encoder_state.writer.w.flush_output()?;
+        Ok(self.encoder_state.writer.w)
+    }
+
+    /// Use the provided data as a preset dictionary, which has to be done before writing any
+    /// data.
+    ///
+    /// The decompressor needs the same dictionary to decompress the output.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<(), Error> {
+        if self.stats.bytes_in > 0 {
+            return Err(Error::InvalidInput("The dictionary has to be set before any data is \
+                                            written!"));
+        }
+        self.zlib_state.set_dictionary(dictionary);
+        Ok(())
+    }
+
+    /// Get statistics about the data compressed so far.
+    pub fn stats(&self) -> CompressionStats {
+        CompressionStats {
+            bytes_out: self.encoder_state.writer.bytes_written(),
+            ..self.stats
+        }
+    }
+
+    /// Get a reference to the sink.
+    pub fn get_ref(&self) -> &S {
+        &self.encoder_state.writer.w
+    }
+
+    /// Get a mutable reference to the sink.
+    ///
+    /// Writing to the sink directly will corrupt the compressed stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.encoder_state.writer.w
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use compression_options::{CompressionOptions, ZlibStrategy};
+    use sink::SinkEncoder;
+    use test_utils::{get_test_data, decompress_to_end, decompress_with_dictionary};
+
+    fn compat(level: u8, strategy: ZlibStrategy) -> ZlibCompat {
+        ZlibCompat { level, strategy }
+    }
+
+    #[test]
+    fn static_encoder_matches_zlib_compat() {
+        let input = get_test_data();
+        let mut buffers = Box::new(StaticBuffers::new());
+        for &(level, strategy) in &[(0, ZlibStrategy::Default),
+                                    (1, ZlibStrategy::Default),
+                                    (6, ZlibStrategy::Default),
+                                    (9, ZlibStrategy::Filtered),
+                                    (6, ZlibStrategy::Rle),
+                                    (6, ZlibStrategy::HuffmanOnly),
+                                    (6, ZlibStrategy::Fixed)] {
+            let compat = compat(level, strategy);
+            let mut encoder = StaticEncoder::new(&mut buffers, Vec::new(), compat);
+            encoder.write(&input).unwrap();
+            let compressed = encoder.finish().unwrap();
+            let options = CompressionOptions::zlib_compatible(level, strategy);
+            let mut reference = SinkEncoder::new(Vec::new(), options);
+            reference.write(&input).unwrap();
+            assert!(compressed == reference.finish().unwrap());
+
+            let mut encoder = StaticEncoder::new(&mut buffers, Vec::new(), compat);
+            for chunk in input.chunks(10000) {
+                encoder.write(chunk).unwrap();
+            }
+            let stats = encoder.stats();
+            let compressed = encoder.finish().unwrap();
+            assert!(decompress_to_end(&compressed) == input);
+            assert_eq!(stats.bytes_in, input.len() as u64);
+        }
+    }
+
+    #[test]
+    fn static_encoder_flush_and_dictionary() {
+        let dictionary = b"Some dictionary data, some dictionary data.";
+        let input = b"Some data, some dictionary data, some more data.";
+        let mut buffers = Box::new(StaticBuffers::new());
+        let compat = compat(6, ZlibStrategy::Default);
+        let mut encoder = StaticEncoder::new(&mut buffers, Vec::new(), compat);
+        encoder.set_dictionary(dictionary).unwrap();
+        encoder.write(input).unwrap();
+        encoder.flush().unwrap();
+        assert_eq!(encoder.stats().bytes_out, encoder.get_ref().len() as u64);
+        assert!(encoder.set_dictionary(dictionary).is_err());
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(decompress_with_dictionary(&compressed, dictionary)[dictionary.len()..],
+                   input[..]);
+    }
+}
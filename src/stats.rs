@@ -0,0 +1,66 @@
+use lzvalue::LZType;
+
+/// Statistics about a compression operation.
+///
+/// The counts cover everything processed since the encoder was created or last reset.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct CompressionStats {
+    /// Number of bytes of input data consumed by the encoder.
+    pub bytes_in: u64,
+    /// Number of bytes written to the underlying writer, including any headers and trailers.
+    pub bytes_out: u64,
+    /// Number of stored (uncompressed) blocks, including the empty blocks written when syncing.
+    pub stored_blocks: u64,
+    /// Number of blocks using the fixed huffman codes.
+    pub fixed_blocks: u64,
+    /// Number of blocks using dynamic huffman codes.
+    pub dynamic_blocks: u64,
+    /// Number of literals output in compressed blocks.
+    pub literals: u64,
+    /// Number of length/distance pairs output in compressed blocks.
+    pub matches: u64,
+    /// Sum of the lengths of all matches.
+    pub total_match_length: u64,
+    /// Sum of the distances of all matches.
+    pub total_match_distance: u64,
+    /// Number of hash chain entries checked while searching for matches.
+    pub hash_probes: u64,
+}
+
+impl CompressionStats {
+    /// Total number of blocks output.
+    pub fn blocks(&self) -> u64 {
+        self.stored_blocks + self.fixed_blocks + self.dynamic_blocks
+    }
+
+    /// The average length of the matches found, or 0 if there were none.
+    pub fn average_match_length(&self) -> f64 {
+        average(self.total_match_length, self.matches)
+    }
+
+    /// The average distance of the matches found, or 0 if there were none.
+    pub fn average_match_distance(&self) -> f64 {
+        average(self.total_match_distance, self.matches)
+    }
+
+    /// Count a value output to a compressed block.
+    #[inline]
+    pub(crate) fn add_lzvalue(&mut self, value: LZType) {
+        match value {
+            LZType::Literal(_) => self.literals += 1,
+            LZType::StoredLengthDistance(l, d) => {
+                self.matches += 1;
+                self.total_match_length += u64::from(l.actual_length());
+                self.total_match_distance += u64::from(d);
+            }
+        }
+    }
+}
+
+fn average(total: u64, count: u64) -> f64 {
+    if count == 0 {
+        0.0
+    } else {
+        total as f64 / count as f64
+    }
+}
@@ -1,16 +1,20 @@
-use std::io::Write;
-use std::io;
-use bitstream::BitWriter;
+use bitstream::{BitWriter, LsbWriter, PendingBuffer};
 
-use byteorder::{LittleEndian, WriteBytesExt};
+use error::Error;
+use sink::Sink;
 
 #[cfg(test)]
 const BLOCK_SIZE: u16 = 32000;
 
+/// The maximum number of bytes that can be stored in one stored block.
+pub const MAX_STORED_BLOCK_LENGTH: usize = 65535;
+
 const STORED_FIRST_BYTE: u8 = 0b0000_0000;
 pub const STORED_FIRST_BYTE_FINAL: u8 = 0b0000_0001;
 
-pub fn write_stored_header<W: BitWriter>(writer: &mut W, final_block: bool) -> io::Result<()> {
+pub fn write_stored_header<W: Sink, P: PendingBuffer>(writer: &mut LsbWriter<W, P>,
+                                                      final_block: bool)
+                                                      -> Result<(), Error> {
     let header = if final_block {
         STORED_FIRST_BYTE_FINAL
     } else {
@@ -19,28 +23,41 @@ pub fn write_stored_header<W: BitWriter>(writer: &mut W, final_block: bool) -> i
     // Write the block header
     writer.write_bits(header.into(), 3)?;
     // Output some extra zeroes if needed to align with the byte boundary.
-    writer.flush()
+    writer.pad_to_byte()
 }
 
 // Compress one stored block (excluding the header)
-pub fn compress_block_stored<W: Write>(input: &[u8], writer: &mut W) -> io::Result<usize> {
-    if input.len() > u16::max_value() as usize {
-        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Stored block too long!"));
+pub fn compress_block_stored<W: Sink, P: PendingBuffer>(input: &[u8],
+                                                        writer: &mut LsbWriter<W, P>)
+                                                        -> Result<usize, Error> {
+    compress_block_stored_parts(input, &[], writer)
+}
+
+// Compress one stored block (excluding the header) containing `first` followed by `second`
+pub fn compress_block_stored_parts<W: Sink, P: PendingBuffer>(first: &[u8],
+                                                              second: &[u8],
+                                                              writer: &mut LsbWriter<W, P>)
+                                                              -> Result<usize, Error> {
+    let len = first.len() + second.len();
+    if len > u16::MAX as usize {
+        return Err(Error::Internal("Stored block too long!"));
     };
     // The header is written before this function.
     // The next two bytes indicates the length
-    writer.write_u16::<LittleEndian>(input.len() as u16)?;
+    writer.write_bytes(&(len as u16).to_le_bytes())?;
     // the next two after the length is the ones complement of the length
-    writer.write_u16::<LittleEndian>(!input.len() as u16)?;
+    writer.write_bytes(&(!len as u16).to_le_bytes())?;
     // After this the data is written directly with no compression
-    writer.write(input)
+    writer.write_bytes(first)?;
+    writer.write_bytes(second)?;
+    Ok(len)
 }
 
 #[cfg(test)]
 pub fn compress_data_stored(input: &[u8]) -> Vec<u8> {
     let block_length = BLOCK_SIZE as usize;
 
-    let mut output = Vec::with_capacity(input.len() + 2);
+    let mut output = LsbWriter::new(Vec::with_capacity(input.len() + 2));
     let mut i = input.chunks(block_length).peekable();
     while let Some(chunk) = i.next() {
         let last_chunk = i.peek().is_none();
@@ -51,11 +68,12 @@ pub fn compress_data_stored(input: &[u8]) -> Vec<u8> {
         } else {
             STORED_FIRST_BYTE
         };
-        output.write(&[first_byte]).unwrap();
+        output.write_bytes(&[first_byte]).unwrap();
 
         compress_block_stored(chunk, &mut output).unwrap();
     }
-    output
+    output.flush_all().unwrap();
+    output.w
 }
 
 
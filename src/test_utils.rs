@@ -63,3 +63,125 @@ pub fn decompress_zlib(compressed: &[u8]) -> Vec<u8> {
     e.read_to_end(&mut result).unwrap();
     result
 }
+
+pub fn decompress_gzip(compressed: &[u8]) -> Vec<u8> {
+    use std::io::Read;
+    use flate2::read::GzDecoder;
+    let mut e = GzDecoder::new(&compressed[..]).unwrap();
+
+    let mut result = Vec::new();
+    e.read_to_end(&mut result).unwrap();
+    result
+}
+
+/// Decompress raw Deflate64 data.
+pub fn decompress_deflate64(compressed: &[u8]) -> Vec<u8> {
+    use std::io::Read;
+    use deflate64::Deflate64Decoder;
+    let mut d = Deflate64Decoder::with_buffer(compressed);
+
+    let mut result = Vec::new();
+    d.read_to_end(&mut result).unwrap();
+    result
+}
+
+/// Decompress raw deflate data that was compressed using a preset dictionary.
+///
+/// The dictionary is output first, followed by the decompressed data.
+pub fn decompress_with_dictionary(compressed: &[u8], dictionary: &[u8]) -> Vec<u8> {
+    use bitstream::LsbWriter;
+    use stored_block::compress_block_stored;
+    // Put the dictionary in a non-final stored block in front of the compressed data, so the
+    // decompressor has it in its window when decompressing the rest of the data.
+    let mut writer = LsbWriter::new(vec![0]);
+    compress_block_stored(dictionary, &mut writer).unwrap();
+    writer.flush_all().unwrap();
+    let mut input = writer.w;
+    input.extend_from_slice(compressed);
+    decompress_to_end(&input)
+}
+
+/// An entry read from a zip archive by `unzip`.
+pub struct ZipEntry {
+    pub name: String,
+    pub data: Vec<u8>,
+    pub external_attributes: u32,
+    pub zip64: bool,
+}
+
+/// A minimal zip archive reader, listing and extracting all entries using the central
+/// directory, and checking the CRC-32 of their data.
+pub fn unzip(archive: &[u8]) -> Vec<ZipEntry> {
+    use byteorder::{ByteOrder, LittleEndian};
+    use checksum::{Crc32Checksum, RollingChecksum};
+
+    let u16_at = |pos: usize| u64::from(LittleEndian::read_u16(&archive[pos..]));
+    let u32_at = |pos: usize| u64::from(LittleEndian::read_u32(&archive[pos..]));
+    let u64_at = |pos: usize| LittleEndian::read_u64(&archive[pos..]);
+
+    let end = (0..archive.len() - 21).rev().find(|&pos| u32_at(pos) == 0x0605_4b50).unwrap();
+    let (mut num_entries, mut offset) = (u16_at(end + 10), u32_at(end + 16));
+    if end >= 20 && u32_at(end - 20) == 0x0706_4b50 {
+        let zip64_end = u64_at(end - 12) as usize;
+        assert_eq!(u32_at(zip64_end), 0x0606_4b50);
+        num_entries = u64_at(zip64_end + 32);
+        offset = u64_at(zip64_end + 48);
+    }
+
+    let mut entries = Vec::new();
+    let mut pos = offset as usize;
+    for _ in 0..num_entries {
+        assert_eq!(u32_at(pos), 0x0201_4b50);
+        let method = u16_at(pos + 10);
+        let crc = u32_at(pos + 16) as u32;
+        let mut compressed_size = u32_at(pos + 20);
+        let mut uncompressed_size = u32_at(pos + 24);
+        let name_length = u16_at(pos + 28) as usize;
+        let extra_length = u16_at(pos + 30) as usize;
+        let comment_length = u16_at(pos + 32) as usize;
+        let external_attributes = u32_at(pos + 38) as u32;
+        let mut local_offset = u32_at(pos + 42);
+        let name = String::from_utf8(archive[pos + 46..pos + 46 + name_length].to_vec()).unwrap();
+
+        // The zip64 extra field contains the values that are 0xFFFFFFFF in the header.
+        let mut zip64 = false;
+        let mut extra = pos + 46 + name_length;
+        while extra < pos + 46 + name_length + extra_length {
+            if u16_at(extra) == 1 {
+                zip64 = true;
+                let mut field = extra + 4;
+                for value in [&mut uncompressed_size, &mut compressed_size, &mut local_offset] {
+                    if *value == 0xFFFF_FFFF {
+                        *value = u64_at(field);
+                        field += 8;
+                    }
+                }
+            }
+            extra += 4 + u16_at(extra + 2) as usize;
+        }
+
+        let local = local_offset as usize;
+        assert_eq!(u32_at(local), 0x0403_4b50);
+        let data_start = local + 30 + u16_at(local + 26) as usize + u16_at(local + 28) as usize;
+        let compressed = &archive[data_start..data_start + compressed_size as usize];
+        let data = match method {
+            0 => compressed.to_vec(),
+            8 => decompress_to_end(compressed),
+            9 => decompress_deflate64(compressed),
+            _ => panic!("Unknown compression method {}", method),
+        };
+        assert_eq!(data.len() as u64, uncompressed_size);
+        let mut checksum = Crc32Checksum::new();
+        checksum.update_from_slice(&data);
+        assert_eq!(checksum.current_hash(), crc);
+
+        entries.push(ZipEntry {
+            name,
+            data,
+            external_attributes,
+            zip64,
+        });
+        pos += 46 + name_length + extra_length + comment_length;
+    }
+    entries
+}
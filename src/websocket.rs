@@ -0,0 +1,267 @@
+//! Compression of WebSocket messages using the permessage-deflate extension
+//! ([RFC 7692](https://tools.ietf.org/html/rfc7692)).
+//!
+//! Each message is compressed as raw DEFLATE data ending with a sync flush, with the trailing
+//! `00 00 ff ff` of the empty stored block removed, as the extension requires. Depending on the
+//! negotiated parameters, the LZ77 history is either kept between messages (context takeover),
+//! so later messages can refer back to earlier ones, or reset after each message.
+//!
+//! # Examples
+//!
+//! ```
+//! use deflate::CompressionOptions;
+//! use deflate::websocket::{PerMessageDeflate, Parameters, Role};
+//!
+//! let parameters = Parameters {
+//!     server_max_window_bits: Some(10),
+//!     ..Default::default()
+//! };
+//! let mut compressor = PerMessageDeflate::new(Role::Server,
+//!                                             parameters,
+//!                                             CompressionOptions::default());
+//! let payload = compressor.compress_message(b"Hello, hello, hello!").unwrap();
+//! # let _ = payload;
+//! ```
+
+use std::mem;
+
+use compress::{compress_data_dynamic_n, Flush};
+use compression_options::{CompressionOptions, MAX_WINDOW_BITS, MIN_WINDOW_BITS};
+use deflate_state::DeflateState;
+use error::Error;
+use stats::CompressionStats;
+
+/// The bytes of the empty stored block ending each sync flushed message, which are removed
+/// from the payload.
+const SYNC_FLUSH_TRAILER: [u8; 4] = [0, 0, 0xFF, 0xFF];
+
+/// Which end of the connection the messages are compressed on.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Role {
+    /// Compress messages sent by the server, using the `server_*` parameters.
+    Server,
+    /// Compress messages sent by the client, using the `client_*` parameters.
+    Client,
+}
+
+/// The negotiated parameters of the permessage-deflate extension.
+///
+/// The default is what is used when no parameters are given: context takeover in both
+/// directions, and the maximum window size of 32 KiB.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Parameters {
+    /// The server resets the LZ77 history after each message.
+    pub server_no_context_takeover: bool,
+    /// The client resets the LZ77 history after each message.
+    pub client_no_context_takeover: bool,
+    /// The base-2 logarithm of the largest window size the server may use, from 8 to 15.
+    pub server_max_window_bits: Option<u8>,
+    /// The base-2 logarithm of the largest window size the client may use, from 8 to 15.
+    pub client_max_window_bits: Option<u8>,
+}
+
+impl Parameters {
+    /// Whether context takeover is used for messages sent by `role`.
+    pub fn context_takeover(&self, role: Role) -> bool {
+        match role {
+            Role::Server => !self.server_no_context_takeover,
+            Role::Client => !self.client_no_context_takeover,
+        }
+    }
+
+    /// The base-2 logarithm of the window size for messages sent by `role`.
+    ///
+    /// Values outside the range allowed by the specification are clamped to it.
+    pub fn window_bits(&self, role: Role) -> u8 {
+        let max_window_bits = match role {
+            Role::Server => self.server_max_window_bits,
+            Role::Client => self.client_max_window_bits,
+        };
+        max_window_bits.unwrap_or(MAX_WINDOW_BITS).clamp(MIN_WINDOW_BITS, MAX_WINDOW_BITS)
+    }
+}
+
+/// A compressor for the messages sent by one end of a WebSocket connection using the
+/// permessage-deflate extension.
+pub struct PerMessageDeflate {
+    // We use a box here to avoid putting the buffers on the stack.
+    deflate_state: Box<DeflateState<Vec<u8>>>,
+    context_takeover: bool,
+}
+
+impl PerMessageDeflate {
+    /// Create a new compressor for the messages sent by `role`, using the negotiated
+    /// `parameters`.
+    ///
+    /// The window size is the smaller of the one in `options` and the negotiated one.
+    pub fn new<O: Into<CompressionOptions>>(role: Role,
+                                            parameters: Parameters,
+                                            options: O)
+                                            -> PerMessageDeflate {
+        let mut options = options.into();
+        options.window_bits = options.clamped_window_bits().min(parameters.window_bits(role));
+        PerMessageDeflate {
+            deflate_state: Box::new(DeflateState::new(options, Vec::new())),
+            context_takeover: parameters.context_takeover(role),
+        }
+    }
+
+    /// Compress a message, returning the payload to send with the RSV1 bit set.
+    pub fn compress_message(&mut self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut payload = Vec::with_capacity(message.len() / 2);
+        self.compress_message_into(message, &mut payload)?;
+        Ok(payload)
+    }
+
+    /// Compress a message, appending the payload to `payload`.
+    pub fn compress_message_into(&mut self,
+                                 message: &[u8],
+                                 payload: &mut Vec<u8>)
+                                 -> Result<(), Error> {
+        // The provided vector is swapped in as the writer, so the payload is written directly
+        // after the existing contents.
+        mem::swap(&mut self.deflate_state.encoder_state.writer.w, payload);
+        let result = compress_data_dynamic_n(message, &mut self.deflate_state, Flush::Sync)
+            .and_then(|_| self.deflate_state.encoder_state.writer.write_pending());
+        mem::swap(&mut self.deflate_state.encoder_state.writer.w, payload);
+        // Writing to a vector can't fail, so this is an internal error.
+        result?;
+
+        if !payload.ends_with(&SYNC_FLUSH_TRAILER) {
+            return Err(Error::Internal("The message did not end with a sync flush!"));
+        }
+        let length = payload.len() - SYNC_FLUSH_TRAILER.len();
+        payload.truncate(length);
+
+        if !self.context_takeover {
+            self.reset();
+        }
+        Ok(())
+    }
+
+    /// Reset the LZ77 history, so the next message doesn't refer back to earlier ones.
+    ///
+    /// This is done after each message if context takeover is not used.
+    pub fn reset(&mut self) {
+        // The writer is empty, since all output is moved to the payload.
+        let _ = self.deflate_state.reset(Vec::new());
+    }
+
+    /// Get statistics about the messages compressed since the compressor was created or last
+    /// reset.
+    ///
+    /// The output byte count includes the removed trailers.
+    pub fn stats(&self) -> CompressionStats {
+        self.deflate_state.stats()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_utils::{get_test_data, decompress_to_end};
+
+    /// Decompress the payloads of a sequence of messages compressed with context takeover.
+    fn decompress_messages(payloads: &[Vec<u8>]) -> Vec<u8> {
+        let mut stream = Vec::new();
+        for payload in payloads {
+            stream.extend_from_slice(payload);
+            stream.extend_from_slice(&SYNC_FLUSH_TRAILER);
+        }
+        // End the stream with an empty final stored block.
+        stream.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+        decompress_to_end(&stream)
+    }
+
+    #[test]
+    fn context_takeover() {
+        let data = get_test_data();
+        let messages: Vec<_> = data.chunks(5000).take(20).collect();
+        let mut compressor = PerMessageDeflate::new(Role::Server,
+                                                    Parameters::default(),
+                                                    CompressionOptions::default());
+        let mut payloads: Vec<_> =
+            messages.iter().map(|m| compressor.compress_message(m).unwrap()).collect();
+        assert!(decompress_messages(&payloads) == messages.concat());
+
+        // Repeating the last message should only need a few matches, as it can refer back to
+        // the previous one.
+        payloads.push(compressor.compress_message(messages[19]).unwrap());
+        assert!(payloads[20].len() < 100);
+        assert!(decompress_messages(&payloads) == [&messages.concat()[..], messages[19]].concat());
+    }
+
+    #[test]
+    fn no_context_takeover() {
+        let data = get_test_data();
+        let message = &data[..5000];
+        let parameters = Parameters {
+            client_no_context_takeover: true,
+            ..Default::default()
+        };
+        let mut compressor = PerMessageDeflate::new(Role::Client,
+                                                    parameters,
+                                                    CompressionOptions::default());
+        let first = compressor.compress_message(message).unwrap();
+        let second = compressor.compress_message(message).unwrap();
+        // Each message has to be decompressible on its own.
+        assert_eq!(first, second);
+        assert!(decompress_messages(&[second]) == message);
+
+        // The server parameters don't apply to the client.
+        assert!(!parameters.context_takeover(Role::Client));
+        assert!(parameters.context_takeover(Role::Server));
+    }
+
+    #[test]
+    fn window_bits() {
+        // Data without internal matches, so it can only be compressed by referring back to
+        // the previous message.
+        let mut state = 0x2545_F491u32;
+        let message: Vec<u8> = (0..1000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state >> 24) as u8
+            })
+            .collect();
+        let compress_twice = |parameters: Parameters| {
+            let mut compressor = PerMessageDeflate::new(Role::Server,
+                                                        parameters,
+                                                        CompressionOptions::default());
+            let first = compressor.compress_message(&message).unwrap();
+            let second = compressor.compress_message(&message).unwrap();
+            assert!(decompress_messages(&[first, second.clone()]) == [&message[..], &message[..]]
+                .concat());
+            second
+        };
+
+        assert!(compress_twice(Parameters::default()).len() < 50);
+        let parameters = Parameters {
+            server_max_window_bits: Some(9),
+            // Applies to the client only.
+            client_max_window_bits: Some(15),
+            ..Default::default()
+        };
+        assert!(compress_twice(parameters).len() > 900);
+        assert_eq!(parameters.window_bits(Role::Server), 9);
+        assert_eq!(Parameters { client_max_window_bits: Some(7), ..parameters }
+                       .window_bits(Role::Client),
+                   8);
+    }
+
+    #[test]
+    fn empty_message() {
+        let mut compressor = PerMessageDeflate::new(Role::Server,
+                                                    Parameters::default(),
+                                                    CompressionOptions::default());
+        let empty = compressor.compress_message(&[]).unwrap();
+        let message = compressor.compress_message(b"Some data").unwrap();
+        assert!(decompress_messages(&[empty, message]) == b"Some data");
+
+        let mut payload = vec![0x42];
+        compressor.compress_message_into(b"Some data", &mut payload).unwrap();
+        assert_eq!(payload[0], 0x42);
+    }
+}
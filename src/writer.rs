@@ -1,99 +1,399 @@
 use std::io::Write;
 use std::io;
 
-use byteorder::{WriteBytesExt, BigEndian};
-
-use checksum::{Adler32Checksum, RollingChecksum};
 use compress::compress_data_dynamic_n;
 use compress::Flush;
 use deflate_state::DeflateState;
 use compression_options::CompressionOptions;
-use zlib::{write_zlib_header, CompressionLevel};
+use framing::{Format, Framing, FormatFraming, RawFraming, ZlibFraming, GzipFraming};
+use stats::CompressionStats;
 use std::thread;
+use error::Error;
+
+/// Where an encoder is in the stream.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    /// Data can be written.
+    Active,
+    /// The end of the stream has been output, no more data is accepted until the encoder is
+    /// reset.
+    Finished,
+    /// Writing failed, the output may be incomplete and the internal state inconsistent, so
+    /// nothing more is output.
+    Poisoned,
+}
 
-/// A DEFLATE encoder/compressor.
-///
-/// A struct implementing a `Write` interface that takes unencoded data and compresses it to
-/// the provided writer using DEFLATE compression.
-///
-/// # Examples
-///
-/// ```
-/// use std::io::Write;
-///
-/// use deflate::Compression;
-/// use deflate::write::DeflateEncoder;
-///
-/// let data = b"This is some test data";
-/// let mut encoder = DeflateEncoder::new(Vec::new(), Compression::Default);
-/// encoder.write_all(data).unwrap();
-/// let compressed_data = encoder.finish().unwrap();
-/// # let _ = compressed_data;
-/// ```
-pub struct DeflateEncoder<W: Write> {
+/// The implementation shared by all the encoders, writing DEFLATE data wrapped in the header
+/// and trailer of the container format `F`.
+struct FramedEncoder<W: Write, F: Framing> {
     // We use a box here to avoid putting the buffers on the stack
     // It's done here rather than in the structs themselves for now to
     // keep the data close in memory.
     // Option is used to allow us to implement `Drop` and `finish()` at the same time.
     deflate_state: Option<Box<DeflateState<W>>>,
+    framing: F,
+    header_written: bool,
+    // Set when a sync flush has been output, but not all of it has been written yet, so
+    // retrying `flush` doesn't output another one.
+    sync_pending: bool,
+    state: State,
 }
 
-impl<W: Write> DeflateEncoder<W> {
-    /// Creates a new encoder using the provided compression options.
-    pub fn new<O: Into<CompressionOptions>>(writer: W, options: O) -> DeflateEncoder<W> {
-        DeflateEncoder { deflate_state: Some(Box::new(DeflateState::new(options.into(), writer))) }
+impl<W: Write, F: Framing> FramedEncoder<W, F> {
+    fn new(writer: W, options: CompressionOptions, framing: F) -> FramedEncoder<W, F> {
+        FramedEncoder {
+            deflate_state: Some(Box::new(DeflateState::new(options, writer))),
+            framing,
+            header_written: false,
+            sync_pending: false,
+            state: State::Active,
+        }
     }
 
-    /// Encode all pending data to the contained writer, consume this `ZlibEncoder`,
-    /// and return the contained writer if writing succeeds.
-    pub fn finish(mut self) -> io::Result<W> {
-        self.output_all().map(|_| ())?;
+    fn state(&mut self) -> &mut DeflateState<W> {
+        self.deflate_state.as_mut().unwrap()
+    }
+
+    /// Write the header if it hasn't been written yet.
+    fn check_write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            let state = self.deflate_state.as_mut().unwrap();
+            self.framing.write_header(&mut state.encoder_state.writer, &state.compression_options)?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+
+    /// Run `f`, refusing to do anything if the encoder is poisoned, and poisoning it if `f`
+    /// fails.
+    ///
+    /// `WouldBlock` errors are only returned after the state is consistent again, so they
+    /// don't poison the encoder.
+    fn check_failure<T, G>(&mut self, f: G) -> io::Result<T>
+        where G: FnOnce(&mut Self) -> io::Result<T>
+    {
+        if self.state == State::Poisoned {
+            return Err(Error::InvalidInput("The encoder can't be used after writing failed!")
+                .into());
+        }
+        let result = f(self);
+        match result {
+            Err(ref err) if err.kind() != io::ErrorKind::WouldBlock => {
+                self.state = State::Poisoned
+            }
+            _ => (),
+        }
+        result
+    }
+
+    /// Write the buffered output to the contained writer.
+    fn write_pending(&mut self) -> io::Result<()> {
+        self.state().encoder_state.writer.write_pending().map_err(io::Error::from)
+    }
+
+    /// Output all pending data, including the trailer, as if encoding is done, but without
+    /// resetting anything.
+    ///
+    /// If writing the output fails with `WouldBlock`, calling this again resumes writing it.
+    fn output_all(&mut self) -> io::Result<()> {
+        self.check_failure(|encoder| {
+            if encoder.state == State::Active {
+                encoder.check_write_header()?;
+                compress_data_dynamic_n(&[], encoder.state(), Flush::Finish)?;
+                let state = encoder.deflate_state.as_mut().unwrap();
+                encoder.framing
+                    .write_trailer(&mut state.encoder_state.writer, state.bytes_written)?;
+                encoder.state = State::Finished;
+            }
+            encoder.write_pending()?;
+            encoder.flush_writer()
+        })
+    }
+
+    /// Flush the contained writer, retrying if it is interrupted.
+    fn flush_writer(&mut self) -> io::Result<()> {
+        loop {
+            match self.state().encoder_state.writer.w.flush() {
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                result => return result,
+            }
+        }
+    }
+
+    fn poll_pending(&mut self) -> io::Result<bool> {
+        self.check_failure(|encoder| match encoder.write_pending() {
+            Ok(()) => Ok(true),
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err),
+        })
+    }
+
+    fn pending_len(&self) -> usize {
+        self.deflate_state.as_ref().unwrap().encoder_state.writer.pending_len()
+    }
+
+    fn finish_with_stats(mut self) -> io::Result<(W, CompressionStats)> {
+        self.output_all()?;
         // We have to move the inner state out of the encoder, and replace it with `None`
-        // to let the `DeflateEncoder` drop safely.
-        let state = self.deflate_state.take();
-        Ok(state.unwrap().encoder_state.writer.w)
+        // to let the encoder drop safely.
+        let state = self.deflate_state.take().unwrap();
+        let stats = state.stats();
+        Ok((state.encoder_state.writer.w, stats))
     }
 
-    /// Resets the encoder (except the compression options), replacing the current writer
-    /// with a new one, returning the old one.
-    pub fn reset(&mut self, w: W) -> io::Result<W> {
-        self.output_all().map(|_| ())?;
-        self.deflate_state.as_mut().unwrap().reset(w)
+    fn into_inner(mut self) -> W {
+        // Taking the state out stops `Drop` from writing anything.
+        self.deflate_state.take().unwrap().encoder_state.writer.w
     }
 
-    /// Output all pending data as if encoding is done, but without resetting anything
-    fn output_all(&mut self) -> io::Result<usize> {
-        compress_data_dynamic_n(&[],
-                                &mut self.deflate_state.as_mut().unwrap(),
-                                Flush::Finish)
+    fn set_dictionary(&mut self, dictionary: &[u8]) -> io::Result<()> {
+        if self.header_written {
+            return Err(Error::InvalidInput("The dictionary has to be set before any data is \
+                                            written!")
+                .into());
+        }
+        self.framing.check_dictionary()?;
+        // Only note the dictionary in the framing once the compressor has accepted it, so a
+        // rejected call doesn't change the header.
+        self.state().set_dictionary(dictionary)?;
+        self.framing.set_dictionary(dictionary);
+        Ok(())
+    }
+
+    fn reset(&mut self, writer: W) -> io::Result<W> {
+        // Nothing is left to do if the stream has been finished with `try_finish` already.
+        if self.state != State::Finished || self.pending_len() > 0 {
+            self.output_all()?;
+        }
+        self.header_written = false;
+        self.sync_pending = false;
+        self.state = State::Active;
+        self.framing.reset();
+        self.state().reset(writer).map_err(io::Error::from)
+    }
+
+    fn stats(&self) -> CompressionStats {
+        self.deflate_state.as_ref().unwrap().stats()
+    }
+
+    fn get_ref(&self) -> &W {
+        &self.deflate_state.as_ref().unwrap().encoder_state.writer.w
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        &mut self.state().encoder_state.writer.w
     }
-}
 
-impl<W: Write> io::Write for DeflateEncoder<W> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        compress_data_dynamic_n(buf, &mut self.deflate_state.as_mut().unwrap(), Flush::None)
+        if self.state == State::Finished {
+            return Err(Error::InvalidInput("Data can't be written after the stream has been \
+                                            finished!")
+                .into());
+        }
+        self.check_failure(|encoder| {
+            // Write out the output of earlier calls first, so the buffered output doesn't keep
+            // growing while the writer doesn't accept any more.
+            encoder.write_pending()?;
+            encoder.sync_pending = false;
+            encoder.check_write_header()?;
+            encoder.framing.update(buf);
+            let n = compress_data_dynamic_n(buf, encoder.state(), Flush::None)?;
+            // The input has been consumed at this point, any output the writer isn't ready for
+            // yet is written on the next call.
+            match encoder.write_pending() {
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => Ok(n),
+                result => result.map(|()| n),
+            }
+        })
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        compress_data_dynamic_n(&[], &mut self.deflate_state.as_mut().unwrap(), Flush::Sync)
-            .map(|_| ())
+        self.check_failure(|encoder| {
+            if !encoder.sync_pending && encoder.state == State::Active {
+                encoder.check_write_header()?;
+                compress_data_dynamic_n(&[], encoder.state(), Flush::Sync)?;
+                encoder.sync_pending = true;
+            }
+            encoder.write_pending()?;
+            encoder.flush_writer()?;
+            encoder.sync_pending = false;
+            Ok(())
+        })
     }
 }
 
-impl<W: Write> Drop for DeflateEncoder<W> {
+impl<W: Write, F: Framing> Drop for FramedEncoder<W, F> {
     /// When the encoder is dropped, output the rest of the data.
     ///
     /// WARNING: This may silently fail if writing fails, so using this to finish encoding
     /// for writers where writing might fail is not recommended, for that call finish() instead.
+    /// Nothing is written if writing failed earlier.
     fn drop(&mut self) {
         // Not sure if implementing drop is a good idea or not, but we follow flate2 for now.
         // We only do this if we are not panicking, to avoid a double panic.
-        if self.deflate_state.is_some() && !thread::panicking() {
+        if self.deflate_state.is_some() && self.state != State::Poisoned && !thread::panicking() {
             let _ = self.output_all();
         }
     }
 }
 
+/// Implement the methods and traits that are the same for all the encoders by forwarding them
+/// to the `FramedEncoder` in the `inner` field.
+///
+/// Any remaining data is written when an encoder is dropped, but as this may silently fail,
+/// `finish()` should be used instead where writing might fail.
+macro_rules! impl_encoder {
+    ($encoder:ident) => {
+        impl<W: Write> $encoder<W> {
+            /// Encode all pending data to the contained writer, consume this encoder,
+            /// and return the contained writer if writing succeeds.
+            ///
+            /// For writers that can return `WouldBlock`, use `try_finish` instead, as the
+            /// encoder can't be resumed after this fails.
+            pub fn finish(self) -> io::Result<W> {
+                self.finish_with_stats().map(|(w, _)| w)
+            }
+
+            /// Encode all pending data to the contained writer, consume this encoder,
+            /// and return the contained writer along with statistics for the whole stream if
+            /// writing succeeds.
+            pub fn finish_with_stats(self) -> io::Result<(W, CompressionStats)> {
+                self.inner.finish_with_stats()
+            }
+
+            /// Encode all pending data to the contained writer, including the trailer, without
+            /// consuming this encoder.
+            ///
+            /// If this fails with `WouldBlock`, it can be called again to continue writing the
+            /// output once the writer is ready. No more data can be written to the encoder
+            /// afterwards until it's reset.
+            pub fn try_finish(&mut self) -> io::Result<()> {
+                self.inner.output_all()
+            }
+
+            /// Write compressed output buffered in the encoder to the contained writer.
+            ///
+            /// Output is buffered when the writer returns `WouldBlock` or doesn't accept all
+            /// of it, and is written out before any more input is accepted. Returns
+            /// `Ok(true)` once all of it has been written, or `Ok(false)` if the writer
+            /// returned `WouldBlock`, in which case this should be called again once the
+            /// writer is ready.
+            pub fn poll_pending(&mut self) -> io::Result<bool> {
+                self.inner.poll_pending()
+            }
+
+            /// Returns the number of bytes of compressed output buffered in the encoder that
+            /// haven't been written to the contained writer yet.
+            pub fn pending_len(&self) -> usize {
+                self.inner.pending_len()
+            }
+
+            /// Resets the encoder (except the compression options), replacing the current
+            /// writer with a new one, returning the old one.
+            ///
+            /// Any preset dictionary is discarded. The current stream is finished first if
+            /// needed, for writers that can return `WouldBlock`, call `try_finish` until it
+            /// succeeds before resetting, as the new writer is dropped if this fails.
+            pub fn reset(&mut self, writer: W) -> io::Result<W> {
+                self.inner.reset(writer)
+            }
+
+            /// Get statistics about the data compressed so far.
+            ///
+            /// Data that has been written to the encoder, but not yet compressed is not
+            /// included. The output byte count includes the header and trailer of the
+            /// container format once they are written.
+            pub fn stats(&self) -> CompressionStats {
+                self.inner.stats()
+            }
+
+            /// Get a reference to the underlying writer.
+            pub fn get_ref(&self) -> &W {
+                self.inner.get_ref()
+            }
+
+            /// Get a mutable reference to the underlying writer.
+            ///
+            /// Data that has been compressed, but not yet flushed to the writer is not
+            /// available here until the encoder is flushed or finished. Writing to the
+            /// underlying writer directly will corrupt the compressed stream.
+            pub fn get_mut(&mut self) -> &mut W {
+                self.inner.get_mut()
+            }
+
+            /// Consume this encoder and return the underlying writer, without writing
+            /// anything more to it.
+            ///
+            /// Unlike `finish`, this works even after writing has failed. Unless the encoder
+            /// has been finished with `try_finish`, the compressed stream in the writer is
+            /// incomplete.
+            pub fn into_inner(self) -> W {
+                self.inner.into_inner()
+            }
+        }
+
+        impl<W: Write> io::Write for $encoder<W> {
+            /// Compress the data in `buf`.
+            ///
+            /// The output only depends on the data written and where the encoder is flushed,
+            /// not on how the data is split between calls to `write`.
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.inner.write(buf)
+            }
+
+            /// Flush the encoder.
+            ///
+            /// This will flush the encoder, emulating the Sync flush method from Zlib.
+            /// This essentially finishes the current block, and sends an additional empty
+            /// stored block to the writer.
+            fn flush(&mut self) -> io::Result<()> {
+                self.inner.flush()
+            }
+        }
+    }
+}
+
+/// A DEFLATE encoder/compressor.
+///
+/// A struct implementing a `Write` interface that takes unencoded data and compresses it to
+/// the provided writer using DEFLATE compression.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Write;
+///
+/// use deflate::Compression;
+/// use deflate::write::DeflateEncoder;
+///
+/// let data = b"This is some test data";
+/// let mut encoder = DeflateEncoder::new(Vec::new(), Compression::Default);
+/// encoder.write_all(data).unwrap();
+/// let compressed_data = encoder.finish().unwrap();
+/// # let _ = compressed_data;
+/// ```
+pub struct DeflateEncoder<W: Write> {
+    inner: FramedEncoder<W, RawFraming>,
+}
+
+impl<W: Write> DeflateEncoder<W> {
+    /// Creates a new encoder using the provided compression options.
+    pub fn new<O: Into<CompressionOptions>>(writer: W, options: O) -> DeflateEncoder<W> {
+        DeflateEncoder { inner: FramedEncoder::new(writer, options.into(), RawFraming) }
+    }
+
+    /// Use the provided data as a preset dictionary that matches can refer back to.
+    ///
+    /// The decompressor has to be supplied with the same dictionary to decompress the data.
+    /// Only the last window size bytes of the dictionary are used.
+    ///
+    /// Returns an error if any data has already been written to the encoder.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) -> io::Result<()> {
+        self.inner.set_dictionary(dictionary)
+    }
+}
+
+impl_encoder!(DeflateEncoder);
 
 /// A Zlib encoder/compressor.
 ///
@@ -115,118 +415,147 @@ impl<W: Write> Drop for DeflateEncoder<W> {
 /// # let _ = compressed_data;
 /// ```
 pub struct ZlibEncoder<W: Write> {
-    // We use a box here to avoid putting the buffers on the stack
-    // It's done here rather than in the structs themselves for now to
-    // keep the data close in memory.
-    // Option is used to allow us to implement `Drop` and `finish()` at the same time.
-    deflate_state: Option<Box<DeflateState<W>>>,
-    checksum: Adler32Checksum,
-    header_written: bool,
+    inner: FramedEncoder<W, ZlibFraming>,
 }
 
 impl<W: Write> ZlibEncoder<W> {
     /// Create a new `ZlibEncoder` using the provided compression options.
     pub fn new<O: Into<CompressionOptions>>(writer: W, options: O) -> ZlibEncoder<W> {
-        ZlibEncoder {
-            deflate_state: Some(Box::new(DeflateState::new(options.into(), writer))),
-            checksum: Adler32Checksum::new(),
-            header_written: false,
-        }
+        ZlibEncoder { inner: FramedEncoder::new(writer, options.into(), ZlibFraming::new()) }
     }
 
-    /// Output all pending data ,including the trailer(checksum) as if encoding is done,
-    /// but without resetting anything.
-    fn output_all(&mut self) -> io::Result<usize> {
-        self.check_write_header()?;
-        let n = compress_data_dynamic_n(&[],
-                                        &mut self.deflate_state.as_mut().unwrap(),
-                                        Flush::Finish)?;
-        self.write_trailer()?;
-        Ok(n)
+    /// Use the provided data as a preset dictionary that matches can refer back to.
+    ///
+    /// The id of the dictionary is stored in the zlib header, and the decompressor has to be
+    /// supplied with the same dictionary to decompress the data.
+    /// Only the last window size bytes of the dictionary are used for matching.
+    ///
+    /// Returns an error if any data has already been written to the encoder.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) -> io::Result<()> {
+        self.inner.set_dictionary(dictionary)
     }
+}
 
-    /// Encode all pending data to the contained writer, consume this `ZlibEncoder`,
-    /// and return the contained writer if writing succeeds.
-    pub fn finish(mut self) -> io::Result<W> {
-        self.output_all()?;
-        // We have to move the inner state out of the encoder, and replace it with `None`
-        // to let the `DeflateEncoder` drop safely.
-        let inner = self.deflate_state.take();
-        Ok(inner.unwrap().encoder_state.writer.w)
-    }
-
-    /// Resets the encoder (except the compression options), replacing the current writer
-    /// with a new one, returning the old one.
-    pub fn reset(&mut self, writer: W) -> io::Result<W> {
-        self.check_write_header()?;
-        compress_data_dynamic_n(&[],
-                                &mut self.deflate_state.as_mut().unwrap(),
-                                Flush::Finish)?;
-        self.write_trailer()?;
-        self.header_written = false;
-        self.checksum = Adler32Checksum::new();
-        self.deflate_state.as_mut().unwrap().reset(writer)
-    }
+impl_encoder!(ZlibEncoder);
 
-    /// Check if a zlib header should be written.
-    fn check_write_header(&mut self) -> io::Result<()> {
-        if !self.header_written {
-            write_zlib_header(&mut self.deflate_state.as_mut().unwrap().encoder_state.writer,
-                              CompressionLevel::Default)?;
-            self.header_written = true;
-        }
-        Ok(())
-    }
+/// A Gzip encoder/compressor.
+///
+/// A struct implementing a `Write` interface that takes unencoded data and compresses it to
+/// the provided writer using DEFLATE compression with a minimal gzip header and trailer.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Write;
+///
+/// use deflate::Compression;
+/// use deflate::write::GzipEncoder;
+///
+/// let data = b"This is some test data";
+/// let mut encoder = GzipEncoder::new(Vec::new(), Compression::Default);
+/// encoder.write_all(data).unwrap();
+/// let compressed_data = encoder.finish().unwrap();
+/// # let _ = compressed_data;
+/// ```
+pub struct GzipEncoder<W: Write> {
+    inner: FramedEncoder<W, GzipFraming>,
+}
 
-    /// Write the trailer, which for zlib is the Adler32 checksum.
-    fn write_trailer(&mut self) -> io::Result<()> {
+impl<W: Write> GzipEncoder<W> {
+    /// Create a new `GzipEncoder` using the provided compression options.
+    pub fn new<O: Into<CompressionOptions>>(writer: W, options: O) -> GzipEncoder<W> {
+        GzipEncoder { inner: FramedEncoder::new(writer, options.into(), GzipFraming::new()) }
+    }
+}
 
-        let hash = self.checksum.current_hash();
+impl_encoder!(GzipEncoder);
 
-        self.deflate_state
-            .as_mut()
-            .unwrap()
-            .encoder_state
-            .writer
-            .write_u32::<BigEndian>(hash)
-    }
+/// An encoder/compressor producing any of the supported container formats.
+///
+/// This works like `DeflateEncoder`, `ZlibEncoder` or `GzipEncoder`, depending on the
+/// `Format` it is created with, which makes it useful when the format is only known at
+/// runtime.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Write;
+///
+/// use deflate::{Compression, Format};
+/// use deflate::write::Encoder;
+///
+/// let data = b"This is some test data";
+/// let mut encoder = Encoder::new(Vec::new(), Format::Gzip, Compression::Default);
+/// encoder.write_all(data).unwrap();
+/// let compressed_data = encoder.finish().unwrap();
+/// # let _ = compressed_data;
+/// ```
+pub struct Encoder<W: Write> {
+    inner: FramedEncoder<W, FormatFraming>,
 }
 
-impl<W: Write> io::Write for ZlibEncoder<W> {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.check_write_header()?;
-        self.checksum.update_from_slice(buf);
-        compress_data_dynamic_n(buf, &mut self.deflate_state.as_mut().unwrap(), Flush::None)
+impl<W: Write> Encoder<W> {
+    /// Create a new `Encoder` producing the given format using the provided compression
+    /// options.
+    pub fn new<O: Into<CompressionOptions>>(writer: W, format: Format, options: O) -> Encoder<W> {
+        Encoder { inner: FramedEncoder::new(writer, options.into(), FormatFraming::new(format)) }
     }
 
-    /// Flush the encoder.
+    /// Create a new `Encoder`, selecting the format and window size from a `windowBits` value
+    /// following the zlib convention (see `Format::from_window_bits`).
     ///
-    /// This will flush the encoder, emulating the Sync flush method from Zlib.
-    /// This essentially finishes the current block, and sends an additional empty stored block to
-    /// the writer.
-    fn flush(&mut self) -> io::Result<()> {
-        compress_data_dynamic_n(&[], &mut self.deflate_state.as_mut().unwrap(), Flush::Sync)
-            .map(|_| ())
+    /// The window size overrides the one in `options`. Returns an error if `window_bits` is
+    /// not valid.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use deflate::{Compression, Format};
+    /// use deflate::write::Encoder;
+    ///
+    /// // Raw DEFLATE with a 512 byte window.
+    /// let encoder = Encoder::with_window_bits(Vec::new(), -9, Compression::Default).unwrap();
+    /// assert_eq!(encoder.format(), Format::Raw);
+    /// ```
+    pub fn with_window_bits<O: Into<CompressionOptions>>(writer: W,
+                                                         window_bits: i32,
+                                                         options: O)
+                                                         -> io::Result<Encoder<W>> {
+        let (format, window_bits) = match Format::from_window_bits(window_bits) {
+            Some(f) => f,
+            None => {
+                return Err(Error::InvalidInput("Invalid window bits!").into())
+            }
+        };
+        let options = CompressionOptions { window_bits, ..options.into() };
+        Ok(Encoder::new(writer, format, options))
     }
-}
 
-impl<W: Write> Drop for ZlibEncoder<W> {
-    /// When the encoder is dropped, output the rest of the data.
+    /// The format this encoder produces.
+    pub fn format(&self) -> Format {
+        self.inner.framing.format()
+    }
+
+    /// Use the provided data as a preset dictionary that matches can refer back to.
     ///
-    /// WARNING: This may silently fail if writing fails, so using this to finish encoding
-    /// for writers where writing might fail is not recommended, for that call finish() instead.
-    fn drop(&mut self) {
-        if self.deflate_state.is_some() && !thread::panicking() {
-            let _ = self.output_all();
-        }
+    /// The decompressor has to be supplied with the same dictionary to decompress the data.
+    /// Only the last window size bytes of the dictionary are used for matching.
+    ///
+    /// Returns an error if any data has already been written to the encoder, or if the format
+    /// is gzip, which doesn't support preset dictionaries.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) -> io::Result<()> {
+        self.inner.set_dictionary(dictionary)
     }
 }
 
+impl_encoder!(Encoder);
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use test_utils::{get_test_data, decompress_to_end, decompress_zlib};
+    use test_utils::{get_test_data, decompress_to_end, decompress_zlib, decompress_gzip,
+                     decompress_with_dictionary};
+    use checksum::{Adler32Checksum, RollingChecksum};
     use compression_options::CompressionOptions;
     use std::io::Write;
 
@@ -288,6 +617,23 @@ mod test {
         assert!(res1 == res2);
     }
 
+    #[test]
+    fn writer_stats() {
+        let data = get_test_data();
+        let mut compressor = ZlibEncoder::new(Vec::with_capacity(data.len() / 3),
+                                              CompressionOptions::default());
+        compressor.write_all(&data).unwrap();
+        compressor.flush().unwrap();
+        let stats = compressor.stats();
+        let compressed = compressor.finish().unwrap();
+
+        assert_eq!(stats.bytes_in, data.len() as u64);
+        // The final empty block and the trailer are written after the stats were collected.
+        assert!(stats.bytes_out < compressed.len() as u64);
+        assert!(stats.stored_blocks > 0);
+        assert_eq!(stats.literals + stats.total_match_length, data.len() as u64);
+    }
+
     #[test]
     fn writer_sync() {
         let data = get_test_data();
@@ -305,4 +651,206 @@ mod test {
         let res = decompress_to_end(&compressed);
         assert!(res == data);
     }
+
+    #[test]
+    fn gzip_writer() {
+        let data = get_test_data();
+        let compressed = {
+            let mut compressor = GzipEncoder::new(Vec::with_capacity(data.len() / 3),
+                                                  CompressionOptions::default());
+            compressor.write_all(&data).unwrap();
+            compressor.finish().unwrap()
+        };
+
+        assert!(compressed == ::deflate_bytes_gzip(&data).unwrap());
+        let res = decompress_gzip(&compressed);
+        assert!(res == data);
+    }
+
+    #[test]
+    fn writer_dictionary() {
+        let data = get_test_data();
+        let (dictionary, data) = data.split_at(40000);
+        let compressed = {
+            let mut compressor = DeflateEncoder::new(Vec::with_capacity(data.len() / 3),
+                                                     CompressionOptions::default());
+            compressor.set_dictionary(dictionary).unwrap();
+            compressor.write_all(data).unwrap();
+            compressor.finish().unwrap()
+        };
+        assert!(compressed.len() < ::deflate_bytes(data).unwrap().len());
+
+        // Only the last 32k of the dictionary is used.
+        let window = &dictionary[dictionary.len() - 32768..];
+        let res = decompress_with_dictionary(&compressed, window);
+        assert!(&res[..window.len()] == window);
+        assert!(&res[window.len()..] == data);
+    }
+
+    #[test]
+    fn writer_binary_tree() {
+        use lz77::MatchFinder;
+        let data = get_test_data();
+        let (dictionary, data) = data.split_at(10000);
+        let mut options = CompressionOptions::high();
+        options.match_finder = MatchFinder::BinaryTree;
+        let compressed = {
+            let mut compressor = DeflateEncoder::new(Vec::with_capacity(data.len() / 3), options);
+            compressor.set_dictionary(dictionary).unwrap();
+            let split = data.len() / 2;
+            compressor.write_all(&data[..split]).unwrap();
+            compressor.flush().unwrap();
+            compressor.write_all(&data[split..]).unwrap();
+            compressor.finish().unwrap()
+        };
+
+        let res = decompress_with_dictionary(&compressed, dictionary);
+        assert!(&res[dictionary.len()..] == data);
+    }
+
+    #[test]
+    fn writer_buffer_windows() {
+        let data = get_test_data();
+        let (dictionary, data) = data.split_at(10000);
+        let compress = |buffer_windows| {
+            let options = CompressionOptions { buffer_windows, ..CompressionOptions::default() };
+            let mut compressor = DeflateEncoder::new(Vec::with_capacity(data.len() / 3), options);
+            compressor.set_dictionary(dictionary).unwrap();
+            for (n, chunk) in data.chunks(50000).enumerate() {
+                compressor.write_all(chunk).unwrap();
+                if n == 1 {
+                    compressor.flush().unwrap();
+                }
+            }
+            compressor.finish().unwrap()
+        };
+
+        // The output doesn't depend on how many windows are buffered.
+        let compressed = compress(1);
+        for &buffer_windows in &[0, 2, 4, 100] {
+            assert!(compress(buffer_windows) == compressed);
+        }
+        let res = decompress_with_dictionary(&compressed, dictionary);
+        assert!(&res[dictionary.len()..] == data);
+    }
+
+    #[test]
+    fn writer_dictionary_zlib() {
+        let dictionary = b"A dictionary";
+        let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.set_dictionary(dictionary).unwrap();
+        compressor.write_all(b"A dictionary, a dictionary").unwrap();
+        assert!(compressor.set_dictionary(dictionary).is_err());
+        let compressed = compressor.finish().unwrap();
+
+        // The header should indicate a preset dictionary, followed by its adler32 checksum.
+        assert_eq!(compressed[1] & 0x20, 0x20);
+        let mut checksum = Adler32Checksum::new();
+        checksum.update_from_slice(dictionary);
+        let id = checksum.current_hash();
+        assert_eq!(&compressed[2..6],
+                   &[(id >> 24) as u8, (id >> 16) as u8, (id >> 8) as u8, id as u8]);
+    }
+
+    #[test]
+    /// Test that a rejected second dictionary doesn't replace the id of the first in the header.
+    fn writer_dictionary_zlib_rejected() {
+        let dictionary = b"A dictionary";
+        let data = b"A dictionary, a dictionary";
+        let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.set_dictionary(dictionary).unwrap();
+        assert!(compressor.set_dictionary(b"Another dictionary").is_err());
+        compressor.write_all(data).unwrap();
+        let compressed = compressor.finish().unwrap();
+
+        let mut checksum = Adler32Checksum::new();
+        checksum.update_from_slice(dictionary);
+        let id = checksum.current_hash();
+        assert_eq!(&compressed[2..6],
+                   &[(id >> 24) as u8, (id >> 16) as u8, (id >> 8) as u8, id as u8]);
+        let res = decompress_with_dictionary(&compressed[6..compressed.len() - 4], dictionary);
+        assert!(&res[dictionary.len()..] == data);
+    }
+
+    #[test]
+    fn writer_finish_with_stats() {
+        let data = get_test_data();
+        let mut compressor = GzipEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.write_all(&data).unwrap();
+        let (compressed, stats) = compressor.finish_with_stats().unwrap();
+        assert_eq!(stats.bytes_in, data.len() as u64);
+        assert_eq!(stats.bytes_out, compressed.len() as u64);
+    }
+
+    #[test]
+    fn writer_sync_first_window() {
+        let data = get_test_data();
+        // Sync both with less and more than a window of data in the first window.
+        for &split in &[10, 1000, 40000] {
+            let mut compressor = DeflateEncoder::new(Vec::new(), CompressionOptions::default());
+            compressor.write_all(&data[..split]).unwrap();
+            compressor.flush().unwrap();
+            compressor.write_all(&data[split..split * 2]).unwrap();
+            compressor.flush().unwrap();
+            compressor.write_all(&data[split * 2..]).unwrap();
+            let compressed = compressor.finish().unwrap();
+            assert!(decompress_to_end(&compressed) == data);
+        }
+    }
+
+    #[test]
+    fn flush_before_write() {
+        let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.flush().unwrap();
+        compressor.write_all(b"Some data").unwrap();
+        let compressed = compressor.finish().unwrap();
+        assert_eq!(decompress_zlib(&compressed), b"Some data");
+
+        let mut compressor = GzipEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.flush().unwrap();
+        let compressed = compressor.finish().unwrap();
+        assert!(decompress_gzip(&compressed).is_empty());
+    }
+
+    #[test]
+    fn encoder_formats() {
+        let data = get_test_data();
+        let options = CompressionOptions::default();
+        let compress = |format| {
+            let mut compressor = Encoder::new(Vec::new(), format, options);
+            compressor.write_all(&data).unwrap();
+            compressor.finish().unwrap()
+        };
+
+        let mut compressor = DeflateEncoder::new(Vec::new(), options);
+        compressor.write_all(&data).unwrap();
+        assert!(compress(Format::Raw) == compressor.finish().unwrap());
+        let mut compressor = ZlibEncoder::new(Vec::new(), options);
+        compressor.write_all(&data).unwrap();
+        assert!(compress(Format::Zlib) == compressor.finish().unwrap());
+        let mut compressor = GzipEncoder::new(Vec::new(), options);
+        compressor.write_all(&data).unwrap();
+        assert!(compress(Format::Gzip) == compressor.finish().unwrap());
+    }
+
+    #[test]
+    fn encoder_window_bits() {
+        let data = get_test_data();
+        let mut compressor = Encoder::with_window_bits(Vec::new(), 25, CompressionOptions::high())
+            .unwrap();
+        assert_eq!(compressor.format(), Format::Gzip);
+        assert!(compressor.set_dictionary(b"A dictionary").is_err());
+        compressor.write_all(&data).unwrap();
+        assert!(decompress_gzip(&compressor.finish().unwrap()) == data);
+
+        let mut compressor = Encoder::with_window_bits(Vec::new(), 9, CompressionOptions::high())
+            .unwrap();
+        compressor.write_all(&data).unwrap();
+        let compressed = compressor.finish().unwrap();
+        // CINFO is the base-2 logarithm of the window size minus 8.
+        assert_eq!(compressed[0] >> 4, 1);
+        assert!(decompress_zlib(&compressed) == data);
+
+        assert!(Encoder::with_window_bits(Vec::new(), 16, CompressionOptions::default()).is_err());
+    }
 }
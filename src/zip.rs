@@ -0,0 +1,722 @@
+//! A streaming writer for [ZIP](https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT)
+//! archives.
+//!
+//! The archive is written in a single pass, so the output doesn't need to be seekable: the
+//! checksum and sizes of each entry are written in a data descriptor after its data, and
+//! repeated in the central directory at the end of the archive. ZIP64 extensions are used
+//! where needed for archives with large entries, large offsets or many entries.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::io::Write;
+//!
+//! use deflate::zip::{ZipWriter, FileOptions, CompressionMethod};
+//!
+//! let mut zip = ZipWriter::new(Vec::new());
+//! zip.start_file("hello.txt", FileOptions::default()).unwrap();
+//! zip.write_all(b"Hello, world!").unwrap();
+//! zip.start_file("stored.txt",
+//!                FileOptions {
+//!                    compression_method: CompressionMethod::Stored,
+//!                    ..Default::default()
+//!                })
+//!     .unwrap();
+//! zip.write_all(b"Not compressed").unwrap();
+//! let archive = zip.finish().unwrap();
+//! # let _ = archive;
+//! ```
+
+use std::io::{self, Write};
+use std::thread;
+
+use byteorder::{WriteBytesExt, LittleEndian};
+
+use checksum::{Crc32Checksum, RollingChecksum};
+use compression_options::CompressionOptions;
+use writer::DeflateEncoder;
+use error::Error;
+
+const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x0807_4b50;
+const CENTRAL_DIRECTORY_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0606_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0605_4b50;
+
+// Version 2.0 is needed for deflate and directories, 2.1 for Deflate64 and 4.5 for ZIP64.
+const VERSION_DEFAULT: u16 = 20;
+const VERSION_DEFLATE64: u16 = 21;
+const VERSION_ZIP64: u16 = 45;
+// The upper byte of "version made by" gives the system the external attributes are for.
+const SYSTEM_MS_DOS: u16 = 0;
+const SYSTEM_UNIX: u16 = 3 << 8;
+
+// Bit 3: The crc and sizes are in a data descriptor after the data.
+const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+// Bit 11: The file name is encoded using UTF-8.
+const FLAG_UTF8: u16 = 1 << 11;
+
+const ZIP64_EXTRA_FIELD_ID: u16 = 0x0001;
+// Size of the zip64 end of central directory record, not counting the signature and this field.
+const ZIP64_END_OF_CENTRAL_DIRECTORY_SIZE: u64 = 44;
+
+// The MS-DOS directory attribute.
+const MS_DOS_DIRECTORY: u32 = 0x10;
+// Unix file type bits for directories and regular files.
+const UNIX_DIRECTORY: u32 = 0o040_000;
+const UNIX_FILE: u32 = 0o100_000;
+
+// Values that don't fit the regular fields are stored in the zip64 fields instead.
+const ZIP64_LIMIT_32: u64 = 0xFFFF_FFFF;
+const ZIP64_LIMIT_16: u64 = 0xFFFF;
+
+/// The compression method of an entry.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum CompressionMethod {
+    /// Store the data without compressing it.
+    Stored,
+    /// Compress the data using DEFLATE.
+    Deflated,
+    /// Compress the data using Deflate64 (enhanced deflate), which is not supported by all zip
+    /// readers.
+    Deflate64,
+}
+
+impl CompressionMethod {
+    fn to_u16(self) -> u16 {
+        match self {
+            CompressionMethod::Stored => 0,
+            CompressionMethod::Deflated => 8,
+            CompressionMethod::Deflate64 => 9,
+        }
+    }
+}
+
+/// A modification date and time, with the resolution of the MS-DOS format used in ZIP files.
+///
+/// Years before 1980 and after 2107 can not be represented, and seconds are rounded down to an
+/// even number.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// The MS-DOS date and time, with values out of range clamped to the valid range.
+    fn to_ms_dos(self) -> (u16, u16) {
+        let year = self.year.clamp(1980, 2107) - 1980;
+        let date = (year << 9) | (u16::from(self.month.clamp(1, 12)) << 5) |
+                   u16::from(self.day.clamp(1, 31));
+        let time = (u16::from(self.hour.min(23)) << 11) | (u16::from(self.minute.min(59)) << 5) |
+                   u16::from(self.second.min(59) / 2);
+        (date, time)
+    }
+}
+
+impl Default for DateTime {
+    /// The earliest date that can be represented, 1980-01-01 00:00:00.
+    fn default() -> DateTime {
+        DateTime {
+            year: 1980,
+            month: 1,
+            day: 1,
+            hour: 0,
+            minute: 0,
+            second: 0,
+        }
+    }
+}
+
+/// Options for an entry in the archive.
+#[derive(Copy, Clone, Debug)]
+pub struct FileOptions {
+    /// The compression method, `Deflated` by default.
+    pub compression_method: CompressionMethod,
+    /// The options used for compressing the entry if the method is `Deflated` or `Deflate64`.
+    ///
+    /// `deflate64` is set according to the compression method.
+    pub compression_options: CompressionOptions,
+    /// The modification time stored for the entry.
+    pub last_modified: DateTime,
+    /// Unix permissions (e.g `0o644`) stored for the entry, if any.
+    pub unix_permissions: Option<u32>,
+    /// Whether to prepare for the entry being 4 GiB or larger.
+    ///
+    /// As the archive is written in a single pass, the sizes have to be written in the ZIP64
+    /// format for entries that turn out to be this large, which needs to be decided before
+    /// the entry is written. Writing an entry that is too large without this set results in
+    /// an error. It does not need to be set for archives that are only large in total.
+    pub large_file: bool,
+}
+
+impl Default for FileOptions {
+    fn default() -> FileOptions {
+        FileOptions {
+            compression_method: CompressionMethod::Deflated,
+            compression_options: CompressionOptions::default(),
+            last_modified: DateTime::default(),
+            unix_permissions: None,
+            large_file: false,
+        }
+    }
+}
+
+/// A writer keeping track of the number of bytes written to it.
+struct CountingWriter<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The information about an entry needed for the central directory.
+struct Entry {
+    name: Vec<u8>,
+    method: CompressionMethod,
+    date: u16,
+    time: u16,
+    flags: u16,
+    version_made_by: u16,
+    external_attributes: u32,
+    large_file: bool,
+    crc: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    // Offset of the local file header.
+    offset: u64,
+}
+
+impl Entry {
+    fn needs_zip64(&self) -> bool {
+        self.large_file || self.compressed_size >= ZIP64_LIMIT_32 ||
+        self.uncompressed_size >= ZIP64_LIMIT_32 || self.offset >= ZIP64_LIMIT_32
+    }
+
+    fn version_needed(&self) -> u16 {
+        if self.needs_zip64() {
+            VERSION_ZIP64
+        } else if self.method == CompressionMethod::Deflate64 {
+            VERSION_DEFLATE64
+        } else {
+            VERSION_DEFAULT
+        }
+    }
+}
+
+/// The entry that is currently being written.
+struct CurrentEntry {
+    entry: Entry,
+    // The compressor for deflated entries, whose output is copied to the archive as it is
+    // produced.
+    encoder: Option<DeflateEncoder<Vec<u8>>>,
+    checksum: Crc32Checksum,
+    // The offset of the data following the local file header.
+    data_start: u64,
+}
+
+/// A writer producing a ZIP archive.
+///
+/// Start each entry with `start_file`, and write its contents using the `Write` interface.
+/// The archive is completed by writing the central directory when calling `finish`.
+/// If writing fails, the archive is left incomplete, and any further calls return an error.
+pub struct ZipWriter<W: Write> {
+    // Option is used to allow us to implement `Drop` and `finish()` at the same time.
+    writer: Option<CountingWriter<W>>,
+    entries: Vec<Entry>,
+    current: Option<CurrentEntry>,
+    // Set when writing failed. The archive is incomplete then, so nothing more is written.
+    poisoned: bool,
+}
+
+impl<W: Write> ZipWriter<W> {
+    /// Create a new `ZipWriter` writing an archive to the provided writer.
+    pub fn new(writer: W) -> ZipWriter<W> {
+        ZipWriter {
+            writer: Some(CountingWriter {
+                inner: writer,
+                count: 0,
+            }),
+            entries: Vec::new(),
+            current: None,
+            poisoned: false,
+        }
+    }
+
+    /// Finish the current entry, if any, and start a new file entry.
+    ///
+    /// The contents of the file are written using the `Write` interface.
+    pub fn start_file(&mut self, name: &str, options: FileOptions) -> io::Result<()> {
+        self.start_entry(name.as_bytes(), options, false)
+    }
+
+    /// Finish the current entry, if any, and add an entry for a directory.
+    ///
+    /// A trailing `/` is added to the name if it is missing.
+    pub fn add_directory(&mut self, name: &str, options: FileOptions) -> io::Result<()> {
+        let mut name = name.as_bytes().to_vec();
+        if name.last() != Some(&b'/') {
+            name.push(b'/');
+        }
+        let options = FileOptions {
+            compression_method: CompressionMethod::Stored,
+            large_file: false,
+            ..options
+        };
+        self.start_entry(&name, options, true)?;
+        self.check_failure(|zip| zip.finish_entry())
+    }
+
+    /// Finish the last entry and write the central directory, returning the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.check_failure(|zip| zip.finish_archive())?;
+        // We have to move the writer out, and replace it with `None` to let the `ZipWriter`
+        // drop safely.
+        Ok(self.writer.take().unwrap().inner)
+    }
+
+    fn writer(&mut self) -> &mut CountingWriter<W> {
+        self.writer.as_mut().unwrap()
+    }
+
+    /// Run `f`, refusing to do anything if writing failed earlier, and remembering it if `f`
+    /// fails.
+    fn check_failure<T, F>(&mut self, f: F) -> io::Result<T>
+        where F: FnOnce(&mut Self) -> io::Result<T>
+    {
+        if self.poisoned {
+            return Err(Error::InvalidInput("The archive can't be used after writing failed!")
+                .into());
+        }
+        let result = f(self);
+        if result.is_err() {
+            self.poisoned = true;
+        }
+        result
+    }
+
+    fn start_entry(&mut self,
+                   name: &[u8],
+                   options: FileOptions,
+                   directory: bool)
+                   -> io::Result<()> {
+        if name.len() as u64 > ZIP64_LIMIT_16 {
+            return Err(Error::InvalidInput("The file name is too long!").into());
+        }
+        self.check_failure(|zip| zip.write_entry_header(name, options, directory))
+    }
+
+    /// Finish the current entry, if any, and write the local file header of a new one.
+    fn write_entry_header(&mut self,
+                          name: &[u8],
+                          options: FileOptions,
+                          directory: bool)
+                          -> io::Result<()> {
+        self.finish_entry()?;
+
+        let (date, time) = options.last_modified.to_ms_dos();
+        let (version_made_by, external_attributes) = match options.unix_permissions {
+            Some(mode) => {
+                let file_type = if directory { UNIX_DIRECTORY } else { UNIX_FILE };
+                let dos_attributes = if directory { MS_DOS_DIRECTORY } else { 0 };
+                (SYSTEM_UNIX | VERSION_ZIP64,
+                 ((file_type | (mode & 0o7777)) << 16) | dos_attributes)
+            }
+            None => {
+                (SYSTEM_MS_DOS | VERSION_ZIP64,
+                 if directory { MS_DOS_DIRECTORY } else { 0 })
+            }
+        };
+        // ASCII names are valid either way, so the flag is only needed for other names.
+        let utf8_flag = if name.is_ascii() { 0 } else { FLAG_UTF8 };
+        let entry = Entry {
+            name: name.to_vec(),
+            method: options.compression_method,
+            date,
+            time,
+            flags: FLAG_DATA_DESCRIPTOR | utf8_flag,
+            version_made_by,
+            external_attributes,
+            large_file: options.large_file,
+            crc: 0,
+            compressed_size: 0,
+            uncompressed_size: 0,
+            offset: self.writer().count,
+        };
+
+        write_local_file_header(self.writer(), &entry)?;
+        let deflate64 = match options.compression_method {
+            CompressionMethod::Stored => None,
+            CompressionMethod::Deflated => Some(false),
+            CompressionMethod::Deflate64 => Some(true),
+        };
+        let encoder = deflate64.map(|deflate64| {
+            let compression_options = CompressionOptions {
+                deflate64,
+                ..options.compression_options
+            };
+            DeflateEncoder::new(Vec::new(), compression_options)
+        });
+        self.current = Some(CurrentEntry {
+            entry,
+            encoder,
+            checksum: Crc32Checksum::new(),
+            data_start: self.writer().count,
+        });
+        Ok(())
+    }
+
+    /// Finish writing the current entry, if any, by writing the remaining compressed data and
+    /// the data descriptor.
+    fn finish_entry(&mut self) -> io::Result<()> {
+        let mut current = match self.current.take() {
+            Some(current) => current,
+            None => return Ok(()),
+        };
+        let writer = self.writer.as_mut().unwrap();
+        if let Some(encoder) = current.encoder.take() {
+            writer.write_all(&encoder.finish()?)?;
+        }
+
+        let mut entry = current.entry;
+        entry.crc = current.checksum.current_hash();
+        entry.compressed_size = writer.count - current.data_start;
+        if !entry.large_file &&
+           (entry.compressed_size >= ZIP64_LIMIT_32 || entry.uncompressed_size >= ZIP64_LIMIT_32) {
+            return Err(Error::InvalidInput("The entry is too large, `large_file` has to be set \
+                                            for entries of 4 GiB or more!")
+                .into());
+        }
+        write_data_descriptor(writer, &entry)?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Finish the last entry, and write the central directory and end of central directory
+    /// records.
+    fn finish_archive(&mut self) -> io::Result<()> {
+        self.finish_entry()?;
+        let writer = self.writer.as_mut().unwrap();
+        let central_directory_offset = writer.count;
+        for entry in &self.entries {
+            write_central_directory_header(writer, entry)?;
+        }
+        let central_directory_size = writer.count - central_directory_offset;
+        let num_entries = self.entries.len() as u64;
+
+        if num_entries >= ZIP64_LIMIT_16 || central_directory_offset >= ZIP64_LIMIT_32 ||
+           central_directory_size >= ZIP64_LIMIT_32 {
+            let zip64_end_offset = writer.count;
+            writer.write_u32::<LittleEndian>(ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE)?;
+            writer.write_u64::<LittleEndian>(ZIP64_END_OF_CENTRAL_DIRECTORY_SIZE)?;
+            writer.write_u16::<LittleEndian>(SYSTEM_MS_DOS | VERSION_ZIP64)?;
+            writer.write_u16::<LittleEndian>(VERSION_ZIP64)?;
+            // Number of this disk, and the disk the central directory starts on.
+            writer.write_u32::<LittleEndian>(0)?;
+            writer.write_u32::<LittleEndian>(0)?;
+            // Number of entries on this disk, and in total.
+            writer.write_u64::<LittleEndian>(num_entries)?;
+            writer.write_u64::<LittleEndian>(num_entries)?;
+            writer.write_u64::<LittleEndian>(central_directory_size)?;
+            writer.write_u64::<LittleEndian>(central_directory_offset)?;
+
+            writer.write_u32::<LittleEndian>(ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR_SIGNATURE)?;
+            // The disk the zip64 end of central directory record is on.
+            writer.write_u32::<LittleEndian>(0)?;
+            writer.write_u64::<LittleEndian>(zip64_end_offset)?;
+            // Total number of disks.
+            writer.write_u32::<LittleEndian>(1)?;
+        }
+
+        writer.write_u32::<LittleEndian>(END_OF_CENTRAL_DIRECTORY_SIGNATURE)?;
+        writer.write_u16::<LittleEndian>(0)?;
+        writer.write_u16::<LittleEndian>(0)?;
+        writer.write_u16::<LittleEndian>(num_entries.min(ZIP64_LIMIT_16) as u16)?;
+        writer.write_u16::<LittleEndian>(num_entries.min(ZIP64_LIMIT_16) as u16)?;
+        writer.write_u32::<LittleEndian>(central_directory_size.min(ZIP64_LIMIT_32) as u32)?;
+        writer.write_u32::<LittleEndian>(central_directory_offset.min(ZIP64_LIMIT_32) as u32)?;
+        // No comment.
+        writer.write_u16::<LittleEndian>(0)?;
+        self.entries.clear();
+        flush_writer(writer)
+    }
+}
+
+impl<W: Write> io::Write for ZipWriter<W> {
+    /// Write data to the current entry.
+    ///
+    /// Returns an error if no entry has been started, or the current entry is a directory.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.current.is_none() {
+            return Err(Error::InvalidInput("No file has been started in the archive!").into());
+        }
+        self.check_failure(|zip| {
+            let writer = zip.writer.as_mut().unwrap();
+            let current = zip.current.as_mut().unwrap();
+            match current.encoder {
+                Some(ref mut encoder) => {
+                    encoder.write_all(buf)?;
+                    writer.write_all(encoder.get_mut())?;
+                    encoder.get_mut().clear();
+                }
+                None => writer.write_all(buf)?,
+            }
+            current.checksum.update_from_slice(buf);
+            current.entry.uncompressed_size += buf.len() as u64;
+            Ok(buf.len())
+        })
+    }
+
+    /// Flush the underlying writer.
+    ///
+    /// Data held back by the compressor of a deflated entry is not flushed, as that would make
+    /// the compression worse.
+    fn flush(&mut self) -> io::Result<()> {
+        self.check_failure(|zip| flush_writer(zip.writer()))
+    }
+}
+
+impl<W: Write> Drop for ZipWriter<W> {
+    /// When the writer is dropped, finish the archive, unless writing failed earlier.
+    ///
+    /// WARNING: This may silently fail if writing fails, so using this to finish the archive
+    /// for writers where writing might fail is not recommended, for that call finish() instead.
+    fn drop(&mut self) {
+        if self.writer.is_some() && !self.poisoned && !thread::panicking() {
+            let _ = self.finish_archive();
+        }
+    }
+}
+
+/// Flush `writer`, retrying if it is interrupted.
+fn flush_writer<W: Write>(writer: &mut W) -> io::Result<()> {
+    loop {
+        match writer.flush() {
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+            result => return result,
+        }
+    }
+}
+
+fn write_local_file_header<W: Write>(writer: &mut W, entry: &Entry) -> io::Result<()> {
+    writer.write_u32::<LittleEndian>(LOCAL_FILE_HEADER_SIGNATURE)?;
+    writer.write_u16::<LittleEndian>(entry.version_needed())?;
+    writer.write_u16::<LittleEndian>(entry.flags)?;
+    writer.write_u16::<LittleEndian>(entry.method.to_u16())?;
+    writer.write_u16::<LittleEndian>(entry.time)?;
+    writer.write_u16::<LittleEndian>(entry.date)?;
+    // The crc and sizes are not known yet, they are written in the data descriptor.
+    writer.write_u32::<LittleEndian>(0)?;
+    let sizes = if entry.large_file { ZIP64_LIMIT_32 as u32 } else { 0 };
+    writer.write_u32::<LittleEndian>(sizes)?;
+    writer.write_u32::<LittleEndian>(sizes)?;
+    writer.write_u16::<LittleEndian>(entry.name.len() as u16)?;
+    writer.write_u16::<LittleEndian>(if entry.large_file { 20 } else { 0 })?;
+    writer.write_all(&entry.name)?;
+    if entry.large_file {
+        // Placeholders for the uncompressed and compressed sizes.
+        writer.write_u16::<LittleEndian>(ZIP64_EXTRA_FIELD_ID)?;
+        writer.write_u16::<LittleEndian>(16)?;
+        writer.write_u64::<LittleEndian>(0)?;
+        writer.write_u64::<LittleEndian>(0)?;
+    }
+    Ok(())
+}
+
+fn write_data_descriptor<W: Write>(writer: &mut W, entry: &Entry) -> io::Result<()> {
+    writer.write_u32::<LittleEndian>(DATA_DESCRIPTOR_SIGNATURE)?;
+    writer.write_u32::<LittleEndian>(entry.crc)?;
+    if entry.large_file {
+        writer.write_u64::<LittleEndian>(entry.compressed_size)?;
+        writer.write_u64::<LittleEndian>(entry.uncompressed_size)
+    } else {
+        writer.write_u32::<LittleEndian>(entry.compressed_size as u32)?;
+        writer.write_u32::<LittleEndian>(entry.uncompressed_size as u32)
+    }
+}
+
+fn write_central_directory_header<W: Write>(writer: &mut W, entry: &Entry) -> io::Result<()> {
+    // Values that don't fit are replaced by 0xFFFFFFFF, and stored in the zip64 extra field in
+    // this order instead.
+    let mut zip64_fields = Vec::new();
+    let mut field = |value: u64| if value >= ZIP64_LIMIT_32 || entry.large_file {
+        zip64_fields.push(value);
+        ZIP64_LIMIT_32 as u32
+    } else {
+        value as u32
+    };
+    let uncompressed_size = field(entry.uncompressed_size);
+    let compressed_size = field(entry.compressed_size);
+    // The offset is only moved to the extra field if it doesn't fit.
+    let offset = if entry.offset >= ZIP64_LIMIT_32 {
+        zip64_fields.push(entry.offset);
+        ZIP64_LIMIT_32 as u32
+    } else {
+        entry.offset as u32
+    };
+    let extra_length = if zip64_fields.is_empty() {
+        0
+    } else {
+        4 + 8 * zip64_fields.len() as u16
+    };
+
+    writer.write_u32::<LittleEndian>(CENTRAL_DIRECTORY_HEADER_SIGNATURE)?;
+    writer.write_u16::<LittleEndian>(entry.version_made_by)?;
+    writer.write_u16::<LittleEndian>(entry.version_needed())?;
+    writer.write_u16::<LittleEndian>(entry.flags)?;
+    writer.write_u16::<LittleEndian>(entry.method.to_u16())?;
+    writer.write_u16::<LittleEndian>(entry.time)?;
+    writer.write_u16::<LittleEndian>(entry.date)?;
+    writer.write_u32::<LittleEndian>(entry.crc)?;
+    writer.write_u32::<LittleEndian>(compressed_size)?;
+    writer.write_u32::<LittleEndian>(uncompressed_size)?;
+    writer.write_u16::<LittleEndian>(entry.name.len() as u16)?;
+    writer.write_u16::<LittleEndian>(extra_length)?;
+    // No comment, starts on disk 0, and no internal attributes.
+    writer.write_u16::<LittleEndian>(0)?;
+    writer.write_u16::<LittleEndian>(0)?;
+    writer.write_u16::<LittleEndian>(0)?;
+    writer.write_u32::<LittleEndian>(entry.external_attributes)?;
+    writer.write_u32::<LittleEndian>(offset)?;
+    writer.write_all(&entry.name)?;
+    if !zip64_fields.is_empty() {
+        writer.write_u16::<LittleEndian>(ZIP64_EXTRA_FIELD_ID)?;
+        writer.write_u16::<LittleEndian>(extra_length - 4)?;
+        for &value in &zip64_fields {
+            writer.write_u64::<LittleEndian>(value)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_utils::{get_test_data, unzip};
+
+    #[test]
+    fn zip_entries() {
+        let data = get_test_data();
+        let mut zip = ZipWriter::new(Vec::new());
+        zip.start_file("pg11.txt", FileOptions::default()).unwrap();
+        // Write in multiple steps to check that the output is copied as it's produced.
+        for chunk in data.chunks(10000) {
+            zip.write_all(chunk).unwrap();
+        }
+        zip.add_directory("dir", FileOptions::default()).unwrap();
+        let stored = FileOptions {
+            compression_method: CompressionMethod::Stored,
+            unix_permissions: Some(0o644),
+            ..Default::default()
+        };
+        zip.start_file("dir/grüße.txt", stored).unwrap();
+        zip.write_all("Grüße".as_bytes()).unwrap();
+        zip.start_file("empty", FileOptions::default()).unwrap();
+        let archive = zip.finish().unwrap();
+        assert!(archive.len() < data.len() / 2);
+
+        let entries = unzip(&archive);
+        let names: Vec<_> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["pg11.txt", "dir/", "dir/grüße.txt", "empty"]);
+        assert!(entries[0].data == data);
+        assert_eq!(entries[1].external_attributes, MS_DOS_DIRECTORY);
+        assert_eq!(entries[2].data, "Grüße".as_bytes());
+        assert_eq!(entries[2].external_attributes, (UNIX_FILE | 0o644) << 16);
+        assert!(entries[3].data.is_empty());
+        assert!(entries.iter().all(|e| !e.zip64));
+    }
+
+    #[test]
+    fn zip_large_file() {
+        let data = get_test_data();
+        let mut zip = ZipWriter::new(Vec::new());
+        let options = FileOptions {
+            large_file: true,
+            ..Default::default()
+        };
+        zip.start_file("large", options).unwrap();
+        zip.write_all(&data).unwrap();
+        let archive = zip.finish().unwrap();
+
+        // The local header should have the zip64 version and extra field.
+        assert_eq!(&archive[4..6], &[45, 0]);
+        let entries = unzip(&archive);
+        assert!(entries[0].zip64);
+        assert!(entries[0].data == data);
+    }
+
+    #[test]
+    fn zip64_many_entries() {
+        let mut zip = ZipWriter::new(Vec::new());
+        let options = FileOptions {
+            compression_method: CompressionMethod::Stored,
+            ..Default::default()
+        };
+        let num_entries = 70000;
+        for i in 0..num_entries {
+            zip.start_file(&i.to_string(), options).unwrap();
+        }
+        let archive = zip.finish().unwrap();
+        let entries = unzip(&archive);
+        assert_eq!(entries.len(), num_entries);
+        assert_eq!(entries[num_entries - 1].name, (num_entries - 1).to_string());
+    }
+
+    #[test]
+    fn zip_deflate64() {
+        let data = get_test_data();
+        let mut zip = ZipWriter::new(Vec::new());
+        let options = FileOptions {
+            compression_method: CompressionMethod::Deflate64,
+            ..Default::default()
+        };
+        zip.start_file("pg11.txt", options).unwrap();
+        zip.write_all(&data).unwrap();
+        let archive = zip.finish().unwrap();
+
+        // Version 2.1 is needed to extract the entry, and the method is 9.
+        assert_eq!(&archive[4..6], &[21, 0]);
+        assert_eq!(&archive[8..10], &[9, 0]);
+        let entries = unzip(&archive);
+        assert!(entries[0].data == data);
+    }
+
+    #[test]
+    fn zip_write_without_file() {
+        let mut zip = ZipWriter::new(Vec::new());
+        assert!(zip.write_all(b"data").is_err());
+        zip.add_directory("dir/", FileOptions::default()).unwrap();
+        assert!(zip.write_all(b"data").is_err());
+        assert_eq!(unzip(&zip.finish().unwrap())[0].name, "dir/");
+    }
+
+    #[test]
+    fn ms_dos_date_time() {
+        let date_time = DateTime {
+            year: 2017,
+            month: 6,
+            day: 15,
+            hour: 13,
+            minute: 37,
+            second: 59,
+        };
+        assert_eq!(date_time.to_ms_dos(),
+                   ((37 << 9) | (6 << 5) | 15, (13 << 11) | (37 << 5) | 29));
+        assert_eq!(DateTime::default().to_ms_dos(), ((1 << 5) | 1, 0));
+    }
+}
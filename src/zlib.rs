@@ -2,23 +2,32 @@
 //! header.
 //!
 //! The Zlib header contains some metadata (a window size and a compression level), and optionally
-//! a block of data serving as an extra dictionary for the compressor/decompressor.
-//! The dictionary is not implemented in this library.
+//! the id of a preset dictionary used by the compressor/decompressor.
 //! The data in the header aside from the dictionary doesn't actually have any effect on the
 //! decompressed data, it only offers some hints for the decompressor on how the data was
 //! compressed.
 
 use std::io::{Write, Result};
 
+use byteorder::{WriteBytesExt, BigEndian};
+
+use compression_options::{ZlibCompat, ZlibStrategy};
+
 // CM = 8 means to use the DEFLATE compression method.
 const DEFAULT_CM: u8 = 8;
 // CINFO = 7 Indicates a 32k window size.
+#[cfg(test)]
 const DEFAULT_CINFO: u8 = 7 << 4;
+#[cfg(test)]
 const DEFAULT_CMF: u8 = DEFAULT_CM | DEFAULT_CINFO;
+// CINFO is the base-2 logarithm of the window size minus 8.
+const CINFO_OFFSET: u8 = 8;
 
 // No dict by default.
 #[cfg(test)]
 const DEFAULT_FDICT: u8 = 0;
+// FDICT set indicates that a preset dictionary is used.
+const FDICT: u8 = 1 << 5;
 // FLEVEL = 0 means fastest compression algorithm.
 const _DEFAULT_FLEVEL: u8 = 0 << 7;
 
@@ -34,6 +43,25 @@ pub enum CompressionLevel {
     Maximum = 3 << 6,
 }
 
+impl CompressionLevel {
+    /// The level zlib puts in the header when compressing with the given level and strategy.
+    pub fn from_zlib_compat(compat: &ZlibCompat) -> CompressionLevel {
+        match compat.strategy {
+            ZlibStrategy::HuffmanOnly | ZlibStrategy::Rle | ZlibStrategy::Fixed => {
+                CompressionLevel::Fastest
+            }
+            ZlibStrategy::Default | ZlibStrategy::Filtered => {
+                match compat.level {
+                    0 | 1 => CompressionLevel::Fastest,
+                    2..=5 => CompressionLevel::Fast,
+                    6 => CompressionLevel::Default,
+                    _ => CompressionLevel::Maximum,
+                }
+            }
+        }
+    }
+}
+
 /// Generate FCHECK from CMF and FLG (without FCKECH )so that they are correct according to the
 /// specification, i.e (CMF*256 + FCHK) % 31 = 0.
 /// Returns flg with the FCHKECK bits added (any existing FCHECK bits are ignored).
@@ -48,17 +76,32 @@ fn add_fcheck(cmf: u8, flg: u8) -> u8 {
     flg + (FCHECK_DIVISOR - rem as u8)
 }
 
-/// Write a zlib header with an empty dictionary to the writer using the specified
-/// compression level preset.
-pub fn write_zlib_header<W: Write>(writer: &mut W, level: CompressionLevel) -> Result<()> {
-    writer.write_all(&get_zlib_header(level))
+/// Get the zlib header for the `CompressionLevel` level and a window size of
+/// `2^window_bits`, setting the flag that indicates the use of a preset dictionary if
+/// `dictionary` is true.
+///
+/// `window_bits` should be between 8 and 15.
+pub fn get_zlib_header_conf(level: CompressionLevel, window_bits: u8, dictionary: bool) -> [u8; 2] {
+    debug_assert!((8..=15).contains(&window_bits));
+    let cmf = DEFAULT_CM | ((window_bits - CINFO_OFFSET) << 4);
+    let flg = level as u8 | if dictionary { FDICT } else { 0 };
+    [cmf, add_fcheck(cmf, flg)]
 }
 
-/// Get the zlib header for the `CompressionLevel` level using the default window size and no
-/// dictionary.
-pub fn get_zlib_header(level: CompressionLevel) -> [u8; 2] {
-    let cmf = DEFAULT_CMF;
-    [cmf, add_fcheck(cmf, level as u8)]
+/// Write a zlib header to the writer using the specified compression level preset and window size.
+///
+/// If `dictionary_id` is given, the header will indicate that a preset dictionary is used, and
+/// the id (the adler32 checksum of the dictionary) is written after it.
+pub fn write_zlib_header_conf<W: Write>(writer: &mut W,
+                                        level: CompressionLevel,
+                                        window_bits: u8,
+                                        dictionary_id: Option<u32>)
+                                        -> Result<()> {
+    writer.write_all(&get_zlib_header_conf(level, window_bits, dictionary_id.is_some()))?;
+    if let Some(id) = dictionary_id {
+        writer.write_u32::<BigEndian>(id)?;
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -74,9 +117,39 @@ mod test {
         assert_eq!(((usize::from(cmf) * 256) + usize::from(flg)) % 31, 0);
     }
 
+    #[test]
+    fn test_header_conf() {
+        assert_eq!(get_zlib_header_conf(CompressionLevel::Default, 15, false),
+                   [DEFAULT_CMF, 0x9C]);
+        for window_bits in 8..16 {
+            let header = get_zlib_header_conf(CompressionLevel::Maximum, window_bits, true);
+            assert_eq!(header[0] >> 4, window_bits - 8);
+            assert!(header[1] & FDICT != 0);
+            assert_eq!(((usize::from(header[0]) * 256) + usize::from(header[1])) % 31, 0);
+        }
+        let mut out = Vec::new();
+        write_zlib_header_conf(&mut out, CompressionLevel::Default, 15, Some(0x0102_0304))
+            .unwrap();
+        assert_eq!(&out[2..], &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn zlib_compat_level() {
+        let header = |level, strategy| {
+            let level = CompressionLevel::from_zlib_compat(&ZlibCompat { level, strategy });
+            get_zlib_header_conf(level, 15, false)
+        };
+        // The headers written by zlib.
+        assert_eq!(header(1, ZlibStrategy::Default), [0x78, 0x01]);
+        assert_eq!(header(5, ZlibStrategy::Filtered), [0x78, 0x5e]);
+        assert_eq!(header(6, ZlibStrategy::Default), [0x78, 0x9c]);
+        assert_eq!(header(9, ZlibStrategy::Default), [0x78, 0xda]);
+        assert_eq!(header(9, ZlibStrategy::Rle), [0x78, 0x01]);
+    }
+
     #[test]
     fn test_header() {
-        let header = get_zlib_header(CompressionLevel::Fastest);
+        let header = get_zlib_header_conf(CompressionLevel::Fastest, 15, false);
         assert_eq!(((usize::from(header[0]) * 256) + usize::from(header[1])) % 31,
                    0);
     }
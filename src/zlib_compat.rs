@@ -0,0 +1,1304 @@
+//! An encoder giving the exact same output as zlib.
+//!
+//! This is a port of the compression code in zlib 1.2.13 (`deflate.c` and `trees.c`), which
+//! gives the same output as zlib 1.2.12 as well. Everything that affects the output is kept the
+//! same: the hash function and hash chains, the handling of the sliding window, the matching
+//! loops and their parameters for each level, ending blocks when the symbol buffer is full, and
+//! how the huffman trees are built, including how ties are broken. The names follow zlib to make
+//! it easy to compare the two.
+//!
+//! zlib is assumed to use the default `memLevel` of `8`, and to always have enough room for its
+//! output, which matters for level `0`.
+
+use core::cmp;
+
+use bitstream::{BitWriter, PendingBuffer};
+use compress::{flush_to_bitstream, Flush};
+use compression_options::{ZlibCompat, ZlibStrategy};
+use encoder_state::EncoderState;
+use huffman_lengths::{write_encoded_lengths, HUFFMAN_LENGTH_ORDER, HLIT_BITS, HDIST_BITS,
+                      HCLEN_BITS};
+use huffman_table::{create_codes_in_place, get_length_code, get_distance_code, HuffmanCode,
+                    FIXED_CODE_LENGTHS, FIXED_CODE_LENGTHS_DISTANCE, LENGTH_EXTRA_BITS_LENGTH,
+                    DISTANCE_EXTRA_BITS, LENGTH_BITS_START, NUM_LITERALS_AND_LENGTHS,
+                    NUM_DISTANCE_CODES, MAX_CODE_LENGTH, END_OF_BLOCK_POSITION};
+use length_encode::{EncodedLength, EncodedLengths, COPY_PREVIOUS, REPEAT_ZERO_3_BITS,
+                    REPEAT_ZERO_7_BITS};
+use lzvalue::LZValue;
+use matching::get_match_length;
+use stats::CompressionStats;
+use stored_block::{write_stored_header, compress_block_stored, compress_block_stored_parts,
+                   MAX_STORED_BLOCK_LENGTH};
+use error::Error;
+use sink::Sink;
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// The amount of input kept ahead of the current position, so a match of any length can be
+/// found there, unless the end of the input is reached.
+const MIN_LOOKAHEAD: usize = MAX_MATCH + MIN_MATCH + 1;
+/// Matches of the minimum length are discarded by the lazy matcher if they are further back
+/// than this.
+const TOO_FAR: usize = 4096;
+/// The number of bytes after the end of the data in the window that are zeroed, as the matching
+/// functions may read (but not use) them.
+const WIN_INIT: usize = MAX_MATCH;
+
+// The hash table size for the default memLevel of 8.
+/// The largest window size.
+const MAX_W_SIZE: usize = 1 << 15;
+
+const HASH_BITS: usize = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+const HASH_MASK: usize = HASH_SIZE - 1;
+/// Shift so that the hash only depends on the last `MIN_MATCH` bytes, `HASH_BITS / MIN_MATCH`
+/// rounded up.
+const HASH_SHIFT: usize = 5;
+/// The empty hash chain entry, which means position 0 can't be used as a match.
+const NIL: usize = 0;
+
+/// The size of the symbol buffer for the default memLevel of 8. A block is ended when it holds
+/// one symbol less than this.
+const LIT_BUFSIZE: usize = 1 << 14;
+/// The size of the buffer holding pending output in zlib, which limits the size of the stored
+/// blocks written by level `0`.
+const PENDING_BUF_SIZE: usize = LIT_BUFSIZE * 4;
+
+/// The number of codes in the huffman table used to encode the code lengths.
+const BL_CODES: usize = 19;
+/// The size of the heap used for building the largest huffman tree.
+const HEAP_SIZE: usize = 2 * NUM_LITERALS_AND_LENGTHS + 1;
+/// The longest code allowed in the huffman table for the code lengths.
+const MAX_BL_BITS: usize = 7;
+/// The number of extra bits used by the repeat codes in the code length table.
+static BL_EXTRA_BITS: [u8; BL_CODES] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 3, 7];
+
+/// The matching parameters of a compression level.
+#[derive(Copy, Clone)]
+struct Config {
+    /// Search less when the previous match is at least this long.
+    good_length: usize,
+    /// Don't look for a better match if the current one is at least this long. For the fast
+    /// levels, this is the longest match that is inserted into the hash table.
+    max_lazy: usize,
+    /// Stop searching when finding a match of this length.
+    nice_length: usize,
+    /// The maximum number of hash chain entries checked.
+    max_chain: usize,
+}
+
+const fn config(good_length: usize,
+                max_lazy: usize,
+                nice_length: usize,
+                max_chain: usize)
+                -> Config {
+    Config {
+        good_length,
+        max_lazy,
+        nice_length,
+        max_chain,
+    }
+}
+
+/// The parameters of zlib's compression levels. Level `0` only outputs stored blocks, levels
+/// `1` to `3` use `deflate_fast` and the others `deflate_slow`.
+static CONFIGURATION_TABLE: [Config; 10] = [config(0, 0, 0, 0),
+                                            config(4, 4, 8, 4),
+                                            config(4, 5, 16, 8),
+                                            config(4, 6, 32, 32),
+                                            config(4, 4, 16, 16),
+                                            config(8, 16, 32, 32),
+                                            config(8, 16, 128, 128),
+                                            config(8, 32, 128, 256),
+                                            config(32, 128, 258, 1024),
+                                            config(32, 258, 258, 4096)];
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum BlockState {
+    /// More input or output space is needed to continue.
+    NeedMore,
+    /// The current block has been output, for a flush.
+    BlockDone,
+    /// The last block has been output.
+    FinishDone,
+}
+
+/// The rank of a flush mode, used to skip flushes that wouldn't do anything.
+fn rank(flush: Flush) -> i32 {
+    match flush {
+        Flush::None => 0,
+        Flush::_Block => 1,
+        Flush::_Partial => 2,
+        Flush::Sync => 4,
+        Flush::Full => 6,
+        Flush::Finish => 8,
+    }
+}
+
+/// The static parts of the description of a huffman tree.
+struct StaticTreeDesc {
+    /// The code lengths of the fixed huffman codes, if any.
+    static_lengths: Option<&'static [u8]>,
+    /// The number of extra bits following each code, starting from `extra_base`.
+    extra_bits: &'static [u8],
+    extra_base: usize,
+    /// The number of codes.
+    elems: usize,
+    /// The longest code allowed.
+    max_length: usize,
+}
+
+static L_DESC: StaticTreeDesc = StaticTreeDesc {
+    static_lengths: Some(&FIXED_CODE_LENGTHS),
+    extra_bits: &LENGTH_EXTRA_BITS_LENGTH,
+    extra_base: LENGTH_BITS_START as usize,
+    elems: NUM_LITERALS_AND_LENGTHS,
+    max_length: MAX_CODE_LENGTH,
+};
+
+static D_DESC: StaticTreeDesc = StaticTreeDesc {
+    static_lengths: Some(&FIXED_CODE_LENGTHS_DISTANCE),
+    extra_bits: &DISTANCE_EXTRA_BITS,
+    extra_base: 0,
+    elems: NUM_DISTANCE_CODES,
+    max_length: MAX_CODE_LENGTH,
+};
+
+static BL_DESC: StaticTreeDesc = StaticTreeDesc {
+    static_lengths: None,
+    extra_bits: &BL_EXTRA_BITS,
+    extra_base: 0,
+    elems: BL_CODES,
+    max_length: MAX_BL_BITS,
+};
+
+/// A huffman tree, with room for the internal nodes after the leaves.
+///
+/// All the trees have room for the largest one, the literal/length tree.
+struct Tree {
+    freq: [u16; HEAP_SIZE],
+    len: [u8; HEAP_SIZE],
+    dad: [usize; HEAP_SIZE],
+    /// The largest code with a non-zero frequency.
+    max_code: usize,
+}
+
+impl Tree {
+    const fn new() -> Tree {
+        Tree {
+            freq: [0; HEAP_SIZE],
+            len: [0; HEAP_SIZE],
+            dad: [0; HEAP_SIZE],
+            max_code: 0,
+        }
+    }
+
+    /// The code lengths of the codes up to and including `max_code`.
+    fn lengths(&self) -> &[u8] {
+        &self.len[..self.max_code + 1]
+    }
+}
+
+/// The state used while building a huffman tree, and the resulting block sizes.
+struct TreeBuilder {
+    heap: [usize; HEAP_SIZE],
+    heap_len: usize,
+    heap_max: usize,
+    /// The depth of each subtree, used to break ties between nodes of equal frequency.
+    depth: [u8; HEAP_SIZE],
+    bl_count: [u16; MAX_CODE_LENGTH + 1],
+    /// The size in bits of the current block using dynamic codes, including the code lengths.
+    opt_len: u64,
+    /// The size in bits of the current block using the fixed codes.
+    static_len: u64,
+}
+
+impl TreeBuilder {
+    const fn new() -> TreeBuilder {
+        TreeBuilder {
+            heap: [0; HEAP_SIZE],
+            heap_len: 0,
+            heap_max: 0,
+            depth: [0; HEAP_SIZE],
+            bl_count: [0; MAX_CODE_LENGTH + 1],
+            opt_len: 0,
+            static_len: 0,
+        }
+    }
+
+    /// Compare two nodes, using the depth when the frequencies are equal.
+    fn smaller(&self, tree: &Tree, n: usize, m: usize) -> bool {
+        tree.freq[n] < tree.freq[m] ||
+        (tree.freq[n] == tree.freq[m] && self.depth[n] <= self.depth[m])
+    }
+
+    /// Restore the heap property by moving the node at position `k` down the heap.
+    fn pqdownheap(&mut self, tree: &Tree, mut k: usize) {
+        let v = self.heap[k];
+        let mut j = k << 1;
+        while j <= self.heap_len {
+            if j < self.heap_len && self.smaller(tree, self.heap[j + 1], self.heap[j]) {
+                j += 1;
+            }
+            if self.smaller(tree, v, self.heap[j]) {
+                break;
+            }
+            self.heap[k] = self.heap[j];
+            k = j;
+            j <<= 1;
+        }
+        self.heap[k] = v;
+    }
+
+    /// Remove the smallest node from the heap and return it.
+    fn pqremove(&mut self, tree: &Tree) -> usize {
+        let top = self.heap[1];
+        self.heap[1] = self.heap[self.heap_len];
+        self.heap_len -= 1;
+        self.pqdownheap(tree, 1);
+        top
+    }
+
+    /// Build the huffman tree for the frequencies in `tree`, setting the code lengths and
+    /// `max_code`, and adding the size of the data using it to `opt_len` and `static_len`.
+    fn build_tree(&mut self, tree: &mut Tree, desc: &StaticTreeDesc) {
+        let mut max_code: isize = -1;
+        self.heap_len = 0;
+        self.heap_max = HEAP_SIZE;
+        for n in 0..desc.elems {
+            if tree.freq[n] != 0 {
+                self.heap_len += 1;
+                self.heap[self.heap_len] = n;
+                max_code = n as isize;
+                self.depth[n] = 0;
+            } else {
+                tree.len[n] = 0;
+            }
+        }
+
+        // There has to be at least two codes, so one or two codes are added if needed. The
+        // block sizes are adjusted as if the codes were not there.
+        while self.heap_len < 2 {
+            let node = if max_code < 2 {
+                max_code += 1;
+                max_code as usize
+            } else {
+                0
+            };
+            self.heap_len += 1;
+            self.heap[self.heap_len] = node;
+            tree.freq[node] = 1;
+            self.depth[node] = 0;
+            self.opt_len = self.opt_len.wrapping_sub(1);
+            if let Some(lengths) = desc.static_lengths {
+                self.static_len = self.static_len.wrapping_sub(u64::from(lengths[node]));
+            }
+        }
+        tree.max_code = max_code as usize;
+
+        for n in (1..self.heap_len / 2 + 1).rev() {
+            self.pqdownheap(tree, n);
+        }
+
+        // Combine the two least frequent nodes until only the root is left. The nodes are
+        // stored at the end of the heap in order of decreasing frequency.
+        let mut node = desc.elems;
+        loop {
+            let n = self.pqremove(tree);
+            let m = self.heap[1];
+            self.heap_max -= 1;
+            self.heap[self.heap_max] = n;
+            self.heap_max -= 1;
+            self.heap[self.heap_max] = m;
+
+            tree.freq[node] = tree.freq[n] + tree.freq[m];
+            self.depth[node] = cmp::max(self.depth[n], self.depth[m]) + 1;
+            tree.dad[n] = node;
+            tree.dad[m] = node;
+            self.heap[1] = node;
+            node += 1;
+            self.pqdownheap(tree, 1);
+            if self.heap_len < 2 {
+                break;
+            }
+        }
+        self.heap_max -= 1;
+        self.heap[self.heap_max] = self.heap[1];
+
+        self.gen_bitlen(tree, desc);
+    }
+
+    /// Compute the code lengths from the tree, limiting them to the maximum length.
+    fn gen_bitlen(&mut self, tree: &mut Tree, desc: &StaticTreeDesc) {
+        let max_code = tree.max_code;
+        let max_length = desc.max_length;
+        self.bl_count = [0; MAX_CODE_LENGTH + 1];
+
+        // The parent of each node is before it in the heap, so the lengths can be computed in
+        // one pass starting at the root.
+        tree.len[self.heap[self.heap_max]] = 0;
+        let mut overflow = 0;
+        for h in self.heap_max + 1..HEAP_SIZE {
+            let n = self.heap[h];
+            let mut bits = usize::from(tree.len[tree.dad[n]]) + 1;
+            if bits > max_length {
+                bits = max_length;
+                overflow += 1;
+            }
+            tree.len[n] = bits as u8;
+            if n > max_code {
+                // Not a leaf.
+                continue;
+            }
+            self.bl_count[bits] += 1;
+            let xbits = if n >= desc.extra_base {
+                usize::from(desc.extra_bits[n - desc.extra_base])
+            } else {
+                0
+            };
+            let f = u64::from(tree.freq[n]);
+            self.opt_len = self.opt_len.wrapping_add(f * (bits + xbits) as u64);
+            if let Some(lengths) = desc.static_lengths {
+                let static_bits = usize::from(lengths[n]) + xbits;
+                self.static_len = self.static_len.wrapping_add(f * static_bits as u64);
+            }
+        }
+        if overflow == 0 {
+            return;
+        }
+
+        // Move leaves from the maximum length up the tree until the lengths are valid again.
+        while overflow > 0 {
+            let mut bits = max_length - 1;
+            while self.bl_count[bits] == 0 {
+                bits -= 1;
+            }
+            self.bl_count[bits] -= 1;
+            self.bl_count[bits + 1] += 2;
+            self.bl_count[max_length] -= 1;
+            overflow -= 2;
+        }
+
+        // Assign the new lengths to the leaves, in order of increasing frequency.
+        let mut h = HEAP_SIZE;
+        for bits in (1..max_length + 1).rev() {
+            let mut n = self.bl_count[bits];
+            while n != 0 {
+                h -= 1;
+                let m = self.heap[h];
+                if m > max_code {
+                    continue;
+                }
+                let len = u64::from(tree.len[m]);
+                if len != bits as u64 {
+                    let change = (bits as u64).wrapping_sub(len);
+                    self.opt_len =
+                        self.opt_len.wrapping_add(change.wrapping_mul(u64::from(tree.freq[m])));
+                    tree.len[m] = bits as u8;
+                }
+                n -= 1;
+            }
+        }
+    }
+}
+
+/// Run-length encode code lengths the way zlib does, which doesn't let runs continue from the
+/// literal/length codes to the distance codes.
+fn scan_tree(lengths: &[u8], encoded: &mut EncodedLengths) {
+    // The length after the last one acts as a guard that is different from all lengths.
+    let len_at = |n: usize| lengths.get(n).map_or(-1, |&l| i32::from(l));
+    encoded.clear();
+    let mut prevlen = -1;
+    let mut nextlen = len_at(0);
+    let mut count = 0;
+    let (mut max_count, mut min_count) = if nextlen == 0 { (138, 3) } else { (7, 4) };
+    for n in 0..lengths.len() {
+        let curlen = nextlen;
+        nextlen = len_at(n + 1);
+        count += 1;
+        if count < max_count && curlen == nextlen {
+            continue;
+        } else if count < min_count {
+            for _ in 0..count {
+                encoded.push(EncodedLength::Length(curlen as u8));
+            }
+        } else if curlen != 0 {
+            if curlen != prevlen {
+                encoded.push(EncodedLength::Length(curlen as u8));
+                count -= 1;
+            }
+            encoded.push(EncodedLength::CopyPrevious(count as u8));
+        } else if count <= 10 {
+            encoded.push(EncodedLength::RepeatZero3Bits(count as u8));
+        } else {
+            encoded.push(EncodedLength::RepeatZero7Bits(count as u8));
+        }
+        count = 0;
+        prevlen = curlen;
+        let (max, min) = if nextlen == 0 {
+            (138, 3)
+        } else if curlen == nextlen {
+            (6, 3)
+        } else {
+            (7, 4)
+        };
+        max_count = max;
+        min_count = min;
+    }
+}
+
+/// The symbols of the current block, and the huffman trees used to output them.
+struct Trees {
+    /// The literals and matches of the current block, the first `sym_count` of `syms`.
+    syms: [LZValue; LIT_BUFSIZE],
+    sym_count: usize,
+    ltree: Tree,
+    dtree: Tree,
+    bltree: Tree,
+    builder: TreeBuilder,
+    /// The run-length encoded code lengths of the literal/length and distance trees.
+    l_encoded: EncodedLengths,
+    d_encoded: EncodedLengths,
+}
+
+impl Trees {
+    /// Create the trees, which have to be initialized with `init_block` before use.
+    const fn new() -> Trees {
+        Trees {
+            syms: [LZValue::literal(0); LIT_BUFSIZE],
+            sym_count: 0,
+            ltree: Tree::new(),
+            dtree: Tree::new(),
+            bltree: Tree::new(),
+            builder: TreeBuilder::new(),
+            l_encoded: EncodedLengths::new(),
+            d_encoded: EncodedLengths::new(),
+        }
+    }
+
+    /// The literals and matches of the current block.
+    fn syms(&self) -> &[LZValue] {
+        &self.syms[..self.sym_count]
+    }
+
+    fn init_block(&mut self) {
+        for f in &mut self.ltree.freq[..L_DESC.elems] {
+            *f = 0;
+        }
+        for f in &mut self.dtree.freq[..D_DESC.elems] {
+            *f = 0;
+        }
+        for f in &mut self.bltree.freq[..BL_DESC.elems] {
+            *f = 0;
+        }
+        self.ltree.freq[END_OF_BLOCK_POSITION] = 1;
+        self.builder.opt_len = 0;
+        self.builder.static_len = 0;
+        self.sym_count = 0;
+    }
+
+    /// Add a literal to the block, returning true if the block is full.
+    fn tally_lit(&mut self, c: u8) -> bool {
+        self.syms[self.sym_count] = LZValue::literal(c);
+        self.sym_count += 1;
+        self.ltree.freq[usize::from(c)] += 1;
+        self.sym_count == LIT_BUFSIZE - 1
+    }
+
+    /// Add a match to the block, returning true if the block is full.
+    fn tally_dist(&mut self, distance: usize, length: usize) -> bool {
+        let (length, distance) = (length as u16, distance as u16);
+        self.syms[self.sym_count] = LZValue::length_distance(length, distance);
+        self.sym_count += 1;
+        if let Some(code) = get_length_code(length) {
+            self.ltree.freq[code] += 1;
+        }
+        if let Some(code) = get_distance_code(distance) {
+            self.dtree.freq[usize::from(code)] += 1;
+        }
+        self.sym_count == LIT_BUFSIZE - 1
+    }
+
+    /// Build the tree for the code lengths, returning the index in `HUFFMAN_LENGTH_ORDER` of
+    /// the last code length that has to be sent.
+    fn build_bl_tree(&mut self) -> usize {
+        scan_tree(self.ltree.lengths(), &mut self.l_encoded);
+        scan_tree(self.dtree.lengths(), &mut self.d_encoded);
+        for &e in self.l_encoded.as_slice().iter().chain(self.d_encoded.as_slice()) {
+            let symbol = match e {
+                EncodedLength::Length(l) => usize::from(l),
+                EncodedLength::CopyPrevious(_) => COPY_PREVIOUS,
+                EncodedLength::RepeatZero3Bits(_) => REPEAT_ZERO_3_BITS,
+                EncodedLength::RepeatZero7Bits(_) => REPEAT_ZERO_7_BITS,
+            };
+            self.bltree.freq[symbol] += 1;
+        }
+        self.builder.build_tree(&mut self.bltree, &BL_DESC);
+
+        let mut max_blindex = BL_CODES - 1;
+        while max_blindex >= 3 &&
+              self.bltree.len[usize::from(HUFFMAN_LENGTH_ORDER[max_blindex])] == 0 {
+            max_blindex -= 1;
+        }
+        // The code lengths, and the numbers of codes.
+        self.builder.opt_len += 3 * (max_blindex as u64 + 1) + 5 + 5 + 4;
+        max_blindex
+    }
+
+    /// Write the code lengths of the dynamic trees.
+    fn send_all_trees<W: Sink, P: PendingBuffer>(&self,
+                                                 max_blindex: usize,
+                                                 encoder: &mut EncoderState<W, P>)
+                                                 -> Result<(), Error> {
+        let writer = &mut encoder.writer;
+        writer.write_bits((self.ltree.max_code + 1 - usize::from(LENGTH_BITS_START)) as u16,
+                        HLIT_BITS)?;
+        writer.write_bits(self.dtree.max_code as u16, HDIST_BITS)?;
+        writer.write_bits((max_blindex + 1 - 4) as u16, HCLEN_BITS)?;
+        for &n in &HUFFMAN_LENGTH_ORDER[..max_blindex + 1] {
+            writer.write_bits(u16::from(self.bltree.len[usize::from(n)]), 3)?;
+        }
+        let mut codes = [HuffmanCode::default(); BL_CODES];
+        create_codes_in_place(&mut codes, &self.bltree.len[..BL_CODES])?;
+        write_encoded_lengths(self.l_encoded.as_slice(), &codes, writer)?;
+        write_encoded_lengths(self.d_encoded.as_slice(), &codes, writer)
+    }
+
+    /// Output the current block as a stored block if `stored` holds its data and that is the
+    /// smallest option, or using fixed or dynamic codes.
+    fn flush_block<W: Sink, P: PendingBuffer>(&mut self,
+                                              stored: Option<&[u8]>,
+                                              last: bool,
+                                              fixed: bool,
+                                              encoder: &mut EncoderState<W, P>,
+                                              stats: &mut CompressionStats)
+                                              -> Result<(), Error> {
+        self.builder.build_tree(&mut self.ltree, &L_DESC);
+        self.builder.build_tree(&mut self.dtree, &D_DESC);
+        let max_blindex = self.build_bl_tree();
+
+        // The sizes in bytes, including the block header.
+        let mut opt_lenb = self.builder.opt_len.wrapping_add(3 + 7) >> 3;
+        let static_lenb = self.builder.static_len.wrapping_add(3 + 7) >> 3;
+        if static_lenb <= opt_lenb || fixed {
+            opt_lenb = static_lenb;
+        }
+
+        match stored {
+            Some(data) if data.len() as u64 + 4 <= opt_lenb => {
+                write_stored_header(&mut encoder.writer, last)?;
+                compress_block_stored(data, &mut encoder.writer)?;
+                stats.stored_blocks += 1;
+            }
+            _ if static_lenb == opt_lenb => {
+                encoder.write_start_of_block(true, last)?;
+                encoder.update_huffman_table(&FIXED_CODE_LENGTHS, &FIXED_CODE_LENGTHS_DISTANCE)?;
+                flush_to_bitstream(self.syms(), encoder, stats)?;
+                stats.fixed_blocks += 1;
+            }
+            _ => {
+                encoder.write_start_of_block(false, last)?;
+                self.send_all_trees(max_blindex, encoder)?;
+                encoder.update_huffman_table(self.ltree.lengths(), self.dtree.lengths())?;
+                flush_to_bitstream(self.syms(), encoder, stats)?;
+                stats.dynamic_blocks += 1;
+            }
+        }
+        self.init_block();
+        if last {
+            encoder.pad_to_byte()?;
+        }
+        Ok(())
+    }
+}
+
+/// The state of the zlib compatible encoder.
+pub struct ZlibState {
+    level: usize,
+    strategy: ZlibStrategy,
+    config: Config,
+    w_size: usize,
+    w_mask: usize,
+    /// The sliding window, which is twice the window size so data can be read in large parts.
+    ///
+    /// Only the first `window_size` bytes are used if the window is smaller than the largest one.
+    window: [u8; 2 * MAX_W_SIZE],
+    window_size: usize,
+    /// The previous position with the same hash, for the last `w_size` positions.
+    prev: [u16; MAX_W_SIZE],
+    /// The last position with each hash value.
+    head: [u16; HASH_SIZE],
+    /// The hash of the bytes at the current position.
+    ins_h: usize,
+    /// The start of the current block in the window, negative if the window has slid past it.
+    block_start: isize,
+    match_length: usize,
+    prev_match: usize,
+    /// Whether the previous byte has not been output yet.
+    match_available: bool,
+    strstart: usize,
+    match_start: usize,
+    /// The number of bytes of input in the window after `strstart`.
+    lookahead: usize,
+    prev_length: usize,
+    /// The number of bytes before `strstart` that still have to be inserted into the hash table.
+    insert: usize,
+    /// The end of the part of the window that has been initialized.
+    high_water: usize,
+    /// The last flush mode, or `None` if nothing was compressed yet.
+    last_flush: Option<Flush>,
+    /// Whether the last block has been output.
+    finished: bool,
+    trees: Trees,
+}
+
+impl ZlibState {
+    pub fn new(compat: ZlibCompat, window_bits: u8) -> ZlibState {
+        let mut state = ZlibState::empty();
+        state.init(compat, window_bits);
+        state
+    }
+
+    /// Create a state that has to be set up with `init` before use.
+    ///
+    /// As this is a `const fn`, the state can be placed in a `static`.
+    pub const fn empty() -> ZlibState {
+        ZlibState {
+            level: 0,
+            strategy: ZlibStrategy::Default,
+            config: config(0, 0, 0, 0),
+            w_size: MAX_W_SIZE,
+            w_mask: MAX_W_SIZE - 1,
+            window: [0; 2 * MAX_W_SIZE],
+            window_size: 2 * MAX_W_SIZE,
+            prev: [0; MAX_W_SIZE],
+            head: [0; HASH_SIZE],
+            ins_h: 0,
+            block_start: 0,
+            match_length: 0,
+            prev_match: 0,
+            match_available: false,
+            strstart: 0,
+            match_start: 0,
+            lookahead: 0,
+            prev_length: 0,
+            insert: 0,
+            high_water: 0,
+            last_flush: None,
+            finished: false,
+            trees: Trees::new(),
+        }
+    }
+
+    /// Set up the state for a new stream with the given options, without allocating.
+    ///
+    /// `window_bits` can be at most `15`.
+    pub fn init(&mut self, compat: ZlibCompat, window_bits: u8) {
+        let level = usize::from(cmp::min(compat.level, 9));
+        let w_size = 1 << window_bits;
+        debug_assert!(w_size <= MAX_W_SIZE);
+        self.level = level;
+        self.strategy = compat.strategy;
+        self.config = CONFIGURATION_TABLE[level];
+        self.w_size = w_size;
+        self.w_mask = w_size - 1;
+        self.window_size = 2 * w_size;
+        self.high_water = 0;
+        self.reset();
+    }
+
+    /// Start a new stream, like `deflateReset`.
+    ///
+    /// Like in zlib, the contents of the window are kept, as they can affect the output.
+    pub fn reset(&mut self) {
+        self.clear_hash();
+        self.strstart = 0;
+        self.block_start = 0;
+        self.lookahead = 0;
+        self.insert = 0;
+        self.match_length = MIN_MATCH - 1;
+        self.prev_length = MIN_MATCH - 1;
+        self.match_available = false;
+        self.ins_h = 0;
+        self.last_flush = None;
+        self.finished = false;
+        self.trees.init_block();
+    }
+
+    /// Use `dictionary` as the history matches can refer back to, like `deflateSetDictionary`.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) {
+        if self.level == 0 {
+            // Stored blocks don't use the history.
+            return;
+        }
+        let mut dictionary = &dictionary[dictionary.len().saturating_sub(self.w_size)..];
+        self.fill_window(&mut dictionary);
+        while self.lookahead >= MIN_MATCH {
+            let mut pos = self.strstart;
+            for _ in 0..self.lookahead - (MIN_MATCH - 1) {
+                self.insert_string(pos);
+                pos += 1;
+            }
+            self.strstart = pos;
+            self.lookahead = MIN_MATCH - 1;
+            self.fill_window(&mut dictionary);
+        }
+        self.strstart += self.lookahead;
+        self.block_start = self.strstart as isize;
+        self.insert = self.lookahead;
+        self.lookahead = 0;
+        self.match_length = MIN_MATCH - 1;
+        self.prev_length = MIN_MATCH - 1;
+        self.match_available = false;
+    }
+
+    /// Compress `input`, like a call to `deflate` with `input` as the available input.
+    pub fn deflate<W: Sink, P: PendingBuffer>(&mut self,
+                                               mut input: &[u8],
+                                               flush: Flush,
+                                               encoder: &mut EncoderState<W, P>,
+                                               stats: &mut CompressionStats)
+                                               -> Result<(), Error> {
+        let old_flush = self.last_flush;
+        self.last_flush = Some(flush);
+        // Repeated flushes without new input don't do anything.
+        if input.is_empty() && flush != Flush::Finish &&
+           old_flush.map(rank) >= Some(rank(flush)) {
+            return Ok(());
+        }
+
+        if !input.is_empty() || self.lookahead != 0 || (flush != Flush::None && !self.finished) {
+            let input = &mut input;
+            let state = if self.level == 0 {
+                self.deflate_stored(input, flush, encoder, stats)?
+            } else {
+                match self.strategy {
+                    ZlibStrategy::HuffmanOnly => self.deflate_huff(input, flush, encoder, stats)?,
+                    ZlibStrategy::Rle => self.deflate_rle(input, flush, encoder, stats)?,
+                    _ if self.level <= 3 => self.deflate_fast(input, flush, encoder, stats)?,
+                    _ => self.deflate_slow(input, flush, encoder, stats)?,
+                }
+            };
+
+            if state == BlockState::FinishDone {
+                self.finished = true;
+            }
+            if state == BlockState::BlockDone && (flush == Flush::Sync || flush == Flush::Full) {
+                write_stored_header(&mut encoder.writer, false)?;
+                compress_block_stored(&[], &mut encoder.writer)?;
+                stats.stored_blocks += 1;
+                if flush == Flush::Full {
+                    // Forget the history.
+                    self.clear_hash();
+                    if self.lookahead == 0 {
+                        self.strstart = 0;
+                        self.block_start = 0;
+                        self.insert = 0;
+                    }
+                }
+            }
+        }
+
+        if flush != Flush::None {
+            encoder.pad_to_byte()?;
+        }
+        Ok(())
+    }
+
+    fn max_dist(&self) -> usize {
+        self.w_size - MIN_LOOKAHEAD
+    }
+
+    fn clear_hash(&mut self) {
+        for h in &mut self.head[..] {
+            *h = NIL as u16;
+        }
+    }
+
+    fn update_hash(&mut self, c: u8) {
+        self.ins_h = ((self.ins_h << HASH_SHIFT) ^ usize::from(c)) & HASH_MASK;
+    }
+
+    /// Insert the string at `pos` into the hash table, returning the previous position with the
+    /// same hash.
+    fn insert_string(&mut self, pos: usize) -> usize {
+        self.update_hash(self.window[pos + MIN_MATCH - 1]);
+        let match_head = self.head[self.ins_h];
+        self.prev[pos & self.w_mask] = match_head;
+        self.head[self.ins_h] = pos as u16;
+        usize::from(match_head)
+    }
+
+    /// Move the hash table entries down when the window slides.
+    fn slide_hash(&mut self) {
+        let w_size = self.w_size;
+        for p in self.head.iter_mut().chain(&mut self.prev[..w_size]) {
+            let m = usize::from(*p);
+            *p = if m >= w_size { (m - w_size) as u16 } else { NIL as u16 };
+        }
+    }
+
+    /// Read input into the window, sliding it first if needed, until there is enough lookahead or
+    /// the input runs out.
+    fn fill_window(&mut self, input: &mut &[u8]) {
+        let w_size = self.w_size;
+        loop {
+            let mut more = self.window_size - self.lookahead - self.strstart;
+
+            // Slide the window when the current position gets too close to the end.
+            if self.strstart >= w_size + self.max_dist() {
+                let (lower, upper) = self.window.split_at_mut(w_size);
+                let kept = self.strstart + self.lookahead - w_size;
+                lower[..kept].copy_from_slice(&upper[..kept]);
+                self.match_start = self.match_start.wrapping_sub(w_size);
+                self.strstart -= w_size;
+                self.block_start -= w_size as isize;
+                if self.insert > self.strstart {
+                    self.insert = self.strstart;
+                }
+                self.slide_hash();
+                more += w_size;
+            }
+            if input.is_empty() {
+                break;
+            }
+
+            let n = cmp::min(more, input.len());
+            let start = self.strstart + self.lookahead;
+            self.window[start..start + n].copy_from_slice(&input[..n]);
+            *input = &input[n..];
+            self.lookahead += n;
+
+            // Insert the strings that couldn't be hashed before.
+            if self.lookahead + self.insert >= MIN_MATCH {
+                let mut pos = self.strstart - self.insert;
+                self.ins_h = usize::from(self.window[pos]);
+                self.update_hash(self.window[pos + 1]);
+                while self.insert > 0 {
+                    self.insert_string(pos);
+                    pos += 1;
+                    self.insert -= 1;
+                    if self.lookahead + self.insert < MIN_MATCH {
+                        break;
+                    }
+                }
+            }
+            if self.lookahead >= MIN_LOOKAHEAD || input.is_empty() {
+                break;
+            }
+        }
+
+        // Zero the bytes after the data that may be read when looking for matches near the end
+        // of the input, unless they have been initialized already.
+        if self.high_water < self.window_size {
+            let curr = self.strstart + self.lookahead;
+            let (start, end) = if self.high_water < curr {
+                (curr, curr + cmp::min(self.window_size - curr, WIN_INIT))
+            } else {
+                (self.high_water, cmp::min(curr + WIN_INIT, self.window_size))
+            };
+            if end > start {
+                for b in &mut self.window[start..end] {
+                    *b = 0;
+                }
+                self.high_water = end;
+            }
+        }
+    }
+
+    /// Find the longest match at `strstart` along the hash chain starting at `cur_match`,
+    /// setting `match_start` to its position and returning its length.
+    fn longest_match(&mut self, mut cur_match: usize) -> usize {
+        let mut chain_length = self.config.max_chain;
+        let window = &self.window;
+        let scan = self.strstart;
+        let mut best_len = self.prev_length;
+        let mut nice_match = self.config.nice_length;
+        let limit = self.strstart.saturating_sub(self.max_dist());
+        let mut scan_end1 = window[scan + best_len - 1];
+        let mut scan_end = window[scan + best_len];
+
+        // Search less if the previous match is already good.
+        if self.prev_length >= self.config.good_length {
+            chain_length >>= 2;
+        }
+        if nice_match > self.lookahead {
+            nice_match = self.lookahead;
+        }
+
+        loop {
+            let m = cur_match;
+            // Check the bytes that are most likely to differ first. The third byte is equal if
+            // the first two are, since the hashes are equal.
+            if window[m + best_len] == scan_end && window[m + best_len - 1] == scan_end1 &&
+               window[m] == window[scan] && window[m + 1] == window[scan + 1] {
+                let len = MIN_MATCH +
+                          get_match_length(window,
+                                           scan + MIN_MATCH,
+                                           m + MIN_MATCH,
+                                           MAX_MATCH - MIN_MATCH);
+                if len > best_len {
+                    self.match_start = cur_match;
+                    best_len = len;
+                    if len >= nice_match {
+                        break;
+                    }
+                    scan_end1 = window[scan + best_len - 1];
+                    scan_end = window[scan + best_len];
+                }
+            }
+
+            cur_match = usize::from(self.prev[cur_match & self.w_mask]);
+            if cur_match <= limit {
+                break;
+            }
+            chain_length -= 1;
+            if chain_length == 0 {
+                break;
+            }
+        }
+
+        cmp::min(best_len, self.lookahead)
+    }
+
+    /// Output the current block, and start a new one at `strstart`.
+    fn flush_block<W: Sink, P: PendingBuffer>(&mut self,
+                                               last: bool,
+                                               encoder: &mut EncoderState<W, P>,
+                                               stats: &mut CompressionStats)
+                                               -> Result<(), Error> {
+        // The block can only be stored if it's still in the window.
+        let stored = if self.block_start >= 0 {
+            Some(&self.window[self.block_start as usize..self.strstart])
+        } else {
+            None
+        };
+        let fixed = self.strategy == ZlibStrategy::Fixed;
+        self.trees.flush_block(stored, last, fixed, encoder, stats)?;
+        self.block_start = self.strstart as isize;
+        Ok(())
+    }
+
+    /// Output the last block if finishing, or the current block if it isn't empty.
+    fn finish_blocks<W: Sink, P: PendingBuffer>(&mut self,
+                                                 flush: Flush,
+                                                 encoder: &mut EncoderState<W, P>,
+                                                 stats: &mut CompressionStats)
+                                                 -> Result<BlockState, Error> {
+        if flush == Flush::Finish {
+            self.flush_block(true, encoder, stats)?;
+            return Ok(BlockState::FinishDone);
+        }
+        if self.trees.sym_count != 0 {
+            self.flush_block(false, encoder, stats)?;
+        }
+        Ok(BlockState::BlockDone)
+    }
+
+    /// Level `0`, only using stored blocks.
+    ///
+    /// With unlimited output space, zlib writes stored blocks of up to 65535 bytes as soon as
+    /// it has at least a window of data, and buffers smaller amounts unless flushing.
+    fn deflate_stored<W: Sink, P: PendingBuffer>(&mut self,
+                                                  input: &mut &[u8],
+                                                  flush: Flush,
+                                                  encoder: &mut EncoderState<W, P>,
+                                                  stats: &mut CompressionStats)
+                                                  -> Result<BlockState, Error> {
+        // The buffered data is kept at the start of the window.
+        let min_block = cmp::min(PENDING_BUF_SIZE - 5, self.w_size);
+        let mut last = false;
+        while !last {
+            let left = self.strstart;
+            let total = left + input.len();
+            let len = cmp::min(MAX_STORED_BLOCK_LENGTH, total);
+            if len < min_block &&
+               (flush == Flush::None || (len == 0 && flush != Flush::Finish)) {
+                break;
+            }
+            last = flush == Flush::Finish && len == total;
+            write_stored_header(&mut encoder.writer, last)?;
+            if left == 0 {
+                compress_block_stored(&input[..len], &mut encoder.writer)?;
+            } else {
+                compress_block_stored_parts(&self.window[..left],
+                                            &input[..len - left],
+                                            &mut encoder.writer)?;
+            }
+            stats.stored_blocks += 1;
+            *input = &input[len - left..];
+            self.strstart = 0;
+        }
+
+        let start = self.strstart;
+        self.window[start..start + input.len()].copy_from_slice(input);
+        self.strstart += input.len();
+        *input = &[];
+
+        Ok(if last {
+            BlockState::FinishDone
+        } else if flush != Flush::None && self.strstart == 0 {
+            BlockState::BlockDone
+        } else {
+            BlockState::NeedMore
+        })
+    }
+
+    /// Levels `1` to `3`, which take the first match found and skip inserting long matches
+    /// into the hash table.
+    fn deflate_fast<W: Sink, P: PendingBuffer>(&mut self,
+                                                input: &mut &[u8],
+                                                flush: Flush,
+                                                encoder: &mut EncoderState<W, P>,
+                                                stats: &mut CompressionStats)
+                                                -> Result<BlockState, Error> {
+        loop {
+            if self.lookahead < MIN_LOOKAHEAD {
+                self.fill_window(input);
+                if self.lookahead < MIN_LOOKAHEAD && flush == Flush::None {
+                    return Ok(BlockState::NeedMore);
+                }
+                if self.lookahead == 0 {
+                    break;
+                }
+            }
+
+            let mut hash_head = NIL;
+            if self.lookahead >= MIN_MATCH {
+                hash_head = self.insert_string(self.strstart);
+            }
+            if hash_head != NIL && self.strstart - hash_head <= self.max_dist() {
+                self.match_length = self.longest_match(hash_head);
+            }
+
+            let bflush = if self.match_length >= MIN_MATCH {
+                let bflush = self.trees
+                    .tally_dist(self.strstart - self.match_start, self.match_length);
+                self.lookahead -= self.match_length;
+                if self.match_length <= self.config.max_lazy && self.lookahead >= MIN_MATCH {
+                    // Insert the strings in the match into the hash table.
+                    self.match_length -= 1;
+                    while self.match_length != 0 {
+                        self.strstart += 1;
+                        self.insert_string(self.strstart);
+                        self.match_length -= 1;
+                    }
+                    self.strstart += 1;
+                } else {
+                    self.strstart += self.match_length;
+                    self.match_length = 0;
+                    self.ins_h = usize::from(self.window[self.strstart]);
+                    self.update_hash(self.window[self.strstart + 1]);
+                }
+                bflush
+            } else {
+                let bflush = self.trees.tally_lit(self.window[self.strstart]);
+                self.lookahead -= 1;
+                self.strstart += 1;
+                bflush
+            };
+            if bflush {
+                self.flush_block(false, encoder, stats)?;
+            }
+        }
+        self.insert = cmp::min(self.strstart, MIN_MATCH - 1);
+        self.finish_blocks(flush, encoder, stats)
+    }
+
+    /// Levels `4` to `9`, which only use a match if there is no longer one at the next byte.
+    fn deflate_slow<W: Sink, P: PendingBuffer>(&mut self,
+                                                input: &mut &[u8],
+                                                flush: Flush,
+                                                encoder: &mut EncoderState<W, P>,
+                                                stats: &mut CompressionStats)
+                                                -> Result<BlockState, Error> {
+        loop {
+            if self.lookahead < MIN_LOOKAHEAD {
+                self.fill_window(input);
+                if self.lookahead < MIN_LOOKAHEAD && flush == Flush::None {
+                    return Ok(BlockState::NeedMore);
+                }
+                if self.lookahead == 0 {
+                    break;
+                }
+            }
+
+            let mut hash_head = NIL;
+            if self.lookahead >= MIN_MATCH {
+                hash_head = self.insert_string(self.strstart);
+            }
+
+            self.prev_length = self.match_length;
+            self.prev_match = self.match_start;
+            self.match_length = MIN_MATCH - 1;
+
+            if hash_head != NIL && self.prev_length < self.config.max_lazy &&
+               self.strstart - hash_head <= self.max_dist() {
+                self.match_length = self.longest_match(hash_head);
+                if self.match_length <= 5 &&
+                   (self.strategy == ZlibStrategy::Filtered ||
+                    (self.match_length == MIN_MATCH &&
+                     self.strstart - self.match_start > TOO_FAR)) {
+                    // Short matches far back are likely to cost more than the literals.
+                    self.match_length = MIN_MATCH - 1;
+                }
+            }
+
+            if self.prev_length >= MIN_MATCH && self.match_length <= self.prev_length {
+                // Use the match at the previous byte.
+                let max_insert = self.strstart + self.lookahead - MIN_MATCH;
+                let bflush = self.trees
+                    .tally_dist(self.strstart - 1 - self.prev_match, self.prev_length);
+                self.lookahead -= self.prev_length - 1;
+                self.prev_length -= 2;
+                while self.prev_length != 0 {
+                    self.strstart += 1;
+                    if self.strstart <= max_insert {
+                        self.insert_string(self.strstart);
+                    }
+                    self.prev_length -= 1;
+                }
+                self.match_available = false;
+                self.match_length = MIN_MATCH - 1;
+                self.strstart += 1;
+                if bflush {
+                    self.flush_block(false, encoder, stats)?;
+                }
+            } else if self.match_available {
+                // There was no match at the previous byte, or the one here is longer, so output
+                // the previous byte as a literal.
+                if self.trees.tally_lit(self.window[self.strstart - 1]) {
+                    self.flush_block(false, encoder, stats)?;
+                }
+                self.strstart += 1;
+                self.lookahead -= 1;
+            } else {
+                // Wait for the next byte to decide what to do.
+                self.match_available = true;
+                self.strstart += 1;
+                self.lookahead -= 1;
+            }
+        }
+        if self.match_available {
+            self.trees.tally_lit(self.window[self.strstart - 1]);
+            self.match_available = false;
+        }
+        self.insert = cmp::min(self.strstart, MIN_MATCH - 1);
+        self.finish_blocks(flush, encoder, stats)
+    }
+
+    /// The `Z_RLE` strategy, which only looks for runs of the previous byte.
+    fn deflate_rle<W: Sink, P: PendingBuffer>(&mut self,
+                                               input: &mut &[u8],
+                                               flush: Flush,
+                                               encoder: &mut EncoderState<W, P>,
+                                               stats: &mut CompressionStats)
+                                               -> Result<BlockState, Error> {
+        loop {
+            if self.lookahead <= MAX_MATCH {
+                self.fill_window(input);
+                if self.lookahead <= MAX_MATCH && flush == Flush::None {
+                    return Ok(BlockState::NeedMore);
+                }
+                if self.lookahead == 0 {
+                    break;
+                }
+            }
+
+            self.match_length = 0;
+            if self.lookahead >= MIN_MATCH && self.strstart > 0 {
+                let window = &self.window;
+                let prev = window[self.strstart - 1];
+                let run = window[self.strstart..self.strstart + MAX_MATCH]
+                    .iter()
+                    .take_while(|&&b| b == prev)
+                    .count();
+                if run >= MIN_MATCH {
+                    self.match_length = cmp::min(run, self.lookahead);
+                }
+            }
+
+            let bflush = if self.match_length >= MIN_MATCH {
+                let bflush = self.trees.tally_dist(1, self.match_length);
+                self.lookahead -= self.match_length;
+                self.strstart += self.match_length;
+                self.match_length = 0;
+                bflush
+            } else {
+                let bflush = self.trees.tally_lit(self.window[self.strstart]);
+                self.lookahead -= 1;
+                self.strstart += 1;
+                bflush
+            };
+            if bflush {
+                self.flush_block(false, encoder, stats)?;
+            }
+        }
+        self.insert = 0;
+        self.finish_blocks(flush, encoder, stats)
+    }
+
+    /// The `Z_HUFFMAN_ONLY` strategy, which only outputs literals.
+    fn deflate_huff<W: Sink, P: PendingBuffer>(&mut self,
+                                                input: &mut &[u8],
+                                                flush: Flush,
+                                                encoder: &mut EncoderState<W, P>,
+                                                stats: &mut CompressionStats)
+                                                -> Result<BlockState, Error> {
+        loop {
+            if self.lookahead == 0 {
+                self.fill_window(input);
+                if self.lookahead == 0 {
+                    if flush == Flush::None {
+                        return Ok(BlockState::NeedMore);
+                    }
+                    break;
+                }
+            }
+            self.match_length = 0;
+            let bflush = self.trees.tally_lit(self.window[self.strstart]);
+            self.lookahead -= 1;
+            self.strstart += 1;
+            if bflush {
+                self.flush_block(false, encoder, stats)?;
+            }
+        }
+        self.insert = 0;
+        self.finish_blocks(flush, encoder, stats)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use compression_options::CompressionOptions;
+    use deflate_state::DeflateState;
+    use test_utils::{get_test_data, decompress_to_end};
+
+    fn compress(chunks: &[(&[u8], Flush)], options: CompressionOptions) -> Vec<u8> {
+        let mut state = DeflateState::new(options, Vec::new());
+        for &(chunk, flush) in chunks {
+            let zlib_state = state.zlib_state.as_mut().unwrap();
+            zlib_state.deflate(chunk, flush, &mut state.encoder_state, &mut state.stats).unwrap();
+        }
+        state.encoder_state.writer.write_pending().unwrap();
+        state.encoder_state.writer.w
+    }
+
+    #[test]
+    fn full_flush() {
+        let input = get_test_data();
+        let (first, second) = input[..100000].split_at(60000);
+        for &level in &[0, 1, 6, 9] {
+            let options = CompressionOptions::zlib_compatible(level, ZlibStrategy::Default);
+            let compressed = compress(&[(first, Flush::Full), (second, Flush::Finish)], options);
+            assert!(decompress_to_end(&compressed) == input[..100000]);
+
+            // Nothing before a full flush is used after it, so the rest is compressed the same
+            // way as a separate stream would be.
+            let flushed = compress(&[(first, Flush::Full)], options);
+            let rest = compress(&[(second, Flush::Finish)], options);
+            assert!(compressed[flushed.len()..] == rest[..]);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! Compiles the C test program in `tests/capi` against the library built as a static library,
+//! and checks that it produces the same output as the library API.
+#![cfg(all(feature = "capi", unix))]
+
+extern crate deflate;
+
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use deflate::{deflate_bytes_conf, deflate_bytes_zlib_conf, deflate_bytes_gzip_conf,
+              CompressionOptions, SpecialOptions};
+use deflate::write::ZlibEncoder;
+
+const TEST_FILE: &str = "tests/pg11.txt";
+
+fn get_test_data() -> Vec<u8> {
+    let mut input = Vec::new();
+    File::open(TEST_FILE).unwrap().read_to_end(&mut input).unwrap();
+    input
+}
+
+fn run(command: &mut Command) {
+    let status = command.status().unwrap();
+    assert!(status.success(), "{:?} failed", command);
+}
+
+/// Build the library as a static library and link the C test program against it.
+fn build_test_program() -> PathBuf {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    // Use a separate target directory to avoid waiting for the lock held by the running cargo.
+    let target_dir = manifest_dir.join("target").join("capi-test");
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_owned());
+    run(Command::new(cargo)
+        .current_dir(manifest_dir)
+        .args(["rustc", "--lib", "--features", "capi", "--crate-type", "staticlib"])
+        .arg("--target-dir")
+        .arg(&target_dir));
+
+    let program = target_dir.join("capi-test");
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_owned());
+    run(Command::new(cc)
+        .current_dir(manifest_dir)
+        .args(["-Wall", "-Werror", "-Iinclude", "tests/capi/test.c"])
+        .arg(target_dir.join("debug").join("libdeflate.a"))
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&program));
+    program
+}
+
+fn run_program(program: &Path, args: &[&str]) -> Vec<u8> {
+    let output = Command::new(program).args(args).output().unwrap();
+    assert!(output.status.success(),
+            "capi-test {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr));
+    output.stdout
+}
+
+#[test]
+fn c_program() {
+    let program = build_test_program();
+    let data = get_test_data();
+
+    // (window bits, level, strategy, expected output)
+    let options = |level| CompressionOptions::from_level(level);
+    let cases = [("15", "6", "0", deflate_bytes_zlib_conf(&data, options(6)).unwrap()),
+                 ("-15", "1", "0", deflate_bytes_conf(&data, options(1)).unwrap()),
+                 ("31", "9", "0", deflate_bytes_gzip_conf(&data, options(9)).unwrap()),
+                 ("15", "0", "0", deflate_bytes_zlib_conf(&data, options(0)).unwrap()),
+                 ("-15",
+                  "2",
+                  "2",
+                  deflate_bytes_conf(&data, CompressionOptions::huffman_only()).unwrap()),
+                 ("-15",
+                  "6",
+                  "4",
+                  deflate_bytes_conf(&data,
+                                     CompressionOptions {
+                                         special: SpecialOptions::ForceFixed,
+                                         ..options(6)
+                                     }).unwrap()),
+                 ("15",
+                  "6",
+                  "1",
+                  deflate_bytes_zlib_conf(&data,
+                                          CompressionOptions {
+                                              special: SpecialOptions::Filtered,
+                                              ..options(6)
+                                          }).unwrap()),
+                 ("10",
+                  "-1",
+                  "0",
+                  deflate_bytes_zlib_conf(&data,
+                                          CompressionOptions {
+                                              window_bits: 10,
+                                              ..options(6)
+                                          }).unwrap())];
+    for &(window_bits, level, strategy, ref expected) in cases.iter() {
+        let output = run_program(&program, &[TEST_FILE, window_bits, level, strategy]);
+        assert!(&output == expected,
+                "Output differs for window bits {}, level {}, strategy {}",
+                window_bits,
+                level,
+                strategy);
+    }
+}
+
+#[test]
+fn c_program_dictionary() {
+    let program = build_test_program();
+    let data = get_test_data();
+    let (dictionary, data) = data.split_at(10000);
+    let dir = env::temp_dir();
+    let dictionary_path = dir.join(format!("deflate-capi-dictionary-{}", std::process::id()));
+    let input_path = dir.join(format!("deflate-capi-input-{}", std::process::id()));
+    File::create(&dictionary_path).unwrap().write_all(dictionary).unwrap();
+    File::create(&input_path).unwrap().write_all(data).unwrap();
+
+    let output = run_program(&program,
+                             &[input_path.to_str().unwrap(),
+                               "15",
+                               "6",
+                               "0",
+                               dictionary_path.to_str().unwrap()]);
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+    encoder.set_dictionary(dictionary).unwrap();
+    encoder.write_all(data).unwrap();
+    assert!(output == encoder.finish().unwrap());
+
+    let _ = std::fs::remove_file(dictionary_path);
+    let _ = std::fs::remove_file(input_path);
+}
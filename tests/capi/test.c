@@ -0,0 +1,168 @@
+/*
+ * Test program for the C API, compiled and run by `tests/capi.rs`.
+ *
+ * Usage: test INPUT WINDOW_BITS LEVEL STRATEGY [DICTIONARY]
+ *
+ * Compresses INPUT using the given parameters and writes the result to standard output. The
+ * input is compressed twice, using `drs_deflateReset` in between, and the program fails if
+ * the two outputs differ. A small output buffer is used to check that output that doesn't fit
+ * is kept for later calls. Finally, the input is compressed again in a single call with an
+ * output buffer of the size given by `drs_deflateBound`, which has to complete the stream.
+ */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "deflate.h"
+
+#define OUT_CHUNK 1000
+
+static unsigned char *read_file(const char *path, size_t *length) {
+    FILE *file = fopen(path, "rb");
+    unsigned char *data;
+    long size;
+    if (file == NULL) {
+        return NULL;
+    }
+    fseek(file, 0, SEEK_END);
+    size = ftell(file);
+    fseek(file, 0, SEEK_SET);
+    data = malloc(size > 0 ? size : 1);
+    if (data == NULL || fread(data, 1, size, file) != (size_t)size) {
+        fclose(file);
+        free(data);
+        return NULL;
+    }
+    fclose(file);
+    *length = size;
+    return data;
+}
+
+/* Compress `input`, returning the size of the output written to `output`, or -1 on error. */
+static long compress(drs_stream *strm, const unsigned char *input, size_t input_length,
+                     const unsigned char *dictionary, size_t dictionary_length,
+                     unsigned char *output, size_t output_capacity) {
+    size_t written = 0;
+    int ret;
+
+    if (dictionary != NULL &&
+        drs_deflateSetDictionary(strm, dictionary, dictionary_length) != DRS_OK) {
+        fprintf(stderr, "drs_deflateSetDictionary failed\n");
+        return -1;
+    }
+
+    strm->next_in = input;
+    strm->avail_in = input_length;
+    do {
+        if (output_capacity - written < OUT_CHUNK) {
+            fprintf(stderr, "output is larger than drs_deflateBound\n");
+            return -1;
+        }
+        strm->next_out = output + written;
+        strm->avail_out = OUT_CHUNK;
+        ret = drs_deflate(strm, DRS_FINISH);
+        if (ret != DRS_OK && ret != DRS_STREAM_END) {
+            fprintf(stderr, "drs_deflate failed: %d (%s)\n", ret, strm->msg ? strm->msg : "");
+            return -1;
+        }
+        written += OUT_CHUNK - strm->avail_out;
+    } while (ret != DRS_STREAM_END);
+
+    if (strm->avail_in != 0 || strm->total_in != input_length || strm->total_out != written) {
+        fprintf(stderr, "inconsistent stream totals\n");
+        return -1;
+    }
+    return written;
+}
+
+/* Compress `input` in a single call with an output buffer of the size given by
+   `drs_deflateBound`, returning the size of the output, or -1 on error. */
+static long compress_single_call(drs_stream *strm, const unsigned char *input,
+                                 size_t input_length, const unsigned char *dictionary,
+                                 size_t dictionary_length, unsigned char *output) {
+    int ret;
+
+    if (dictionary != NULL &&
+        drs_deflateSetDictionary(strm, dictionary, dictionary_length) != DRS_OK) {
+        fprintf(stderr, "drs_deflateSetDictionary failed\n");
+        return -1;
+    }
+
+    strm->next_in = input;
+    strm->avail_in = input_length;
+    strm->next_out = output;
+    strm->avail_out = drs_deflateBound(strm, input_length);
+    ret = drs_deflate(strm, DRS_FINISH);
+    if (ret != DRS_STREAM_END) {
+        fprintf(stderr, "single drs_deflate call did not finish: %d\n", ret);
+        return -1;
+    }
+    return strm->total_out;
+}
+
+int main(int argc, char **argv) {
+    drs_stream strm;
+    unsigned char *input, *dictionary = NULL, *output, *second_output;
+    size_t input_length, dictionary_length = 0, bound;
+    long written, second_written;
+
+    if (argc < 5 || argc > 6) {
+        fprintf(stderr, "usage: %s INPUT WINDOW_BITS LEVEL STRATEGY [DICTIONARY]\n", argv[0]);
+        return 2;
+    }
+    input = read_file(argv[1], &input_length);
+    if (input == NULL || (argc == 6 && !(dictionary = read_file(argv[5], &dictionary_length)))) {
+        fprintf(stderr, "failed to read input\n");
+        return 1;
+    }
+
+    memset(&strm, 0, sizeof(strm));
+    if (drs_deflateInit2(&strm, atoi(argv[3]), DRS_DEFLATED, atoi(argv[2]), 8,
+                         atoi(argv[4])) != DRS_OK) {
+        fprintf(stderr, "drs_deflateInit2 failed\n");
+        return 1;
+    }
+
+    /* Leave room for a full output chunk past the bound. */
+    bound = drs_deflateBound(&strm, input_length) + OUT_CHUNK;
+    output = malloc(bound);
+    second_output = malloc(bound);
+    written = compress(&strm, input, input_length, dictionary, dictionary_length, output, bound);
+    if (written < 0) {
+        return 1;
+    }
+
+    if (drs_deflateReset(&strm) != DRS_OK) {
+        fprintf(stderr, "drs_deflateReset failed\n");
+        return 1;
+    }
+    second_written = compress(&strm, input, input_length, dictionary, dictionary_length,
+                              second_output, bound);
+    if (second_written != written || memcmp(output, second_output, written) != 0) {
+        fprintf(stderr, "output differs after drs_deflateReset\n");
+        return 1;
+    }
+
+    if (drs_deflateReset(&strm) != DRS_OK) {
+        fprintf(stderr, "drs_deflateReset failed\n");
+        return 1;
+    }
+    second_written = compress_single_call(&strm, input, input_length, dictionary,
+                                          dictionary_length, second_output);
+    if (second_written != written || memcmp(output, second_output, written) != 0) {
+        fprintf(stderr, "output differs when compressing in a single call\n");
+        return 1;
+    }
+
+    if (drs_deflateEnd(&strm) != DRS_OK) {
+        fprintf(stderr, "drs_deflateEnd failed\n");
+        return 1;
+    }
+
+    fwrite(output, 1, written, stdout);
+    free(input);
+    free(dictionary);
+    free(output);
+    free(second_output);
+    return 0;
+}
@@ -0,0 +1,180 @@
+//! Tests checking that the command line tool produces the same output as the library.
+#![cfg(feature = "cli")]
+
+extern crate deflate;
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::process::Command;
+
+use deflate::{deflate_bytes_conf, deflate_bytes_zlib_conf, deflate_bytes_gzip_conf,
+              CompressionOptions, MatchingType, SpecialOptions};
+use deflate::write::ZlibEncoder;
+
+const TEST_FILE: &str = "tests/pg11.txt";
+
+fn get_test_data() -> Vec<u8> {
+    let mut input = Vec::new();
+    File::open(TEST_FILE).unwrap().read_to_end(&mut input).unwrap();
+    input
+}
+
+/// Run the command line tool with the given arguments and return the output.
+fn run_cli(args: &[&str]) -> Vec<u8> {
+    let output = Command::new(env!("CARGO_BIN_EXE_deflate")).args(args).output().unwrap();
+    assert!(output.status.success(),
+            "deflate {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr));
+    output.stdout
+}
+
+#[test]
+fn cli_formats_and_levels() {
+    let data = get_test_data();
+    for level in 0..10u8 {
+        let options = CompressionOptions::from_level(level);
+        let level = level.to_string();
+        assert!(run_cli(&["-f", "raw", "-l", &level, TEST_FILE]) ==
+                deflate_bytes_conf(&data, options).unwrap());
+        assert!(run_cli(&["-f", "zlib", "-l", &level, TEST_FILE]) ==
+                deflate_bytes_zlib_conf(&data, options).unwrap());
+        assert!(run_cli(&["--level", &level, TEST_FILE]) ==
+                deflate_bytes_gzip_conf(&data, options).unwrap());
+    }
+}
+
+#[test]
+fn cli_input_larger_than_chunk() {
+    // The tool reads and compresses the input in chunks of 1 MiB, so use an input spanning
+    // several chunks.
+    let data: Vec<u8> = get_test_data().iter().cycle().take(5 << 19).cloned().collect();
+    let input_path = std::env::temp_dir()
+        .join(format!("deflate-cli-large-input-{}", std::process::id()));
+    File::create(&input_path).unwrap().write_all(&data).unwrap();
+    let input = input_path.to_str().unwrap();
+
+    for &level in &[0u8, 1, 6] {
+        let options = CompressionOptions::from_level(level);
+        let level = level.to_string();
+        assert!(run_cli(&["-f", "raw", "-l", &level, input]) ==
+                deflate_bytes_conf(&data, options).unwrap());
+    }
+    assert!(run_cli(&["-f", "zlib", input]) ==
+            deflate_bytes_zlib_conf(&data, CompressionOptions::default()).unwrap());
+    assert!(run_cli(&["-s", "fixed", input]) ==
+            deflate_bytes_gzip_conf(&data,
+                                    CompressionOptions {
+                                        special: SpecialOptions::ForceFixed,
+                                        ..Default::default()
+                                    })
+                .unwrap());
+
+    let _ = std::fs::remove_file(input_path);
+}
+
+#[test]
+fn cli_strategy_and_window_bits() {
+    let data = get_test_data();
+    let options = CompressionOptions {
+        matching_type: MatchingType::Greedy,
+        window_bits: 10,
+        ..CompressionOptions::from_level(9)
+    };
+    assert!(run_cli(&["-f", "zlib", "-l", "9", "-s", "greedy", "-w", "10", TEST_FILE]) ==
+            deflate_bytes_zlib_conf(&data, options).unwrap());
+
+    let options = CompressionOptions {
+        matching_type: MatchingType::Lazy2,
+        ..Default::default()
+    };
+    assert!(run_cli(&["-f", "raw", "-s", "lazy2", TEST_FILE]) ==
+            deflate_bytes_conf(&data, options).unwrap());
+
+    let options = CompressionOptions {
+        special: SpecialOptions::ForceFixed,
+        ..Default::default()
+    };
+    assert!(run_cli(&["-f", "raw", "--strategy", "fixed", TEST_FILE]) ==
+            deflate_bytes_conf(&data, options).unwrap());
+
+    let options = CompressionOptions {
+        special: SpecialOptions::Filtered,
+        ..Default::default()
+    };
+    assert!(run_cli(&["-f", "raw", "-s", "filtered", TEST_FILE]) ==
+            deflate_bytes_conf(&data, options).unwrap());
+
+    assert!(run_cli(&["-f", "raw", "-s", "huffman-only", TEST_FILE]) ==
+            deflate_bytes_conf(&data, CompressionOptions::huffman_only()).unwrap());
+
+    let options = CompressionOptions {
+        rsyncable: true,
+        ..Default::default()
+    };
+    assert!(run_cli(&["-f", "raw", "--rsyncable", TEST_FILE]) ==
+            deflate_bytes_conf(&data, options).unwrap());
+}
+
+#[test]
+fn cli_dictionary() {
+    let data = get_test_data();
+    let (dictionary, data) = data.split_at(10000);
+    let dir = std::env::temp_dir();
+    let dictionary_path = dir.join(format!("deflate-cli-dictionary-{}", std::process::id()));
+    let input_path = dir.join(format!("deflate-cli-input-{}", std::process::id()));
+    File::create(&dictionary_path).unwrap().write_all(dictionary).unwrap();
+    File::create(&input_path).unwrap().write_all(data).unwrap();
+
+    let output = run_cli(&["-f",
+                           "zlib",
+                           "-d",
+                           dictionary_path.to_str().unwrap(),
+                           input_path.to_str().unwrap()]);
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+    encoder.set_dictionary(dictionary).unwrap();
+    encoder.write_all(data).unwrap();
+    assert!(output == encoder.finish().unwrap());
+
+    let _ = std::fs::remove_file(dictionary_path);
+    let _ = std::fs::remove_file(input_path);
+}
+
+#[test]
+fn cli_stdin_and_stats() {
+    use std::process::Stdio;
+
+    let data = get_test_data();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_deflate"))
+        .args(["-f", "raw", "--stats"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    {
+        let mut stdin = child.stdin.take().unwrap();
+        // Write in small pieces to check that the output doesn't depend on how the input
+        // arrives.
+        for chunk in data.chunks(1000) {
+            stdin.write_all(chunk).unwrap();
+        }
+    }
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert!(output.stdout == deflate_bytes_conf(&data, CompressionOptions::default()).unwrap());
+    let stats = String::from_utf8(output.stderr).unwrap();
+    assert!(stats.contains(&format!("bytes in:         {}", data.len())));
+}
+
+#[test]
+fn cli_invalid_arguments() {
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_deflate")).args(args).output().unwrap().status.code()
+    };
+    assert_eq!(run(&["-l", "10", TEST_FILE]), Some(2));
+    assert_eq!(run(&["-w", "16", TEST_FILE]), Some(2));
+    assert_eq!(run(&["-f", "gzip", "-d", TEST_FILE, TEST_FILE]), Some(2));
+    assert_eq!(run(&["--bogus"]), Some(2));
+}
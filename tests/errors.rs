@@ -0,0 +1,429 @@
+//! Tests checking that errors from the underlying writer are returned rather than causing a
+//! panic, wherever in the output they happen, and that the encoders can resume after transient
+//! errors.
+
+extern crate deflate;
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+use deflate::{CompressionOptions, Error, Format, SpecialOptions, ZlibStrategy};
+use deflate::write::{DeflateEncoder, ZlibEncoder, GzipEncoder, Encoder};
+use deflate::zip::{ZipWriter, FileOptions};
+use deflate::png::IdatWriter;
+
+fn get_test_data() -> Vec<u8> {
+    let mut input = Vec::new();
+    File::open("tests/pg11.txt").unwrap().read_to_end(&mut input).unwrap();
+    input.truncate(50000);
+    input
+}
+
+/// A writer that fails once more than `limit` bytes have been written to it.
+#[derive(Debug)]
+struct FailingWriter {
+    written: usize,
+    limit: usize,
+}
+
+impl FailingWriter {
+    fn new(limit: usize) -> FailingWriter {
+        FailingWriter {
+            written: 0,
+            limit,
+        }
+    }
+}
+
+impl Write for FailingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written + buf.len() > self.limit {
+            return Err(io::Error::new(io::ErrorKind::Other, "Out of space"));
+        }
+        self.written += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn test_options() -> Vec<CompressionOptions> {
+    vec![CompressionOptions::default(),
+         CompressionOptions::fast(),
+         CompressionOptions::from_level(0),
+         CompressionOptions {
+             special: SpecialOptions::ForceFixed,
+             ..Default::default()
+         },
+         CompressionOptions {
+             rsyncable: true,
+             ..Default::default()
+         },
+         CompressionOptions::zlib_compatible(6, ZlibStrategy::Default)]
+}
+
+/// The limits to test, from failing on the first bytes to failing at the last one.
+fn limits(output_length: usize) -> Vec<usize> {
+    (0..10).chain((1..20).map(|n| n * output_length / 20)).chain(Some(output_length - 1)).collect()
+}
+
+/// Check that writing `data` with the encoder returned by `encode` fails with the error from
+/// the writer for every limit below the output length.
+fn check_failures<F>(data: &[u8], encode: F)
+    where F: Fn(&[u8], FailingWriter) -> io::Result<FailingWriter>
+{
+    let output_length = encode(data, FailingWriter::new(usize::max_value())).unwrap().written;
+    for limit in limits(output_length) {
+        let err = match encode(data, FailingWriter::new(limit)) {
+            Ok(_) => panic!("Writing succeeded with a limit of {} bytes", limit),
+            Err(err) => err,
+        };
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        match Error::from(err) {
+            Error::Io(_) => (),
+            other => panic!("Unexpected error {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn encoders() {
+    let data = get_test_data();
+    for options in test_options() {
+        check_failures(&data, |data, writer| {
+            let mut encoder = DeflateEncoder::new(writer, options);
+            encoder.write_all(data)?;
+            encoder.finish()
+        });
+        check_failures(&data, |data, writer| {
+            let mut encoder = ZlibEncoder::new(writer, options);
+            // Flushing in the middle of the data writes output before finishing.
+            encoder.write_all(&data[..data.len() / 2])?;
+            encoder.flush()?;
+            encoder.write_all(&data[data.len() / 2..])?;
+            encoder.finish()
+        });
+        check_failures(&data, |data, writer| {
+            let mut encoder = GzipEncoder::new(writer, options);
+            encoder.write_all(data)?;
+            encoder.finish()
+        });
+    }
+}
+
+#[test]
+fn zip() {
+    let data = get_test_data();
+    check_failures(&data, |data, writer| {
+        let mut zip = ZipWriter::new(writer);
+        zip.start_file("first.txt", FileOptions::default())?;
+        zip.write_all(data)?;
+        zip.start_file("second.txt", FileOptions::default())?;
+        zip.write_all(&data[..1000])?;
+        zip.finish()
+    });
+}
+
+/// A writer that fails once when more than `limit` bytes would have been written to it, and
+/// accepts anything after that.
+#[derive(Debug)]
+struct FailOnceWriter {
+    written: usize,
+    limit: Option<usize>,
+}
+
+impl Write for FailOnceWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(limit) = self.limit {
+            if self.written + buf.len() > limit {
+                self.limit = None;
+                return Err(io::Error::new(io::ErrorKind::Other, "Out of space"));
+            }
+        }
+        self.written += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn zip_use_after_failure() {
+    let data = get_test_data();
+    let archive = |zip: &mut ZipWriter<&mut FailOnceWriter>| -> io::Result<()> {
+        zip.start_file("first.txt", FileOptions::default())?;
+        zip.write_all(&data)?;
+        zip.add_directory("dir", FileOptions::default())?;
+        zip.start_file("second.txt", FileOptions::default())?;
+        zip.write_all(&data[..1000])
+    };
+    let mut writer = FailOnceWriter {
+        written: 0,
+        limit: None,
+    };
+    {
+        let mut zip = ZipWriter::new(&mut writer);
+        archive(&mut zip).unwrap();
+        zip.finish().unwrap();
+    }
+    let output_length = writer.written;
+
+    for limit in limits(output_length) {
+        let mut writer = FailOnceWriter {
+            written: 0,
+            limit: Some(limit),
+        };
+        {
+            let mut zip = ZipWriter::new(&mut writer);
+            match archive(&mut zip) {
+                Ok(()) => {
+                    // Neither the failed `finish` nor dropping the writer afterwards should
+                    // write a second central directory.
+                    assert_eq!(zip.finish().unwrap_err().kind(), io::ErrorKind::Other);
+                }
+                Err(err) => {
+                    assert_eq!(err.kind(), io::ErrorKind::Other);
+                    // The writer refuses to continue, and dropping it doesn't finish the
+                    // archive.
+                    let err = zip.write(b"More data").unwrap_err();
+                    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+                    let err = zip.start_file("third.txt", FileOptions::default()).unwrap_err();
+                    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+                }
+            }
+        }
+        assert!(writer.written <= limit,
+                "{} bytes were written with a limit of {}",
+                writer.written,
+                limit);
+    }
+}
+
+/// A writer whose `flush` is interrupted every other time it is called.
+struct InterruptedFlushWriter {
+    output: Vec<u8>,
+    interrupt: bool,
+}
+
+impl Write for InterruptedFlushWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.interrupt = !self.interrupt;
+        if self.interrupt {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "Interrupted"));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn zip_interrupted_flush() {
+    let writer = InterruptedFlushWriter {
+        output: Vec::new(),
+        interrupt: false,
+    };
+    let mut zip = ZipWriter::new(writer);
+    zip.start_file("file.txt", FileOptions::default()).unwrap();
+    zip.write_all(b"Some data").unwrap();
+    zip.flush().unwrap();
+    zip.write_all(b"More data").unwrap();
+    assert!(!zip.finish().unwrap().output.is_empty());
+}
+
+#[test]
+fn png() {
+    let data = get_test_data();
+    check_failures(&data, |data, writer| {
+        let mut idat = IdatWriter::with_chunk_size(writer, 1, 5000, CompressionOptions::default());
+        for row in data.chunks(500) {
+            idat.write_row(deflate::png::FilterType::Paeth, row)?;
+        }
+        idat.finish()
+    });
+}
+
+#[test]
+fn use_after_failure() {
+    let data = get_test_data();
+    let mut encoder = ZlibEncoder::new(FailingWriter::new(0), CompressionOptions::default());
+    assert_eq!(encoder.write_all(&data).unwrap_err().kind(), io::ErrorKind::Other);
+    // The encoder refuses to continue, and dropping it doesn't write anything more.
+    assert_eq!(encoder.write(b"More data").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(encoder.flush().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(encoder.finish().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+    let mut writer = FailingWriter::new(5000);
+    {
+        let mut encoder = DeflateEncoder::new(&mut writer, CompressionOptions::default());
+        let err = encoder.write_all(&data).and_then(|_| encoder.try_finish()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(encoder.try_finish().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        // Nothing is written when dropping the encoder, even if the writer would accept it.
+        encoder.get_mut().limit = usize::max_value();
+    }
+    assert_eq!(writer.written, 0);
+
+    // The writer can be taken back after a failure, and nothing is written by doing so either.
+    let mut encoder = ZlibEncoder::new(FailingWriter::new(5000), CompressionOptions::default());
+    let err = encoder.write_all(&data).and_then(|_| encoder.try_finish()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Other);
+    let written = encoder.get_ref().written;
+    encoder.get_mut().limit = usize::max_value();
+    assert_eq!(encoder.into_inner().written, written);
+
+    // Without a failure, taking back the writer doesn't finish the stream.
+    let mut encoder = GzipEncoder::new(FailingWriter::new(usize::max_value()),
+                                       CompressionOptions::default());
+    encoder.write_all(&data[..1000]).unwrap();
+    let written = encoder.get_ref().written;
+    assert_eq!(encoder.into_inner().written, written);
+}
+
+#[test]
+fn use_after_finish() {
+    let mut encoder = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+    encoder.write_all(b"Some data").unwrap();
+    encoder.try_finish().unwrap();
+    let finished = encoder.get_ref().clone();
+    assert_eq!(encoder.write(b"More data").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    // Flushing or finishing again doesn't write anything more.
+    encoder.flush().unwrap();
+    encoder.try_finish().unwrap();
+    assert_eq!(encoder.finish().unwrap(), finished);
+}
+
+#[test]
+fn invalid_input() {
+    let mut encoder = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+    encoder.write_all(b"Some data").unwrap();
+    let err = encoder.set_dictionary(b"Too late").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    match Error::from(err) {
+        Error::InvalidInput(_) => (),
+        other => panic!("Unexpected error {:?}", other),
+    }
+
+    let mut zip = ZipWriter::new(Vec::new());
+    let err = zip.write_all(b"Some data").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+/// A writer that randomly returns `WouldBlock` or `Interrupted`, or only accepts part of the
+/// data.
+struct UnreliableWriter {
+    output: Vec<u8>,
+    state: u32,
+}
+
+impl UnreliableWriter {
+    fn new(seed: u32) -> UnreliableWriter {
+        UnreliableWriter {
+            output: Vec::new(),
+            state: seed,
+        }
+    }
+
+    fn next_random(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state
+    }
+}
+
+impl Write for UnreliableWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let random = self.next_random();
+        match random & 7 {
+            0 | 1 => Err(io::Error::new(io::ErrorKind::WouldBlock, "Not ready")),
+            2 => Err(io::Error::new(io::ErrorKind::Interrupted, "Interrupted")),
+            _ => {
+                let n = (random >> 8) as usize % buf.len().max(1) + 1;
+                let n = n.min(buf.len());
+                self.output.extend_from_slice(&buf[..n]);
+                Ok(n)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.next_random() & 7 {
+            0 | 1 => Err(io::Error::new(io::ErrorKind::WouldBlock, "Not ready")),
+            2 => Err(io::Error::new(io::ErrorKind::Interrupted, "Interrupted")),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Call `f` until it doesn't return `WouldBlock`.
+fn retry<T, F>(mut f: F) -> T
+    where F: FnMut() -> io::Result<T>
+{
+    loop {
+        match f() {
+            Ok(value) => return value,
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => (),
+            Err(err) => panic!("Unexpected error {:?}", err),
+        }
+    }
+}
+
+/// Compress `data` in chunks of varying size, flushing after some of them.
+fn write_chunks<W: Write>(encoder: &mut Encoder<W>, data: &[u8], seed: u32) {
+    let mut state = seed;
+    let mut remaining = data;
+    while !remaining.is_empty() {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let length = (state as usize % 5000 + 1).min(remaining.len());
+        let (chunk, rest) = remaining.split_at(length);
+        let mut chunk = chunk;
+        while !chunk.is_empty() {
+            let n = retry(|| encoder.write(chunk));
+            chunk = &chunk[n..];
+        }
+        remaining = rest;
+        if state >> 28 == 0 {
+            retry(|| encoder.flush());
+        }
+    }
+}
+
+#[test]
+fn transient_errors() {
+    let data = get_test_data();
+    for (n, options) in test_options().into_iter().enumerate() {
+        for &format in &[Format::Raw, Format::Zlib, Format::Gzip] {
+            let seed = 0x1234_5679 + n as u32;
+            let mut expected = Encoder::new(Vec::new(), format, options);
+            write_chunks(&mut expected, &data, seed);
+            let expected = expected.finish().unwrap();
+
+            let mut encoder = Encoder::new(UnreliableWriter::new(seed), format, options);
+            write_chunks(&mut encoder, &data, seed);
+            while !encoder.poll_pending().unwrap() {}
+            assert_eq!(encoder.pending_len(), 0);
+            retry(|| encoder.try_finish());
+            assert_eq!(encoder.pending_len(), 0);
+            assert_eq!(encoder.write(b"More data").unwrap_err().kind(),
+                       io::ErrorKind::InvalidInput);
+            // The output is the same as when writing never fails.
+            assert!(encoder.get_ref().output == expected);
+
+            // The encoder can be reused after being reset.
+            let writer = encoder.reset(UnreliableWriter::new(seed)).unwrap();
+            assert!(writer.output == expected);
+            write_chunks(&mut encoder, &data, seed);
+            retry(|| encoder.try_finish());
+            assert!(encoder.get_ref().output == expected);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Tests decoding images written using `IdatWriter` with a separate PNG decoder.
+
+extern crate deflate;
+extern crate png;
+
+use std::io::Write;
+
+use deflate::{CompressionOptions, SpecialOptions};
+use deflate::png::{IdatWriter, FilterType, SIGNATURE, write_chunk};
+
+const WIDTH: u32 = 200;
+const HEIGHT: u32 = 150;
+
+/// An 8-bit RGB test image with gradients and some noise.
+fn test_image() -> Vec<Vec<u8>> {
+    let mut state = 0x1234_5678u32;
+    (0..HEIGHT)
+        .map(|y| {
+            (0..WIDTH)
+                .flat_map(|x| {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    let noise = (state >> 29) as u8;
+                    vec![x as u8, y as u8 ^ noise, (x + y) as u8 / 2]
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Write a PNG file with the given rows, using `filter` to pick the filter of each row.
+fn write_png<F: Fn(usize) -> FilterType>(rows: &[Vec<u8>],
+                                         options: CompressionOptions,
+                                         chunk_size: usize,
+                                         filter: F)
+                                         -> Vec<u8> {
+    let mut output = SIGNATURE.to_vec();
+    let mut header = Vec::new();
+    header.extend_from_slice(&WIDTH.to_be_bytes());
+    header.extend_from_slice(&HEIGHT.to_be_bytes());
+    // 8-bit RGB, deflate compression, adaptive filtering, no interlacing.
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(&mut output, b"IHDR", &header).unwrap();
+
+    let mut writer = IdatWriter::with_chunk_size(output, 3, chunk_size, options);
+    for (y, row) in rows.iter().enumerate() {
+        writer.write_row(filter(y), row).unwrap();
+    }
+    let mut output = writer.finish().unwrap();
+    write_chunk(&mut output, b"IEND", &[]).unwrap();
+    output
+}
+
+fn decode_png(data: &[u8]) -> Vec<u8> {
+    let decoder = png::Decoder::new(data);
+    let mut reader = decoder.read_info().unwrap();
+    let mut image = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut image).unwrap();
+    assert_eq!((info.width, info.height), (WIDTH, HEIGHT));
+    assert_eq!(info.color_type, png::ColorType::Rgb);
+    image.truncate(info.buffer_size());
+    image
+}
+
+#[test]
+fn decode_filtered_rows() {
+    let rows = test_image();
+    let filters = [FilterType::None,
+                   FilterType::Sub,
+                   FilterType::Up,
+                   FilterType::Average,
+                   FilterType::Paeth];
+    let filtered = CompressionOptions {
+        special: SpecialOptions::Filtered,
+        ..Default::default()
+    };
+
+    for &options in &[CompressionOptions::default(), filtered, CompressionOptions::from_level(0)] {
+        for &chunk_size in &[1, 1000, 1 << 20] {
+            let png = write_png(&rows, options, chunk_size, |y| filters[y % filters.len()]);
+            assert!(decode_png(&png) == rows.concat());
+        }
+    }
+}
+
+#[test]
+fn decode_prefiltered_data() {
+    // Write the rows through the `Write` interface, with the filter type byte added by hand.
+    let rows = test_image();
+    let mut output = SIGNATURE.to_vec();
+    let mut header = Vec::new();
+    header.extend_from_slice(&WIDTH.to_be_bytes());
+    header.extend_from_slice(&HEIGHT.to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(&mut output, b"IHDR", &header).unwrap();
+
+    let mut writer = IdatWriter::new(output, 3, CompressionOptions::default());
+    for row in &rows {
+        writer.write_all(&[0]).unwrap();
+        writer.write_all(row).unwrap();
+        // Flushing ends the current chunk early, which the decoder has to handle.
+        writer.flush().unwrap();
+    }
+    let mut output = writer.finish().unwrap();
+    write_chunk(&mut output, b"IEND", &[]).unwrap();
+    assert!(decode_png(&output) == rows.concat());
+}
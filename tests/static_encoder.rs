@@ -0,0 +1,69 @@
+//! Test checking that `StaticEncoder` never allocates, using an allocator that counts the
+//! allocations made by the test thread.
+
+extern crate deflate;
+extern crate flate2;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::fs::File;
+use std::io::Read;
+
+use deflate::{StaticBuffers, StaticEncoder, ZlibCompat, ZlibStrategy};
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|a| a.set(a.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(|a| a.get())
+}
+
+#[test]
+fn static_encoder_does_not_allocate() {
+    let mut input = Vec::new();
+    File::open("tests/pg11.txt").unwrap().read_to_end(&mut input).unwrap();
+    let mut output = vec![0; input.len() * 2];
+    let mut buffers = Box::new(StaticBuffers::new());
+
+    for &(level, strategy) in &[(0, ZlibStrategy::Default),
+                                (1, ZlibStrategy::Default),
+                                (9, ZlibStrategy::Default),
+                                (6, ZlibStrategy::Rle),
+                                (6, ZlibStrategy::HuffmanOnly)] {
+        let before = allocations();
+        let written = {
+            let compat = ZlibCompat { level, strategy };
+            let mut encoder = StaticEncoder::new(&mut buffers, &mut output[..], compat);
+            for chunk in input.chunks(5000) {
+                encoder.write(chunk).unwrap();
+            }
+            encoder.flush().unwrap();
+            let remaining = encoder.finish().unwrap().len();
+            output.len() - remaining
+        };
+        assert_eq!(allocations(), before);
+
+        let mut decompressed = Vec::new();
+        flate2::read::DeflateDecoder::new(&output[..written])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert!(decompressed == input);
+    }
+}
@@ -30,7 +30,8 @@ fn test_file_zlib_compare_output() {
         e.finish().unwrap()
     };
 
-    let deflate_compressed = deflate_bytes_zlib_conf(&test_data, CompressionOptions::high());
+    let deflate_compressed =
+        deflate_bytes_zlib_conf(&test_data, CompressionOptions::high()).unwrap();
 
     // {
     //     use std::fs::File;
@@ -58,3 +59,67 @@ fn test_file_zlib_compare_output() {
 
     assert!(decompressed == test_data);
 }
+
+// Checking that the two-step lazy matching never does worse than normal lazy matching
+// searching as deep.
+#[test]
+fn lazy2_not_worse_than_lazy() {
+    use std::io::Read;
+    use deflate::{CompressionOptions, MatchFinder, MatchingType, deflate_bytes_conf};
+    let test_data = get_test_file_data("tests/pg11.txt");
+
+    for &match_finder in &[MatchFinder::HashChains, MatchFinder::BinaryTree] {
+        for level in 4..10 {
+            let compress = |matching_type| {
+                let options = CompressionOptions {
+                    matching_type,
+                    match_finder,
+                    ..CompressionOptions::from_level(level)
+                };
+                deflate_bytes_conf(&test_data, options).unwrap()
+            };
+            let lazy = compress(MatchingType::Lazy);
+            let lazy2 = compress(MatchingType::Lazy2);
+            assert!(lazy2.len() <= lazy.len());
+
+            let mut decompressed = Vec::new();
+            flate2::read::DeflateDecoder::new(lazy2.as_slice())
+                .read_to_end(&mut decompressed)
+                .unwrap();
+            assert!(decompressed == test_data);
+        }
+    }
+}
+
+// Checking that data with skewed byte frequencies, which needs the huffman codes to be limited
+// in length, round-trips with both algorithms generating the code lengths, and that the optimal
+// lengths from package-merge don't give larger output.
+#[test]
+fn huffman_algorithms_roundtrip() {
+    use std::io::Read;
+    use deflate::{CompressionOptions, HuffmanAlgorithm, deflate_bytes_conf};
+    let mut seed = 1u32;
+    let test_data: Vec<u8> = (0..200000)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            (seed | 0x8000_0000).trailing_zeros() as u8
+        })
+        .collect();
+
+    for &options in &[CompressionOptions::huffman_only(), CompressionOptions::from_level(9)] {
+        let compress = |huffman_algorithm| {
+            let options = CompressionOptions { huffman_algorithm, ..options };
+            let compressed = deflate_bytes_conf(&test_data, options).unwrap();
+
+            let mut decompressed = Vec::new();
+            flate2::read::DeflateDecoder::new(compressed.as_slice())
+                .read_to_end(&mut decompressed)
+                .unwrap();
+            assert!(decompressed == test_data);
+            compressed.len()
+        };
+        assert!(compress(HuffmanAlgorithm::PackageMerge) <= compress(HuffmanAlgorithm::InPlace));
+    }
+}
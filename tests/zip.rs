@@ -0,0 +1,129 @@
+//! Tests listing and extracting archives written by `ZipWriter` with the `unzip` tool.
+#![cfg(unix)]
+
+extern crate deflate;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use deflate::zip::{ZipWriter, FileOptions, CompressionMethod, DateTime};
+
+fn get_test_data() -> Vec<u8> {
+    let mut input = Vec::new();
+    File::open("tests/pg11.txt").unwrap().read_to_end(&mut input).unwrap();
+    input
+}
+
+fn unzip_available() -> bool {
+    let available = Command::new("unzip").arg("-v").output().is_ok();
+    if !available {
+        println!("unzip is not available, skipping test");
+    }
+    available
+}
+
+/// Run `unzip` with the given arguments, returning its output.
+fn unzip(args: &[&str], archive: &Path) -> String {
+    // Use a UTF-8 locale, as non-ASCII names are escaped otherwise.
+    let output = Command::new("unzip")
+        .env("LC_ALL", "C.UTF-8")
+        .args(args)
+        .arg(archive)
+        .output()
+        .unwrap();
+    assert!(output.status.success(),
+            "unzip {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stdout));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("deflate-zip-{}-{}", name, std::process::id()))
+}
+
+#[test]
+fn unzip_list_and_extract() {
+    if !unzip_available() {
+        return;
+    }
+    let data = get_test_data();
+    let archive_path = temp_path("archive.zip");
+    let extract_dir = temp_path("extract");
+
+    let mut zip = ZipWriter::new(File::create(&archive_path).unwrap());
+    let options = FileOptions {
+        last_modified: DateTime {
+            year: 2017,
+            month: 6,
+            day: 15,
+            hour: 13,
+            minute: 37,
+            second: 0,
+        },
+        unix_permissions: Some(0o640),
+        ..Default::default()
+    };
+    zip.start_file("pg11.txt", options).unwrap();
+    zip.write_all(&data).unwrap();
+    zip.add_directory("dir", options).unwrap();
+    zip.start_file("dir/stored.txt",
+                    FileOptions { compression_method: CompressionMethod::Stored, ..options })
+        .unwrap();
+    zip.write_all(&data[..1000]).unwrap();
+    zip.start_file("dir/grüße.txt", FileOptions { large_file: true, ..options }).unwrap();
+    zip.write_all("Grüße".as_bytes()).unwrap();
+    zip.finish().unwrap();
+
+    // Check the CRCs, and list the entries.
+    assert!(unzip(&["-tq"], &archive_path).contains("No errors detected"));
+    let names = unzip(&["-Z1"], &archive_path);
+    assert_eq!(names.lines().collect::<Vec<_>>(),
+               ["pg11.txt", "dir/", "dir/stored.txt", "dir/grüße.txt"]);
+    let listing = unzip(&["-Zl"], &archive_path);
+    assert!(listing.contains("-rw-r-----"), "{}", listing);
+    assert!(listing.contains("17-Jun-15 13:37"), "{}", listing);
+
+    unzip(&["-q", "-d", extract_dir.to_str().unwrap()], &archive_path);
+    let read = |name: &str| {
+        let mut contents = Vec::new();
+        File::open(extract_dir.join(name)).unwrap().read_to_end(&mut contents).unwrap();
+        contents
+    };
+    assert!(read("pg11.txt") == data);
+    assert!(read("dir/stored.txt") == data[..1000]);
+    assert_eq!(read("dir/grüße.txt"), "Grüße".as_bytes());
+
+    let _ = fs::remove_file(archive_path);
+    let _ = fs::remove_dir_all(extract_dir);
+}
+
+#[test]
+fn unzip_zip64() {
+    if !unzip_available() {
+        return;
+    }
+    let archive_path = temp_path("zip64.zip");
+    let mut zip = ZipWriter::new(File::create(&archive_path).unwrap());
+    let options = FileOptions {
+        compression_method: CompressionMethod::Stored,
+        ..Default::default()
+    };
+    // More entries than fit in the regular end of central directory record.
+    for i in 0..70000 {
+        zip.start_file(&format!("{:05}", i), options).unwrap();
+        zip.write_all(i.to_string().as_bytes()).unwrap();
+    }
+    zip.finish().unwrap();
+
+    assert!(unzip(&["-tq"], &archive_path).contains("No errors detected"));
+    let names = unzip(&["-Z1"], &archive_path);
+    assert_eq!(names.lines().count(), 70000);
+    assert_eq!(unzip(&["-p"], &archive_path).len(),
+               (0..70000).map(|i: u32| i.to_string().len()).sum::<usize>());
+
+    let _ = fs::remove_file(archive_path);
+}
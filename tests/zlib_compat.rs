@@ -0,0 +1,162 @@
+//! Tests comparing the output of the zlib compatible mode to output captured from zlib.
+//!
+//! The reference outputs in `tests/zlib_reference` were created by `generate.py` in that
+//! directory, which also describes how each of them was made.
+
+extern crate deflate;
+
+use std::fs::File;
+use std::io::{Read, Write};
+
+use deflate::{deflate_bytes_conf, deflate_bytes_zlib_conf, deflate_bytes_gzip_conf,
+              CompressionOptions, ZlibStrategy};
+use deflate::write::ZlibEncoder;
+
+fn read_file(name: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    File::open(name).unwrap().read_to_end(&mut data).unwrap();
+    data
+}
+
+fn get_test_data() -> Vec<u8> {
+    read_file("tests/pg11.txt")
+}
+
+fn get_text() -> Vec<u8> {
+    let mut data = get_test_data();
+    data.truncate(20000);
+    data
+}
+
+/// Pseudo-random data with some repeated parts and runs, using up more symbols per block than
+/// text does.
+fn binary_data() -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::new();
+    let mut state = 0x2545_F491u32;
+    while data.len() < 40000 {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        if state % 64 == 0 && data.len() > 1000 {
+            // Copy an earlier part of the data.
+            let start = (state >> 8) as usize % (data.len() - 300);
+            let length = (state >> 20) as usize % 300;
+            let copy = data[start..start + length].to_vec();
+            data.extend_from_slice(&copy);
+        } else if state % 64 == 1 {
+            let length = (state >> 16) as usize % 64;
+            data.extend(std::iter::repeat((state >> 24) as u8).take(length));
+        } else {
+            data.push((state >> 24) as u8 % 64);
+        }
+    }
+    data.truncate(40000);
+    data
+}
+
+fn check_reference(name: &str, output: &[u8]) {
+    let reference = read_file(&format!("tests/zlib_reference/{}", name));
+    if output != &reference[..] {
+        let first_difference = output.iter().zip(&reference).take_while(|&(a, b)| a == b).count();
+        panic!("Output differs from {} at byte {} (lengths {} and {})",
+               name,
+               first_difference,
+               output.len(),
+               reference.len());
+    }
+}
+
+fn options(level: u8, strategy: ZlibStrategy, window_bits: u8) -> CompressionOptions {
+    CompressionOptions {
+        window_bits,
+        ..CompressionOptions::zlib_compatible(level, strategy)
+    }
+}
+
+#[test]
+fn levels() {
+    let text = get_text();
+    for level in 0..10 {
+        let output =
+            deflate_bytes_zlib_conf(&text, options(level, ZlibStrategy::Default, 15)).unwrap();
+        check_reference(&format!("text-l{}.zz", level), &output);
+    }
+}
+
+#[test]
+fn strategies() {
+    let text = get_text();
+    let strategies = [("filtered", ZlibStrategy::Filtered),
+                      ("huffman", ZlibStrategy::HuffmanOnly),
+                      ("rle", ZlibStrategy::Rle),
+                      ("fixed", ZlibStrategy::Fixed)];
+    for &(name, strategy) in &strategies {
+        let output = deflate_bytes_zlib_conf(&text, options(6, strategy, 15)).unwrap();
+        check_reference(&format!("text-l6-{}.zz", name), &output);
+    }
+}
+
+#[test]
+fn window_sizes() {
+    let text = get_text();
+    for &(level, window_bits) in &[(1, 9), (4, 12), (9, 10)] {
+        let options = options(level, ZlibStrategy::Default, window_bits);
+        let output = deflate_bytes_zlib_conf(&text, options).unwrap();
+        check_reference(&format!("text-l{}-w{}.zz", level, window_bits), &output);
+    }
+}
+
+#[test]
+fn raw_and_gzip() {
+    let text = get_text();
+    let output = deflate_bytes_conf(&text, options(6, ZlibStrategy::Default, 15)).unwrap();
+    check_reference("text-l6.raw", &output);
+    for &level in &[1, 9] {
+        let output =
+            deflate_bytes_gzip_conf(&text, options(level, ZlibStrategy::Default, 15)).unwrap();
+        check_reference(&format!("text-l{}.gz", level), &output);
+    }
+}
+
+#[test]
+fn dictionary() {
+    let data = get_test_data();
+    let mut encoder = ZlibEncoder::new(Vec::new(), options(6, ZlibStrategy::Default, 15));
+    encoder.set_dictionary(&data[..10000]).unwrap();
+    encoder.write_all(&data[10000..30000]).unwrap();
+    check_reference("text-l6-dict.zz", &encoder.finish().unwrap());
+}
+
+#[test]
+fn streaming_with_sync_flushes() {
+    let text = get_text();
+    for &level in &[0, 1, 6] {
+        let mut encoder = ZlibEncoder::new(Vec::new(), options(level, ZlibStrategy::Default, 15));
+        for (n, chunk) in text.chunks(3000).enumerate() {
+            encoder.write_all(chunk).unwrap();
+            if n % 3 == 2 {
+                encoder.flush().unwrap();
+            }
+        }
+        check_reference(&format!("text-l{}-stream.zz", level), &encoder.finish().unwrap());
+    }
+}
+
+#[test]
+fn large_input() {
+    let data = get_test_data();
+    let output = deflate_bytes_zlib_conf(&data, options(6, ZlibStrategy::Default, 15)).unwrap();
+    check_reference("pg11-l6.zz", &output);
+}
+
+#[test]
+fn binary() {
+    let data = binary_data();
+    for &level in &[1, 6] {
+        let output =
+            deflate_bytes_zlib_conf(&data, options(level, ZlibStrategy::Default, 15)).unwrap();
+        check_reference(&format!("binary-l{}.zz", level), &output);
+    }
+    let output = deflate_bytes_zlib_conf(&data, options(6, ZlibStrategy::Rle, 15)).unwrap();
+    check_reference("binary-l6-rle.zz", &output);
+}
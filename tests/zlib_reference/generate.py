@@ -0,0 +1,117 @@
+#!/usr/bin/env python3
+"""Capture the reference outputs used by `tests/zlib_compat.rs` from the system zlib.
+
+The library is called directly through ctypes, so every `deflate()` call gets exactly the input
+and flush mode listed here, and an output buffer large enough to never fill up. The outputs in
+this directory were captured with zlib 1.2.13.
+"""
+
+import ctypes
+import ctypes.util
+import os
+
+Z_NO_FLUSH, Z_SYNC_FLUSH, Z_FINISH = 0, 2, 4
+Z_DEFAULT, Z_FILTERED, Z_HUFFMAN_ONLY, Z_RLE, Z_FIXED = 0, 1, 2, 3, 4
+
+
+class ZStream(ctypes.Structure):
+    _fields_ = [("next_in", ctypes.c_void_p), ("avail_in", ctypes.c_uint),
+                ("total_in", ctypes.c_ulong), ("next_out", ctypes.c_void_p),
+                ("avail_out", ctypes.c_uint), ("total_out", ctypes.c_ulong),
+                ("msg", ctypes.c_char_p), ("state", ctypes.c_void_p),
+                ("zalloc", ctypes.c_void_p), ("zfree", ctypes.c_void_p),
+                ("opaque", ctypes.c_void_p), ("data_type", ctypes.c_int),
+                ("adler", ctypes.c_ulong), ("reserved", ctypes.c_ulong)]
+
+
+zlib = ctypes.CDLL(ctypes.util.find_library("z"))
+zlib.zlibVersion.restype = ctypes.c_char_p
+VERSION = zlib.zlibVersion()
+
+
+def deflate(calls, level, strategy=Z_DEFAULT, window_bits=15, dictionary=None):
+    """Compress the data in `calls`, a list of `(input, flush)` pairs passed to `deflate()`."""
+    stream = ZStream()
+    assert zlib.deflateInit2_(ctypes.byref(stream), level, 8, window_bits, 8, strategy, VERSION,
+                              ctypes.sizeof(stream)) == 0
+    if dictionary is not None:
+        assert zlib.deflateSetDictionary(ctypes.byref(stream), dictionary, len(dictionary)) == 0
+    output = b""
+    for data, flush in calls:
+        input_buffer = ctypes.create_string_buffer(data, len(data))
+        output_buffer = ctypes.create_string_buffer(2 * len(data) + (1 << 20))
+        stream.next_in = ctypes.addressof(input_buffer)
+        stream.avail_in = len(data)
+        stream.next_out = ctypes.addressof(output_buffer)
+        stream.avail_out = len(output_buffer)
+        # Z_BUF_ERROR (-5) is returned for flushes that have nothing to do.
+        assert zlib.deflate(ctypes.byref(stream), flush) in (0, 1, -5)
+        assert stream.avail_in == 0 and stream.avail_out > 0
+        output += output_buffer.raw[:len(output_buffer) - stream.avail_out]
+    zlib.deflateEnd(ctypes.byref(stream))
+    return output
+
+
+def binary_data():
+    """Generate the same data as `binary_data()` in `tests/zlib_compat.rs`."""
+    data = bytearray()
+    state = 0x2545F491
+    while len(data) < 40000:
+        state ^= (state << 13) & 0xFFFFFFFF
+        state ^= state >> 17
+        state ^= (state << 5) & 0xFFFFFFFF
+        if state % 64 == 0 and len(data) > 1000:
+            # Copy an earlier part of the data.
+            start = (state >> 8) % (len(data) - 300)
+            data += data[start:start + (state >> 20) % 300]
+        elif state % 64 == 1:
+            data += bytes([state >> 24]) * ((state >> 16) % 64)
+        else:
+            data.append((state >> 24) % 64)
+    return bytes(data[:40000])
+
+
+def streamed(data, chunk_size, sync_every):
+    calls = []
+    for n, start in enumerate(range(0, len(data), chunk_size)):
+        calls.append((data[start:start + chunk_size], Z_NO_FLUSH))
+        if n % sync_every == sync_every - 1:
+            calls.append((b"", Z_SYNC_FLUSH))
+    return calls + [(b"", Z_FINISH)]
+
+
+def main():
+    directory = os.path.dirname(os.path.abspath(__file__))
+    with open(os.path.join(directory, "..", "pg11.txt"), "rb") as f:
+        pg11 = f.read()
+    text = pg11[:20000]
+    binary = binary_data()
+
+    outputs = {}
+    for level in range(10):
+        outputs["text-l%d.zz" % level] = deflate([(text, Z_FINISH)], level)
+    for name, strategy in [("filtered", Z_FILTERED), ("huffman", Z_HUFFMAN_ONLY),
+                           ("rle", Z_RLE), ("fixed", Z_FIXED)]:
+        outputs["text-l6-%s.zz" % name] = deflate([(text, Z_FINISH)], 6, strategy)
+    for level, bits in [(1, 9), (4, 12), (9, 10)]:
+        outputs["text-l%d-w%d.zz" % (level, bits)] = deflate([(text, Z_FINISH)], level,
+                                                             window_bits=bits)
+    outputs["text-l6.raw"] = deflate([(text, Z_FINISH)], 6, window_bits=-15)
+    for level in [1, 9]:
+        outputs["text-l%d.gz" % level] = deflate([(text, Z_FINISH)], level, window_bits=31)
+    outputs["text-l6-dict.zz"] = deflate([(pg11[10000:30000], Z_FINISH)], 6,
+                                         dictionary=pg11[:10000])
+    for level in [0, 1, 6]:
+        outputs["text-l%d-stream.zz" % level] = deflate(streamed(text, 3000, 3), level)
+    outputs["pg11-l6.zz"] = deflate([(pg11, Z_FINISH)], 6)
+    for level in [1, 6]:
+        outputs["binary-l%d.zz" % level] = deflate([(binary, Z_FINISH)], level)
+    outputs["binary-l6-rle.zz"] = deflate([(binary, Z_FINISH)], 6, Z_RLE)
+
+    for name, output in sorted(outputs.items()):
+        with open(os.path.join(directory, name), "wb") as f:
+            f.write(output)
+
+
+if __name__ == "__main__":
+    main()